@@ -0,0 +1,163 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, Ident, ItemFn, LitStr, ReturnType};
+
+// Attribute macros for `worker-rust`.
+//
+// The expansions refer to the span helpers through `crate::` paths, so the macros are only
+// meant to be used from within `worker-rust` itself.
+
+#[derive(Default)]
+struct Args {
+    name: Option<LitStr>,
+    fields: Vec<Ident>,
+    err: Option<ErrFormat>,
+}
+
+enum ErrFormat {
+    Display,
+    Debug,
+}
+
+/// Like `#[minitrace::trace]`, but the span always ends up in the `LocalCollector`, and the
+/// arguments listed in `fields(...)` are recorded as properties using their `Debug` output.
+///
+/// ```ignore
+/// #[traced(name = "load_user", fields(req_id, user))]
+/// async fn load_user(req_id: u64, user: &str) { ... }
+/// ```
+///
+/// Sync functions are wrapped in a `ScopedSpan`, async functions are polled `in_local_span`.
+///
+/// With `err`, a function returning `Result` sets the status of its span to `Error` when it
+/// returns an `Err`, using the error's `Display` output as the description and as an
+/// `exception` event (`err(Debug)` uses its `Debug` output instead).
+///
+/// With the `off` feature, the function is left as it is.
+#[proc_macro_attribute]
+pub fn traced(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut args = Args::default();
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("name") {
+            args.name = Some(meta.value()?.parse()?);
+            Ok(())
+        } else if meta.path.is_ident("fields") {
+            meta.parse_nested_meta(|field| {
+                args.fields.push(field.path.require_ident()?.clone());
+                Ok(())
+            })
+        } else if meta.path.is_ident("err") {
+            let mut format = ErrFormat::Display;
+            if !meta.input.is_empty() && !meta.input.peek(syn::Token![,]) {
+                meta.parse_nested_meta(|nested| {
+                    if nested.path.is_ident("Debug") {
+                        format = ErrFormat::Debug;
+                    } else if !nested.path.is_ident("Display") {
+                        return Err(nested.error("expected `Display` or `Debug`"));
+                    }
+                    Ok(())
+                })?;
+            }
+            args.err = Some(format);
+            Ok(())
+        } else {
+            Err(meta.error("unsupported `traced` argument"))
+        }
+    });
+    parse_macro_input!(attr with parser);
+    if cfg!(feature = "off") {
+        return item;
+    }
+
+    let ItemFn {
+        attrs,
+        vis,
+        sig,
+        mut block,
+    } = parse_macro_input!(item as ItemFn);
+
+    let name = match &args.name {
+        Some(name) => quote!(#name),
+        None => quote!(minitrace::full_name!()),
+    };
+    let properties = args.fields.iter().map(|field| {
+        let key = field.to_string();
+        quote! {
+            (
+                std::borrow::Cow::<'static, str>::from(#key),
+                std::borrow::Cow::<'static, str>::from(format!("{:?}", &#field)),
+            )
+        }
+    });
+    let properties = if args.fields.is_empty() {
+        None
+    } else {
+        Some(quote!([#(#properties),*]))
+    };
+
+    if let Some(format) = &args.err {
+        let ret = match &sig.output {
+            ReturnType::Type(_, ty) => quote!(#ty),
+            ReturnType::Default => {
+                return syn::Error::new_spanned(
+                    &sig,
+                    "`err` requires a function returning `Result`",
+                )
+                .to_compile_error()
+                .into()
+            }
+        };
+        let message = match format {
+            ErrFormat::Display => quote!(format!("{}", __err__)),
+            ErrFormat::Debug => quote!(format!("{:?}", __err__)),
+        };
+        // Run the original body first so an early `return` or `?` still passes through here.
+        let call = if sig.asyncness.is_some() {
+            quote!(async move #block.await)
+        } else {
+            quote!((|| #block)())
+        };
+        block = syn::parse_quote!({
+            #[allow(clippy::redundant_closure_call)]
+            let __ret__: #ret = #call;
+            if let Err(__err__) = &__ret__ {
+                crate::error::record(#message);
+            }
+            __ret__
+        });
+    }
+
+    let body = if sig.asyncness.is_some() {
+        // The properties are formatted before the arguments are moved into the future, which
+        // is only worth it if something is collecting.
+        let with_properties = properties
+            .is_some()
+            .then(|| quote!(.with_properties(__properties__.into_iter().flatten())));
+        let properties = properties.map(|properties| {
+            quote! {
+                let __properties__ = crate::collector::is_collecting().then(|| #properties);
+            }
+        });
+        quote! {
+            #properties
+            crate::local_future::LocalFutureExt::in_local_span(async move #block, #name)
+                #with_properties
+                .await
+        }
+    } else {
+        let with_properties = properties.map(|properties| quote!(.with_properties(|| #properties)));
+        quote! {
+            let __guard__ = crate::scoped_span::ScopedSpan::enter_with_local_parent(#name)
+                #with_properties;
+            #block
+        }
+    };
+
+    quote! {
+        #(#attrs)*
+        #vis #sig {
+            #body
+        }
+    }
+    .into()
+}
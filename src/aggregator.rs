@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use worker::{
+    durable::{ListOptions, State},
+    durable_object, js_sys, wasm_bindgen, wasm_bindgen_futures, worker_sys, Date, Env, Method,
+    Request, Response,
+};
+
+use crate::export::{tail, OtlpExporter, SpanExporter};
+
+// The Durable Object end of `AggregatorExporter`. Batches are written to storage as they come
+// in, so an eviction doesn't lose them, and an alarm exports them all at once, in one OTLP
+// request, `AGGREGATOR_FLUSH_SECONDS` after the first one came in. A failed export throws
+// from the alarm, which the runtime retries with backoff, the batches staying where they are
+// until an export goes through.
+//
+// The object is configured by the worker it's deployed with:
+//
+// - `OTLP_ENDPOINT`, the collector's traces endpoint, a var or a secret
+// - `OTLP_HEADERS`, optional, `name=value` pairs separated by commas
+// - `OTEL_SERVICE_NAME`, optional, `worker` otherwise
+// - `AGGREGATOR_FLUSH_SECONDS`, optional, `10` otherwise
+//
+// ```toml
+// [[durable_objects.bindings]]
+// name = "TRACE_AGGREGATOR"
+// class_name = "TraceAggregator"
+// ```
+
+const BATCH_PREFIX: &str = "batch/";
+/// The most batches exported by one alarm, the rest are left for the next one.
+const MAX_BATCHES: usize = 1000;
+/// The most keys storage deletes at once.
+const MAX_DELETES: usize = 128;
+
+/// Aggregates the batches of `AggregatorExporter`s and exports them on a timer.
+#[durable_object]
+pub struct TraceAggregator {
+    state: State,
+    exporter: Option<OtlpExporter>,
+    flush_after: Duration,
+}
+
+#[durable_object]
+impl DurableObject for TraceAggregator {
+    fn new(state: State, env: Env) -> Self {
+        let text = |name: &str| {
+            env.var(name)
+                .map(|var| var.to_string())
+                .or_else(|_| env.secret(name).map(|secret| secret.to_string()))
+                .ok()
+        };
+        let exporter = text("OTLP_ENDPOINT").map(|endpoint| {
+            let mut exporter = OtlpExporter::new(endpoint)
+                .service_name(text("OTEL_SERVICE_NAME").unwrap_or_else(|| "worker".to_owned()));
+            for header in text("OTLP_HEADERS").iter().flat_map(|h| h.split(',')) {
+                if let Some((name, value)) = header.split_once('=') {
+                    exporter = exporter.header(name.trim(), value.trim());
+                }
+            }
+            exporter
+        });
+        let flush_after = text("AGGREGATOR_FLUSH_SECONDS")
+            .and_then(|seconds| seconds.parse().ok())
+            .map_or(Duration::from_secs(10), Duration::from_secs);
+        Self {
+            state,
+            exporter,
+            flush_after,
+        }
+    }
+
+    async fn fetch(&mut self, mut req: Request) -> worker::Result<Response> {
+        if req.method() != Method::Post {
+            return Response::error("batches are POSTed", 405);
+        }
+        let batch = req.text().await?;
+        if batch.is_empty() {
+            return Response::empty();
+        }
+        let mut id = [0; 4];
+        getrandom::getrandom(&mut id).expect("failed to generate a random batch id");
+        let key = format!(
+            "{BATCH_PREFIX}{:015}/{:08x}",
+            Date::now().as_millis(),
+            u32::from_ne_bytes(id)
+        );
+        let mut storage = self.state.storage();
+        storage.put(&key, batch).await?;
+        if storage.get_alarm().await?.is_none() {
+            storage.set_alarm(self.flush_after).await?;
+        }
+        Response::empty()
+    }
+
+    async fn alarm(&mut self) -> worker::Result<Response> {
+        let mut storage = self.state.storage();
+        let batches = storage
+            .list_with_options(ListOptions::new().prefix(BATCH_PREFIX).limit(MAX_BATCHES))
+            .await?;
+        let mut keys = Vec::new();
+        let mut records = Vec::new();
+        batches.for_each(&mut |batch, key| {
+            if let Some(key) = key.as_string() {
+                keys.push(key);
+            }
+            if let Some(batch) = batch.as_string() {
+                records.extend(batch.lines().filter_map(tail::decode).flatten());
+            }
+        });
+
+        match &self.exporter {
+            Some(exporter) if !records.is_empty() => exporter.export(records).await?,
+            Some(_) => {}
+            None => worker::console_error!("the trace aggregator has no OTLP_ENDPOINT"),
+        }
+        for chunk in keys.chunks(MAX_DELETES) {
+            storage.delete_multiple(chunk.to_vec()).await?;
+        }
+        if keys.len() == MAX_BATCHES {
+            storage.set_alarm(Duration::ZERO).await?;
+        }
+        Response::empty()
+    }
+}
@@ -0,0 +1,92 @@
+use serde::{de::DeserializeOwned, Serialize};
+use worker::{
+    d1::serde_wasm_bindgen,
+    js_sys::{Object, Promise, Reflect},
+    wasm_bindgen::{self, prelude::*, JsCast},
+    wasm_bindgen_futures::JsFuture,
+    EnvBinding,
+};
+
+use crate::{binding, current};
+
+// This version of `worker` has no Workers AI binding, it's bound here. Each `run` gets a
+// client span after the generative AI conventions, with the size of the input and, for the
+// models that report it, how many tokens went in and out. The time inference takes is the
+// span's duration.
+
+const GEN_AI_SYSTEM: &str = "cloudflare_workers_ai";
+
+#[wasm_bindgen]
+extern "C" {
+    /// A Workers AI binding.
+    #[derive(Clone)]
+    pub type Ai;
+
+    #[wasm_bindgen(method)]
+    fn run(this: &Ai, model: &str, inputs: JsValue) -> Promise;
+}
+
+impl EnvBinding for Ai {
+    const TYPE_NAME: &'static str = "Ai";
+}
+
+/// An `Ai` binding whose runs get a client span each.
+#[derive(Clone)]
+pub struct TracedAi {
+    ai: Ai,
+}
+
+impl TracedAi {
+    pub fn new(ai: Ai) -> Self {
+        Self { ai }
+    }
+
+    /// Traces the binding bound as `binding` in `env`.
+    pub fn from_env(env: &worker::Env, binding: &str) -> worker::Result<Self> {
+        Ok(Self::new(env.get_binding(binding)?))
+    }
+
+    /// Runs `model` on `inputs`.
+    pub async fn run<I, O>(&self, model: &str, inputs: &I) -> worker::Result<O>
+    where
+        I: Serialize,
+        O: DeserializeOwned,
+    {
+        let properties = vec![
+            ("gen_ai.system", GEN_AI_SYSTEM.to_owned()),
+            ("gen_ai.operation.name", "run".to_owned()),
+            ("gen_ai.request.model", model.to_owned()),
+        ];
+        binding::call("run", model, properties, async {
+            if let Ok(json) = serde_json::to_string(inputs) {
+                current::add_attribute("gen_ai.request.size", json.len() as i64);
+            }
+            let inputs = inputs.serialize(&serde_wasm_bindgen::Serializer::json_compatible())?;
+            let output = JsFuture::from(self.ai.run(model, inputs)).await?;
+            record_usage(&output);
+            Ok(serde_wasm_bindgen::from_value(output)?)
+        })
+        .await
+    }
+}
+
+/// Records the `usage` the text generation models report, if any.
+fn record_usage(output: &JsValue) {
+    let Some(usage) = Reflect::get(output, &"usage".into())
+        .ok()
+        .and_then(|usage| usage.dyn_into::<Object>().ok())
+    else {
+        return;
+    };
+    for (key, attribute) in [
+        ("prompt_tokens", "gen_ai.usage.input_tokens"),
+        ("completion_tokens", "gen_ai.usage.output_tokens"),
+    ] {
+        if let Some(tokens) = Reflect::get(&usage, &key.into())
+            .ok()
+            .and_then(|tokens| tokens.as_f64())
+        {
+            current::add_attribute(attribute, tokens as i64);
+        }
+    }
+}
@@ -0,0 +1,18 @@
+use std::borrow::Cow;
+
+use minitrace::Event;
+
+use crate::record::AMBIENT_EVENT;
+
+// Attributes that apply to a whole part of the trace, like the tenant or request id set
+// once at the root of the fetch handler. They are recorded on the current local parent and
+// copied onto it and every span below it by `record::normalize`, which includes spans
+// created by `#[trace]`, `#[traced]` or plain `LocalSpan`s.
+
+/// Sets `key = value` on the current local parent and every span nested in it.
+///
+/// A span that already has a property named `key` keeps its own value.
+pub fn set(key: impl Into<Cow<'static, str>>, value: impl Into<Cow<'static, str>>) {
+    let property = (key.into(), value.into());
+    Event::add_to_local_parent(AMBIENT_EVENT, || [property]);
+}
@@ -0,0 +1,264 @@
+use std::fmt;
+
+use worker::Headers;
+
+use crate::{ambient, collector};
+
+// W3C Baggage (https://www.w3.org/TR/baggage/): application defined `key=value` pairs, like
+// the tenant of a request, that travel along with the trace context to every service the
+// request goes through. The baggage of a request is kept by its `Collector` (see
+// `Collector::with_baggage`), so handler code can read and change it with `get`/`set` and
+// have it passed on to subrequests.
+//
+// Baggage isn't recorded on spans by default, `record` copies it onto the current span and
+// the spans below it.
+
+pub const BAGGAGE: &str = "baggage";
+/// The prefix of the properties `record` adds to spans.
+pub const PROPERTY_PREFIX: &str = "baggage.";
+
+// Limits from the spec, entries past them are dropped.
+const MAX_ENTRIES: usize = 180;
+const MAX_LEN: usize = 8192;
+
+/// A list of baggage entries.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Baggage(Vec<Entry>);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Entry {
+    key: String,
+    value: String,
+    // Kept as is (`;`-separated) and passed on untouched.
+    metadata: String,
+}
+
+impl Baggage {
+    /// Parses a `baggage` header value, skipping invalid entries.
+    pub fn parse(value: &str) -> Self {
+        let mut baggage = Self::default();
+        for member in value.split(',').map(str::trim).filter(|m| !m.is_empty()) {
+            let (pair, metadata) = member.split_once(';').unwrap_or((member, ""));
+            let Some((key, value)) = pair.split_once('=') else {
+                continue;
+            };
+            let key = key.trim();
+            if !is_token(key) {
+                continue;
+            }
+            if let Some(value) = percent_decode(value.trim()) {
+                baggage.insert_entry(Entry {
+                    key: key.to_owned(),
+                    value,
+                    metadata: metadata.trim().to_owned(),
+                });
+            }
+        }
+        baggage
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|entry| entry.key == key)
+            .map(|entry| entry.value.as_str())
+    }
+
+    /// Sets `key`, replacing any previous value. Invalid keys are ignored.
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        let key = key.into();
+        if !is_token(&key) {
+            return;
+        }
+        self.insert_entry(Entry {
+            key,
+            value: value.into(),
+            metadata: String::new(),
+        });
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let index = self.0.iter().position(|entry| entry.key == key)?;
+        Some(self.0.remove(index).value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|entry| (entry.key.as_str(), entry.value.as_str()))
+    }
+
+    fn insert_entry(&mut self, entry: Entry) {
+        self.0.retain(|e| e.key != entry.key);
+        if self.0.len() < MAX_ENTRIES {
+            self.0.push(entry);
+        }
+    }
+}
+
+impl fmt::Display for Baggage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut len = 0;
+        for entry in &self.0 {
+            let mut member = format!("{}={}", entry.key, percent_encode(&entry.value));
+            if !entry.metadata.is_empty() {
+                member.push(';');
+                member.push_str(&entry.metadata);
+            }
+            // The separator counts too.
+            let added = member.len() + (len > 0) as usize;
+            if len + added > MAX_LEN {
+                continue;
+            }
+            if len > 0 {
+                f.write_str(",")?;
+            }
+            f.write_str(&member)?;
+            len += added;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the baggage propagated in `headers`, empty if there's none.
+pub fn extract(headers: &Headers) -> Baggage {
+    headers
+        .get(BAGGAGE)
+        .ok()
+        .flatten()
+        .map(|value| Baggage::parse(&value))
+        .unwrap_or_default()
+}
+
+/// Propagates `baggage` in `headers`.
+pub fn inject(headers: &mut Headers, baggage: &Baggage) -> worker::Result<()> {
+    if baggage.is_empty() {
+        headers.delete(BAGGAGE)
+    } else {
+        headers.set(BAGGAGE, &baggage.to_string())
+    }
+}
+
+/// The value of `key` in the baggage of the trace being collected.
+pub fn get(key: &str) -> Option<String> {
+    collector::active_baggage()?.get(key).map(str::to_owned)
+}
+
+/// Sets `key` in the baggage of the trace being collected, so it's passed on to the
+/// subrequests made from now on.
+pub fn set(key: impl Into<String>, value: impl Into<String>) {
+    let (key, value) = (key.into(), value.into());
+    collector::update_active_baggage(|baggage| baggage.insert(key, value));
+}
+
+/// The whole baggage of the trace being collected.
+pub fn current() -> Baggage {
+    collector::active_baggage().unwrap_or_default()
+}
+
+/// Copies the baggage of the trace being collected onto the current local parent and every
+/// span nested in it, as `baggage.<key>` properties.
+pub fn record() {
+    for (key, value) in current().iter() {
+        ambient::set(format!("{PROPERTY_PREFIX}{key}"), value.to_owned());
+    }
+}
+
+fn is_token(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+fn percent_decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut iter = value.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            let hex = std::str::from_utf8(&hex).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+// Everything but the characters allowed as is in a value (`baggage-octet`).
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for b in value.bytes() {
+        match b {
+            0x21 | 0x23..=0x2b | 0x2d..=0x3a | 0x3c..=0x5b | 0x5d..=0x7e if b != b'%' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{b:02X}")),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let baggage = Baggage::parse(
+            " tenant = acme , user=J%C3%B6rg;ttl=60, invalid, bad key=1, empty=,novalue=%4",
+        );
+        assert_eq!(
+            baggage.iter().collect::<Vec<_>>(),
+            [("tenant", "acme"), ("user", "Jörg"), ("empty", "")]
+        );
+        // The metadata is passed on untouched.
+        assert_eq!(
+            baggage.to_string(),
+            "tenant=acme,user=J%C3%B6rg;ttl=60,empty="
+        );
+        assert!(Baggage::parse("").is_empty());
+    }
+
+    #[test]
+    fn insert() {
+        let mut baggage = Baggage::parse("tenant=acme,region=eu");
+        baggage.insert("tenant", "globex");
+        baggage.insert("note", "50% off, \"today\";");
+        baggage.insert("bad key", "x");
+        assert_eq!(baggage.get("tenant"), Some("globex"));
+        assert_eq!(
+            baggage.to_string(),
+            "region=eu,tenant=globex,note=50%25%20off%2C%20%22today%22%3B"
+        );
+        assert_eq!(
+            Baggage::parse(&baggage.to_string()).get("note"),
+            Some("50% off, \"today\";")
+        );
+        assert_eq!(baggage.remove("region").as_deref(), Some("eu"));
+        assert_eq!(baggage.get("region"), None);
+    }
+
+    #[test]
+    fn limits() {
+        let members = (0..200).map(|i| format!("k{i}=v")).collect::<Vec<_>>();
+        assert_eq!(
+            Baggage::parse(&members.join(",")).iter().count(),
+            MAX_ENTRIES
+        );
+
+        // Entries that would make the header too long are left out.
+        let mut baggage = Baggage::default();
+        baggage.insert("a", "x".repeat(MAX_LEN - 2));
+        baggage.insert("b", "y".repeat(10));
+        baggage.insert("c", "");
+        let header = baggage.to_string();
+        assert_eq!(header.len(), MAX_LEN);
+        assert!(header.starts_with("a=x") && !header.contains("b="));
+    }
+}
@@ -0,0 +1,41 @@
+use std::{fmt::Display, future::Future};
+
+use crate::{
+    error,
+    kind::{SpanKind, SPAN_KIND},
+    local_future::LocalFutureExt,
+};
+
+// What the traced bindings (`kv`, `d1`...) have in common: each call is a client span named
+// `{operation} {target}` as the database conventions go, errors go through `error::record`,
+// and whatever is only known once the call is done is added from inside `call` with the
+// `current` helpers.
+
+/// Awaits `call` inside a client span for `operation` on `target`.
+pub(crate) async fn call<T, E, F>(
+    operation: &str,
+    target: &str,
+    properties: Vec<(&'static str, String)>,
+    call: F,
+) -> Result<T, E>
+where
+    E: Display,
+    F: Future<Output = Result<T, E>>,
+{
+    let name = if target.is_empty() {
+        operation.to_owned()
+    } else {
+        format!("{operation} {target}")
+    };
+    async {
+        let res = call.await;
+        if let Err(err) = &res {
+            error::record(err.to_string());
+        }
+        res
+    }
+    .in_local_span(name)
+    .with_property(SPAN_KIND, SpanKind::Client.as_str())
+    .with_properties(properties)
+    .await
+}
@@ -0,0 +1,153 @@
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+};
+
+use futures_core::Stream;
+use pin_project::{pin_project, pinned_drop};
+use worker::{ByteStream, Request, Response, ResponseBody};
+
+use crate::{current, value::TYPE_PREFIX};
+
+// Body sizes as the HTTP semantic conventions name them. A body read in one go is counted
+// right away; a streamed one is only known once the stream ends, which can be well after the
+// span it belongs to did, so the span gets a lazy property (see `lazy::resolve`) reading a
+// counter the stream keeps up to date, and whoever exports the span waits for the stream to
+// end first, see `FetchTracing::body_sizes`.
+
+pub(crate) const REQUEST_BODY_SIZE: &str = "http.request.body.size";
+pub(crate) const RESPONSE_BODY_SIZE: &str = "http.response.body.size";
+
+/// Reads the body of `req` like `Request::bytes`, recording its size on the current span.
+pub async fn bytes(req: &mut Request) -> worker::Result<Vec<u8>> {
+    let bytes = req.bytes().await?;
+    current::add_attribute(REQUEST_BODY_SIZE, bytes.len() as i64);
+    Ok(bytes)
+}
+
+/// Reads the body of `req` like `Request::text`, recording its size on the current span.
+pub async fn text(req: &mut Request) -> worker::Result<String> {
+    let text = req.text().await?;
+    current::add_attribute(REQUEST_BODY_SIZE, text.len() as i64);
+    Ok(text)
+}
+
+/// Streams the body of `req` like `Request::stream`, recording its size on the current span
+/// once the stream ends.
+pub fn stream(req: &mut Request) -> worker::Result<Counted<ByteStream>> {
+    let (stream, _) = Counted::new(req.stream()?, REQUEST_BODY_SIZE);
+    Ok(stream)
+}
+
+/// Records the size of the body of `res` on the current span. For a streamed body, the
+/// response is rebuilt around a counting stream, and the returned future resolves once it
+/// ended.
+pub(crate) fn count_response(mut res: Response) -> worker::Result<(Response, Option<Finished>)> {
+    match res.body() {
+        ResponseBody::Empty => {
+            current::add_attribute(RESPONSE_BODY_SIZE, 0_i64);
+            Ok((res, None))
+        }
+        ResponseBody::Body(bytes) => {
+            current::add_attribute(RESPONSE_BODY_SIZE, bytes.len() as i64);
+            Ok((res, None))
+        }
+        ResponseBody::Stream(_) => {
+            let (status_code, headers) = (res.status_code(), res.headers().clone());
+            let (stream, finished) = Counted::new(res.stream()?, RESPONSE_BODY_SIZE);
+            let res = Response::from_stream(stream)?
+                .with_status(status_code)
+                .with_headers(headers);
+            Ok((res, Some(finished)))
+        }
+    }
+}
+
+/// A stream of bytes counting how many went through it.
+#[pin_project(PinnedDrop)]
+pub struct Counted<S> {
+    #[pin]
+    inner: S,
+    state: Rc<RefCell<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    size: u64,
+    done: bool,
+    waker: Option<Waker>,
+}
+
+impl<S> Counted<S> {
+    fn new(inner: S, key: &'static str) -> (Self, Finished) {
+        let state = Rc::new(RefCell::new(State::default()));
+        let counter = state.clone();
+        current::add_property(format!("{TYPE_PREFIX}{key}"), "i64");
+        current::add_property_lazy(key, move || -> Cow<'static, str> {
+            counter.borrow().size.to_string().into()
+        });
+        (
+            Self {
+                inner,
+                state: state.clone(),
+            },
+            Finished { state },
+        )
+    }
+}
+
+impl<S, E> Stream for Counted<S>
+where
+    S: Stream<Item = Result<Vec<u8>, E>>,
+{
+    type Item = Result<Vec<u8>, E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let item = this.inner.poll_next(cx);
+        match &item {
+            Poll::Ready(Some(Ok(chunk))) => this.state.borrow_mut().size += chunk.len() as u64,
+            Poll::Ready(None) => finish(this.state),
+            _ => {}
+        }
+        item
+    }
+}
+
+// A stream dropped halfway, e.g. because the client went away, is done as well.
+#[pinned_drop]
+impl<S> PinnedDrop for Counted<S> {
+    fn drop(self: Pin<&mut Self>) {
+        finish(self.project().state);
+    }
+}
+
+fn finish(state: &RefCell<State>) {
+    let mut state = state.borrow_mut();
+    state.done = true;
+    if let Some(waker) = state.waker.take() {
+        waker.wake();
+    }
+}
+
+/// Resolves once a `Counted` stream ended.
+pub(crate) struct Finished {
+    state: Rc<RefCell<State>>,
+}
+
+impl Future for Finished {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.state.borrow_mut();
+        if state.done {
+            return Poll::Ready(());
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
@@ -0,0 +1,132 @@
+use std::borrow::Cow;
+
+use crate::{
+    kind::{SpanKind, SPAN_KIND},
+    link::{self, Link},
+    scoped_span::{ScopedSpan, SpanHandle},
+    value::Value,
+};
+
+/// Collects everything a span should start with, then enters it in one go.
+///
+/// ```ignore
+/// let _span = LocalSpanBuilder::new("lookup")
+///     .property("key", key)
+///     .enter();
+/// ```
+#[must_use]
+pub struct LocalSpanBuilder {
+    name: Cow<'static, str>,
+    parent: Option<SpanHandle>,
+    properties: Vec<(Cow<'static, str>, Cow<'static, str>)>,
+    links: Vec<Link>,
+}
+
+impl LocalSpanBuilder {
+    pub fn new(name: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            name: name.into(),
+            parent: None,
+            properties: Vec::new(),
+            links: Vec::new(),
+        }
+    }
+
+    /// Enters the span under `parent` instead of the current local parent.
+    pub fn parent(mut self, parent: &SpanHandle) -> Self {
+        self.parent = Some(parent.clone());
+        self
+    }
+
+    pub fn kind(self, kind: SpanKind) -> Self {
+        self.property(SPAN_KIND, kind.as_str())
+    }
+
+    /// Links the span to a span of another trace.
+    pub fn link(mut self, link: impl Into<Link>) -> Self {
+        self.links.push(link.into());
+        self
+    }
+
+    pub fn property(
+        mut self,
+        key: impl Into<Cow<'static, str>>,
+        value: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        self.properties.push((key.into(), value.into()));
+        self
+    }
+
+    /// Adds a typed attribute, see [`Value`].
+    pub fn attribute(mut self, key: impl Into<Cow<'static, str>>, value: impl Into<Value>) -> Self {
+        self.properties
+            .extend(value.into().to_properties(key.into()));
+        self
+    }
+
+    pub fn properties<K, V>(mut self, properties: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: Into<Cow<'static, str>>,
+        V: Into<Cow<'static, str>>,
+    {
+        self.properties
+            .extend(properties.into_iter().map(|(k, v)| (k.into(), v.into())));
+        self
+    }
+
+    pub fn enter(self) -> ScopedSpan {
+        let span = match &self.parent {
+            Some(parent) => ScopedSpan::enter_with_parent(self.name, parent),
+            None => ScopedSpan::enter_with_local_parent(self.name),
+        };
+        let span = span.with_properties(|| self.properties);
+        // The new span is the current local parent now.
+        for link in self.links {
+            link::add(link);
+        }
+        span
+    }
+}
+
+// Nothing is collected with `tracing-off`.
+#[cfg(all(test, not(feature = "tracing-off")))]
+mod tests {
+    use minitrace::collector::{SpanContext, SpanId, TraceId};
+
+    use super::*;
+    use crate::collector::Collector;
+
+    #[test]
+    fn enter() {
+        let collector = Collector::start(SpanContext::new(TraceId(1), SpanId(1)));
+        {
+            let _root = ScopedSpan::enter_with_local_parent("root");
+            let root = SpanHandle::current().unwrap();
+            let _child = ScopedSpan::enter_with_local_parent("child");
+            let _span = LocalSpanBuilder::new("lookup")
+                .parent(&root)
+                .kind(SpanKind::Client)
+                .property("key", "users")
+                .attribute("attempt", 2)
+                .enter();
+        }
+        let records = collector.collect();
+        let [root, child, span] = records.as_slice() else {
+            panic!("unexpected records: {records:?}");
+        };
+        assert_eq!(child.parent_id, root.span_id);
+        assert_eq!(
+            (span.name.as_ref(), span.parent_id),
+            ("lookup", root.span_id)
+        );
+        assert_eq!(
+            span.properties,
+            [
+                ("span.kind".into(), "client".into()),
+                ("key".into(), "users".into()),
+                ("__type.attempt".into(), "i64".into()),
+                ("attempt".into(), "2".into()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use worker::{Cache, CacheDeletionOutcome, CacheKey, Response};
+
+use crate::{binding, current};
+
+// Every lookup records `cache.hit`, so hit ratios can be computed from the traces alone, per
+// cache and per key.
+
+/// A `Cache` whose operations get a client span each.
+pub struct TracedCache {
+    cache: Cache,
+    name: String,
+}
+
+impl Default for TracedCache {
+    /// The default cache, see `Cache::default`.
+    fn default() -> Self {
+        Self::new(Cache::default(), "default")
+    }
+}
+
+impl TracedCache {
+    /// Traces the operations on `cache`, named `name`.
+    pub fn new(cache: Cache, name: impl Into<String>) -> Self {
+        Self {
+            cache,
+            name: name.into(),
+        }
+    }
+
+    /// Opens the cache named `name`, see `Cache::open`.
+    pub async fn open(name: String) -> Self {
+        Self::new(Cache::open(name.clone()).await, name)
+    }
+
+    /// The traced cache.
+    pub fn inner(&self) -> &Cache {
+        &self.cache
+    }
+
+    /// Looks `key` up, see `Cache::get`.
+    pub async fn get<'a>(
+        &self,
+        key: impl Into<CacheKey<'a>>,
+        ignore_method: bool,
+    ) -> worker::Result<Option<Response>> {
+        let key = key.into();
+        binding::call("match", &self.name, self.properties(&key), async {
+            let response = self.cache.get(key, ignore_method).await?;
+            current::add_attribute("cache.hit", response.is_some());
+            Ok(response)
+        })
+        .await
+    }
+
+    /// Stores `response` under `key`, see `Cache::put`.
+    pub async fn put<'a>(
+        &self,
+        key: impl Into<CacheKey<'a>>,
+        response: Response,
+    ) -> worker::Result<()> {
+        let key = key.into();
+        binding::call(
+            "put",
+            &self.name,
+            self.properties(&key),
+            self.cache.put(key, response),
+        )
+        .await
+    }
+
+    /// Deletes what's stored under `key`, recording whether there was anything.
+    pub async fn delete<'a>(
+        &self,
+        key: impl Into<CacheKey<'a>>,
+        ignore_method: bool,
+    ) -> worker::Result<CacheDeletionOutcome> {
+        let key = key.into();
+        binding::call("delete", &self.name, self.properties(&key), async {
+            let outcome = self.cache.delete(key, ignore_method).await?;
+            current::add_attribute(
+                "cache.hit",
+                matches!(outcome, CacheDeletionOutcome::Success),
+            );
+            Ok(outcome)
+        })
+        .await
+    }
+
+    fn properties(&self, key: &CacheKey) -> Vec<(&'static str, String)> {
+        let key = match key {
+            CacheKey::Url(url) => url.clone(),
+            CacheKey::Request(request) => {
+                request.url().map(|url| url.to_string()).unwrap_or_default()
+            }
+        };
+        vec![("cache.name", self.name.clone()), ("cache.key", key)]
+    }
+}
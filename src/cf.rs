@@ -0,0 +1,45 @@
+use worker::{Cf, Request};
+
+use crate::collector;
+
+// What Cloudflare tells about the connection a request came in on, in `req.cf()`. The colo
+// in particular is what latency that only happens in some places is usually down to, so it's
+// attached to every span of the invocation as a resource attribute (see
+// `Collector::with_resource`) rather than just the server span.
+
+/// The `cf` metadata of a request, named after the OpenTelemetry conventions where there are
+/// some.
+pub fn attributes(cf: &Cf) -> Vec<(&'static str, String)> {
+    let mut attributes = vec![
+        ("cloudflare.colo", cf.colo()),
+        ("cloudflare.asn", cf.asn().to_string()),
+    ];
+    if let Some(country) = cf.country() {
+        attributes.push(("cloudflare.country", country));
+    }
+    // `HTTP/2`, `HTTP/1.1`...
+    let http_protocol = cf.http_protocol();
+    if let Some((name, version)) = http_protocol.split_once('/') {
+        attributes.push(("network.protocol.name", name.to_ascii_lowercase()));
+        attributes.push(("network.protocol.version", version.to_owned()));
+    }
+    // `TLSv1.3`, or empty over plain HTTP.
+    let tls_version = cf.tls_version();
+    if let Some(version) = tls_version.strip_prefix("TLSv") {
+        attributes.push(("tls.protocol.name", "tls".to_owned()));
+        attributes.push(("tls.protocol.version", version.to_owned()));
+    }
+    attributes
+}
+
+/// Adds the `cf` metadata of `req` to every span collected by the current collector, see
+/// [`attributes`].
+pub fn record(req: &Request) {
+    if !collector::is_collecting() {
+        return;
+    }
+    let attributes = attributes(req.cf());
+    collector::update_active_resource(|resource| {
+        resource.extend(attributes.into_iter().map(|(k, v)| (k.into(), v.into())));
+    });
+}
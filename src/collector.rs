@@ -0,0 +1,510 @@
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use minitrace::{
+    collector::{SpanContext, SpanRecord},
+    local::LocalCollector,
+};
+
+use crate::{
+    baggage::Baggage,
+    lazy,
+    limits::{self, Limits},
+    propagation::{TraceContext, TraceState, TRACE_STATE},
+    record, switch,
+};
+
+// A `LocalCollector` that knows which trace it's collecting for, so code running under it
+// can ask for the current trace id, and that hands back records that went through
+// `record::normalize` already.
+//
+// Collectors nest: a collector started while another one is running takes over until it's
+// collected, and spans entered in between only show up in the inner one. The local span
+// stack requires them to be collected in reverse order of starting though, which doesn't
+// work for a subtask that keeps running next to the rest of the request (a background
+// prefetch, say). `isolate` is meant for those: it only collects while the subtask is being
+// polled, so it never overlaps with what the outer collector sees.
+//
+// The same goes for a request handler: another request can be polled while it waits on a
+// subrequest, on the same thread, so a collector left running across an `.await` would
+// record that request's spans too (and the local span stack would panic on them). `run`
+// only resumes the collector while the handler is being polled, and sets the spans recorded
+// so far aside in between, so any number of requests can be in flight at once.
+//
+// A nested collector enforces the same `Limits`, sees the same `Baggage` and adds the same
+// resource attributes as the one it's nested in, unless told otherwise.
+//
+// Collectors started while tracing is turned off (see `switch`) aren't sampled.
+
+struct Active {
+    id: u64,
+    context: SpanContext,
+    limits: Limits,
+    trace_state: TraceState,
+    baggage: Baggage,
+    resource: Vec<Property>,
+    sampled: bool,
+}
+
+type Property = (Cow<'static, str>, Cow<'static, str>);
+
+thread_local! {
+    static ACTIVE: RefCell<Vec<Active>> = const { RefCell::new(Vec::new()) };
+    static NEXT_ID: Cell<u64> = const { Cell::new(0) };
+    // Collectors that weren't dropped yet, running or not.
+    static LIVE: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Collects the local spans of one trace.
+///
+/// Nested collectors have to be collected before the ones they are nested in. A collector
+/// that isn't sampled (see `start_remote`) records nothing, and neither do the collectors
+/// nested in it.
+///
+/// A collector is running from the moment it's started. Once handed to `run`, it only runs
+/// while the future is being polled.
+#[must_use]
+pub struct Collector {
+    id: u64,
+    context: SpanContext,
+    limits: Limits,
+    trace_state: TraceState,
+    sampled: bool,
+    // `None` while suspended, once collected, or from the start if not sampled.
+    inner: Option<LocalCollector>,
+    // What's on the active stack while running, kept here while suspended.
+    suspended: Option<Active>,
+    // Collected whenever it was suspended, not normalized yet.
+    records: Vec<SpanRecord>,
+}
+
+impl Collector {
+    /// Starts collecting. `parent` is the context the collected spans are attached to: its
+    /// trace id, and the span id the top-level spans get as their parent.
+    pub fn start(parent: SpanContext) -> Self {
+        let sampled = active_sampled().unwrap_or(true);
+        Self::start_with(parent, sampled)
+    }
+
+    /// Starts collecting for a request that came with `parent`, with its `tracestate`.
+    ///
+    /// With `honor_sampled`, a `parent` that wasn't sampled by the caller isn't sampled here
+    /// either: nothing gets recorded, but the context is still propagated to subrequests (as
+    /// not sampled) so the services down the line can make the same decision.
+    pub fn start_remote(parent: &TraceContext, honor_sampled: bool) -> Self {
+        let sampled = !honor_sampled || parent.sampled;
+        Self::start_with(parent.span_context, sampled).with_trace_state(parent.trace_state.clone())
+    }
+
+    fn start_with(parent: SpanContext, sampled: bool) -> Self {
+        let sampled = sampled && switch::is_enabled();
+        let id = NEXT_ID.with(|next| {
+            next.set(next.get() + 1);
+            next.get()
+        });
+        if LIVE.with(|live| live.replace(live.get() + 1)) == 0 {
+            // No other collector is left, whatever was counted before is stale.
+            limits::reset();
+        }
+        let limits = active_limits().unwrap_or_default();
+        let baggage = active_baggage().unwrap_or_default();
+        let resource = active_resource().unwrap_or_default();
+        activate(Active {
+            id,
+            context: parent,
+            limits,
+            trace_state: TraceState::default(),
+            baggage,
+            resource,
+            sampled,
+        });
+
+        Self {
+            id,
+            context: parent,
+            limits,
+            trace_state: TraceState::default(),
+            sampled,
+            inner: sampled.then(LocalCollector::start),
+            suspended: None,
+            records: Vec::new(),
+        }
+    }
+
+    /// Caps what each span collected from here on can carry, see [`Limits`].
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self.update(|active| active.limits = limits);
+        self
+    }
+
+    /// Sets the `tracestate` received along with the parent context. It's available to the
+    /// collected code through `current::trace_state` and added to every collected record as
+    /// `propagation::TRACE_STATE`.
+    pub fn with_trace_state(mut self, trace_state: TraceState) -> Self {
+        self.update(|active| active.trace_state = trace_state.clone());
+        self.trace_state = trace_state;
+        self
+    }
+
+    /// Sets the baggage received along with the parent context, see `baggage`.
+    pub fn with_baggage(mut self, baggage: Baggage) -> Self {
+        self.update(|active| active.baggage = baggage);
+        self
+    }
+
+    /// Adds attributes describing where the trace is collected (the colo, the deployment...)
+    /// to every collected record that doesn't have them already.
+    pub fn with_resource<K, V>(mut self, attributes: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: Into<Cow<'static, str>>,
+        V: Into<Cow<'static, str>>,
+    {
+        let attributes = attributes.into_iter().map(|(k, v)| (k.into(), v.into()));
+        self.update(|active| active.resource.extend(attributes));
+        self
+    }
+
+    /// Runs `future` under this collector: it's resumed right before every poll and
+    /// suspended right after, so whatever else runs in between isn't collected here.
+    ///
+    /// Like with `isolate`, spans must not be held across an `.await` inside `future`; use
+    /// `in_local_span` or `#[traced]` there instead.
+    pub fn run<F: Future>(&mut self, future: F) -> Running<'_, F> {
+        Running {
+            collector: self,
+            inner: future,
+        }
+    }
+
+    fn update(&mut self, f: impl FnOnce(&mut Active)) {
+        if let Some(active) = &mut self.suspended {
+            return f(active);
+        }
+        ACTIVE.with(|active| {
+            if let Some(active) = active.borrow_mut().iter_mut().find(|a| a.id == self.id) {
+                f(active);
+            }
+        });
+    }
+
+    fn resource(&self) -> Vec<Property> {
+        if let Some(active) = &self.suspended {
+            return active.resource.clone();
+        }
+        ACTIVE.with(|active| {
+            active
+                .borrow()
+                .iter()
+                .find(|a| a.id == self.id)
+                .map(|a| a.resource.clone())
+                .unwrap_or_default()
+        })
+    }
+
+    /// Takes the collector off the active stack, keeping what it recorded so far.
+    fn suspend(&mut self) {
+        if self.suspended.is_some() {
+            return;
+        }
+        self.suspended = ACTIVE.with(|active| {
+            let mut active = active.borrow_mut();
+            let index = active.iter().rposition(|a| a.id == self.id)?;
+            Some(active.remove(index))
+        });
+        if let Some(inner) = self.inner.take() {
+            // Not normalized yet, spans pinned across polls are merged once it's done.
+            self.records
+                .extend(inner.collect().to_span_records(self.context));
+        }
+    }
+
+    /// Puts a suspended collector back on the active stack.
+    fn resume(&mut self) {
+        let Some(active) = self.suspended.take() else {
+            return;
+        };
+        activate(active);
+        if self.sampled {
+            self.inner = Some(LocalCollector::start());
+        }
+    }
+
+    pub fn context(&self) -> SpanContext {
+        self.context
+    }
+
+    /// Whether anything is being recorded.
+    pub fn is_sampled(&self) -> bool {
+        self.sampled
+    }
+
+    /// Stops collecting and returns the normalized records.
+    pub fn collect(mut self) -> Vec<SpanRecord> {
+        self.suspend();
+        if !self.sampled {
+            return Vec::new();
+        }
+        let limits = self.limits;
+        let trace_state = std::mem::take(&mut self.trace_state);
+        let resource = self.resource();
+        let mut records = std::mem::take(&mut self.records);
+        record::normalize(&mut records);
+        limits::apply(&mut records, limits);
+        add_resource(&mut records, &resource);
+        if !trace_state.is_empty() {
+            let trace_state = Cow::<'static, str>::from(trace_state.to_string());
+            for record in &mut records {
+                record
+                    .properties
+                    .push((TRACE_STATE.into(), trace_state.clone()));
+            }
+        }
+        records
+    }
+}
+
+impl Drop for Collector {
+    fn drop(&mut self) {
+        // Dropped without being collected, its records won't be exported.
+        self.suspend();
+        lazy::discard(&self.records);
+        deactivate(self.id);
+        LIVE.with(|live| live.set(live.get() - 1));
+    }
+}
+
+/// A future polled under a collector, see [`Collector::run`].
+#[pin_project::pin_project]
+pub struct Running<'a, F> {
+    collector: &'a mut Collector,
+    #[pin]
+    inner: F,
+}
+
+impl<F: Future> Future for Running<'_, F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        this.collector.resume();
+        let res = this.inner.poll(cx);
+        this.collector.suspend();
+        res
+    }
+}
+
+fn add_resource(records: &mut [SpanRecord], resource: &[Property]) {
+    for record in records {
+        for (key, value) in resource {
+            if !record.properties.iter().any(|(k, _)| k == key) {
+                record.properties.push((key.clone(), value.clone()));
+            }
+        }
+    }
+}
+
+fn activate(active: Active) {
+    ACTIVE.with(|stack| stack.borrow_mut().push(active));
+}
+
+fn deactivate(id: u64) {
+    ACTIVE.with(|active| active.borrow_mut().retain(|active| active.id != id));
+}
+
+/// Runs `future` with its own collector, independently of the collector of the task that
+/// awaits it. Resolves to the future's output and the records collected from it.
+///
+/// A new collector is started and collected around every poll, so spans must not be held
+/// across an `.await` inside `future`; use `in_local_span` or `#[traced]` there instead.
+pub fn isolate<F: Future>(parent: SpanContext, future: F) -> Isolated<F> {
+    Isolated {
+        inner: future,
+        context: parent,
+        collector: None,
+    }
+}
+
+#[pin_project::pin_project]
+pub struct Isolated<F> {
+    #[pin]
+    inner: F,
+    context: SpanContext,
+    // Started on the first poll, so it's nested in whatever collector runs then.
+    collector: Option<Collector>,
+}
+
+impl<F: Future> Future for Isolated<F> {
+    type Output = (F::Output, Vec<SpanRecord>);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        let collector = this
+            .collector
+            .get_or_insert_with(|| Collector::start(*this.context));
+        collector.resume();
+        let res = this.inner.poll(cx);
+        collector.suspend();
+
+        match res {
+            Poll::Ready(output) => {
+                let records = this.collector.take().map(Collector::collect);
+                Poll::Ready((output, records.unwrap_or_default()))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Whether a collector is running. The span helpers in this crate turn into noops if not.
+#[inline]
+pub(crate) fn is_collecting() -> bool {
+    !cfg!(feature = "tracing-off")
+        && ACTIVE.with(|active| active.borrow().last().is_some_and(|active| active.sampled))
+}
+
+/// Whether the innermost collector that is still running is sampled.
+pub(crate) fn active_sampled() -> Option<bool> {
+    ACTIVE.with(|active| active.borrow().last().map(|active| active.sampled))
+}
+
+/// The context of the innermost collector that is still running.
+pub(crate) fn active_context() -> Option<SpanContext> {
+    ACTIVE.with(|active| active.borrow().last().map(|active| active.context))
+}
+
+/// The `tracestate` of the innermost collector that is still running.
+pub(crate) fn active_trace_state() -> Option<TraceState> {
+    ACTIVE.with(|active| {
+        active
+            .borrow()
+            .last()
+            .map(|active| active.trace_state.clone())
+    })
+}
+
+/// The baggage of the innermost collector that is still running.
+pub(crate) fn active_baggage() -> Option<Baggage> {
+    ACTIVE.with(|active| active.borrow().last().map(|active| active.baggage.clone()))
+}
+
+pub(crate) fn update_active_baggage(f: impl FnOnce(&mut Baggage)) {
+    ACTIVE.with(|active| {
+        if let Some(active) = active.borrow_mut().last_mut() {
+            f(&mut active.baggage);
+        }
+    });
+}
+
+/// The resource attributes of the innermost collector that is still running.
+pub(crate) fn active_resource() -> Option<Vec<Property>> {
+    ACTIVE.with(|active| active.borrow().last().map(|active| active.resource.clone()))
+}
+
+pub(crate) fn update_active_resource(f: impl FnOnce(&mut Vec<Property>)) {
+    ACTIVE.with(|active| {
+        if let Some(active) = active.borrow_mut().last_mut() {
+            f(&mut active.resource);
+        }
+    });
+}
+
+/// The limits of the innermost collector that is still running.
+pub(crate) fn active_limits() -> Option<Limits> {
+    ACTIVE.with(|active| active.borrow().last().map(|active| active.limits))
+}
+
+// Nothing is collected with `tracing-off`.
+#[cfg(all(test, not(feature = "tracing-off")))]
+mod tests {
+    use std::task::{RawWaker, RawWakerVTable, Waker};
+
+    use minitrace::{
+        collector::{SpanId, TraceId},
+        local::LocalSpan,
+    };
+
+    use super::*;
+    use crate::local_future::LocalFutureExt;
+
+    // Pending on the first poll, like a subrequest that isn't back yet.
+    #[derive(Default)]
+    struct YieldOnce(bool);
+
+    impl Future for YieldOnce {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if std::mem::replace(&mut self.0, true) {
+                return Poll::Ready(());
+            }
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    fn noop_waker() -> Waker {
+        const VTABLE: RawWakerVTable = RawWakerVTable::new(
+            |_| RawWaker::new(std::ptr::null(), &VTABLE),
+            |_| {},
+            |_| {},
+            |_| {},
+        );
+        unsafe { Waker::from_raw(RawWaker::new(std::ptr::null(), &VTABLE)) }
+    }
+
+    fn handler(name: &'static str) -> impl Future<Output = ()> {
+        async move {
+            drop(LocalSpan::enter_with_local_parent(format!("{name} before")));
+            YieldOnce::default().await;
+            drop(LocalSpan::enter_with_local_parent(format!("{name} after")));
+        }
+        .in_local_span(name)
+    }
+
+    #[test]
+    fn run_interleaved() {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        // Each request starts its collector once the previous one is waiting.
+        let mut a = Collector::start(SpanContext::new(TraceId(1), SpanId(1)));
+        let mut run_a = Box::pin(a.run(handler("a")));
+        assert!(run_a.as_mut().poll(&mut cx).is_pending());
+        let mut b = Collector::start(SpanContext::new(TraceId(2), SpanId(2)));
+        {
+            let mut run_b = Box::pin(b.run(handler("b")));
+            assert!(run_b.as_mut().poll(&mut cx).is_pending());
+            assert!(!is_collecting());
+            assert!(run_a.as_mut().poll(&mut cx).is_ready());
+            assert!(run_b.as_mut().poll(&mut cx).is_ready());
+        }
+        drop(run_a);
+
+        for (collector, name, trace_id) in [(b, "b", 2), (a, "a", 1)] {
+            let records = collector.collect();
+            let mut names: Vec<_> = records.iter().map(|r| r.name.to_string()).collect();
+            names.sort();
+            assert_eq!(
+                names,
+                [
+                    name.to_owned(),
+                    format!("{name} after"),
+                    format!("{name} before")
+                ]
+            );
+            assert!(records.iter().all(|r| r.trace_id == TraceId(trace_id)));
+            let server = records.iter().find(|r| r.name == name).unwrap();
+            assert_eq!(server.parent_id, SpanId(trace_id as u64));
+            assert!(records
+                .iter()
+                .filter(|r| r.name != name)
+                .all(|r| r.parent_id == server.span_id));
+        }
+    }
+}
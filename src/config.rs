@@ -0,0 +1,585 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use serde::Deserialize;
+use worker::{Env, Url};
+
+use crate::{
+    limits::Limits,
+    processor::{LocalRoots, MinDuration, NameFilter, Processors, Redact, TailSampling},
+    resource::Resource,
+    sampler::{self, ParentBased, Ratio, Rule, Rules},
+    tracer::TracingConfig,
+};
+
+// A whole `TracingConfig` can be written as a single JSON document instead, kept in a var, a
+// KV key or bundled with the worker, for setups that would take too many vars otherwise:
+//
+// ```json
+// {
+//   "service": { "name": "checkout", "environment": "production" },
+//   "sampling": {
+//     "ratio": 0.1,
+//     "rules": [{ "path": "/checkout", "method": "POST", "ratio": 1.0 }]
+//   },
+//   "processors": { "deny": ["worker_rust::helpers::*"], "redact": {} },
+//   "limits": { "max_spans": 1024 },
+//   "exporters": [
+//     { "type": "console" },
+//     {
+//       "type": "otlp",
+//       "endpoint": "https://otlp.example.com/v1/traces",
+//       "headers": { "authorization": { "secret": "OTLP_TOKEN" } },
+//       "filter": { "tail_sampling": { "slower_than_ms": 500 } }
+//     }
+//   ]
+// }
+// ```
+//
+// ```ignore
+// let tracer = TracingConfig::from_json(include_str!("tracing.json"), &env)?.build(&env);
+// let tracer = TracingConfig::from_json_var(&env, "TRACING_CONFIG")?.build(&env);
+// let tracer = TracingConfig::from_json_kv(&env, "CONFIG", "tracing").await?.build(&env);
+// ```
+//
+// Every section is optional:
+//
+// - `service`: `name`, `namespace`, `version` and `environment`, see `Resource`, the version
+//   metadata being read either way
+// - `sampling`: `ratio` (`1` otherwise) and `rules` like `Rule`'s, following the callers'
+//   decisions unless `parent_based` is `false`
+// - `processors` and the `filter` of each exporter: `deny`, `allow`, `min_duration_us`,
+//   `redact` (`true`, or with `keys`, `emails` and `hash`), `tail_sampling` (`{}`, or with
+//   `slower_than_ms`) and `roots_only`, run in that order
+// - `limits`: `max_events`, `max_properties`, `max_value_len` and `max_spans`
+// - `exporters`: a list, each one of `type` `console` (with `attributes`), `otlp` (with
+//   `endpoint`, `protocol` and `headers`), or `zipkin` and `jaeger` (with `url` and
+//   `headers`), named after their type unless they have a `name`
+//
+// Header values are either the value itself, or `{ "var": name }` / `{ "secret": name }` to
+// read it from the `Env`. Unknown fields are rejected, and every value that doesn't make
+// sense is reported in a single error, with its path. With `tracing-off`, the document is
+// still checked, but the exporters are left out.
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Document {
+    service: Option<ServiceDocument>,
+    sampling: Option<SamplingDocument>,
+    processors: Option<FilterDocument>,
+    limits: Option<LimitsDocument>,
+    exporters: Vec<ExporterDocument>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ServiceDocument {
+    name: String,
+    namespace: Option<String>,
+    version: Option<String>,
+    environment: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SamplingDocument {
+    ratio: f64,
+    parent_based: bool,
+    rules: Vec<RuleDocument>,
+}
+
+impl Default for SamplingDocument {
+    fn default() -> Self {
+        Self {
+            ratio: 1.0,
+            parent_based: true,
+            rules: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleDocument {
+    path: String,
+    method: Option<String>,
+    ratio: f64,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FilterDocument {
+    deny: Vec<String>,
+    allow: Vec<String>,
+    min_duration_us: Option<u64>,
+    redact: Option<RedactDocument>,
+    tail_sampling: Option<TailSamplingDocument>,
+    roots_only: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RedactDocument {
+    Enabled(bool),
+    Options(RedactOptions),
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RedactOptions {
+    keys: Vec<String>,
+    emails: bool,
+    hash: bool,
+}
+
+impl Default for RedactOptions {
+    fn default() -> Self {
+        Self {
+            keys: Vec::new(),
+            emails: true,
+            hash: false,
+        }
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TailSamplingDocument {
+    slower_than_ms: Option<u64>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LimitsDocument {
+    max_events: Option<usize>,
+    max_properties: Option<usize>,
+    max_value_len: Option<usize>,
+    max_spans: Option<usize>,
+}
+
+// Only read to build the exporters, which `tracing-off` leaves out.
+#[cfg_attr(feature = "tracing-off", allow(dead_code))]
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ExporterDocument {
+    Console {
+        name: Option<String>,
+        #[serde(default)]
+        attributes: bool,
+        filter: Option<FilterDocument>,
+    },
+    Otlp {
+        name: Option<String>,
+        endpoint: String,
+        protocol: Option<String>,
+        #[serde(default)]
+        headers: BTreeMap<String, HeaderDocument>,
+        filter: Option<FilterDocument>,
+    },
+    Zipkin {
+        name: Option<String>,
+        url: String,
+        #[serde(default)]
+        headers: BTreeMap<String, HeaderDocument>,
+        filter: Option<FilterDocument>,
+    },
+    Jaeger {
+        name: Option<String>,
+        url: String,
+        #[serde(default)]
+        headers: BTreeMap<String, HeaderDocument>,
+        filter: Option<FilterDocument>,
+    },
+}
+
+#[cfg_attr(feature = "tracing-off", allow(dead_code))]
+#[derive(Clone, Deserialize)]
+#[serde(untagged)]
+enum HeaderDocument {
+    Literal(String),
+    Binding(BindingDocument),
+}
+
+#[cfg_attr(feature = "tracing-off", allow(dead_code))]
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum BindingDocument {
+    Var(String),
+    Secret(String),
+}
+
+impl Document {
+    /// Every value that doesn't make sense, with its path.
+    fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if let Some(service) = &self.service {
+            if service.name.trim().is_empty() {
+                errors.push("service.name must not be empty".to_owned());
+            }
+        }
+        if let Some(sampling) = &self.sampling {
+            check_ratio(&mut errors, "sampling.ratio", sampling.ratio);
+            for (i, rule) in sampling.rules.iter().enumerate() {
+                check_ratio(
+                    &mut errors,
+                    &format!("sampling.rules[{i}].ratio"),
+                    rule.ratio,
+                );
+                if !rule.path.starts_with('/') {
+                    errors.push(format!("sampling.rules[{i}].path must start with /"));
+                }
+                let method = rule.method.as_deref();
+                if method.is_some_and(|method| sampler::parse_method(method).is_none()) {
+                    errors.push(format!("sampling.rules[{i}].method must be an HTTP method"));
+                }
+            }
+        }
+        for (i, exporter) in self.exporters.iter().enumerate() {
+            let path = format!("exporters[{i}]");
+            match exporter {
+                ExporterDocument::Console { .. } => {}
+                ExporterDocument::Otlp {
+                    endpoint, protocol, ..
+                } => {
+                    check_url(&mut errors, &format!("{path}.endpoint"), endpoint);
+                    if let Some(protocol) = protocol {
+                        if !["http/protobuf", "http/json"].contains(&protocol.as_str()) {
+                            errors.push(format!(
+                                "{path}.protocol must be http/protobuf or http/json"
+                            ));
+                        }
+                    }
+                }
+                ExporterDocument::Zipkin { url, .. } | ExporterDocument::Jaeger { url, .. } => {
+                    check_url(&mut errors, &format!("{path}.url"), url)
+                }
+            }
+        }
+        let mut names: Vec<_> = self.exporters.iter().map(ExporterDocument::name).collect();
+        names.sort_unstable();
+        for pair in names.windows(2) {
+            if pair[0] == pair[1] {
+                errors.push(format!("exporters has two exporters named {}", pair[0]));
+            }
+        }
+        errors
+    }
+}
+
+fn check_ratio(errors: &mut Vec<String>, path: &str, ratio: f64) {
+    if !(0.0..=1.0).contains(&ratio) {
+        errors.push(format!("{path} must be between 0 and 1"));
+    }
+}
+
+fn check_url(errors: &mut Vec<String>, path: &str, url: &str) {
+    if Url::parse(url).is_err() {
+        errors.push(format!("{path} must be a URL"));
+    }
+}
+
+impl SamplingDocument {
+    fn rules(self) -> Rules {
+        let mut rules = Rules::new(Ratio::new(self.ratio));
+        for rule in self.rules {
+            let mut sampled = Rule::new(rule.path, Ratio::new(rule.ratio));
+            // Unknown methods are rejected by `validate`.
+            if let Some(method) = rule.method.as_deref().and_then(sampler::parse_method) {
+                sampled = sampled.method(method);
+            }
+            rules = rules.rule(sampled);
+        }
+        rules
+    }
+}
+
+impl FilterDocument {
+    fn processors(self) -> Processors {
+        let mut processors = Processors::default();
+        if !self.deny.is_empty() {
+            processors = processors.with(NameFilter::deny(self.deny));
+        }
+        if !self.allow.is_empty() {
+            processors = processors.with(NameFilter::allow(self.allow));
+        }
+        if let Some(us) = self.min_duration_us {
+            processors = processors.with(MinDuration::new(Duration::from_micros(us)));
+        }
+        match self.redact {
+            None | Some(RedactDocument::Enabled(false)) => {}
+            Some(RedactDocument::Enabled(true)) => processors = processors.with(Redact::new()),
+            Some(RedactDocument::Options(options)) => {
+                let redact = options
+                    .keys
+                    .into_iter()
+                    .fold(Redact::new(), Redact::key)
+                    .emails(options.emails)
+                    .hash(options.hash);
+                processors = processors.with(redact);
+            }
+        }
+        if let Some(tail_sampling) = self.tail_sampling {
+            let mut tail = TailSampling::new();
+            if let Some(ms) = tail_sampling.slower_than_ms {
+                tail = tail.slower_than(Duration::from_millis(ms));
+            }
+            processors = processors.with(tail);
+        }
+        if self.roots_only {
+            processors = processors.with(LocalRoots);
+        }
+        processors
+    }
+}
+
+impl LimitsDocument {
+    fn limits(self) -> Limits {
+        let defaults = Limits::default();
+        Limits {
+            max_events: self.max_events.unwrap_or(defaults.max_events),
+            max_properties: self.max_properties.unwrap_or(defaults.max_properties),
+            max_value_len: self.max_value_len.unwrap_or(defaults.max_value_len),
+            max_spans: self.max_spans.unwrap_or(defaults.max_spans),
+        }
+    }
+}
+
+impl ExporterDocument {
+    fn name(&self) -> &str {
+        let (name, kind) = match self {
+            ExporterDocument::Console { name, .. } => (name, "console"),
+            ExporterDocument::Otlp { name, .. } => (name, "otlp"),
+            ExporterDocument::Zipkin { name, .. } => (name, "zipkin"),
+            ExporterDocument::Jaeger { name, .. } => (name, "jaeger"),
+        };
+        name.as_deref().unwrap_or(kind)
+    }
+
+    #[cfg(not(feature = "tracing-off"))]
+    fn add_to(
+        self,
+        config: TracingConfig,
+        service_name: &str,
+        env: &Env,
+    ) -> worker::Result<TracingConfig> {
+        use crate::export::{
+            Auth, ConsoleExporter, Credential, Encoding, JaegerExporter, OtlpExporter,
+            ZipkinExporter,
+        };
+
+        let auth = |headers: BTreeMap<String, HeaderDocument>| {
+            headers
+                .into_iter()
+                .fold(Auth::new(), |auth, (name, value)| {
+                    let value = match value {
+                        HeaderDocument::Literal(value) => Credential::Literal(value),
+                        HeaderDocument::Binding(BindingDocument::Var(name)) => {
+                            Credential::Var(name)
+                        }
+                        HeaderDocument::Binding(BindingDocument::Secret(name)) => {
+                            Credential::Secret(name)
+                        }
+                    };
+                    auth.header(name, value)
+                })
+                .resolve(env)
+        };
+        let name = self.name().to_owned();
+        let (filter, exporter): (_, Box<dyn crate::export::SpanExporter>) = match self {
+            ExporterDocument::Console {
+                attributes, filter, ..
+            } => (
+                filter,
+                Box::new(ConsoleExporter::new().attributes(attributes)),
+            ),
+            ExporterDocument::Otlp {
+                endpoint,
+                protocol,
+                headers,
+                filter,
+                ..
+            } => {
+                let encoding = match protocol.as_deref() {
+                    Some("http/json") => Encoding::Json,
+                    _ => Encoding::Protobuf,
+                };
+                let otlp = OtlpExporter::new(endpoint)
+                    .encoding(encoding)
+                    .auth(&auth(headers)?);
+                (filter, Box::new(otlp))
+            }
+            ExporterDocument::Zipkin {
+                url,
+                headers,
+                filter,
+                ..
+            } => {
+                let zipkin = ZipkinExporter::new(&url, service_name).auth(&auth(headers)?);
+                (filter, Box::new(zipkin))
+            }
+            ExporterDocument::Jaeger {
+                url,
+                headers,
+                filter,
+                ..
+            } => {
+                let jaeger = JaegerExporter::new(&url, service_name).auth(&auth(headers)?);
+                (filter, Box::new(jaeger))
+            }
+        };
+        Ok(match filter {
+            Some(filter) => config.exporter_with_filter(name, filter.processors(), exporter),
+            None => config.exporter(name, exporter),
+        })
+    }
+
+    // There's nothing to export to with `tracing-off`.
+    #[cfg(feature = "tracing-off")]
+    fn add_to(
+        self,
+        config: TracingConfig,
+        _service_name: &str,
+        _env: &Env,
+    ) -> worker::Result<TracingConfig> {
+        Ok(config)
+    }
+}
+
+impl TracingConfig {
+    /// Reads the config from the JSON document `json`, see the module docs.
+    pub fn from_json(json: &str, env: &Env) -> worker::Result<Self> {
+        let document: Document = serde_json::from_str(json)
+            .map_err(|err| worker::Error::RustError(format!("invalid tracing config: {err}")))?;
+        let errors = document.validate();
+        if !errors.is_empty() {
+            return Err(worker::Error::RustError(format!(
+                "invalid tracing config: {}",
+                errors.join(", ")
+            )));
+        }
+
+        let mut config = TracingConfig::new();
+        let mut service_name = "worker".to_owned();
+        let mut resource = Resource::default();
+        if let Some(service) = document.service {
+            resource = Resource::new(service.name.clone());
+            if let Some(namespace) = service.namespace {
+                resource = resource.namespace(namespace);
+            }
+            if let Some(version) = service.version {
+                resource = resource.version(version);
+            }
+            if let Some(environment) = service.environment {
+                resource = resource.environment(environment);
+            }
+            service_name = service.name;
+        }
+        config = config.service(resource);
+        if let Some(sampling) = document.sampling {
+            config = if sampling.parent_based {
+                config.sampler(ParentBased::new(sampling.rules()))
+            } else {
+                config.sampler(sampling.rules())
+            };
+        }
+        if let Some(processors) = document.processors {
+            config = config.processor(processors.processors());
+        }
+        if let Some(limits) = document.limits {
+            config = config.limits(limits.limits());
+        }
+        for exporter in document.exporters {
+            config = exporter.add_to(config, &service_name, env)?;
+        }
+        Ok(config)
+    }
+
+    /// Reads the config from the JSON document in the var or secret `name`.
+    pub fn from_json_var(env: &Env, name: &str) -> worker::Result<Self> {
+        let json = env
+            .var(name)
+            .map(|var| var.to_string())
+            .or_else(|_| env.secret(name).map(|secret| secret.to_string()))?;
+        Self::from_json(&json, env)
+    }
+
+    /// Reads the config from the JSON document at `key`, in the KV namespace bound as
+    /// `binding`.
+    pub async fn from_json_kv(env: &Env, binding: &str, key: &str) -> worker::Result<Self> {
+        let json = env.kv(binding)?.get(key).text().await?.ok_or_else(|| {
+            worker::Error::RustError(format!("no tracing config at {key} in {binding}"))
+        })?;
+        Self::from_json(&json, env)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(json: &str) -> Vec<String> {
+        serde_json::from_str::<Document>(json).unwrap().validate()
+    }
+
+    #[test]
+    fn valid() {
+        let json = r#"{
+            "service": { "name": "checkout", "environment": "production" },
+            "sampling": {
+                "ratio": 0.1,
+                "rules": [{ "path": "/checkout", "method": "POST", "ratio": 1.0 }]
+            },
+            "processors": { "deny": ["worker_rust::helpers::*"], "redact": {} },
+            "limits": { "max_spans": 1024 },
+            "exporters": [
+                { "type": "console" },
+                {
+                    "type": "otlp",
+                    "endpoint": "https://otlp.example.com/v1/traces",
+                    "headers": { "authorization": { "secret": "OTLP_TOKEN" } },
+                    "filter": { "tail_sampling": { "slower_than_ms": 500 } }
+                }
+            ]
+        }"#;
+        assert_eq!(validate(json), Vec::<String>::new());
+        assert_eq!(validate("{}"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn invalid_values() {
+        let json = r#"{
+            "service": { "name": " " },
+            "sampling": { "ratio": 1.5, "rules": [{ "path": "checkout", "method": "GRAB", "ratio": -1 }] },
+            "exporters": [
+                { "type": "otlp", "endpoint": "not a url", "protocol": "grpc" },
+                { "type": "zipkin", "url": "https://zipkin.example.com", "name": "console" },
+                { "type": "console" }
+            ]
+        }"#;
+        assert_eq!(
+            validate(json),
+            [
+                "service.name must not be empty",
+                "sampling.ratio must be between 0 and 1",
+                "sampling.rules[0].ratio must be between 0 and 1",
+                "sampling.rules[0].path must start with /",
+                "sampling.rules[0].method must be an HTTP method",
+                "exporters[0].endpoint must be a URL",
+                "exporters[0].protocol must be http/protobuf or http/json",
+                "exporters has two exporters named console",
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_fields() {
+        for json in [
+            r#"{ "sevrice": { "name": "checkout" } }"#,
+            r#"{ "limits": { "max_span": 10 } }"#,
+            r#"{ "exporters": [{ "type": "console", "endpoint": "https://example.com" }] }"#,
+            r#"{ "exporters": [{ "type": "stdout" }] }"#,
+            r#"{ "processors": { "redact": { "key": ["token"] } } }"#,
+        ] {
+            assert!(serde_json::from_str::<Document>(json).is_err(), "{json}");
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use std::{borrow::Cow, cell::RefCell};
+
+use minitrace::{
+    collector::{SpanContext, SpanId, TraceId},
+    Event,
+};
+
+use crate::{
+    collector,
+    lazy::{self, LAZY},
+    limits,
+    propagation::TraceState,
+    record::{NAME, PROPERTY_EVENT},
+    value::Value,
+};
+
+// Helpers acting on whatever span is the current local parent, for code that has no access
+// to the span's guard (like a `#[trace]`-ed function annotating its own span). The properties
+// travel as a marker event and are moved onto the span by `record::normalize`.
+//
+// The ids of the current span are a different story: the stack doesn't tell what the
+// current local parent is, so only the spans created by this crate (`ScopedSpan`,
+// `in_local_span` and `#[traced]`) are tracked here. Inside a plain `LocalSpan` or
+// `#[trace]` function, `span_id` returns the id of the closest of those around it.
+
+thread_local! {
+    static SPANS: RefCell<Vec<SpanId>> = const { RefCell::new(Vec::new()) };
+}
+
+pub(crate) fn push_span(id: SpanId) {
+    SPANS.with(|spans| spans.borrow_mut().push(id));
+}
+
+pub(crate) fn pop_span(id: SpanId) {
+    SPANS.with(|spans| {
+        let mut spans = spans.borrow_mut();
+        if let Some(index) = spans.iter().rposition(|span| *span == id) {
+            spans.remove(index);
+        }
+    });
+}
+
+/// The id of the trace being collected, if any.
+pub fn trace_id() -> Option<TraceId> {
+    collector::active_context().map(|context| context.trace_id)
+}
+
+/// The `tracestate` received with the trace being collected, if any.
+pub fn trace_state() -> Option<TraceState> {
+    collector::active_trace_state().filter(|trace_state| !trace_state.is_empty())
+}
+
+/// The id the current span will be exported with, if any.
+pub fn span_id() -> Option<SpanId> {
+    SPANS.with(|spans| spans.borrow().last().copied())
+}
+
+/// The context of the current span, as it would be propagated to another service.
+pub fn span_context() -> Option<SpanContext> {
+    Some(SpanContext::new(trace_id()?, span_id()?))
+}
+
+/// Adds a property to the current local parent.
+pub fn add_property(key: impl Into<Cow<'static, str>>, value: impl Into<Cow<'static, str>>) {
+    add_properties([(key.into(), value.into())]);
+}
+
+/// Adds several properties to the current local parent.
+pub fn add_properties<K, V>(properties: impl IntoIterator<Item = (K, V)>)
+where
+    K: Into<Cow<'static, str>>,
+    V: Into<Cow<'static, str>>,
+{
+    let properties =
+        limits::admit_properties(properties.into_iter().map(|(k, v)| (k.into(), v.into())));
+    if properties.is_empty() {
+        return;
+    }
+    Event::add_to_local_parent(PROPERTY_EVENT, || properties);
+}
+
+/// Adds a typed attribute to the current local parent, see [`Value`].
+pub fn add_attribute(key: impl Into<Cow<'static, str>>, value: impl Into<Value>) {
+    add_properties(value.into().to_properties(key.into()));
+}
+
+/// Adds a property to the current local parent whose value is only computed if the span ends
+/// up being exported, see `lazy::resolve`.
+pub fn add_property_lazy<V, F>(key: impl Into<Cow<'static, str>>, value: F)
+where
+    V: Into<Cow<'static, str>>,
+    F: FnOnce() -> V + 'static,
+{
+    let key = key.into();
+    Event::add_to_local_parent(PROPERTY_EVENT, || {
+        [(LAZY.into(), lazy::register(key, value).to_string().into())]
+    });
+}
+
+/// Renames the current local parent.
+pub fn set_name(name: impl Into<Cow<'static, str>>) {
+    add_property(NAME, name);
+}
@@ -0,0 +1,238 @@
+use serde::Deserialize;
+use worker::{
+    d1::serde_wasm_bindgen,
+    js_sys::{Array, Object, Reflect},
+    wasm_bindgen::{JsCast, JsValue},
+    wasm_bindgen_futures::JsFuture,
+    worker_sys::types::{
+        D1Database as D1DatabaseSys, D1PreparedStatement as D1PreparedStatementSys,
+        D1Result as D1ResultSys,
+    },
+    D1Database,
+};
+
+use crate::{binding, current, event, sql, value::Value};
+
+// `worker`'s D1 types keep what D1 reports about a query (`meta`: how long it took, how many
+// rows it read and wrote) to themselves, so the traced ones go through the raw bindings
+// instead, and hand results back as a `QueryResult` that exposes it.
+//
+// A batch is a single round trip, there's no way to time its statements on their own: the
+// batch gets the span, and each statement an event on it once the batch is done, recording
+// its text and what D1 reported for it, `d1.duration_ms` included.
+
+const DB_SYSTEM: &str = "sqlite";
+
+/// A `D1Database` whose queries get a client span each.
+#[derive(Clone)]
+pub struct TracedD1 {
+    db: D1DatabaseSys,
+    name: String,
+}
+
+/// A statement prepared by a `TracedD1`.
+#[derive(Clone)]
+pub struct TracedStatement {
+    statement: D1PreparedStatementSys,
+    sql: String,
+    db: String,
+}
+
+/// The outcome of a query.
+pub struct QueryResult(D1ResultSys);
+
+/// What D1 reports about a query.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct QueryMeta {
+    pub duration_ms: f64,
+    pub rows_read: u64,
+    pub rows_written: u64,
+    pub changes: u64,
+}
+
+impl TracedD1 {
+    /// Traces the queries run on `db`, bound as `name`.
+    pub fn new(db: D1Database, name: impl Into<String>) -> Self {
+        let db: &JsValue = db.as_ref();
+        Self {
+            db: db.unchecked_ref::<D1DatabaseSys>().clone(),
+            name: name.into(),
+        }
+    }
+
+    /// Traces the database bound as `binding` in `env`.
+    pub fn from_env(env: &worker::Env, binding: &str) -> worker::Result<Self> {
+        Ok(Self::new(env.d1(binding)?, binding))
+    }
+
+    pub fn prepare(&self, sql: impl Into<String>) -> TracedStatement {
+        let sql = sql.into();
+        TracedStatement {
+            statement: self.db.prepare(&sql),
+            sql,
+            db: self.name.clone(),
+        }
+    }
+
+    /// Runs `statements` in a single transaction, inside a span with an event per statement.
+    pub async fn batch(
+        &self,
+        statements: Vec<TracedStatement>,
+    ) -> worker::Result<Vec<QueryResult>> {
+        let properties = vec![
+            ("db.system", DB_SYSTEM.to_owned()),
+            ("db.operation.name", "BATCH".to_owned()),
+            ("db.namespace", self.name.clone()),
+        ];
+        binding::call("BATCH", &self.name, properties, async {
+            current::add_attribute("db.operation.batch.size", statements.len() as i64);
+            let batch = statements
+                .iter()
+                .map(|statement| statement.statement.clone())
+                .collect::<Array>();
+            let results = JsFuture::from(self.db.batch(batch)).await?;
+            let results = results
+                .dyn_into::<Array>()?
+                .iter()
+                .map(|result| QueryResult(result.unchecked_into()))
+                .collect::<Vec<_>>();
+            for (statement, result) in statements.iter().zip(&results) {
+                let mut attributes = vec![
+                    ("db.operation.name", sql::operation(&statement.sql)),
+                    ("db.query.text", sql::sanitize(&statement.sql)),
+                ];
+                attributes.extend(
+                    result
+                        .attributes()
+                        .into_iter()
+                        .map(|(key, value)| (key, value.to_string())),
+                );
+                event::record("db.statement", &attributes);
+            }
+            Ok(results)
+        })
+        .await
+    }
+}
+
+impl TracedStatement {
+    /// Binds `values` to the parameters of the statement. They aren't recorded.
+    pub fn bind(mut self, values: &[JsValue]) -> worker::Result<Self> {
+        self.statement = self.statement.bind(values.iter().collect())?;
+        Ok(self)
+    }
+
+    /// Runs the statement and returns its first row, if any. See `D1PreparedStatement::first`.
+    pub async fn first<T>(&self, col_name: Option<&str>) -> worker::Result<Option<T>>
+    where
+        T: for<'a> Deserialize<'a>,
+    {
+        self.call(async {
+            let row = JsFuture::from(self.statement.first(col_name)).await?;
+            let row: Option<T> = serde_wasm_bindgen::from_value(row)?;
+            current::add_attribute("db.response.returned_rows", row.is_some() as i64);
+            Ok(row)
+        })
+        .await
+    }
+
+    /// Runs the statement and returns its rows.
+    pub async fn all(&self) -> worker::Result<QueryResult> {
+        self.call(async {
+            let result = QueryResult(JsFuture::from(self.statement.all()).await?.into());
+            for (key, value) in result.attributes() {
+                current::add_attribute(key, value);
+            }
+            Ok(result)
+        })
+        .await
+    }
+
+    /// Runs the statement for its side effects.
+    pub async fn run(&self) -> worker::Result<QueryResult> {
+        self.call(async {
+            let result = QueryResult(JsFuture::from(self.statement.run()).await?.into());
+            for (key, value) in result.attributes() {
+                current::add_attribute(key, value);
+            }
+            Ok(result)
+        })
+        .await
+    }
+
+    async fn call<T>(
+        &self,
+        call: impl std::future::Future<Output = worker::Result<T>>,
+    ) -> worker::Result<T> {
+        binding::call(
+            &sql::operation(&self.sql),
+            &self.db,
+            self.properties(),
+            call,
+        )
+        .await
+    }
+
+    fn properties(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("db.system", DB_SYSTEM.to_owned()),
+            ("db.operation.name", sql::operation(&self.sql)),
+            ("db.namespace", self.db.clone()),
+            ("db.query.text", sql::sanitize(&self.sql)),
+        ]
+    }
+}
+
+impl QueryResult {
+    pub fn success(&self) -> bool {
+        self.0.success()
+    }
+
+    pub fn error(&self) -> Option<String> {
+        self.0.error()
+    }
+
+    /// The rows returned by the query.
+    pub fn results<T>(&self) -> worker::Result<Vec<T>>
+    where
+        T: for<'a> Deserialize<'a>,
+    {
+        let Some(results) = self.0.results() else {
+            return Ok(Vec::new());
+        };
+        results
+            .iter()
+            .map(|row| Ok(serde_wasm_bindgen::from_value(row)?))
+            .collect()
+    }
+
+    pub fn meta(&self) -> QueryMeta {
+        let meta = self.0.meta();
+        QueryMeta {
+            duration_ms: number(&meta, "duration"),
+            rows_read: number(&meta, "rows_read") as u64,
+            rows_written: number(&meta, "rows_written") as u64,
+            changes: number(&meta, "changes") as u64,
+        }
+    }
+
+    fn attributes(&self) -> Vec<(&'static str, Value)> {
+        let meta = self.meta();
+        let mut attributes = vec![
+            ("d1.duration_ms", Value::F64(meta.duration_ms)),
+            ("d1.rows_read", Value::I64(meta.rows_read as i64)),
+            ("d1.rows_written", Value::I64(meta.rows_written as i64)),
+        ];
+        if let Some(results) = self.0.results() {
+            attributes.push(("db.response.returned_rows", results.length().into()));
+        }
+        attributes
+    }
+}
+
+fn number(object: &Object, key: &str) -> f64 {
+    Reflect::get(object, &JsValue::from_str(key))
+        .ok()
+        .and_then(|value| value.as_f64())
+        .unwrap_or_default()
+}
@@ -0,0 +1,205 @@
+use std::{cell::RefCell, future::Future, rc::Rc, time::Duration};
+
+use minitrace::collector::SpanRecord;
+use worker::{Date, Request, Response};
+
+use crate::{
+    collector::Collector, error, lazy, limits::Limits, local_future::LocalFutureExt, middleware,
+    processor, propagation::TraceContext,
+};
+
+// Durable Objects handle many invocations over their lifetime, and exporting after each one
+// of them would mean a subrequest per invocation. `DurableObjectTracing` traces every
+// invocation like the top-level handlers do, but buffers the records in the object and
+// exports them on a cadence instead: at the end of the first invocation that finds the
+// buffer older than `flush_every`, or bigger than `max_buffered` spans. Whatever is left
+// when the object is evicted is lost, so an alarm calling `flush` is a good idea for objects
+// that go quiet for long.
+//
+// The `DurableObject` trait of this version of `worker` has no WebSocket callbacks, so only
+// `fetch` and `alarm` are covered.
+//
+// It's configured with a `DurableObjectTracingBuilder` and cheap to clone once built, so it
+// can be held by the object and cloned into a handler that borrows the object:
+//
+// ```ignore
+// let tracing = DurableObjectTracing::builder(export).max_buffered(256).build();
+//
+// async fn fetch(&mut self, req: Request) -> Result<Response> {
+//     let tracing = self.tracing.clone();
+//     tracing.fetch(req, |req| self.handle(req)).await
+// }
+// ```
+
+/// Traces the invocations of a Durable Object.
+pub struct DurableObjectTracing<E> {
+    inner: Rc<Inner<E>>,
+}
+
+struct Inner<E> {
+    export: E,
+    limits: Limits,
+    honor_sampled: bool,
+    flush_every: Duration,
+    max_buffered: usize,
+    buffer: RefCell<Buffer>,
+}
+
+#[derive(Default)]
+struct Buffer {
+    records: Vec<SpanRecord>,
+    // When the oldest buffered records were added, in ms since the epoch.
+    since: Option<u64>,
+}
+
+/// Configures a [`DurableObjectTracing`], see `DurableObjectTracing::builder`.
+#[must_use]
+pub struct DurableObjectTracingBuilder<E> {
+    export: E,
+    limits: Limits,
+    honor_sampled: bool,
+    flush_every: Duration,
+    max_buffered: usize,
+}
+
+impl<E> DurableObjectTracingBuilder<E> {
+    /// Caps what each span can carry, see [`Limits`].
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Whether requests the caller didn't sample are left unrecorded, which is the default.
+    pub fn honor_sampled(mut self, honor_sampled: bool) -> Self {
+        self.honor_sampled = honor_sampled;
+        self
+    }
+
+    /// How long records are buffered before being exported.
+    pub fn flush_every(mut self, interval: Duration) -> Self {
+        self.flush_every = interval;
+        self
+    }
+
+    /// How many spans are buffered at most before being exported.
+    pub fn max_buffered(mut self, max_buffered: usize) -> Self {
+        self.max_buffered = max_buffered;
+        self
+    }
+
+    /// Freezes the configuration into a tracing that can be cloned.
+    pub fn build(self) -> DurableObjectTracing<E> {
+        DurableObjectTracing {
+            inner: Rc::new(Inner {
+                export: self.export,
+                limits: self.limits,
+                honor_sampled: self.honor_sampled,
+                flush_every: self.flush_every,
+                max_buffered: self.max_buffered,
+                buffer: RefCell::default(),
+            }),
+        }
+    }
+}
+
+impl<E> Clone for DurableObjectTracing<E> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<E, Fut> DurableObjectTracing<E>
+where
+    E: Fn(Vec<SpanRecord>) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    /// Traces the invocations and hands the records to `export` every 10 seconds or 512 spans,
+    /// whichever comes first.
+    pub fn new(export: E) -> Self {
+        Self::builder(export).build()
+    }
+
+    /// Like `new`, configured before it's built.
+    pub fn builder(export: E) -> DurableObjectTracingBuilder<E> {
+        DurableObjectTracingBuilder {
+            export,
+            limits: Limits::default(),
+            honor_sampled: true,
+            flush_every: Duration::from_secs(10),
+            max_buffered: 512,
+        }
+    }
+
+    /// Runs `handler` on `req` inside a server span, like `FetchTracing::handle`.
+    pub async fn fetch<H, HFut>(&self, req: Request, handler: H) -> worker::Result<Response>
+    where
+        H: FnOnce(Request) -> HFut,
+        HFut: Future<Output = worker::Result<Response>>,
+    {
+        let mut collector =
+            middleware::start_collector(&req, self.inner.honor_sampled, self.inner.limits);
+        let res = collector
+            .run(middleware::serve(req, false, handler))
+            .await
+            .map(|(res, ..)| res);
+        self.finish(collector).await;
+        res
+    }
+
+    /// Runs an alarm `handler` inside a span of a new trace.
+    pub async fn alarm<H, HFut>(&self, handler: H) -> worker::Result<Response>
+    where
+        H: FnOnce() -> HFut,
+        HFut: Future<Output = worker::Result<Response>>,
+    {
+        let mut collector = Collector::start_remote(&TraceContext::new_root(), false)
+            .with_limits(self.inner.limits);
+        let alarm = async {
+            let res = handler().await;
+            if let Err(err) = &res {
+                error::record(err.to_string());
+            }
+            res
+        }
+        .in_local_span("alarm")
+        .with_property("faas.trigger", "timer");
+        let res = collector.run(alarm).await;
+        self.finish(collector).await;
+        res
+    }
+
+    /// Exports whatever is buffered right away.
+    pub async fn flush(&self) {
+        let records = {
+            let mut buffer = self.inner.buffer.borrow_mut();
+            buffer.since = None;
+            std::mem::take(&mut buffer.records)
+        };
+        if !records.is_empty() {
+            (self.inner.export)(records).await;
+        }
+    }
+
+    async fn finish(&self, collector: Collector) {
+        let mut records = collector.collect();
+        processor::run(&mut records);
+        lazy::resolve(&mut records);
+
+        let now = Date::now().as_millis();
+        let due = {
+            let mut buffer = self.inner.buffer.borrow_mut();
+            if records.is_empty() && buffer.records.is_empty() {
+                return;
+            }
+            buffer.records.extend(records);
+            let since = *buffer.since.get_or_insert(now);
+            buffer.records.len() >= self.inner.max_buffered
+                || now.saturating_sub(since) >= self.inner.flush_every.as_millis() as u64
+        };
+        if due {
+            self.flush().await;
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::future::Future;
+
+use minitrace::collector::SpanRecord;
+use worker::{
+    js_sys,
+    wasm_bindgen::{self, prelude::*},
+};
+
+use crate::{
+    collector::Collector,
+    error,
+    kind::{SpanKind, SPAN_KIND},
+    limits::Limits,
+    local_future::LocalFutureExt,
+    middleware,
+    propagation::TraceContext,
+};
+
+// This version of `worker` has no `#[event(email)]`, nor bindings for the message an email
+// worker receives, so the handler has to be exported by hand and the message is bound here:
+//
+// ```ignore
+// #[wasm_bindgen]
+// pub async fn email(message: ForwardableEmailMessage, _env: JsValue, _ctx: JsValue) {
+//     EmailTracing::new(export)
+//         .handle(&message, || async { route(&message).await })
+//         .await;
+// }
+// ```
+//
+// The records are exported before `handle` resolves, as there's no `ctx.wait_until` to
+// defer it to.
+
+#[wasm_bindgen]
+extern "C" {
+    /// The message received by an email worker.
+    pub type ForwardableEmailMessage;
+
+    #[wasm_bindgen(method, getter)]
+    pub fn from(this: &ForwardableEmailMessage) -> String;
+
+    #[wasm_bindgen(method, getter)]
+    pub fn to(this: &ForwardableEmailMessage) -> String;
+
+    #[wasm_bindgen(method, getter, js_name = rawSize)]
+    pub fn raw_size(this: &ForwardableEmailMessage) -> f64;
+
+    #[wasm_bindgen(method, js_name = setReject)]
+    pub fn set_reject(this: &ForwardableEmailMessage, reason: &str);
+
+    #[wasm_bindgen(method)]
+    pub fn forward(this: &ForwardableEmailMessage, rcpt_to: &str) -> js_sys::Promise;
+}
+
+/// Traces an email handler, see [`EmailTracing::handle`].
+#[must_use]
+pub struct EmailTracing<E> {
+    export: E,
+    limits: Limits,
+}
+
+impl<E, Fut> EmailTracing<E>
+where
+    E: FnOnce(Vec<SpanRecord>) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    /// Traces the message and hands its records to `export` when it's done.
+    pub fn new(export: E) -> Self {
+        Self {
+            export,
+            limits: Limits::default(),
+        }
+    }
+
+    /// Caps what each span can carry, see [`Limits`].
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Runs `handler` inside a span of a new trace, and exports the records before returning.
+    pub async fn handle<H, HFut>(
+        self,
+        message: &ForwardableEmailMessage,
+        handler: H,
+    ) -> Result<(), JsValue>
+    where
+        H: FnOnce() -> HFut,
+        HFut: Future<Output = Result<(), JsValue>>,
+    {
+        let mut collector =
+            Collector::start_remote(&TraceContext::new_root(), false).with_limits(self.limits);
+        let properties = [
+            (SPAN_KIND, SpanKind::Server.as_str().to_owned()),
+            ("email.from", message.from()),
+            ("email.to", message.to()),
+        ];
+
+        let email = async {
+            let res = handler().await;
+            if let Err(err) = &res {
+                error::record(format!("{err:?}"));
+            }
+            res
+        }
+        .in_local_span("email")
+        .with_properties(properties)
+        .with_attribute("email.size", message.raw_size() as i64);
+        let res = collector.run(email).await;
+
+        middleware::flush(collector, Vec::new(), self.export).await;
+        res
+    }
+}
@@ -0,0 +1,14 @@
+use std::borrow::Cow;
+
+use crate::{
+    event,
+    status::{self, SpanStatus},
+};
+
+/// Sets the status of the current local parent to `Error(message)` and attaches `message` to
+/// it as an `exception` event.
+pub fn record(message: impl Into<Cow<'static, str>>) {
+    let message = message.into();
+    status::set(SpanStatus::Error(message.clone()));
+    event::record("exception", &[("exception.message", message)]);
+}
@@ -0,0 +1,58 @@
+use std::borrow::Cow;
+
+use minitrace::Event;
+
+use crate::{limits, record::TIMESTAMP};
+
+// Point-in-time events on the current local parent. They show up in the `events` of its
+// `SpanRecord`. Events past `Limits::max_events` are dropped, see `limits`.
+
+/// Records an event on the current local parent, timestamped now.
+///
+/// ```ignore
+/// event::record("cache_miss", &[("key", key)]);
+/// ```
+pub fn record<K, V>(name: impl Into<Cow<'static, str>>, attributes: &[(K, V)])
+where
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    if !limits::admit_event() {
+        return;
+    }
+    Event::add_to_local_parent(name, || to_properties(attributes));
+}
+
+/// Records an event on the current local parent that happened at `timestamp_unix_ns`, e.g.
+/// a time reported by a binding rather than observed by the worker.
+pub fn record_at<K, V>(
+    name: impl Into<Cow<'static, str>>,
+    timestamp_unix_ns: u64,
+    attributes: &[(K, V)],
+) where
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    if !limits::admit_event() {
+        return;
+    }
+    Event::add_to_local_parent(name, || {
+        let mut properties = to_properties(attributes);
+        properties.push((TIMESTAMP.into(), timestamp_unix_ns.to_string().into()));
+        properties
+    });
+}
+
+fn to_properties<K, V>(attributes: &[(K, V)]) -> Vec<(Cow<'static, str>, Cow<'static, str>)>
+where
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    attributes
+        .iter()
+        .map(|(k, v)| {
+            let v = limits::truncate_value(v.as_ref().to_owned().into());
+            (k.as_ref().to_owned().into(), v)
+        })
+        .collect()
+}
@@ -0,0 +1,356 @@
+// The helpers are only used by the backends, which aren't compiled with `tracing-off`.
+#![cfg_attr(feature = "tracing-off", allow(dead_code, unused_imports))]
+
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+};
+
+use async_trait::async_trait;
+
+use minitrace::collector::{EventRecord, SpanId, SpanRecord};
+use worker::{js_sys::Uint8Array, Fetch, Headers, Method, Request, RequestInit};
+
+use crate::{
+    kind::SPAN_KIND,
+    limits::{DROPPED_EVENTS, DROPPED_PROPERTIES},
+    link::Link,
+    propagation::TRACE_STATE,
+    status::{STATUS_CODE, STATUS_DESCRIPTION},
+    value::{self, Value},
+};
+
+pub mod fanout;
+pub mod retry;
+
+pub use fanout::Fanout;
+
+// With `tracing-off`, only what the middlewares need to hand records over is compiled.
+macro_rules! backends {
+    ($($item:item)*) => {
+        $(
+            #[cfg(not(feature = "tracing-off"))]
+            $item
+        )*
+    };
+}
+
+backends! {
+    pub mod aggregator;
+    pub mod analytics_engine;
+    pub mod auth;
+    pub mod axiom;
+    pub mod batched;
+    pub mod chrome;
+    pub mod circuit_breaker;
+    pub mod compression;
+    pub mod console;
+    pub mod datadog;
+    pub mod deadline;
+    pub mod elastic;
+    pub mod folded;
+    pub mod honeycomb;
+    pub mod jaeger;
+    pub mod kv_buffer;
+    pub mod newrelic;
+    pub mod otlp;
+    pub mod queue;
+    pub mod r2_archive;
+    pub mod sentry;
+    pub mod speedscope;
+    pub mod tail;
+    pub mod tempo;
+    pub mod tenant;
+    pub mod waterfall;
+    pub mod zipkin;
+
+    pub use aggregator::AggregatorExporter;
+    pub use analytics_engine::AnalyticsEngineExporter;
+    pub use auth::{Auth, Credential, Credentials};
+    pub use axiom::AxiomExporter;
+    pub use batched::Batched;
+    pub use circuit_breaker::CircuitBreaker;
+    pub use compression::Compression;
+    pub use console::ConsoleExporter;
+    pub use datadog::DatadogExporter;
+    pub use deadline::Deadline;
+    pub use elastic::ElasticExporter;
+    pub use honeycomb::HoneycombExporter;
+    pub use jaeger::JaegerExporter;
+    pub use kv_buffer::KvBuffer;
+    pub use newrelic::NewRelicExporter;
+    pub use otlp::{Encoding, OtlpExporter};
+    pub use queue::QueueExporter;
+    pub use r2_archive::R2Archive;
+    pub use sentry::SentryExporter;
+    pub use tail::TailExporter;
+    pub use tenant::TenantRouter;
+    pub use zipkin::ZipkinExporter;
+}
+
+// Exporters turn the records handed to the export callback of the middlewares into whatever
+// a backend ingests, and send it there. They all implement `SpanExporter`, as can anything
+// sending records somewhere else, and `to` turns any of them into such a callback:
+//
+// ```ignore
+// let exporter = OtlpExporter::new("https://otlp.example.com/v1/traces")
+//     .service_name("my-worker")
+//     .header("authorization", format!("Bearer {token}"));
+// FetchTracing::new(export::to(exporter))
+// ```
+//
+// The export runs after the spans are collected, so the requests sending them are made with
+// a plain `Fetch` and aren't traced themselves.
+
+/// Sends collected records somewhere.
+#[async_trait(?Send)]
+pub trait SpanExporter {
+    async fn export(&self, records: Vec<SpanRecord>) -> worker::Result<()>;
+}
+
+#[async_trait(?Send)]
+impl<T: SpanExporter + ?Sized> SpanExporter for Box<T> {
+    async fn export(&self, records: Vec<SpanRecord>) -> worker::Result<()> {
+        (**self).export(records).await
+    }
+}
+
+#[async_trait(?Send)]
+impl<T: SpanExporter + ?Sized> SpanExporter for Rc<T> {
+    async fn export(&self, records: Vec<SpanRecord>) -> worker::Result<()> {
+        (**self).export(records).await
+    }
+}
+
+/// The export callback of the middlewares sending records to `exporter`, logging failures.
+pub fn to<X>(exporter: X) -> impl Fn(Vec<SpanRecord>) -> Pin<Box<dyn Future<Output = ()>>> + Clone
+where
+    X: SpanExporter + 'static,
+{
+    let exporter = Rc::new(exporter);
+    move |records| {
+        let exporter = exporter.clone();
+        Box::pin(async move {
+            if let Err(err) = exporter.export(records).await {
+                worker::console_error!("export failed: {err}");
+            }
+        })
+    }
+}
+
+/// The attributes of a record, leaving out the properties exporters map to a field of their
+/// own: the kind, the status, the `tracestate` and the dropped counts.
+pub(crate) fn attributes(record: &SpanRecord) -> Vec<(Cow<'static, str>, Value)> {
+    value::attributes(record)
+        .into_iter()
+        .filter(|(k, _)| {
+            ![
+                SPAN_KIND,
+                STATUS_CODE,
+                STATUS_DESCRIPTION,
+                TRACE_STATE,
+                DROPPED_EVENTS,
+                DROPPED_PROPERTIES,
+            ]
+            .contains(&k.as_ref())
+        })
+        .collect()
+}
+
+/// `attributes` as a JSON object.
+pub(crate) fn object(attributes: Vec<(Cow<'static, str>, Value)>) -> serde_json::Value {
+    attributes
+        .into_iter()
+        .map(|(key, value)| (key.into_owned(), value.to_json()))
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+/// The events of a record, without the ones carrying links.
+pub(crate) fn events(record: &SpanRecord) -> impl Iterator<Item = &EventRecord> {
+    record.events.iter().filter(|event| !Link::is_link(event))
+}
+
+/// The records as a forest: the local roots (spans whose parent isn't among the records, e.g.
+/// the server span of a request) and the children of each span, all in start order.
+pub(crate) fn tree(
+    records: &[SpanRecord],
+) -> (Vec<&SpanRecord>, HashMap<SpanId, Vec<&SpanRecord>>) {
+    let ids: HashSet<SpanId> = records.iter().map(|record| record.span_id).collect();
+    let mut roots = Vec::new();
+    let mut children: HashMap<SpanId, Vec<&SpanRecord>> = HashMap::new();
+    for record in records {
+        if ids.contains(&record.parent_id) {
+            children.entry(record.parent_id).or_default().push(record);
+        } else {
+            roots.push(record);
+        }
+    }
+    roots.sort_by_key(|record| record.begin_time_unix_ns);
+    for siblings in children.values_mut() {
+        siblings.sort_by_key(|record| record.begin_time_unix_ns);
+    }
+    (roots, children)
+}
+
+/// Groups `records` the way backends with a notion of transaction want them: each local root
+/// with the spans below it, see `tree`.
+pub(crate) fn transactions(records: &[SpanRecord]) -> Vec<(&SpanRecord, Vec<&SpanRecord>)> {
+    let (roots, children) = tree(records);
+    roots
+        .into_iter()
+        .map(|root| {
+            let mut spans = Vec::new();
+            let mut stack = vec![root.span_id];
+            while let Some(id) = stack.pop() {
+                for child in children.get(&id).into_iter().flatten() {
+                    spans.push(*child);
+                    stack.push(child.span_id);
+                }
+            }
+            (root, spans)
+        })
+        .collect()
+}
+
+/// Reads a count recorded as a property, e.g. `DROPPED_EVENTS`.
+pub(crate) fn count(record: &SpanRecord, key: &str) -> u32 {
+    property(record, key)
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_default()
+}
+
+/// The last value of the property `key`.
+pub(crate) fn property<'a>(record: &'a SpanRecord, key: &str) -> Option<&'a str> {
+    record
+        .properties
+        .iter()
+        .rev()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.as_ref())
+}
+
+/// Formats a duration for people to read, e.g. `12.3ms`.
+pub(crate) fn format_duration(ns: u64) -> String {
+    match ns {
+        0..=999 => format!("{ns}ns"),
+        1_000..=999_999 => format!("{:.1}µs", ns as f64 / 1e3),
+        1_000_000..=999_999_999 => format!("{:.1}ms", ns as f64 / 1e6),
+        _ => format!("{:.2}s", ns as f64 / 1e9),
+    }
+}
+
+/// Formats `unix_ns` as an RFC 3339 UTC timestamp, e.g. `2024-02-04T07:26:50.622070313Z`.
+pub(crate) fn rfc3339(unix_ns: u64) -> String {
+    let secs = unix_ns / 1_000_000_000;
+    let days = (secs / 86_400) as i64;
+    let (h, m, s) = (secs % 86_400 / 3600, secs % 3600 / 60, secs % 60);
+
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{h:02}:{m:02}:{s:02}.{:09}Z",
+        unix_ns % 1_000_000_000
+    )
+}
+
+/// The value of an `authorization` header for HTTP basic auth.
+pub(crate) fn basic_auth(user: &str, password: &str) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let credentials = format!("{user}:{password}");
+    let mut encoded = String::with_capacity(credentials.len().div_ceil(3) * 4 + 6);
+    encoded.push_str("Basic ");
+    for chunk in credentials.as_bytes().chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// POSTs `body` to `url`, failing unless the backend answers with a `2xx`. Transient failures
+/// are retried, see `retry`.
+pub(crate) async fn post(
+    url: &str,
+    headers: &[(String, String)],
+    content_type: &str,
+    body: &[u8],
+) -> worker::Result<()> {
+    let response = retry::send(|| async {
+        let mut request_headers = Headers::new();
+        request_headers.set("content-type", content_type)?;
+        for (name, value) in headers {
+            request_headers.set(name, value)?;
+        }
+        let mut init = RequestInit::new();
+        init.with_method(Method::Post)
+            .with_headers(request_headers)
+            .with_body(Some(Uint8Array::from(body).into()));
+        Fetch::Request(Request::new_with_init(url, &init)?)
+            .send()
+            .await
+    })
+    .await?;
+    match response.status_code() {
+        200..=299 => Ok(()),
+        status => Err(worker::Error::RustError(format!(
+            "{url} answered the export with {status}"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00.000000000Z");
+        assert_eq!(
+            rfc3339(1_707_031_610_622_070_313),
+            "2024-02-04T07:26:50.622070313Z"
+        );
+        // Leap days, and the last second of a leap year.
+        assert_eq!(
+            rfc3339(951_782_400_000_000_001),
+            "2000-02-29T00:00:00.000000001Z"
+        );
+        assert_eq!(
+            rfc3339(1_735_689_599_999_999_999),
+            "2024-12-31T23:59:59.999999999Z"
+        );
+    }
+
+    #[test]
+    fn basic_auth_padding() {
+        assert_eq!(
+            basic_auth("Aladdin", "open sesame"),
+            "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="
+        );
+        assert_eq!(basic_auth("user", "pass"), "Basic dXNlcjpwYXNz");
+        assert_eq!(basic_auth("u", "ab"), "Basic dTphYg==");
+        assert_eq!(basic_auth("u", "abc"), "Basic dTphYmM=");
+        assert_eq!(basic_auth("", ""), "Basic Og==");
+    }
+}
@@ -0,0 +1,87 @@
+use async_trait::async_trait;
+use minitrace::collector::SpanRecord;
+use worker::{durable::ObjectNamespace, Method, Request, RequestInit};
+
+use super::{tail, SpanExporter};
+
+// Sends the records to a `TraceAggregator` Durable Object (see `crate::aggregator`, behind
+// the `aggregator` feature), which gathers the records of many invocations and exports them
+// together. Calling an object is cheaper than calling a backend, and what the backend gets is
+// a few big batches instead of a small one per request.
+//
+// ```ignore
+// FetchTracing::new(export::to(AggregatorExporter::from_env(&env, "TRACE_AGGREGATOR")?))
+// ```
+//
+// A single object handles about a thousand requests a second, a busier worker spreads its
+// batches over several with `shards`. Batches are sent in the lines of `TailExporter`.
+
+/// Exports records to a `TraceAggregator`.
+pub struct AggregatorExporter {
+    namespace: ObjectNamespace,
+    name: String,
+    shards: u32,
+}
+
+impl AggregatorExporter {
+    /// Sends to the object named `traces` of `namespace`.
+    pub fn new(namespace: ObjectNamespace) -> Self {
+        Self {
+            namespace,
+            name: "traces".to_owned(),
+            shards: 1,
+        }
+    }
+
+    /// Sends to the Durable Object namespace bound as `binding` in `env`.
+    pub fn from_env(env: &worker::Env, binding: &str) -> worker::Result<Self> {
+        Ok(Self::new(env.durable_object(binding)?))
+    }
+
+    /// Sends to the object named `name` instead.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Spreads the batches over `shards` objects, `{name}-0` to `{name}-{shards - 1}`, each
+    /// invocation picking one at random.
+    pub fn shards(mut self, shards: u32) -> Self {
+        self.shards = shards.max(1);
+        self
+    }
+
+    fn object_name(&self) -> String {
+        if self.shards == 1 {
+            return self.name.clone();
+        }
+        let mut buf = [0; 4];
+        getrandom::getrandom(&mut buf).expect("failed to generate a random shard");
+        format!("{}-{}", self.name, u32::from_ne_bytes(buf) % self.shards)
+    }
+}
+
+#[async_trait(?Send)]
+impl SpanExporter for AggregatorExporter {
+    /// Sends `records`, if there are any.
+    async fn export(&self, records: Vec<SpanRecord>) -> worker::Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let batch = tail::TailExporter::new().encode(&records).join("\n");
+        let mut init = RequestInit::new();
+        init.with_method(Method::Post).with_body(Some(batch.into()));
+        let request = Request::new_with_init("https://aggregator/batch", &init)?;
+        let stub = self
+            .namespace
+            .id_from_name(&self.object_name())?
+            .get_stub()?;
+        let response = stub.fetch_with_request(request).await?;
+        match response.status_code() {
+            200..=299 => Ok(()),
+            status => Err(worker::Error::RustError(format!(
+                "the trace aggregator answered the export with {status}"
+            ))),
+        }
+    }
+}
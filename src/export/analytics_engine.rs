@@ -0,0 +1,141 @@
+use async_trait::async_trait;
+use minitrace::collector::SpanRecord;
+use serde::Serialize;
+use worker::{
+    d1::serde_wasm_bindgen,
+    wasm_bindgen::{self, prelude::*, JsCast},
+    EnvBinding,
+};
+
+use crate::{kind::SpanKind, status::SpanStatus};
+
+use super::SpanExporter;
+
+// Writes a data point per span to a Workers Analytics Engine dataset, which is enough to
+// chart latencies per route for months without a tracing backend. This version of `worker`
+// has no binding for it, so it's bound here. Each data point is, by position:
+//
+// - index: the route, or the name when there's none
+// - blobs: name, route, kind, status (`ok`, `unset` or `error`), service name, trace id
+// - doubles: duration in milliseconds, `http.response.status_code` (`0` when there's none)
+//
+// ```sql
+// SELECT blob2 AS route, quantileWeighted(0.99)(double1, _sample_interval) AS p99
+// FROM my_dataset WHERE timestamp > NOW() - INTERVAL '1' DAY GROUP BY route
+// ```
+//
+// Writes are fire and forget, and the runtime only takes so many per invocation (250 at the
+// time of writing), so busy workers may want `roots_only`.
+
+const HTTP_ROUTE: &str = "http.route";
+const HTTP_STATUS_CODE: &str = "http.response.status_code";
+
+/// Indexes are capped at 96 bytes.
+const MAX_INDEX_LEN: usize = 96;
+
+#[wasm_bindgen]
+extern "C" {
+    /// An Analytics Engine dataset binding.
+    #[derive(Clone)]
+    pub type AnalyticsEngineDataset;
+
+    #[wasm_bindgen(method, catch, js_name = writeDataPoint)]
+    fn write_data_point(this: &AnalyticsEngineDataset, point: &JsValue) -> Result<(), JsValue>;
+}
+
+impl EnvBinding for AnalyticsEngineDataset {
+    const TYPE_NAME: &'static str = "AnalyticsEngineDataset";
+
+    // The binding's constructor isn't named after it in every runtime version.
+    fn get(val: JsValue) -> worker::Result<Self> {
+        Ok(val.unchecked_into())
+    }
+}
+
+/// Exports records as Analytics Engine data points.
+#[derive(Clone)]
+pub struct AnalyticsEngineExporter {
+    dataset: AnalyticsEngineDataset,
+    service_name: String,
+    roots_only: bool,
+}
+
+impl AnalyticsEngineExporter {
+    /// Writes to `dataset`, as `service_name`.
+    pub fn new(dataset: AnalyticsEngineDataset, service_name: impl Into<String>) -> Self {
+        Self {
+            dataset,
+            service_name: service_name.into(),
+            roots_only: false,
+        }
+    }
+
+    /// Writes to the dataset bound as `binding` in `env`.
+    pub fn from_env(
+        env: &worker::Env,
+        binding: &str,
+        service_name: impl Into<String>,
+    ) -> worker::Result<Self> {
+        Ok(Self::new(env.get_binding(binding)?, service_name))
+    }
+
+    /// Whether to only write the local roots, one data point per invocation, rather than every
+    /// span.
+    pub fn roots_only(mut self, enabled: bool) -> Self {
+        self.roots_only = enabled;
+        self
+    }
+
+    /// The data points `export` writes for `records`.
+    pub fn encode(&self, records: &[SpanRecord]) -> Vec<serde_json::Value> {
+        if self.roots_only {
+            let (roots, _) = super::tree(records);
+            roots.into_iter().map(|root| self.point(root)).collect()
+        } else {
+            records.iter().map(|record| self.point(record)).collect()
+        }
+    }
+
+    fn point(&self, record: &SpanRecord) -> serde_json::Value {
+        let route = super::property(record, HTTP_ROUTE).unwrap_or_default();
+        let status = match SpanStatus::of(record) {
+            SpanStatus::Unset => "unset",
+            SpanStatus::Ok => "ok",
+            SpanStatus::Error(_) => "error",
+        };
+        let status_code = super::count(record, HTTP_STATUS_CODE);
+        serde_json::json!({
+            "indexes": [index(if route.is_empty() { &record.name } else { route })],
+            "blobs": [
+                record.name,
+                route,
+                SpanKind::of(record).as_str(),
+                status,
+                self.service_name,
+                format!("{:032x}", record.trace_id.0),
+            ],
+            "doubles": [record.duration_ns as f64 / 1e6, status_code],
+        })
+    }
+}
+
+#[async_trait(?Send)]
+impl SpanExporter for AnalyticsEngineExporter {
+    /// Writes `records`, failing on the first data point the runtime refuses.
+    async fn export(&self, records: Vec<SpanRecord>) -> worker::Result<()> {
+        for point in self.encode(&records) {
+            let point = point.serialize(&serde_wasm_bindgen::Serializer::json_compatible())?;
+            self.dataset.write_data_point(&point)?;
+        }
+        Ok(())
+    }
+}
+
+/// `key` cut to the longest prefix that fits in an index.
+fn index(key: &str) -> &str {
+    let mut end = key.len().min(MAX_INDEX_LEN);
+    while !key.is_char_boundary(end) {
+        end -= 1;
+    }
+    &key[..end]
+}
@@ -0,0 +1,120 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use worker::Env;
+
+// Exporters authenticate with headers, and what goes in them usually comes from secrets.
+// `Auth` says which headers to send and where their values come from, and `resolve` reads
+// them from the `Env` once per isolate: every request after the first gets the same
+// `Credentials` back without going through the bindings again.
+//
+// ```ignore
+// let credentials = Auth::new()
+//     .bearer(Credential::Secret("OTLP_TOKEN".into()))
+//     .header("x-scope-orgid", Credential::Var("TENANT".into()))
+//     .resolve(&env)?;
+// let exporter = OtlpExporter::new(endpoint).auth(&credentials);
+// ```
+//
+// A missing binding fails `resolve`, naming it, rather than sending exports that would be
+// turned away.
+
+thread_local! {
+    static RESOLVED: RefCell<HashMap<Auth, Credentials>> = RefCell::default();
+}
+
+/// Where the value of a credential comes from.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Credential {
+    /// The value itself.
+    Literal(String),
+    /// The `[vars]` binding of that name.
+    Var(String),
+    /// The secret of that name.
+    Secret(String),
+}
+
+impl Credential {
+    fn resolve(&self, env: &Env) -> worker::Result<String> {
+        match self {
+            Credential::Literal(value) => Ok(value.clone()),
+            Credential::Var(name) => env.var(name).map(|var| var.to_string()),
+            Credential::Secret(name) => env.secret(name).map(|secret| secret.to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Header {
+    Plain(String, Credential),
+    Bearer(Credential),
+    Basic(Credential, Credential),
+}
+
+/// The headers exporters authenticate with, before they're read from the `Env`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Auth {
+    headers: Vec<Header>,
+}
+
+impl Auth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sends `name: {value}`.
+    pub fn header(mut self, name: impl Into<String>, value: Credential) -> Self {
+        self.headers.push(Header::Plain(name.into(), value));
+        self
+    }
+
+    /// Sends `authorization: Bearer {token}`.
+    pub fn bearer(mut self, token: Credential) -> Self {
+        self.headers.push(Header::Bearer(token));
+        self
+    }
+
+    /// Sends `authorization: Basic …`, for `user` and `password`.
+    pub fn basic(mut self, user: Credential, password: Credential) -> Self {
+        self.headers.push(Header::Basic(user, password));
+        self
+    }
+
+    /// Reads the headers from `env`, the first time in this isolate.
+    pub fn resolve(&self, env: &Env) -> worker::Result<Credentials> {
+        if let Some(credentials) = RESOLVED.with(|resolved| resolved.borrow().get(self).cloned()) {
+            return Ok(credentials);
+        }
+        let headers = self
+            .headers
+            .iter()
+            .map(|header| match header {
+                Header::Plain(name, value) => Ok((name.clone(), value.resolve(env)?)),
+                Header::Bearer(token) => Ok((
+                    "authorization".to_owned(),
+                    format!("Bearer {}", token.resolve(env)?),
+                )),
+                Header::Basic(user, password) => Ok((
+                    "authorization".to_owned(),
+                    super::basic_auth(&user.resolve(env)?, &password.resolve(env)?),
+                )),
+            })
+            .collect::<worker::Result<Vec<_>>>()?;
+        let credentials = Credentials(headers.into());
+        RESOLVED.with(|resolved| {
+            resolved
+                .borrow_mut()
+                .insert(self.clone(), credentials.clone())
+        });
+        Ok(credentials)
+    }
+}
+
+/// The headers of an `Auth`, read from the `Env`.
+#[derive(Clone, Debug)]
+pub struct Credentials(Rc<[(String, String)]>);
+
+impl Credentials {
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.0
+    }
+}
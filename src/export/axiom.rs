@@ -0,0 +1,128 @@
+use async_trait::async_trait;
+use minitrace::collector::SpanRecord;
+use serde_json::{json, Map, Value as Json};
+
+use crate::{kind::SpanKind, link::Link, status::SpanStatus, value};
+
+use super::SpanExporter;
+
+// Sends the records to Axiom's ingest API (https://axiom.co/docs/restapi/ingest), one event
+// per span, laid out the way Axiom's own OpenTelemetry ingest stores spans so its trace views
+// work on the dataset: `_time` is when the span started, `duration` its length in
+// nanoseconds, and properties go under `attributes`.
+
+const DEFAULT_API_URL: &str = "https://api.axiom.co";
+
+/// Exports records to an Axiom dataset.
+#[derive(Clone, Debug)]
+pub struct AxiomExporter {
+    api_url: String,
+    dataset: String,
+    token: String,
+    service_name: String,
+}
+
+impl AxiomExporter {
+    /// Exports to `dataset`, authenticating with the API `token`, as `service_name`.
+    pub fn new(
+        dataset: impl Into<String>,
+        token: impl Into<String>,
+        service_name: impl Into<String>,
+    ) -> Self {
+        Self {
+            api_url: DEFAULT_API_URL.to_owned(),
+            dataset: dataset.into(),
+            token: token.into(),
+            service_name: service_name.into(),
+        }
+    }
+
+    /// Sends to another API than `https://api.axiom.co`, e.g. a regional edge.
+    pub fn api_url(mut self, api_url: &str) -> Self {
+        self.api_url = api_url.trim_end_matches('/').to_owned();
+        self
+    }
+
+    /// The body `export` sends for `records`.
+    pub fn encode(&self, records: &[SpanRecord]) -> Vec<u8> {
+        let events: Vec<_> = records.iter().map(|record| self.event(record)).collect();
+        serde_json::to_vec(&events).unwrap_or_default()
+    }
+
+    fn event(&self, record: &SpanRecord) -> Json {
+        let mut event = Map::new();
+        event.insert(
+            "_time".into(),
+            super::rfc3339(record.begin_time_unix_ns).into(),
+        );
+        event.insert(
+            "trace_id".into(),
+            format!("{:032x}", record.trace_id.0).into(),
+        );
+        event.insert(
+            "span_id".into(),
+            format!("{:016x}", record.span_id.0).into(),
+        );
+        if record.parent_id.0 != 0 {
+            event.insert(
+                "parent_span_id".into(),
+                format!("{:016x}", record.parent_id.0).into(),
+            );
+        }
+        event.insert("name".into(), record.name.as_ref().into());
+        event.insert("kind".into(), SpanKind::of(record).as_str().into());
+        event.insert("duration".into(), record.duration_ns.into());
+        event.insert("service".into(), json!({ "name": self.service_name }));
+        event.insert(
+            "attributes".into(),
+            super::object(super::attributes(record)),
+        );
+
+        let status = match SpanStatus::of(record) {
+            SpanStatus::Unset => json!({ "code": "UNSET" }),
+            SpanStatus::Ok => json!({ "code": "OK" }),
+            SpanStatus::Error(description) => json!({ "code": "ERROR", "message": description }),
+        };
+        event.insert("status".into(), status);
+
+        let events: Vec<_> = super::events(record)
+            .map(|event| {
+                json!({
+                    "name": event.name,
+                    "timestamp": super::rfc3339(event.timestamp_unix_ns),
+                    "attributes": super::object(value::typed(&event.properties)),
+                })
+            })
+            .collect();
+        if !events.is_empty() {
+            event.insert("events".into(), events.into());
+        }
+        let links: Vec<_> = Link::of(record)
+            .into_iter()
+            .map(|link| {
+                json!({
+                    "trace_id": format!("{:032x}", link.trace_id.0),
+                    "span_id": format!("{:016x}", link.span_id.0),
+                })
+            })
+            .collect();
+        if !links.is_empty() {
+            event.insert("links".into(), links.into());
+        }
+        event.into()
+    }
+}
+
+#[async_trait(?Send)]
+impl SpanExporter for AxiomExporter {
+    /// Sends `records`, if there are any.
+    async fn export(&self, records: Vec<SpanRecord>) -> worker::Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let url = format!("{}/v1/datasets/{}/ingest", self.api_url, self.dataset);
+        let headers = [("authorization".to_owned(), format!("Bearer {}", self.token))];
+        let body = self.encode(&records);
+        super::post(&url, &headers, "application/json", &body).await
+    }
+}
@@ -0,0 +1,105 @@
+use std::{cell::RefCell, collections::HashMap, time::Duration};
+
+use async_trait::async_trait;
+use minitrace::collector::SpanRecord;
+use worker::Date;
+
+use super::SpanExporter;
+
+// An isolate serves many requests, one after the other or at once, and everything global
+// lives as long as it does. `Batched` keeps the records of every request in a buffer of the
+// isolate rather than exporting them right away, and it's the export of the first request to
+// find the buffer older than `flush_every`, or bigger than `max_buffered` spans, that sends
+// the lot, in its `wait_until`. A busy worker makes an export every few requests instead of
+// every request.
+//
+// ```ignore
+// FetchTracing::new(export::to(Batched::new("otlp", exporter)))
+// ```
+//
+// The buffers are global to the isolate, keyed by the name given to `new` like the circuits
+// of `CircuitBreaker`, so each exporter behind a `Batched` gets its own. Nothing flushes the buffer when no requests come in, and it's
+// lost when the isolate is evicted, which quiet workers may want to make up for with a cron
+// trigger calling `flush`. Records that fail to export are dropped, not buffered again.
+
+thread_local! {
+    static BUFFERS: RefCell<HashMap<String, Buffer>> = RefCell::default();
+}
+
+#[derive(Default)]
+struct Buffer {
+    records: Vec<SpanRecord>,
+    // When the oldest buffered records were added, in ms since the epoch.
+    since: Option<u64>,
+}
+
+/// Exports records with `exporter`, batched across the requests of the isolate.
+pub struct Batched<X> {
+    name: String,
+    exporter: X,
+    flush_every: Duration,
+    max_buffered: usize,
+}
+
+impl<X: SpanExporter> Batched<X> {
+    /// Exports every 10 seconds or 1024 spans, whichever comes first, buffering in the buffer
+    /// named `name`.
+    pub fn new(name: impl Into<String>, exporter: X) -> Self {
+        Self {
+            name: name.into(),
+            exporter,
+            flush_every: Duration::from_secs(10),
+            max_buffered: 1024,
+        }
+    }
+
+    /// How long records are buffered before being exported.
+    pub fn flush_every(mut self, interval: Duration) -> Self {
+        self.flush_every = interval;
+        self
+    }
+
+    /// How many spans are buffered at most before being exported.
+    pub fn max_buffered(mut self, max_buffered: usize) -> Self {
+        self.max_buffered = max_buffered;
+        self
+    }
+
+    /// Exports whatever is buffered right away.
+    pub async fn flush(&self) -> worker::Result<()> {
+        let records = self.with_buffer(|buffer| {
+            buffer.since = None;
+            std::mem::take(&mut buffer.records)
+        });
+        if records.is_empty() {
+            return Ok(());
+        }
+        self.exporter.export(records).await
+    }
+
+    fn with_buffer<T>(&self, f: impl FnOnce(&mut Buffer) -> T) -> T {
+        BUFFERS.with(|buffers| f(buffers.borrow_mut().entry(self.name.clone()).or_default()))
+    }
+}
+
+#[async_trait(?Send)]
+impl<X: SpanExporter> SpanExporter for Batched<X> {
+    /// Buffers `records`, and exports the buffer if it's due.
+    async fn export(&self, records: Vec<SpanRecord>) -> worker::Result<()> {
+        let now = Date::now().as_millis();
+        let due = self.with_buffer(|buffer| {
+            if records.is_empty() && buffer.records.is_empty() {
+                return false;
+            }
+            buffer.records.extend(records);
+            let since = *buffer.since.get_or_insert(now);
+            buffer.records.len() >= self.max_buffered
+                || now.saturating_sub(since) >= self.flush_every.as_millis() as u64
+        });
+        if due {
+            self.flush().await
+        } else {
+            Ok(())
+        }
+    }
+}
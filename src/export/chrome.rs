@@ -0,0 +1,56 @@
+use minitrace::collector::SpanRecord;
+use serde_json::json;
+
+use crate::{kind::SpanKind, value};
+
+// The Trace Event format (https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU)
+// that `chrome://tracing` and Perfetto open: a complete (`X`) event per span and an instant
+// (`i`) event per span event. Each local root gets a track (`tid`) of its own, on which its
+// spans stack up by time. Timestamps are microseconds from the first span.
+
+/// Encodes `records` as a Trace Event JSON object.
+pub fn encode(records: &[SpanRecord]) -> Vec<u8> {
+    let origin = records
+        .iter()
+        .map(|record| record.begin_time_unix_ns)
+        .min()
+        .unwrap_or_default();
+    let micros = |unix_ns: u64| (unix_ns - origin) as f64 / 1e3;
+
+    let mut events = Vec::new();
+    for (tid, (root, spans)) in super::transactions(records).into_iter().enumerate() {
+        events.push(json!({
+            "name": "thread_name",
+            "ph": "M",
+            "pid": 1,
+            "tid": tid,
+            "args": { "name": root.name },
+        }));
+        for record in std::iter::once(root).chain(spans) {
+            events.push(json!({
+                "name": record.name,
+                "cat": SpanKind::of(record).as_str(),
+                "ph": "X",
+                "ts": micros(record.begin_time_unix_ns),
+                "dur": record.duration_ns as f64 / 1e3,
+                "pid": 1,
+                "tid": tid,
+                "args": super::object(super::attributes(record)),
+            }));
+            events.extend(super::events(record).map(|event| {
+                json!({
+                    "name": event.name,
+                    "ph": "i",
+                    "s": "t",
+                    "ts": micros(event.timestamp_unix_ns.max(origin)),
+                    "pid": 1,
+                    "tid": tid,
+                    "args": super::object(value::typed(&event.properties)),
+                })
+            }));
+        }
+    }
+
+    serde_json::to_vec(&json!({ "traceEvents": events, "displayTimeUnit": "ms" }))
+        .unwrap_or_default()
+}
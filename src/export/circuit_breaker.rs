@@ -0,0 +1,232 @@
+use std::{cell::RefCell, collections::HashMap, time::Duration};
+
+use async_trait::async_trait;
+use minitrace::collector::SpanRecord;
+use worker::Date;
+
+use super::SpanExporter;
+
+// A backend that's down fails every export, each one after its retries, which every request
+// waits on in `wait_until`. After `failure_threshold` failed exports in a row, `CircuitBreaker`
+// opens the circuit for `cooldown`: the records are dropped without trying, and counted. Then
+// a single export is let through, closing the circuit again if it goes through and opening it
+// for another `cooldown` if it doesn't. If that export is dropped before it's done, the next
+// one is let through instead.
+//
+// ```ignore
+// let otlp = CircuitBreaker::new("otlp", OtlpExporter::new(endpoint));
+// ```
+//
+// The state of the circuits is global to the isolate, keyed by the name given to `new`, so it
+// carries over from one request to the next even though the exporter is built for each.
+
+thread_local! {
+    static CIRCUITS: RefCell<HashMap<String, Circuit>> = RefCell::default();
+}
+
+#[derive(Default)]
+struct Circuit {
+    consecutive_failures: u32,
+    // Until when the circuit is open, in ms since the epoch.
+    open_until: Option<u64>,
+    // Whether an export is let through to see if the backend is back.
+    probing: bool,
+    stats: Stats,
+}
+
+/// What happened to the exports through a circuit so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Whether exports are currently skipped.
+    pub open: bool,
+    /// How many times the circuit opened.
+    pub opened: u64,
+    /// The exports skipped while it was open.
+    pub dropped_exports: u64,
+    /// The spans of those exports.
+    pub dropped_spans: u64,
+}
+
+/// The exports through the circuit named `name` in this isolate so far.
+pub fn stats(name: &str) -> Stats {
+    CIRCUITS.with(|circuits| {
+        circuits
+            .borrow()
+            .get(name)
+            .map(|circuit| circuit.stats)
+            .unwrap_or_default()
+    })
+}
+
+/// Exports records with `exporter`, unless it's been failing.
+pub struct CircuitBreaker<X> {
+    name: String,
+    exporter: X,
+    failure_threshold: u32,
+    cooldown: Duration,
+}
+
+impl<X: SpanExporter> CircuitBreaker<X> {
+    /// Opens the circuit named `name` for 30 seconds after 5 failures in a row.
+    pub fn new(name: impl Into<String>, exporter: X) -> Self {
+        Self {
+            name: name.into(),
+            exporter,
+            failure_threshold: 5,
+            cooldown: Duration::from_secs(30),
+        }
+    }
+
+    /// How many exports in a row have to fail for the circuit to open.
+    pub fn failure_threshold(mut self, failures: u32) -> Self {
+        self.failure_threshold = failures.max(1);
+        self
+    }
+
+    /// How long the circuit stays open before an export is tried again.
+    pub fn cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    fn with_circuit<T>(&self, f: impl FnOnce(&mut Circuit) -> T) -> T {
+        CIRCUITS.with(|circuits| f(circuits.borrow_mut().entry(self.name.clone()).or_default()))
+    }
+}
+
+#[async_trait(?Send)]
+impl<X: SpanExporter> SpanExporter for CircuitBreaker<X> {
+    /// Exports `records`, or drops them if the circuit is open.
+    async fn export(&self, records: Vec<SpanRecord>) -> worker::Result<()> {
+        self.export_at(records, || Date::now().as_millis()).await
+    }
+}
+
+impl<X: SpanExporter> CircuitBreaker<X> {
+    async fn export_at(
+        &self,
+        records: Vec<SpanRecord>,
+        now: impl Fn() -> u64,
+    ) -> worker::Result<()> {
+        let spans = records.len() as u64;
+        let started = now();
+        let allowed = self.with_circuit(|circuit| match circuit.open_until {
+            Some(until) if started < until || circuit.probing => {
+                circuit.stats.dropped_exports += 1;
+                circuit.stats.dropped_spans += spans;
+                None
+            }
+            Some(_) => {
+                circuit.probing = true;
+                Some(true)
+            }
+            None => Some(false),
+        });
+        let Some(probing) = allowed else {
+            return Ok(());
+        };
+        let _probe = probing.then(|| Probe { name: &self.name });
+
+        let res = self.exporter.export(records).await;
+        let now = now();
+        self.with_circuit(|circuit| {
+            let probing = std::mem::take(&mut circuit.probing);
+            if res.is_ok() {
+                circuit.consecutive_failures = 0;
+                circuit.open_until = None;
+                circuit.stats.open = false;
+                return;
+            }
+            circuit.consecutive_failures += 1;
+            if probing || circuit.consecutive_failures >= self.failure_threshold {
+                if !circuit.stats.open {
+                    circuit.stats.opened += 1;
+                    worker::console_error!(
+                        "opening the circuit of {} for {}s",
+                        self.name,
+                        self.cooldown.as_secs()
+                    );
+                }
+                circuit.open_until = Some(now + self.cooldown.as_millis() as u64);
+                circuit.stats.open = true;
+            }
+        });
+        res
+    }
+}
+
+// Lets the next export probe if this one is dropped before it's done, e.g. when the
+// invocation runs out of time.
+struct Probe<'a> {
+    name: &'a str,
+}
+
+impl Drop for Probe<'_> {
+    fn drop(&mut self) {
+        CIRCUITS.with(|circuits| {
+            if let Some(circuit) = circuits.borrow_mut().get_mut(self.name) {
+                circuit.probing = false;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::Cell,
+        future::Future,
+        pin::pin,
+        task::{Context, RawWaker, RawWakerVTable, Waker},
+    };
+
+    use super::*;
+
+    // Never done, like a backend that doesn't answer.
+    struct Hanging {
+        calls: Cell<u32>,
+    }
+
+    #[async_trait(?Send)]
+    impl SpanExporter for Hanging {
+        async fn export(&self, _: Vec<SpanRecord>) -> worker::Result<()> {
+            self.calls.set(self.calls.get() + 1);
+            std::future::pending().await
+        }
+    }
+
+    fn noop_waker() -> Waker {
+        const VTABLE: RawWakerVTable = RawWakerVTable::new(
+            |_| RawWaker::new(std::ptr::null(), &VTABLE),
+            |_| {},
+            |_| {},
+            |_| {},
+        );
+        unsafe { Waker::from_raw(RawWaker::new(std::ptr::null(), &VTABLE)) }
+    }
+
+    fn poll_once(future: impl Future) -> bool {
+        let waker = noop_waker();
+        pin!(future)
+            .poll(&mut Context::from_waker(&waker))
+            .is_ready()
+    }
+
+    #[test]
+    fn dropped_probe() {
+        let breaker = CircuitBreaker::new(
+            "dropped_probe",
+            Hanging {
+                calls: Cell::new(0),
+            },
+        );
+        breaker.with_circuit(|circuit| circuit.open_until = Some(1_000));
+
+        assert!(!poll_once(breaker.export_at(Vec::new(), || 2_000)));
+        assert_eq!(breaker.exporter.calls.get(), 1);
+        // Dropped along with the probe that never finished, so it's tried again.
+        assert!(!poll_once(breaker.export_at(Vec::new(), || 2_000)));
+        assert_eq!(breaker.exporter.calls.get(), 2);
+        assert_eq!(stats("dropped_probe").dropped_exports, 0);
+    }
+}
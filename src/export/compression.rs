@@ -0,0 +1,65 @@
+use worker::{
+    js_sys::{ArrayBuffer, Uint8Array},
+    wasm_bindgen::{self, prelude::*, JsCast},
+    wasm_bindgen_futures::JsFuture,
+    worker_sys::web_sys::{ReadableStream, Response},
+};
+
+// Export bodies are compressed by the runtime's `CompressionStream`, which is faster than
+// anything compiled to wasm and keeps the worker small. It only does gzip and deflate, no
+// brotli.
+
+/// How an export body is compressed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Deflate,
+}
+
+impl Compression {
+    /// The `content-encoding` of a body compressed this way, `None` when it isn't.
+    pub fn content_encoding(&self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gzip"),
+            Compression::Deflate => Some("deflate"),
+        }
+    }
+}
+
+#[wasm_bindgen]
+extern "C" {
+    type CompressionStream;
+
+    #[wasm_bindgen(constructor)]
+    fn new(format: &str) -> CompressionStream;
+
+    /// The methods of `ReadableStream` missing from `web_sys`'s.
+    #[wasm_bindgen(js_name = ReadableStream)]
+    type Stream;
+
+    #[wasm_bindgen(method, js_name = pipeThrough)]
+    fn pipe_through(this: &Stream, transform: &CompressionStream) -> ReadableStream;
+}
+
+/// `body` compressed with `compression`.
+pub(crate) async fn compress(body: &[u8], compression: Compression) -> worker::Result<Vec<u8>> {
+    let Some(format) = compression.content_encoding() else {
+        return Ok(body.to_vec());
+    };
+    // A `Response` is the shortest way between bytes and a stream, both ways.
+    let mut body = body.to_vec();
+    let uncompressed = Response::new_with_opt_u8_array(Some(&mut body))?;
+    let Some(stream) = uncompressed.body() else {
+        return Ok(body);
+    };
+    let compressed = stream
+        .unchecked_into::<Stream>()
+        .pipe_through(&CompressionStream::new(format));
+    let buffer =
+        JsFuture::from(Response::new_with_opt_readable_stream(Some(&compressed))?.array_buffer()?)
+            .await?;
+    Ok(Uint8Array::new(&buffer.unchecked_into::<ArrayBuffer>()).to_vec())
+}
@@ -0,0 +1,104 @@
+use std::{collections::HashMap, fmt::Write};
+
+use async_trait::async_trait;
+use minitrace::collector::{SpanId, SpanRecord};
+
+use crate::{kind::SpanKind, status::SpanStatus};
+
+use super::SpanExporter;
+
+// Logs every trace as a tree, which is what's worth looking at in `wrangler tail` while
+// developing:
+//
+// ```text
+// GET /users/:id 12.3ms [server]
+// ├─ route /users/:id 10.1ms (82%)
+// │  ├─ SELECT users 8.0ms (65%) [client]
+// │  └─ render 1.2ms (10%)
+// └─ worker_rust::parse_attempt 0.1ms (1%) ERROR (invalid digit found in string)
+// ```
+//
+// Percentages are of the root's duration.
+
+/// Logs records as trees of spans, to the console.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ConsoleExporter {
+    attributes: bool,
+}
+
+impl ConsoleExporter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether to list the attributes of each span after its name.
+    pub fn attributes(mut self, enabled: bool) -> Self {
+        self.attributes = enabled;
+        self
+    }
+
+    /// The trees `export` logs.
+    pub fn render(&self, records: &[SpanRecord]) -> String {
+        let (roots, children) = super::tree(records);
+        let mut out = String::new();
+        for root in roots {
+            self.line(&mut out, root, None);
+            self.children(&mut out, root, &children, root.duration_ns, "");
+        }
+        out
+    }
+
+    fn children(
+        &self,
+        out: &mut String,
+        record: &SpanRecord,
+        children: &HashMap<SpanId, Vec<&SpanRecord>>,
+        total_ns: u64,
+        indent: &str,
+    ) {
+        let Some(siblings) = children.get(&record.span_id) else {
+            return;
+        };
+        for (i, child) in siblings.iter().enumerate() {
+            let last = i + 1 == siblings.len();
+            out.push_str(indent);
+            out.push_str(if last { "└─ " } else { "├─ " });
+            self.line(out, child, Some(total_ns));
+            let indent = format!("{indent}{}", if last { "   " } else { "│  " });
+            self.children(out, child, children, total_ns, &indent);
+        }
+    }
+
+    fn line(&self, out: &mut String, record: &SpanRecord, total_ns: Option<u64>) {
+        out.push_str(&record.name);
+        if self.attributes {
+            for (key, value) in super::attributes(record) {
+                let _ = write!(out, " {key}={value}");
+            }
+        }
+        let _ = write!(out, " {}", super::format_duration(record.duration_ns));
+        if let Some(total_ns) = total_ns.filter(|total| *total > 0) {
+            let _ = write!(out, " ({}%)", record.duration_ns * 100 / total_ns);
+        }
+        let kind = SpanKind::of(record);
+        if kind != SpanKind::Internal {
+            let _ = write!(out, " [{kind}]");
+        }
+        let status = SpanStatus::of(record);
+        if status.is_error() {
+            let _ = write!(out, " {status}");
+        }
+        out.push('\n');
+    }
+}
+
+#[async_trait(?Send)]
+impl SpanExporter for ConsoleExporter {
+    /// Logs `records`, if there are any.
+    async fn export(&self, records: Vec<SpanRecord>) -> worker::Result<()> {
+        if !records.is_empty() {
+            worker::console_log!("{}", self.render(&records).trim_end());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,322 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use minitrace::collector::SpanRecord;
+
+use crate::{
+    kind::{SpanKind, SPAN_KIND},
+    status::SpanStatus,
+    value::Value,
+};
+
+use super::{auth::Credentials, SpanExporter};
+
+// Sends the records to a Datadog Agent's (or a compatible intake's) v0.4 traces endpoint, as
+// msgpack (https://github.com/DataDog/datadog-agent/blob/main/pkg/proto/datadog/trace/span.proto).
+// The body is a list of traces, each a list of spans.
+//
+// Datadog ids are 64 bits: spans keep the low half of the trace id, the high half goes in
+// the `_dd.p.tid` tag, which Datadog joins back into the 128-bit id it shows. A span's name
+// is the operation (what kind of thing it is, e.g. `worker.server`) and its resource what it
+// did (the span's own name, e.g. `GET /users/:id`). String and bool attributes are tags
+// (`meta`), numbers are `metrics`.
+
+const DEFAULT_PATH: &str = "/v0.4/traces";
+/// The high 64 bits of the trace id, as 16 hex digits.
+const TRACE_ID_HIGH: &str = "_dd.p.tid";
+
+/// Exports records to the Datadog Agent.
+#[derive(Clone, Debug)]
+pub struct DatadogExporter {
+    url: String,
+    service_name: String,
+    headers: Vec<(String, String)>,
+}
+
+impl DatadogExporter {
+    /// Exports to the agent (or intake) at `base_url`, e.g. `http://datadog-agent:8126`, as
+    /// `service_name`.
+    pub fn new(base_url: &str, service_name: impl Into<String>) -> Self {
+        Self {
+            url: format!("{}{DEFAULT_PATH}", base_url.trim_end_matches('/')),
+            service_name: service_name.into(),
+            headers: vec![("datadog-meta-lang".to_owned(), "rust".to_owned())],
+        }
+    }
+
+    /// Authenticates with `api_key`, for intakes that want one rather than an agent.
+    pub fn api_key(self, api_key: impl Into<String>) -> Self {
+        self.header("dd-api-key", api_key)
+    }
+
+    /// Sends `name: value` with every export.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Sends the headers of `credentials` with every export.
+    pub fn auth(mut self, credentials: &Credentials) -> Self {
+        self.headers.extend_from_slice(credentials.headers());
+        self
+    }
+
+    /// The body `export` sends for `records`, and the number of traces in it.
+    pub fn encode(&self, records: &[SpanRecord]) -> (Vec<u8>, usize) {
+        let mut traces: BTreeMap<u128, Vec<&SpanRecord>> = BTreeMap::new();
+        for record in records {
+            traces.entry(record.trace_id.0).or_default().push(record);
+        }
+
+        let mut w = Writer::default();
+        w.array(traces.len());
+        for spans in traces.values() {
+            w.array(spans.len());
+            for record in spans {
+                self.write_span(&mut w, record);
+            }
+        }
+        (w.buf, traces.len())
+    }
+
+    fn write_span(&self, w: &mut Writer, record: &SpanRecord) {
+        let kind = SpanKind::of(record);
+        let status = SpanStatus::of(record);
+
+        let mut meta = BTreeMap::new();
+        let mut metrics = BTreeMap::new();
+        for (key, value) in super::attributes(record) {
+            match value {
+                Value::I64(v) => {
+                    metrics.insert(key, v as f64);
+                }
+                Value::F64(v) => {
+                    metrics.insert(key, v);
+                }
+                value => {
+                    meta.insert(key, value.to_string());
+                }
+            }
+        }
+        meta.insert(SPAN_KIND.into(), kind.as_str().to_owned());
+        let high = (record.trace_id.0 >> 64) as u64;
+        if high != 0 {
+            meta.insert(TRACE_ID_HIGH.into(), format!("{high:016x}"));
+        }
+        if let SpanStatus::Error(description) = &status {
+            meta.insert("error.message".into(), description.to_string());
+        }
+
+        w.map(12);
+        w.str("trace_id");
+        w.uint(record.trace_id.0 as u64);
+        w.str("span_id");
+        w.uint(record.span_id.0);
+        w.str("parent_id");
+        w.uint(record.parent_id.0);
+        w.str("name");
+        w.str(&format!("worker.{kind}"));
+        w.str("resource");
+        w.str(&record.name);
+        w.str("service");
+        w.str(&self.service_name);
+        w.str("type");
+        w.str(span_type(record, kind));
+        w.str("start");
+        w.int(record.begin_time_unix_ns as i64);
+        w.str("duration");
+        w.int(record.duration_ns as i64);
+        w.str("error");
+        w.int(status.is_error() as i64);
+        w.str("meta");
+        w.map(meta.len());
+        for (key, value) in &meta {
+            w.str(key);
+            w.str(value);
+        }
+        w.str("metrics");
+        w.map(metrics.len());
+        for (key, value) in &metrics {
+            w.str(key);
+            w.float(*value);
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl SpanExporter for DatadogExporter {
+    /// Sends `records`, if there are any.
+    async fn export(&self, records: Vec<SpanRecord>) -> worker::Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let (body, traces) = self.encode(&records);
+        let mut headers = self.headers.clone();
+        headers.push(("x-datadog-trace-count".to_owned(), traces.to_string()));
+        super::post(&self.url, &headers, "application/msgpack", &body).await
+    }
+}
+
+/// What Datadog groups the span under in its UI.
+fn span_type(record: &SpanRecord, kind: SpanKind) -> &'static str {
+    let has = |key| super::property(record, key).is_some();
+    if has("db.system") {
+        "db"
+    } else if kind == SpanKind::Server {
+        "web"
+    } else if has("http.request.method") {
+        "http"
+    } else if has("messaging.system") {
+        "queue"
+    } else {
+        "custom"
+    }
+}
+
+/// The msgpack format (https://github.com/msgpack/msgpack/blob/master/spec.md), just what the
+/// traces need.
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn header(&mut self, len: usize, fix: u8, fix_max: usize, markers: [u8; 2]) {
+        if len <= fix_max {
+            self.buf.push(fix | len as u8);
+        } else if len <= u16::MAX as usize {
+            self.buf.push(markers[0]);
+            self.buf.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            self.buf.push(markers[1]);
+            self.buf.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+    }
+
+    fn array(&mut self, len: usize) {
+        self.header(len, 0x90, 15, [0xdc, 0xdd]);
+    }
+
+    fn map(&mut self, len: usize) {
+        self.header(len, 0x80, 15, [0xde, 0xdf]);
+    }
+
+    fn str(&mut self, v: &str) {
+        if (32..256).contains(&v.len()) {
+            self.buf.push(0xd9);
+            self.buf.push(v.len() as u8);
+        } else {
+            self.header(v.len(), 0xa0, 31, [0xda, 0xdb]);
+        }
+        self.buf.extend_from_slice(v.as_bytes());
+    }
+
+    fn uint(&mut self, v: u64) {
+        self.buf.push(0xcf);
+        self.buf.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn int(&mut self, v: i64) {
+        self.buf.push(0xd3);
+        self.buf.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn float(&mut self, v: f64) {
+        self.buf.push(0xcb);
+        self.buf.extend_from_slice(&v.to_be_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use minitrace::collector::{SpanId, TraceId};
+
+    use super::*;
+
+    #[test]
+    fn encode() {
+        let record = SpanRecord {
+            trace_id: TraceId(5 << 64 | 1),
+            span_id: SpanId(2),
+            parent_id: SpanId(3),
+            begin_time_unix_ns: 5_000,
+            duration_ns: 2_000,
+            name: "GET /users".into(),
+            properties: vec![
+                ("span.kind".into(), "server".into()),
+                ("__type.code".into(), "i64".into()),
+                ("code".into(), "200".into()),
+            ],
+            events: Vec::new(),
+        };
+        let (body, traces) =
+            DatadogExporter::new("http://datadog-agent:8126", "svc").encode(&[record]);
+        let expected = [
+            // one trace of one span, with 12 fields
+            &[0x91, 0x91, 0x8c][..],
+            // trace_id (the low half), span_id, parent_id
+            &[0xa8],
+            b"trace_id",
+            &[0xcf],
+            &1u64.to_be_bytes(),
+            &[0xa7],
+            b"span_id",
+            &[0xcf],
+            &2u64.to_be_bytes(),
+            &[0xa9],
+            b"parent_id",
+            &[0xcf],
+            &3u64.to_be_bytes(),
+            // name, resource, service, type
+            &[0xa4],
+            b"name",
+            &[0xad],
+            b"worker.server",
+            &[0xa8],
+            b"resource",
+            &[0xaa],
+            b"GET /users",
+            &[0xa7],
+            b"service",
+            &[0xa3],
+            b"svc",
+            &[0xa4],
+            b"type",
+            &[0xa3],
+            b"web",
+            // start, duration, error
+            &[0xa5],
+            b"start",
+            &[0xd3],
+            &5_000i64.to_be_bytes(),
+            &[0xa8],
+            b"duration",
+            &[0xd3],
+            &2_000i64.to_be_bytes(),
+            &[0xa5],
+            b"error",
+            &[0xd3],
+            &0i64.to_be_bytes(),
+            // meta: the trace id's high half and the kind
+            &[0xa4],
+            b"meta",
+            &[0x82, 0xa9],
+            b"_dd.p.tid",
+            &[0xb0],
+            b"0000000000000005",
+            &[0xa9],
+            b"span.kind",
+            &[0xa6],
+            b"server",
+            // metrics: code = 200
+            &[0xa7],
+            b"metrics",
+            &[0x81, 0xa4],
+            b"code",
+            &[0xcb],
+            &200f64.to_be_bytes(),
+        ]
+        .concat();
+        assert_eq!((body, traces), (expected, 1));
+    }
+}
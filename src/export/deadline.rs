@@ -0,0 +1,55 @@
+use std::{
+    future::Future,
+    pin::{pin, Pin},
+    task::Poll,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use minitrace::collector::SpanRecord;
+use worker::Delay;
+
+use super::SpanExporter;
+
+// Gives up on an export that takes longer than `timeout`, retries included: the runtime only
+// lets `wait_until` run for so long after the response is sent, and an export still hanging
+// by then holds on to the invocation until it's cut off. The export future is dropped, which
+// abandons whatever request it was waiting on, and the export fails with `export_timed_out`.
+//
+// ```ignore
+// FetchTracing::new(export::to(Deadline::new(exporter, Duration::from_secs(2))))
+// ```
+
+/// Exports records with `exporter`, giving up after `timeout`.
+pub struct Deadline<X> {
+    exporter: X,
+    timeout: Duration,
+}
+
+impl<X> Deadline<X> {
+    pub fn new(exporter: X, timeout: Duration) -> Self {
+        Self { exporter, timeout }
+    }
+}
+
+#[async_trait(?Send)]
+impl<X: SpanExporter> SpanExporter for Deadline<X> {
+    /// Exports `records`, failing if it takes longer than the timeout.
+    async fn export(&self, records: Vec<SpanRecord>) -> worker::Result<()> {
+        let mut export = pin!(self.exporter.export(records));
+        let mut delay = Delay::from(self.timeout);
+        std::future::poll_fn(|cx| {
+            if let Poll::Ready(res) = export.as_mut().poll(cx) {
+                return Poll::Ready(res);
+            }
+            match Pin::new(&mut delay).poll(cx) {
+                Poll::Ready(()) => Poll::Ready(Err(worker::Error::RustError(format!(
+                    "export_timed_out after {}ms",
+                    self.timeout.as_millis()
+                )))),
+                Poll::Pending => Poll::Pending,
+            }
+        })
+        .await
+    }
+}
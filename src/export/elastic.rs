@@ -0,0 +1,241 @@
+use async_trait::async_trait;
+use minitrace::collector::SpanRecord;
+use serde_json::{json, Map, Value as Json};
+
+use crate::{kind::SpanKind, status::SpanStatus};
+
+use super::{auth::Credentials, SpanExporter};
+
+// Sends the records to an Elastic APM server's intake v2 API
+// (https://www.elastic.co/guide/en/observability/current/apm-api-events.html) as NDJSON: a
+// `metadata` line describing the service, then a `transaction` line per local root (see
+// `transactions`) and a `span` line per span below it. Timestamps are microseconds and
+// durations milliseconds, and attributes are sent as labels, which only take scalars. APM has
+// no span events, so they're left out, and neither are array attributes.
+
+const AGENT_NAME: &str = env!("CARGO_PKG_NAME");
+const AGENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Exports records to an Elastic APM server.
+#[derive(Clone, Debug)]
+pub struct ElasticExporter {
+    url: String,
+    service_name: String,
+    headers: Vec<(String, String)>,
+}
+
+impl ElasticExporter {
+    /// Exports to the APM server at `base_url`, as `service_name`.
+    pub fn new(base_url: &str, service_name: impl Into<String>) -> Self {
+        Self {
+            url: format!("{}/intake/v2/events", base_url.trim_end_matches('/')),
+            service_name: service_name.into(),
+            headers: Vec::new(),
+        }
+    }
+
+    /// Authenticates with the server's secret token.
+    pub fn secret_token(self, token: &str) -> Self {
+        self.header("authorization", format!("Bearer {token}"))
+    }
+
+    /// Authenticates with an API key.
+    pub fn api_key(self, api_key: &str) -> Self {
+        self.header("authorization", format!("ApiKey {api_key}"))
+    }
+
+    /// Sends `name: value` with every export.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Sends the headers of `credentials` with every export.
+    pub fn auth(mut self, credentials: &Credentials) -> Self {
+        self.headers.extend_from_slice(credentials.headers());
+        self
+    }
+
+    /// The body `export` sends for `records`.
+    pub fn encode(&self, records: &[SpanRecord]) -> Vec<u8> {
+        let mut lines = vec![json!({
+            "metadata": {
+                "service": {
+                    "name": self.service_name,
+                    "agent": { "name": AGENT_NAME, "version": AGENT_VERSION },
+                    "language": { "name": "rust" },
+                },
+            },
+        })];
+        for (root, spans) in super::transactions(records) {
+            lines.push(json!({ "transaction": transaction(root, spans.len()) }));
+            lines.extend(
+                spans
+                    .into_iter()
+                    .map(|record| json!({ "span": span(record, root) })),
+            );
+        }
+
+        let mut body = Vec::new();
+        for line in lines {
+            body.extend(line.to_string().into_bytes());
+            body.push(b'\n');
+        }
+        body
+    }
+}
+
+#[async_trait(?Send)]
+impl SpanExporter for ElasticExporter {
+    /// Sends `records`, if there are any.
+    async fn export(&self, records: Vec<SpanRecord>) -> worker::Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let body = self.encode(&records);
+        super::post(&self.url, &self.headers, "application/x-ndjson", &body).await
+    }
+}
+
+fn transaction(record: &SpanRecord, span_count: usize) -> Json {
+    let mut transaction = common(record);
+    let kind = match SpanKind::of(record) {
+        SpanKind::Server => "request",
+        SpanKind::Consumer => "messaging",
+        _ => "custom",
+    };
+    transaction.insert("type".into(), kind.into());
+    transaction.insert("sampled".into(), true.into());
+    transaction.insert("span_count".into(), json!({ "started": span_count }));
+    transaction.into()
+}
+
+fn span(record: &SpanRecord, transaction: &SpanRecord) -> Json {
+    let mut span = common(record);
+    span.insert(
+        "transaction_id".into(),
+        format!("{:016x}", transaction.span_id.0).into(),
+    );
+    let (kind, subtype) = if let Some(system) = super::property(record, "db.system") {
+        ("db", Some(system))
+    } else if let Some(system) = super::property(record, "messaging.system") {
+        ("messaging", Some(system))
+    } else if super::property(record, "http.request.method").is_some() {
+        ("external", Some("http"))
+    } else {
+        ("app", None)
+    };
+    span.insert("type".into(), kind.into());
+    if let Some(subtype) = subtype {
+        span.insert("subtype".into(), subtype.into());
+    }
+    span.into()
+}
+
+/// The fields transactions and spans share.
+fn common(record: &SpanRecord) -> Map<String, Json> {
+    let labels: Map<_, _> = super::attributes(record)
+        .into_iter()
+        .map(|(key, value)| (key.into_owned(), value.to_json()))
+        .filter(|(_, value)| !value.is_array())
+        .collect();
+    let outcome = match SpanStatus::of(record) {
+        SpanStatus::Error(_) => "failure",
+        _ => "success",
+    };
+
+    let mut common = Map::new();
+    common.insert("id".into(), format!("{:016x}", record.span_id.0).into());
+    common.insert(
+        "trace_id".into(),
+        format!("{:032x}", record.trace_id.0).into(),
+    );
+    if record.parent_id.0 != 0 {
+        common.insert(
+            "parent_id".into(),
+            format!("{:016x}", record.parent_id.0).into(),
+        );
+    }
+    common.insert("name".into(), record.name.as_ref().into());
+    common.insert(
+        "timestamp".into(),
+        (record.begin_time_unix_ns / 1000).into(),
+    );
+    common.insert("duration".into(), (record.duration_ns as f64 / 1e6).into());
+    common.insert("outcome".into(), outcome.into());
+    common.insert("context".into(), json!({ "tags": labels }));
+    common
+}
+
+#[cfg(test)]
+mod tests {
+    use minitrace::collector::{SpanId, TraceId};
+
+    use super::*;
+
+    /// A span of trace 1, from `begin_ms` for `duration_ms`.
+    fn record(
+        (span_id, parent_id): (u64, u64),
+        (begin_ms, duration_ms): (u64, u64),
+        name: &str,
+        properties: &[(&str, &str)],
+    ) -> SpanRecord {
+        SpanRecord {
+            trace_id: TraceId(1),
+            span_id: SpanId(span_id),
+            parent_id: SpanId(parent_id),
+            begin_time_unix_ns: begin_ms * 1_000_000,
+            duration_ns: duration_ms * 1_000_000,
+            name: name.to_owned().into(),
+            properties: properties
+                .iter()
+                .map(|(k, v)| (k.to_string().into(), v.to_string().into()))
+                .collect(),
+            events: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn encode() {
+        let records = [
+            record(
+                (2, 0),
+                (1_000, 500),
+                "GET /users",
+                &[("span.kind", "server"), ("http.request.method", "GET")],
+            ),
+            record(
+                (3, 2),
+                (1_100, 200),
+                "SELECT",
+                &[
+                    ("span.kind", "client"),
+                    ("db.system", "d1"),
+                    ("otel.status_code", "ERROR"),
+                ],
+            ),
+        ];
+        let body = ElasticExporter::new("http://apm-server:8200", "svc").encode(&records);
+        let expected = concat!(
+            r#"{"metadata":{"service":{"agent":{"name":""#,
+            env!("CARGO_PKG_NAME"),
+            r#"","version":""#,
+            env!("CARGO_PKG_VERSION"),
+            r#""},"language":{"name":"rust"},"name":"svc"}}}"#,
+            "\n",
+            r#"{"transaction":{"context":{"tags":{"http.request.method":"GET"}},"#,
+            r#""duration":500.0,"id":"0000000000000002","name":"GET /users","#,
+            r#""outcome":"success","sampled":true,"span_count":{"started":1},"#,
+            r#""timestamp":1000000,"trace_id":"00000000000000000000000000000001","#,
+            r#""type":"request"}}"#,
+            "\n",
+            r#"{"span":{"context":{"tags":{"db.system":"d1"}},"duration":200.0,"#,
+            r#""id":"0000000000000003","name":"SELECT","outcome":"failure","#,
+            r#""parent_id":"0000000000000002","subtype":"d1","timestamp":1100000,"#,
+            r#""trace_id":"00000000000000000000000000000001","#,
+            r#""transaction_id":"0000000000000002","type":"db"}}"#,
+            "\n",
+        );
+        assert_eq!(String::from_utf8(body).unwrap(), expected);
+    }
+}
@@ -0,0 +1,145 @@
+use std::{future::Future, pin::Pin, task::Poll};
+
+use async_trait::async_trait;
+use minitrace::collector::SpanRecord;
+
+use super::SpanExporter;
+use crate::processor::Processor;
+
+// Sends the same records to several exporters at once, e.g. the console while developing and
+// a backend, or a backend and an archive. The exports run concurrently and don't depend on
+// each other: one failing (or being slow) doesn't keep the records from the others.
+//
+// ```ignore
+// let fanout = Fanout::new()
+//     .with("honeycomb", HoneycombExporter::new(dataset, api_key, "my-worker"))
+//     .with("archive", archive);
+// FetchTracing::new(export::to(fanout))
+// ```
+//
+// An exporter can be given a filter of its own, any `Processor`, that its copy of the records
+// goes through first: the console gets everything, say, while a backend billing by the span
+// only gets the interesting traces, and Analytics Engine the roots. An exporter whose filter
+// leaves nothing isn't called.
+//
+// ```ignore
+// let fanout = Fanout::new()
+//     .with("console", ConsoleExporter::new())
+//     .with_filter("otlp", TailSampling::new(), otlp)
+//     .with_filter("analytics", LocalRoots, analytics);
+// ```
+
+pub(super) type LocalBoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+/// Exports records to every exporter added.
+#[derive(Default)]
+pub struct Fanout {
+    exporters: Vec<Target>,
+}
+
+struct Target {
+    name: String,
+    filter: Option<Box<dyn Processor>>,
+    exporter: Box<dyn SpanExporter>,
+}
+
+impl Target {
+    fn export(&self, mut records: Vec<SpanRecord>) -> LocalBoxFuture<'_, worker::Result<()>> {
+        if let Some(filter) = &self.filter {
+            filter.process(&mut records);
+            if records.is_empty() {
+                return Box::pin(async { Ok(()) });
+            }
+        }
+        self.exporter.export(records)
+    }
+}
+
+impl Fanout {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `exporter`, named `name` in errors.
+    pub fn with(mut self, name: impl Into<String>, exporter: impl SpanExporter + 'static) -> Self {
+        self.exporters.push(Target {
+            name: name.into(),
+            filter: None,
+            exporter: Box::new(exporter),
+        });
+        self
+    }
+
+    /// Adds `exporter`, only getting the records `filter` leaves.
+    pub fn with_filter(
+        mut self,
+        name: impl Into<String>,
+        filter: impl Processor + 'static,
+        exporter: impl SpanExporter + 'static,
+    ) -> Self {
+        self.exporters.push(Target {
+            name: name.into(),
+            filter: Some(Box::new(filter)),
+            exporter: Box::new(exporter),
+        });
+        self
+    }
+}
+
+#[async_trait(?Send)]
+impl SpanExporter for Fanout {
+    /// Sends `records` to every exporter, each failure being logged. Fails if any of them
+    /// failed, once they're all done.
+    async fn export(&self, records: Vec<SpanRecord>) -> worker::Result<()> {
+        let Some((last, rest)) = self.exporters.split_last() else {
+            return Ok(());
+        };
+        let mut exports: Vec<_> = rest
+            .iter()
+            .map(|target| target.export(records.clone()))
+            .collect();
+        exports.push(last.export(records));
+
+        let failed: Vec<_> = join_all(exports)
+            .await
+            .into_iter()
+            .zip(&self.exporters)
+            .filter_map(|(res, target)| {
+                let err = res.err()?;
+                worker::console_error!("export to {} failed: {err}", target.name);
+                Some(target.name.as_str())
+            })
+            .collect();
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(worker::Error::RustError(format!(
+                "export to {} failed",
+                failed.join(", ")
+            )))
+        }
+    }
+}
+
+/// Polls all of `futures` until each one is done, and returns their outputs in order.
+pub(super) async fn join_all<T>(futures: Vec<LocalBoxFuture<'_, T>>) -> Vec<T> {
+    let mut pending: Vec<_> = futures.into_iter().map(Some).collect();
+    let mut outputs: Vec<Option<T>> = pending.iter().map(|_| None).collect();
+    std::future::poll_fn(|cx| {
+        for (future, output) in pending.iter_mut().zip(outputs.iter_mut()) {
+            if let Some(f) = future {
+                if let Poll::Ready(value) = f.as_mut().poll(cx) {
+                    *output = Some(value);
+                    *future = None;
+                }
+            }
+        }
+        if pending.iter().all(Option::is_none) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    })
+    .await;
+    outputs.into_iter().flatten().collect()
+}
@@ -0,0 +1,78 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+use minitrace::collector::{SpanId, SpanRecord};
+
+// Folded stacks, the input of `inferno-flamegraph` and `flamegraph.pl`: a line per stack of
+// span names from the local root down, with the time spent in the last one and not in its
+// children, in nanoseconds:
+//
+// ```text
+// GET /;worker_rust::func_with_trace;child 10240
+// GET /;worker_rust::func_with_trace;child;nested_wrapped 1953125
+// ```
+//
+// Identical stacks are added up, so feeding `Folded` the records of many requests gives a
+// flamegraph of where the time goes across all of them.
+
+/// Folded stacks, aggregated over every batch of records added.
+#[derive(Clone, Debug, Default)]
+pub struct Folded {
+    stacks: BTreeMap<String, u64>,
+}
+
+impl Folded {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the spans of `records` to the stacks.
+    pub fn add(&mut self, records: &[SpanRecord]) {
+        let (roots, children) = super::tree(records);
+        for root in roots {
+            self.fold(root, &children, String::new());
+        }
+    }
+
+    fn fold(
+        &mut self,
+        record: &SpanRecord,
+        children: &HashMap<SpanId, Vec<&SpanRecord>>,
+        mut stack: String,
+    ) {
+        if !stack.is_empty() {
+            stack.push(';');
+        }
+        // `;` separates frames and the last space the count, names can't have the first.
+        stack.push_str(&record.name.replace(';', ":"));
+
+        let mut children_ns = 0;
+        for child in children.get(&record.span_id).into_iter().flatten() {
+            children_ns += child.duration_ns;
+            self.fold(child, children, stack.clone());
+        }
+        // Concurrent children can add up to more than their parent.
+        let self_ns = record.duration_ns.saturating_sub(children_ns);
+        if self_ns > 0 {
+            *self.stacks.entry(stack).or_default() += self_ns;
+        }
+    }
+}
+
+impl fmt::Display for Folded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (stack, ns) in &self.stacks {
+            writeln!(f, "{stack} {ns}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Folds the stacks of `records` alone.
+pub fn encode(records: &[SpanRecord]) -> String {
+    let mut folded = Folded::new();
+    folded.add(records);
+    folded.to_string()
+}
@@ -0,0 +1,151 @@
+use async_trait::async_trait;
+use minitrace::collector::{EventRecord, SpanRecord};
+use serde_json::{json, Map, Value as Json};
+
+use crate::{kind::SpanKind, link::Link, status::SpanStatus, value};
+
+use super::SpanExporter;
+
+// Sends the records straight to Honeycomb's batch events API
+// (https://docs.honeycomb.io/api/tag/Events#operation/createEvents), one event per span
+// with the `trace.*` fields Honeycomb builds its trace view from. Span events and links are
+// events of their own, pointing at their span through `trace.parent_id` and told apart by
+// `meta.annotation_type`, the way Honeycomb's OpenTelemetry ingest stores them.
+
+const DEFAULT_API_HOST: &str = "https://api.honeycomb.io";
+
+/// Exports records to a Honeycomb dataset.
+#[derive(Clone, Debug)]
+pub struct HoneycombExporter {
+    api_host: String,
+    dataset: String,
+    api_key: String,
+    service_name: String,
+}
+
+impl HoneycombExporter {
+    /// Exports to `dataset`, authenticating with `api_key`, as `service_name`.
+    pub fn new(
+        dataset: impl Into<String>,
+        api_key: impl Into<String>,
+        service_name: impl Into<String>,
+    ) -> Self {
+        Self {
+            api_host: DEFAULT_API_HOST.to_owned(),
+            dataset: dataset.into(),
+            api_key: api_key.into(),
+            service_name: service_name.into(),
+        }
+    }
+
+    /// Sends to another API host than `https://api.honeycomb.io`, e.g. the EU one.
+    pub fn api_host(mut self, api_host: &str) -> Self {
+        self.api_host = api_host.trim_end_matches('/').to_owned();
+        self
+    }
+
+    /// The body `export` sends for `records`.
+    pub fn encode(&self, records: &[SpanRecord]) -> Vec<u8> {
+        let mut events = Vec::with_capacity(records.len());
+        for record in records {
+            events.push(self.span(record));
+            events.extend(super::events(record).map(|event| self.span_event(record, event)));
+            events.extend(Link::of(record).into_iter().map(|link| {
+                self.event(
+                    record,
+                    record.begin_time_unix_ns,
+                    json!({
+                        "meta.annotation_type": "link",
+                        "trace.link.trace_id": format!("{:032x}", link.trace_id.0),
+                        "trace.link.span_id": format!("{:016x}", link.span_id.0),
+                    }),
+                )
+            }));
+        }
+        serde_json::to_vec(&events).unwrap_or_default()
+    }
+
+    fn span(&self, record: &SpanRecord) -> Json {
+        let mut data = Map::new();
+        for (key, value) in super::attributes(record) {
+            data.insert(key.into_owned(), value.to_json());
+        }
+        data.insert("name".into(), record.name.as_ref().into());
+        data.insert("service.name".into(), self.service_name.as_str().into());
+        data.insert(
+            "trace.trace_id".into(),
+            format!("{:032x}", record.trace_id.0).into(),
+        );
+        data.insert(
+            "trace.span_id".into(),
+            format!("{:016x}", record.span_id.0).into(),
+        );
+        if record.parent_id.0 != 0 {
+            data.insert(
+                "trace.parent_id".into(),
+                format!("{:016x}", record.parent_id.0).into(),
+            );
+        }
+        data.insert(
+            "duration_ms".into(),
+            (record.duration_ns as f64 / 1e6).into(),
+        );
+        let kind = SpanKind::of(record);
+        data.insert("span.kind".into(), kind.as_str().into());
+        match SpanStatus::of(record) {
+            SpanStatus::Unset => {}
+            SpanStatus::Ok => {
+                data.insert("status_code".into(), "OK".into());
+            }
+            SpanStatus::Error(description) => {
+                data.insert("status_code".into(), "ERROR".into());
+                data.insert("error".into(), true.into());
+                if !description.is_empty() {
+                    data.insert("status_message".into(), description.as_ref().into());
+                }
+            }
+        }
+        json!({ "time": super::rfc3339(record.begin_time_unix_ns), "data": data })
+    }
+
+    fn span_event(&self, record: &SpanRecord, event: &EventRecord) -> Json {
+        let mut data: Map<_, _> = value::typed(&event.properties)
+            .into_iter()
+            .map(|(key, value)| (key.into_owned(), value.to_json()))
+            .collect();
+        data.insert("meta.annotation_type".into(), "span_event".into());
+        data.insert("name".into(), event.name.as_ref().into());
+        self.event(record, event.timestamp_unix_ns, data.into())
+    }
+
+    /// An event of `record` at `unix_ns`, with `data` plus the fields tying it to the span.
+    fn event(&self, record: &SpanRecord, unix_ns: u64, mut data: Json) -> Json {
+        if let Some(data) = data.as_object_mut() {
+            data.insert("service.name".into(), self.service_name.as_str().into());
+            data.insert(
+                "trace.trace_id".into(),
+                format!("{:032x}", record.trace_id.0).into(),
+            );
+            data.insert(
+                "trace.parent_id".into(),
+                format!("{:016x}", record.span_id.0).into(),
+            );
+            data.insert("parent_name".into(), record.name.as_ref().into());
+        }
+        json!({ "time": super::rfc3339(unix_ns), "data": data })
+    }
+}
+
+#[async_trait(?Send)]
+impl SpanExporter for HoneycombExporter {
+    /// Sends `records`, if there are any.
+    async fn export(&self, records: Vec<SpanRecord>) -> worker::Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let url = format!("{}/1/batch/{}", self.api_host, self.dataset);
+        let headers = [("x-honeycomb-team".to_owned(), self.api_key.clone())];
+        let body = self.encode(&records);
+        super::post(&url, &headers, "application/json", &body).await
+    }
+}
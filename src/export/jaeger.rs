@@ -0,0 +1,312 @@
+use std::borrow::Cow;
+
+use async_trait::async_trait;
+use minitrace::collector::SpanRecord;
+
+use crate::{
+    kind::{SpanKind, SPAN_KIND},
+    link::Link,
+    status::{SpanStatus, STATUS_DESCRIPTION},
+    value::{self, Value},
+};
+
+use super::{auth::Credentials, SpanExporter};
+
+// Sends the records to a Jaeger collector's HTTP endpoint as a Thrift `Batch`, in the binary
+// protocol (https://github.com/jaegertracing/jaeger-idl/blob/main/thrift/jaeger.thrift).
+// Events become logs, with the event name as the `event` field, and links become
+// `FOLLOWS_FROM` references. Jaeger has no kind or status fields, they're the `span.kind` and
+// `error` tags it knows to look for.
+
+const DEFAULT_PATH: &str = "/api/traces";
+
+// Thrift field types.
+const BOOL: u8 = 2;
+const DOUBLE: u8 = 4;
+const I32: u8 = 8;
+const I64: u8 = 10;
+const STRING: u8 = 11;
+const STRUCT: u8 = 12;
+const LIST: u8 = 15;
+
+/// Exports records to a Jaeger collector.
+#[derive(Clone, Debug)]
+pub struct JaegerExporter {
+    url: String,
+    service_name: String,
+    headers: Vec<(String, String)>,
+}
+
+impl JaegerExporter {
+    /// Exports to the collector at `base_url`, e.g. `http://jaeger-collector:14268`, as
+    /// `service_name`.
+    pub fn new(base_url: &str, service_name: impl Into<String>) -> Self {
+        Self {
+            url: format!("{}{DEFAULT_PATH}", base_url.trim_end_matches('/')),
+            service_name: service_name.into(),
+            headers: Vec::new(),
+        }
+    }
+
+    /// Sends `name: value` with every export.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Sends the headers of `credentials` with every export.
+    pub fn auth(mut self, credentials: &Credentials) -> Self {
+        self.headers.extend_from_slice(credentials.headers());
+        self
+    }
+
+    /// The body `export` sends for `records`.
+    pub fn encode(&self, records: &[SpanRecord]) -> Vec<u8> {
+        let mut w = Writer::default();
+        // Batch.process
+        w.field(STRUCT, 1);
+        w.field(STRING, 1);
+        w.string(&self.service_name);
+        w.stop();
+        // Batch.spans
+        w.field(LIST, 2);
+        w.list(STRUCT, records.len());
+        for record in records {
+            write_span(&mut w, record);
+        }
+        w.stop();
+        w.buf
+    }
+}
+
+#[async_trait(?Send)]
+impl SpanExporter for JaegerExporter {
+    /// Sends `records`, if there are any.
+    async fn export(&self, records: Vec<SpanRecord>) -> worker::Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let body = self.encode(&records);
+        super::post(&self.url, &self.headers, "application/x-thrift", &body).await
+    }
+}
+
+fn write_span(w: &mut Writer, record: &SpanRecord) {
+    let trace_id = record.trace_id.0;
+    w.field(I64, 1);
+    w.i64(trace_id as i64);
+    w.field(I64, 2);
+    w.i64((trace_id >> 64) as i64);
+    w.field(I64, 3);
+    w.i64(record.span_id.0 as i64);
+    w.field(I64, 4);
+    w.i64(record.parent_id.0 as i64);
+    w.field(STRING, 5);
+    w.string(&record.name);
+
+    let links = Link::of(record);
+    if !links.is_empty() {
+        w.field(LIST, 6);
+        w.list(STRUCT, links.len());
+        for link in links {
+            // SpanRef: FOLLOWS_FROM
+            w.field(I32, 1);
+            w.i32(1);
+            w.field(I64, 2);
+            w.i64(link.trace_id.0 as i64);
+            w.field(I64, 3);
+            w.i64((link.trace_id.0 >> 64) as i64);
+            w.field(I64, 4);
+            w.i64(link.span_id.0 as i64);
+            w.stop();
+        }
+    }
+
+    // Sampled, or it wouldn't have been collected.
+    w.field(I32, 7);
+    w.i32(1);
+    w.field(I64, 8);
+    w.i64((record.begin_time_unix_ns / 1000) as i64);
+    w.field(I64, 9);
+    w.i64((record.duration_ns / 1000) as i64);
+
+    let mut tags = super::attributes(record);
+    let kind = SpanKind::of(record);
+    if kind != SpanKind::Internal {
+        tags.push((SPAN_KIND.into(), Value::String(kind.as_str().into())));
+    }
+    if let SpanStatus::Error(description) = SpanStatus::of(record) {
+        tags.push(("error".into(), Value::Bool(true)));
+        if !description.is_empty() {
+            tags.push((STATUS_DESCRIPTION.into(), Value::String(description)));
+        }
+    }
+    w.field(LIST, 10);
+    write_tags(w, &tags);
+
+    let events: Vec<_> = super::events(record).collect();
+    w.field(LIST, 11);
+    w.list(STRUCT, events.len());
+    for event in events {
+        // Log.timestamp
+        w.field(I64, 1);
+        w.i64((event.timestamp_unix_ns / 1000) as i64);
+        // Log.fields
+        let mut fields = vec![("event".into(), Value::String(event.name.clone()))];
+        fields.extend(value::typed(&event.properties));
+        w.field(LIST, 2);
+        write_tags(w, &fields);
+        w.stop();
+    }
+    w.stop();
+}
+
+fn write_tags(w: &mut Writer, tags: &[(Cow<'static, str>, Value)]) {
+    w.list(STRUCT, tags.len());
+    for (key, value) in tags {
+        w.field(STRING, 1);
+        w.string(key);
+        // Tag.vType, then the field holding a value of that type.
+        w.field(I32, 2);
+        match value {
+            Value::F64(v) => {
+                w.i32(1);
+                w.field(DOUBLE, 4);
+                w.buf.extend_from_slice(&v.to_be_bytes());
+            }
+            Value::Bool(v) => {
+                w.i32(2);
+                w.field(BOOL, 5);
+                w.buf.push(*v as u8);
+            }
+            Value::I64(v) => {
+                w.i32(3);
+                w.field(I64, 6);
+                w.i64(*v);
+            }
+            Value::String(_) | Value::Array(_) => {
+                w.i32(0);
+                w.field(STRING, 3);
+                w.string(&value.to_string());
+            }
+        }
+        w.stop();
+    }
+}
+
+/// Thrift's binary protocol, just what `Batch` needs.
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn field(&mut self, field_type: u8, id: i16) {
+        self.buf.push(field_type);
+        self.buf.extend_from_slice(&id.to_be_bytes());
+    }
+
+    /// Ends a struct.
+    fn stop(&mut self) {
+        self.buf.push(0);
+    }
+
+    fn list(&mut self, element_type: u8, len: usize) {
+        self.buf.push(element_type);
+        self.i32(len as i32);
+    }
+
+    fn i32(&mut self, v: i32) {
+        self.buf.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn i64(&mut self, v: i64) {
+        self.buf.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn string(&mut self, v: &str) {
+        self.i32(v.len() as i32);
+        self.buf.extend_from_slice(v.as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use minitrace::collector::{EventRecord, SpanId, TraceId};
+
+    use super::*;
+
+    #[test]
+    fn encode() {
+        let record = SpanRecord {
+            trace_id: TraceId(1),
+            span_id: SpanId(2),
+            parent_id: SpanId(3),
+            begin_time_unix_ns: 5_000,
+            duration_ns: 2_000,
+            name: "GET".into(),
+            properties: vec![
+                ("span.kind".into(), "server".into()),
+                ("__type.code".into(), "i64".into()),
+                ("code".into(), "200".into()),
+            ],
+            events: vec![EventRecord {
+                name: "retry".into(),
+                timestamp_unix_ns: 6_000,
+                properties: Vec::new(),
+            }],
+        };
+        let body = JaegerExporter::new("http://jaeger-collector:14268", "svc").encode(&[record]);
+        let expected = [
+            // Batch.process, Process.serviceName
+            &[STRUCT, 0, 1, STRING, 0, 1, 0, 0, 0, 3][..],
+            b"svc",
+            &[0],
+            // Batch.spans, one Span
+            &[LIST, 0, 2, STRUCT, 0, 0, 0, 1],
+            // traceIdLow, traceIdHigh, spanId, parentSpanId
+            &[I64, 0, 1],
+            &1i64.to_be_bytes(),
+            &[I64, 0, 2],
+            &0i64.to_be_bytes(),
+            &[I64, 0, 3],
+            &2i64.to_be_bytes(),
+            &[I64, 0, 4],
+            &3i64.to_be_bytes(),
+            // operationName
+            &[STRING, 0, 5, 0, 0, 0, 3],
+            b"GET",
+            // flags, startTime, duration
+            &[I32, 0, 7, 0, 0, 0, 1],
+            &[I64, 0, 8],
+            &5i64.to_be_bytes(),
+            &[I64, 0, 9],
+            &2i64.to_be_bytes(),
+            // tags: code = 200 as a LONG, span.kind = "server" as a STRING
+            &[LIST, 0, 10, STRUCT, 0, 0, 0, 2],
+            &[STRING, 0, 1, 0, 0, 0, 4],
+            b"code",
+            &[I32, 0, 2, 0, 0, 0, 3, I64, 0, 6],
+            &200i64.to_be_bytes(),
+            &[0],
+            &[STRING, 0, 1, 0, 0, 0, 9],
+            b"span.kind",
+            &[I32, 0, 2, 0, 0, 0, 0, STRING, 0, 3, 0, 0, 0, 6],
+            b"server",
+            &[0],
+            // logs: the event, at 6us
+            &[LIST, 0, 11, STRUCT, 0, 0, 0, 1],
+            &[I64, 0, 1],
+            &6i64.to_be_bytes(),
+            &[LIST, 0, 2, STRUCT, 0, 0, 0, 1],
+            &[STRING, 0, 1, 0, 0, 0, 5],
+            b"event",
+            &[I32, 0, 2, 0, 0, 0, 0, STRING, 0, 3, 0, 0, 0, 5],
+            b"retry",
+            // end of the tag, the log, the span and the batch
+            &[0, 0, 0, 0],
+        ]
+        .concat();
+        assert_eq!(body, expected);
+    }
+}
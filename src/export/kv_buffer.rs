@@ -0,0 +1,150 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use minitrace::collector::SpanRecord;
+use worker::{kv::KvStore, Date};
+
+use super::{tail, SpanExporter};
+
+// Writes the records to KV instead of a backend, for a cron trigger to export in bulk: a
+// busy worker makes one exporter call a minute rather than one per request, and requests
+// only wait for a KV write in `wait_until`.
+//
+// ```ignore
+// // In the fetch handler:
+// FetchTracing::new(export::to(KvBuffer::from_env(&env, "TRACES")?))
+//
+// // In the scheduled handler, e.g. every minute:
+// KvBuffer::from_env(&env, "TRACES")?.flush(&otlp).await?;
+// ```
+//
+// KV has no append, so every batch gets a key of its own, `{prefix}{bucket}/{random}`, the
+// bucket being the start of the `bucket` long window it was written in, in milliseconds.
+// `flush` only takes the buckets that are over, exports all of their batches at once, and
+// deletes them once the export went through. A failed export leaves them for the next run,
+// up to `ttl`, after which KV drops them. Batches are in the lines of `TailExporter`.
+//
+// KV is eventually consistent: a batch written in another location may only be listed a
+// minute later, and is then flushed by a later run.
+
+/// Buffers records in KV until `flush`.
+#[derive(Clone)]
+pub struct KvBuffer {
+    store: KvStore,
+    prefix: String,
+    bucket: Duration,
+    ttl: Duration,
+}
+
+impl KvBuffer {
+    /// Buffers in `store`, under `traces/`, in one minute buckets kept for a day at most.
+    pub fn new(store: KvStore) -> Self {
+        Self {
+            store,
+            prefix: "traces/".to_owned(),
+            bucket: Duration::from_secs(60),
+            ttl: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+
+    /// Buffers in the KV namespace bound as `binding` in `env`.
+    pub fn from_env(env: &worker::Env, binding: &str) -> worker::Result<Self> {
+        Ok(Self::new(env.kv(binding)?))
+    }
+
+    /// What the keys of the batches start with.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// How long a bucket is, flushes only taking buckets that are over.
+    pub fn bucket(mut self, bucket: Duration) -> Self {
+        self.bucket = bucket.max(Duration::from_millis(1));
+        self
+    }
+
+    /// How long batches are kept when they can't be exported, at least a minute.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl.max(Duration::from_secs(60));
+        self
+    }
+
+    /// Exports the batches of every bucket that's over, all at once, and deletes them. Returns
+    /// how many batches were exported.
+    pub async fn flush(&self, exporter: &impl SpanExporter) -> worker::Result<usize> {
+        let current = format!(
+            "{}{:015}",
+            self.prefix,
+            self.bucket_of(Date::now().as_millis())
+        );
+        let mut keys = Vec::new();
+        let mut cursor = None;
+        loop {
+            let mut list = self.store.list().prefix(self.prefix.clone());
+            if let Some(cursor) = cursor.take() {
+                list = list.cursor(cursor);
+            }
+            let page = list.execute().await?;
+            // Keys are listed in order, the current bucket and any after it come last.
+            let mut reached_current = false;
+            for key in page.keys {
+                if key.name >= current {
+                    reached_current = true;
+                    break;
+                }
+                keys.push(key.name);
+            }
+            match page.cursor {
+                Some(next) if !page.list_complete && !reached_current => cursor = Some(next),
+                _ => break,
+            }
+        }
+
+        let mut records = Vec::new();
+        for key in &keys {
+            let Some(batch) = self.store.get(key).text().await? else {
+                continue;
+            };
+            records.extend(batch.lines().filter_map(tail::decode).flatten());
+        }
+        if !records.is_empty() {
+            exporter.export(records).await?;
+        }
+        for key in &keys {
+            self.store.delete(key).await?;
+        }
+        Ok(keys.len())
+    }
+
+    /// The start of the bucket `unix_ms` is in.
+    fn bucket_of(&self, unix_ms: u64) -> u64 {
+        let bucket = self.bucket.as_millis() as u64;
+        unix_ms / bucket * bucket
+    }
+}
+
+#[async_trait(?Send)]
+impl SpanExporter for KvBuffer {
+    /// Writes `records` as a batch of their own, if there are any.
+    async fn export(&self, records: Vec<SpanRecord>) -> worker::Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let mut id = [0; 8];
+        getrandom::getrandom(&mut id).expect("failed to generate a random batch id");
+        let key = format!(
+            "{}{:015}/{:016x}",
+            self.prefix,
+            self.bucket_of(Date::now().as_millis()),
+            u64::from_ne_bytes(id)
+        );
+        let batch = tail::TailExporter::new().encode(&records).join("\n");
+        self.store
+            .put(&key, batch)?
+            .expiration_ttl(self.ttl.as_secs())
+            .execute()
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+use async_trait::async_trait;
+use minitrace::collector::SpanRecord;
+use serde_json::{json, Map, Value as Json};
+
+use crate::{kind::SpanKind, status::SpanStatus};
+
+use super::SpanExporter;
+
+// Sends the records to New Relic's Trace API in its own format
+// (https://docs.newrelic.com/docs/distributed-tracing/trace-api/report-new-relic-format-traces-trace-api/).
+// Everything but the ids and the start time is an attribute, durations are milliseconds, and
+// the service name is set once for the whole payload. The format has no span events, so
+// they're left out.
+
+const DEFAULT_URL: &str = "https://trace-api.newrelic.com/trace/v1";
+
+/// Exports records to New Relic.
+#[derive(Clone, Debug)]
+pub struct NewRelicExporter {
+    url: String,
+    insert_key: String,
+    service_name: String,
+}
+
+impl NewRelicExporter {
+    /// Exports with the license or insert key `insert_key`, as `service_name`.
+    pub fn new(insert_key: impl Into<String>, service_name: impl Into<String>) -> Self {
+        Self {
+            url: DEFAULT_URL.to_owned(),
+            insert_key: insert_key.into(),
+            service_name: service_name.into(),
+        }
+    }
+
+    /// Sends to another endpoint than the US one, e.g.
+    /// `https://trace-api.eu.newrelic.com/trace/v1`.
+    pub fn url(mut self, url: impl Into<String>) -> Self {
+        self.url = url.into();
+        self
+    }
+
+    /// The body `export` sends for `records`.
+    pub fn encode(&self, records: &[SpanRecord]) -> Vec<u8> {
+        let payload = json!([{
+            "common": { "attributes": { "service.name": self.service_name } },
+            "spans": records.iter().map(span).collect::<Vec<_>>(),
+        }]);
+        serde_json::to_vec(&payload).unwrap_or_default()
+    }
+}
+
+#[async_trait(?Send)]
+impl SpanExporter for NewRelicExporter {
+    /// Sends `records`, if there are any.
+    async fn export(&self, records: Vec<SpanRecord>) -> worker::Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let headers = [
+            ("api-key".to_owned(), self.insert_key.clone()),
+            ("data-format".to_owned(), "newrelic".to_owned()),
+            ("data-format-version".to_owned(), "1".to_owned()),
+        ];
+        let body = self.encode(&records);
+        super::post(&self.url, &headers, "application/json", &body).await
+    }
+}
+
+fn span(record: &SpanRecord) -> Json {
+    let mut attributes: Map<_, _> = super::attributes(record)
+        .into_iter()
+        .map(|(key, value)| (key.into_owned(), value.to_json()))
+        .collect();
+    attributes.insert("name".into(), record.name.as_ref().into());
+    attributes.insert(
+        "duration.ms".into(),
+        (record.duration_ns as f64 / 1e6).into(),
+    );
+    if record.parent_id.0 != 0 {
+        attributes.insert(
+            "parent.id".into(),
+            format!("{:016x}", record.parent_id.0).into(),
+        );
+    }
+    let kind = SpanKind::of(record);
+    if kind != SpanKind::Internal {
+        attributes.insert("span.kind".into(), kind.as_str().into());
+    }
+    if let SpanStatus::Error(description) = SpanStatus::of(record) {
+        attributes.insert("error".into(), true.into());
+        if !description.is_empty() {
+            attributes.insert("error.message".into(), description.as_ref().into());
+        }
+    }
+
+    json!({
+        "trace.id": format!("{:032x}", record.trace_id.0),
+        "id": format!("{:016x}", record.span_id.0),
+        "timestamp": record.begin_time_unix_ns / 1_000_000,
+        "attributes": attributes,
+    })
+}
@@ -0,0 +1,304 @@
+use std::borrow::Cow;
+
+use async_trait::async_trait;
+use minitrace::collector::SpanRecord;
+
+use crate::{
+    kind::SpanKind,
+    limits::{DROPPED_EVENTS, DROPPED_PROPERTIES},
+    link::Link,
+    propagation::TRACE_STATE,
+    resource::{self, Resource},
+    status::SpanStatus,
+    value::{self, Value},
+};
+
+mod json;
+mod proto;
+
+use super::{auth::Credentials, compression, Compression, SpanExporter};
+
+// Sends the records to an OpenTelemetry collector, or any backend speaking OTLP/HTTP
+// (https://opentelemetry.io/docs/specs/otlp/#otlphttp), as an `ExportTraceServiceRequest`.
+// All of the records of an export go under a single resource: the global one (see
+// `resource`), along with the attributes set on the exporter.
+//
+// The records are first mapped to `Span`s, which hold everything OTLP has a field for, then
+// encoded, as protobuf by default, or as JSON for the backends and proxies that only take
+// that. Either can be compressed, which pays off quickly: a busy request easily makes for
+// export bodies of hundreds of kilobytes.
+
+const SCOPE_NAME: &str = env!("CARGO_PKG_NAME");
+const SCOPE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// How `OtlpExporter` encodes the requests it sends.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Protobuf,
+    Json,
+}
+
+impl Encoding {
+    fn content_type(&self) -> &'static str {
+        match self {
+            Encoding::Protobuf => "application/x-protobuf",
+            Encoding::Json => "application/json",
+        }
+    }
+}
+
+/// Exports records over OTLP/HTTP.
+#[derive(Clone, Debug)]
+pub struct OtlpExporter {
+    endpoint: String,
+    headers: Vec<(String, String)>,
+    resource: Vec<(Cow<'static, str>, Value)>,
+    encoding: Encoding,
+    compression: Compression,
+}
+
+impl OtlpExporter {
+    /// Exports to `endpoint`, the full URL of the traces endpoint (usually ending in
+    /// `/v1/traces`).
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            headers: Vec::new(),
+            resource: Vec::new(),
+            encoding: Encoding::default(),
+            compression: Compression::default(),
+        }
+    }
+
+    /// Sets how requests are encoded, protobuf unless told otherwise.
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Sets how request bodies are compressed, not at all unless told otherwise.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sends `name: value` with every export, e.g. to authenticate.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Sends the headers of `credentials` with every export.
+    pub fn auth(mut self, credentials: &Credentials) -> Self {
+        self.headers.extend_from_slice(credentials.headers());
+        self
+    }
+
+    /// Sets `service.name` on the resource.
+    pub fn service_name(self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.resource("service.name", Value::String(name.into()))
+    }
+
+    /// Sets `key` on the resource the records are exported under.
+    pub fn resource(mut self, key: impl Into<Cow<'static, str>>, value: impl Into<Value>) -> Self {
+        let key = key.into();
+        self.resource.retain(|(k, _)| *k != key);
+        self.resource.push((key, value.into()));
+        self
+    }
+
+    /// The body `export` sends for `records`.
+    pub fn encode(&self, records: &[SpanRecord]) -> Vec<u8> {
+        let spans: Vec<_> = records.iter().map(Span::from_record).collect();
+        let resource = self.resource_with(&resource::global());
+        match self.encoding {
+            Encoding::Protobuf => proto::encode(&resource, &spans),
+            Encoding::Json => json::encode(&resource, &spans),
+        }
+    }
+
+    /// The attributes of `global`, overridden by the ones set on the exporter.
+    fn resource_with(&self, global: &Resource) -> Vec<(Cow<'static, str>, Value)> {
+        let mut resource: Vec<_> = global
+            .attributes()
+            .iter()
+            .filter(|(key, _)| !self.resource.iter().any(|(k, _)| k == key))
+            .cloned()
+            .collect();
+        resource.extend(self.resource.iter().cloned());
+        resource
+    }
+}
+
+#[async_trait(?Send)]
+impl SpanExporter for OtlpExporter {
+    /// Sends `records`, if there are any.
+    async fn export(&self, records: Vec<SpanRecord>) -> worker::Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let body = compression::compress(&self.encode(&records), self.compression).await?;
+        let mut headers = self.headers.clone();
+        if let Some(encoding) = self.compression.content_encoding() {
+            headers.push(("content-encoding".to_owned(), encoding.to_owned()));
+        }
+        super::post(
+            &self.endpoint,
+            &headers,
+            self.encoding.content_type(),
+            &body,
+        )
+        .await
+    }
+}
+
+/// A record as OTLP sees it.
+struct Span {
+    trace_id: u128,
+    span_id: u64,
+    /// `0` for a root span.
+    parent_span_id: u64,
+    trace_state: String,
+    name: Cow<'static, str>,
+    kind: SpanKind,
+    start_time_unix_nano: u64,
+    end_time_unix_nano: u64,
+    attributes: Vec<(Cow<'static, str>, Value)>,
+    dropped_attributes_count: u32,
+    events: Vec<Event>,
+    dropped_events_count: u32,
+    links: Vec<Link>,
+    status: SpanStatus,
+}
+
+struct Event {
+    time_unix_nano: u64,
+    name: Cow<'static, str>,
+    attributes: Vec<(Cow<'static, str>, Value)>,
+}
+
+impl Span {
+    fn from_record(record: &SpanRecord) -> Self {
+        Span {
+            trace_id: record.trace_id.0,
+            span_id: record.span_id.0,
+            parent_span_id: record.parent_id.0,
+            trace_state: super::property(record, TRACE_STATE)
+                .unwrap_or_default()
+                .to_owned(),
+            name: record.name.clone(),
+            kind: SpanKind::of(record),
+            start_time_unix_nano: record.begin_time_unix_ns,
+            end_time_unix_nano: record.begin_time_unix_ns + record.duration_ns,
+            attributes: super::attributes(record),
+            dropped_attributes_count: super::count(record, DROPPED_PROPERTIES),
+            events: super::events(record)
+                .map(|event| Event {
+                    time_unix_nano: event.timestamp_unix_ns,
+                    name: event.name.clone(),
+                    attributes: value::typed(&event.properties),
+                })
+                .collect(),
+            dropped_events_count: super::count(record, DROPPED_EVENTS),
+            links: Link::of(record),
+            status: SpanStatus::of(record),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use minitrace::collector::{EventRecord, SpanId, TraceId};
+
+    use super::*;
+
+    fn record() -> SpanRecord {
+        SpanRecord {
+            trace_id: TraceId(1),
+            span_id: SpanId(2),
+            parent_id: SpanId(3),
+            begin_time_unix_ns: 1000,
+            duration_ns: 500,
+            name: "GET".into(),
+            properties: vec![
+                ("span.kind".into(), "server".into()),
+                ("__type.code".into(), "i64".into()),
+                ("code".into(), "200".into()),
+            ],
+            events: vec![EventRecord {
+                name: "retry".into(),
+                timestamp_unix_ns: 1200,
+                properties: Vec::new(),
+            }],
+        }
+    }
+
+    #[test]
+    fn encode_proto() {
+        let body = OtlpExporter::new("https://otlp.example.com/v1/traces")
+            .service_name("svc")
+            .encode(&[record()]);
+        let expected = [
+            // ExportTraceServiceRequest.resource_spans
+            &[0x0a, 0x91, 0x01][..],
+            // .resource, service.name = "svc"
+            &[0x0a, 0x17, 0x0a, 0x15, 0x0a, 0x0c],
+            b"service.name",
+            &[0x12, 0x05, 0x0a, 0x03],
+            b"svc",
+            // .scope_spans, .scope
+            &[0x12, 0x76, 0x0a, 0x14, 0x0a, 0x0b],
+            b"worker-rust",
+            &[0x12, 0x05],
+            b"0.1.0",
+            // .spans, trace id, span id, parent span id
+            &[0x12, 0x5e, 0x0a, 0x10],
+            &1u128.to_be_bytes(),
+            &[0x12, 0x08],
+            &2u64.to_be_bytes(),
+            &[0x22, 0x08],
+            &3u64.to_be_bytes(),
+            // name, kind = SPAN_KIND_SERVER
+            &[0x2a, 0x03],
+            b"GET",
+            &[0x30, 0x02],
+            // start and end time
+            &[0x39],
+            &1000u64.to_le_bytes(),
+            &[0x41],
+            &1500u64.to_le_bytes(),
+            // attributes, code = 200 as an int_value
+            &[0x4a, 0x0b, 0x0a, 0x04],
+            b"code",
+            &[0x12, 0x03, 0x18, 0xc8, 0x01],
+            // events
+            &[0x5a, 0x10, 0x09],
+            &1200u64.to_le_bytes(),
+            &[0x12, 0x05],
+            b"retry",
+        ]
+        .concat();
+        assert_eq!(body, expected);
+    }
+
+    #[test]
+    fn encode_json() {
+        let body = OtlpExporter::new("https://otlp.example.com/v1/traces")
+            .service_name("svc")
+            .encoding(Encoding::Json)
+            .encode(&[record()]);
+        let expected = concat!(
+            r#"{"resourceSpans":[{"#,
+            r#""resource":{"attributes":[{"key":"service.name","value":{"stringValue":"svc"}}]},"#,
+            r#""scopeSpans":[{"scope":{"name":"worker-rust","version":"0.1.0"},"spans":[{"#,
+            r#""attributes":[{"key":"code","value":{"intValue":"200"}}],"#,
+            r#""endTimeUnixNano":"1500","#,
+            r#""events":[{"attributes":[],"name":"retry","timeUnixNano":"1200"}],"#,
+            r#""kind":2,"links":[],"name":"GET","parentSpanId":"0000000000000003","#,
+            r#""spanId":"0000000000000002","startTimeUnixNano":"1000","status":{},"#,
+            r#""traceId":"00000000000000000000000000000001"}]}]}]}"#,
+        );
+        assert_eq!(String::from_utf8(body).unwrap(), expected);
+    }
+}
@@ -0,0 +1,116 @@
+use std::borrow::Cow;
+
+use serde_json::{json, Map, Value as Json};
+
+use crate::{status::SpanStatus, value::Value};
+
+use super::{proto, Event, Span, SCOPE_NAME, SCOPE_VERSION};
+
+// The JSON mapping of the same messages `proto` writes
+// (https://opentelemetry.io/docs/specs/otlp/#json-protobuf-encoding): ids are hex rather
+// than base64, enums are their numbers, and 64-bit integers are strings.
+
+/// Encodes the spans of a single resource as an `ExportTraceServiceRequest`.
+pub(super) fn encode(resource: &[(Cow<'static, str>, Value)], spans: &[Span]) -> Vec<u8> {
+    let request = json!({
+        "resourceSpans": [{
+            "resource": { "attributes": attributes(resource) },
+            "scopeSpans": [{
+                "scope": { "name": SCOPE_NAME, "version": SCOPE_VERSION },
+                "spans": spans.iter().map(span).collect::<Vec<_>>(),
+            }],
+        }],
+    });
+    serde_json::to_vec(&request).unwrap_or_default()
+}
+
+fn span(span: &Span) -> Json {
+    let mut json = Map::new();
+    json.insert("traceId".into(), format!("{:032x}", span.trace_id).into());
+    json.insert("spanId".into(), format!("{:016x}", span.span_id).into());
+    if !span.trace_state.is_empty() {
+        json.insert("traceState".into(), span.trace_state.clone().into());
+    }
+    if span.parent_span_id != 0 {
+        json.insert(
+            "parentSpanId".into(),
+            format!("{:016x}", span.parent_span_id).into(),
+        );
+    }
+    json.insert("name".into(), span.name.as_ref().into());
+    json.insert("kind".into(), proto::kind(span.kind).into());
+    json.insert(
+        "startTimeUnixNano".into(),
+        span.start_time_unix_nano.to_string().into(),
+    );
+    json.insert(
+        "endTimeUnixNano".into(),
+        span.end_time_unix_nano.to_string().into(),
+    );
+    json.insert("attributes".into(), attributes(&span.attributes));
+    if span.dropped_attributes_count > 0 {
+        json.insert(
+            "droppedAttributesCount".into(),
+            span.dropped_attributes_count.into(),
+        );
+    }
+    json.insert(
+        "events".into(),
+        span.events.iter().map(event).collect::<Vec<_>>().into(),
+    );
+    if span.dropped_events_count > 0 {
+        json.insert(
+            "droppedEventsCount".into(),
+            span.dropped_events_count.into(),
+        );
+    }
+    json.insert(
+        "links".into(),
+        span.links
+            .iter()
+            .map(|link| {
+                json!({
+                    "traceId": format!("{:032x}", link.trace_id.0),
+                    "spanId": format!("{:016x}", link.span_id.0),
+                })
+            })
+            .collect::<Vec<_>>()
+            .into(),
+    );
+    json.insert(
+        "status".into(),
+        match &span.status {
+            SpanStatus::Unset => json!({}),
+            SpanStatus::Ok => json!({ "code": 1 }),
+            SpanStatus::Error(description) => json!({ "code": 2, "message": description }),
+        },
+    );
+    json.into()
+}
+
+fn event(event: &Event) -> Json {
+    json!({
+        "timeUnixNano": event.time_unix_nano.to_string(),
+        "name": event.name,
+        "attributes": attributes(&event.attributes),
+    })
+}
+
+fn attributes(attributes: &[(Cow<'static, str>, Value)]) -> Json {
+    attributes
+        .iter()
+        .map(|(key, value)| json!({ "key": key, "value": any_value(value) }))
+        .collect()
+}
+
+fn any_value(value: &Value) -> Json {
+    match value {
+        Value::String(v) => json!({ "stringValue": v }),
+        Value::Bool(v) => json!({ "boolValue": v }),
+        Value::I64(v) => json!({ "intValue": v.to_string() }),
+        Value::F64(v) => json!({ "doubleValue": v }),
+        Value::Array(values) => json!({
+            "arrayValue": { "values": values.iter().map(any_value).collect::<Vec<_>>() },
+        }),
+    }
+}
@@ -0,0 +1,184 @@
+use std::borrow::Cow;
+
+use crate::{kind::SpanKind, link::Link, status::SpanStatus, value::Value};
+
+use super::{Event, Span, SCOPE_NAME, SCOPE_VERSION};
+
+// Just enough of the protobuf wire format (https://protobuf.dev/programming-guides/encoding/)
+// to write an `ExportTraceServiceRequest`, after the field numbers of
+// `opentelemetry/proto/collector/trace/v1/trace_service.proto` and the files it imports.
+// Fields at their default value are left out, like any proto3 encoder does, except inside
+// the `AnyValue` oneof where the field itself is the value.
+
+const VARINT: u8 = 0;
+const FIXED64: u8 = 1;
+const LEN: u8 = 2;
+
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.buf.push(v as u8 | 0x80);
+            v >>= 7;
+        }
+        self.buf.push(v as u8);
+    }
+
+    fn key(&mut self, field: u32, wire_type: u8) {
+        self.varint(((field as u64) << 3) | wire_type as u64);
+    }
+
+    fn uint(&mut self, field: u32, v: u64) {
+        if v != 0 {
+            self.key(field, VARINT);
+            self.varint(v);
+        }
+    }
+
+    fn fixed64(&mut self, field: u32, v: u64) {
+        if v != 0 {
+            self.key(field, FIXED64);
+            self.buf.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+
+    fn bytes(&mut self, field: u32, v: &[u8]) {
+        if !v.is_empty() {
+            self.key(field, LEN);
+            self.varint(v.len() as u64);
+            self.buf.extend_from_slice(v);
+        }
+    }
+
+    fn string(&mut self, field: u32, v: &str) {
+        self.bytes(field, v.as_bytes());
+    }
+
+    /// Writes the message `write` encodes as field `field`, even when it's empty.
+    fn message(&mut self, field: u32, write: impl FnOnce(&mut Writer)) {
+        let mut message = Writer::default();
+        write(&mut message);
+        self.key(field, LEN);
+        self.varint(message.buf.len() as u64);
+        self.buf.extend_from_slice(&message.buf);
+    }
+}
+
+/// Encodes the spans of a single resource as an `ExportTraceServiceRequest`.
+pub(super) fn encode(resource: &[(Cow<'static, str>, Value)], spans: &[Span]) -> Vec<u8> {
+    let mut request = Writer::default();
+    // ExportTraceServiceRequest.resource_spans
+    request.message(1, |resource_spans| {
+        // ResourceSpans.resource
+        resource_spans.message(1, |r| attributes(r, 1, resource));
+        // ResourceSpans.scope_spans
+        resource_spans.message(2, |scope_spans| {
+            scope_spans.message(1, |scope| {
+                scope.string(1, SCOPE_NAME);
+                scope.string(2, SCOPE_VERSION);
+            });
+            for span in spans {
+                scope_spans.message(2, |w| write_span(w, span));
+            }
+        });
+    });
+    request.buf
+}
+
+fn write_span(w: &mut Writer, span: &Span) {
+    w.bytes(1, &span.trace_id.to_be_bytes());
+    w.bytes(2, &span.span_id.to_be_bytes());
+    w.string(3, &span.trace_state);
+    if span.parent_span_id != 0 {
+        w.bytes(4, &span.parent_span_id.to_be_bytes());
+    }
+    w.string(5, &span.name);
+    w.uint(6, kind(span.kind));
+    w.fixed64(7, span.start_time_unix_nano);
+    w.fixed64(8, span.end_time_unix_nano);
+    attributes(w, 9, &span.attributes);
+    w.uint(10, span.dropped_attributes_count as u64);
+    for event in &span.events {
+        w.message(11, |w| write_event(w, event));
+    }
+    w.uint(12, span.dropped_events_count as u64);
+    for link in &span.links {
+        w.message(13, |w| write_link(w, link));
+    }
+    if span.status != SpanStatus::Unset {
+        w.message(15, |w| write_status(w, &span.status));
+    }
+}
+
+fn write_event(w: &mut Writer, event: &Event) {
+    w.fixed64(1, event.time_unix_nano);
+    w.string(2, &event.name);
+    attributes(w, 3, &event.attributes);
+}
+
+fn write_link(w: &mut Writer, link: &Link) {
+    w.bytes(1, &link.trace_id.0.to_be_bytes());
+    w.bytes(2, &link.span_id.0.to_be_bytes());
+}
+
+fn write_status(w: &mut Writer, status: &SpanStatus) {
+    match status {
+        SpanStatus::Unset => {}
+        SpanStatus::Ok => w.uint(3, 1),
+        SpanStatus::Error(description) => {
+            w.string(2, description);
+            w.uint(3, 2);
+        }
+    }
+}
+
+/// Writes each of `attributes` as a `KeyValue` in field `field`.
+fn attributes(w: &mut Writer, field: u32, attributes: &[(Cow<'static, str>, Value)]) {
+    for (key, value) in attributes {
+        w.message(field, |kv| {
+            kv.string(1, key);
+            kv.message(2, |v| any_value(v, value));
+        });
+    }
+}
+
+fn any_value(w: &mut Writer, value: &Value) {
+    match value {
+        Value::String(v) => {
+            w.key(1, LEN);
+            w.varint(v.len() as u64);
+            w.buf.extend_from_slice(v.as_bytes());
+        }
+        Value::Bool(v) => {
+            w.key(2, VARINT);
+            w.varint(*v as u64);
+        }
+        Value::I64(v) => {
+            w.key(3, VARINT);
+            w.varint(*v as u64);
+        }
+        Value::F64(v) => {
+            w.key(4, FIXED64);
+            w.buf.extend_from_slice(&v.to_le_bytes());
+        }
+        Value::Array(values) => w.message(5, |array| {
+            for value in values {
+                array.message(1, |v| any_value(v, value));
+            }
+        }),
+    }
+}
+
+pub(super) fn kind(kind: SpanKind) -> u64 {
+    match kind {
+        SpanKind::Internal => 1,
+        SpanKind::Server => 2,
+        SpanKind::Client => 3,
+        SpanKind::Producer => 4,
+        SpanKind::Consumer => 5,
+    }
+}
@@ -0,0 +1,99 @@
+use async_trait::async_trait;
+use minitrace::collector::SpanRecord;
+use worker::{MessageBatch, Queue};
+
+use super::{tail, SpanExporter};
+
+// Puts the records on a queue instead of exporting them, for a consumer to export with
+// `consume`: sending a message is about as fast as a subrequest gets, and the consumer gets
+// up to a hundred batches at once, so the backend sees a fraction of the requests. When the
+// backend is down the messages are retried by the queue, not by the worker serving requests.
+//
+// ```ignore
+// // The producer:
+// FetchTracing::new(export::to(QueueExporter::from_env(&env, "TRACES")?))
+//
+// // The consumer, which may well be the same worker:
+// #[event(queue)]
+// async fn traces(batch: MessageBatch<String>, env: Env, _ctx: Context) -> Result<()> {
+//     export::queue::consume(&batch, &otlp).await
+// }
+// ```
+//
+// Messages are in the lines of `TailExporter`, as many traces as fit in one message each.
+// The consumer shouldn't be traced with `QueueTracing`, or every export makes more records to
+// export.
+
+/// The most bytes of lines in a message, queues taking up to 128 KB.
+const MAX_MESSAGE_LEN: usize = 120 * 1024;
+
+/// Exports records to a queue.
+pub struct QueueExporter {
+    queue: Queue,
+}
+
+impl QueueExporter {
+    /// Sends to `queue`.
+    pub fn new(queue: Queue) -> Self {
+        Self { queue }
+    }
+
+    /// Sends to the queue bound as `binding` in `env`.
+    pub fn from_env(env: &worker::Env, binding: &str) -> worker::Result<Self> {
+        Ok(Self::new(env.queue(binding)?))
+    }
+
+    /// The messages `export` sends for `records`.
+    pub fn encode(&self, records: &[SpanRecord]) -> Vec<String> {
+        let mut messages = Vec::new();
+        let mut message = String::new();
+        for line in tail::TailExporter::new().encode(records) {
+            if !message.is_empty() && message.len() + 1 + line.len() > MAX_MESSAGE_LEN {
+                messages.push(std::mem::take(&mut message));
+            }
+            if !message.is_empty() {
+                message.push('\n');
+            }
+            message.push_str(&line);
+        }
+        if !message.is_empty() {
+            messages.push(message);
+        }
+        messages
+    }
+}
+
+#[async_trait(?Send)]
+impl SpanExporter for QueueExporter {
+    /// Sends `records`, if there are any.
+    async fn export(&self, records: Vec<SpanRecord>) -> worker::Result<()> {
+        for message in self.encode(&records) {
+            self.queue.send(&message).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Exports the records of every message of `batch`, sent by a `QueueExporter`, all at once.
+/// Failing makes the queue retry the whole batch if it's returned from the consumer.
+pub async fn consume(
+    batch: &MessageBatch<String>,
+    exporter: &impl SpanExporter,
+) -> worker::Result<()> {
+    let records: Vec<_> = batch
+        .iter()
+        .filter_map(|message| message.ok())
+        .flat_map(|message| {
+            message
+                .body
+                .lines()
+                .filter_map(tail::decode)
+                .flatten()
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    if records.is_empty() {
+        return Ok(());
+    }
+    exporter.export(records).await
+}
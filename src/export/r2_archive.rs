@@ -0,0 +1,105 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use minitrace::collector::SpanRecord;
+use worker::{Bucket, Date, HttpMetadata};
+
+use super::{Encoding, OtlpExporter, SpanExporter};
+
+// Archives the records in R2, where keeping every trace for months costs next to nothing.
+// Each export writes an object of newline delimited JSON, a line per trace, each line being
+// the OTLP/JSON `ExportTraceServiceRequest` of the trace. So any line can be replayed as is,
+// to any collector taking OTLP/JSON, and the objects can be queried in place by tools reading
+// NDJSON, e.g. DuckDB.
+//
+// Objects are partitioned by the hour they were written in, Hive style:
+//
+// ```text
+// traces/dt=2024-05-01/hour=13/1714568400123-9f86d081.ndjson
+// ```
+
+/// Archives records in an R2 bucket.
+pub struct R2Archive {
+    bucket: Bucket,
+    prefix: String,
+    otlp: OtlpExporter,
+}
+
+impl R2Archive {
+    /// Archives in `bucket`, under `traces/`, as `service_name`.
+    pub fn new(bucket: Bucket, service_name: impl Into<String>) -> Self {
+        Self {
+            bucket,
+            prefix: "traces/".to_owned(),
+            otlp: OtlpExporter::new(String::new())
+                .encoding(Encoding::Json)
+                .service_name(service_name.into()),
+        }
+    }
+
+    /// Archives in the bucket bound as `binding` in `env`.
+    pub fn from_env(
+        env: &worker::Env,
+        binding: &str,
+        service_name: impl Into<String>,
+    ) -> worker::Result<Self> {
+        Ok(Self::new(env.bucket(binding)?, service_name))
+    }
+
+    /// What the keys of the objects start with.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// The object `export` writes for `records`.
+    pub fn encode(&self, records: &[SpanRecord]) -> Vec<u8> {
+        let mut traces: BTreeMap<u128, Vec<SpanRecord>> = BTreeMap::new();
+        for record in records {
+            traces
+                .entry(record.trace_id.0)
+                .or_default()
+                .push(record.clone());
+        }
+        let mut out = Vec::new();
+        for trace in traces.values() {
+            out.extend(self.otlp.encode(trace));
+            out.push(b'\n');
+        }
+        out
+    }
+
+    /// The key of an object written at `unix_ms`.
+    fn key(&self, unix_ms: u64) -> String {
+        let timestamp = super::rfc3339(unix_ms * 1_000_000);
+        let mut id = [0; 4];
+        getrandom::getrandom(&mut id).expect("failed to generate a random object id");
+        format!(
+            "{}dt={}/hour={}/{unix_ms}-{:08x}.ndjson",
+            self.prefix,
+            &timestamp[..10],
+            &timestamp[11..13],
+            u32::from_ne_bytes(id)
+        )
+    }
+}
+
+#[async_trait(?Send)]
+impl SpanExporter for R2Archive {
+    /// Writes `records` to an object of their own, if there are any.
+    async fn export(&self, records: Vec<SpanRecord>) -> worker::Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let key = self.key(Date::now().as_millis());
+        self.bucket
+            .put(&key, self.encode(&records))
+            .http_metadata(HttpMetadata {
+                content_type: Some("application/x-ndjson".to_owned()),
+                ..HttpMetadata::default()
+            })
+            .execute()
+            .await?;
+        Ok(())
+    }
+}
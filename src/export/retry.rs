@@ -0,0 +1,117 @@
+use std::{cell::Cell, future::Future, time::Duration};
+
+use worker::{Date, Delay, Response};
+
+// Exports that fail for a reason that may go away (a network error, `429` or a `5xx`) are
+// retried, waiting twice as long before each attempt as before the previous one, or as long
+// as the backend asks with `Retry-After`. The export runs in `wait_until`, which the runtime
+// only lets run for so long after the response is sent, so retries stop once `budget` has
+// elapsed, whatever is left of `max_retries`: an export that can't make it in time is given
+// up rather than cut off mid-request. Given up exports are counted, see `stats`.
+
+/// How exports are retried, set with `set_global`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Retry {
+    /// Retries after the first attempt, `0` to never retry.
+    pub max_retries: u32,
+    /// How long to wait before the first retry.
+    pub initial_backoff: Duration,
+    /// The longest wait between two attempts.
+    pub max_backoff: Duration,
+    /// How long an export can take overall, retries included, before it's given up.
+    pub budget: Duration,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+            budget: Duration::from_secs(10),
+        }
+    }
+}
+
+impl Retry {
+    /// No retries at all.
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+}
+
+/// What happened to the exports of this isolate so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Attempts made after a first one failed.
+    pub retries: u64,
+    /// Exports that still failed when out of retries or budget.
+    pub given_up: u64,
+}
+
+thread_local! {
+    static GLOBAL: Cell<Retry> = Cell::new(Retry::default());
+    static STATS: Cell<Stats> = Cell::new(Stats::default());
+}
+
+/// Sets how every exporter retries.
+pub fn set_global(retry: Retry) {
+    GLOBAL.with(|global| global.set(retry));
+}
+
+/// The retries made and exports given up in this isolate.
+pub fn stats() -> Stats {
+    STATS.with(Cell::get)
+}
+
+fn count(update: impl FnOnce(&mut Stats)) {
+    STATS.with(|stats| {
+        let mut current = stats.get();
+        update(&mut current);
+        stats.set(current);
+    });
+}
+
+/// Runs `attempt` until it gets a response that's not worth retrying, or the global `Retry`
+/// says to stop.
+pub(crate) async fn send<F, Fut>(attempt: F) -> worker::Result<Response>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = worker::Result<Response>>,
+{
+    let retry = GLOBAL.with(Cell::get);
+    let start = Date::now().as_millis();
+    let mut backoff = retry.initial_backoff;
+    let mut retries = 0;
+    loop {
+        let res = attempt().await;
+        let wait = match &res {
+            Ok(response) if !is_transient(response.status_code()) => return res,
+            Ok(response) => retry_after(response).unwrap_or(backoff),
+            Err(_) => backoff,
+        };
+        let elapsed = Duration::from_millis(Date::now().as_millis().saturating_sub(start));
+        if retries >= retry.max_retries || elapsed + wait >= retry.budget {
+            count(|stats| stats.given_up += 1);
+            return res;
+        }
+
+        Delay::from(wait.min(retry.max_backoff)).await;
+        retries += 1;
+        count(|stats| stats.retries += 1);
+        backoff = (backoff * 2).min(retry.max_backoff);
+    }
+}
+
+fn is_transient(status: u16) -> bool {
+    status == 429 || (500..=599).contains(&status)
+}
+
+/// The wait a `Retry-After` header asks for, when it's in seconds.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let seconds = response.headers().get("retry-after").ok()??;
+    seconds.trim().parse().ok().map(Duration::from_secs)
+}
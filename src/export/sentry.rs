@@ -0,0 +1,259 @@
+use async_trait::async_trait;
+use minitrace::collector::SpanRecord;
+use serde_json::{json, Map, Value as Json};
+use worker::Url;
+
+use crate::{kind::SpanKind, status::SpanStatus};
+
+use super::SpanExporter;
+
+// Sends the records to Sentry as transactions (https://develop.sentry.dev/sdk/envelopes/),
+// so performance data ends up next to the errors Sentry already has for the worker. A
+// transaction is a local root with every span below it, see `transactions`; each one goes in
+// an envelope of its own.
+//
+// Sentry spans have an `op`, the kind of operation (`http.server`, `db`...), and a
+// description, which is the span's name. They have no events, so those are left out.
+
+const CLIENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// Exports records to a Sentry project.
+#[derive(Clone, Debug)]
+pub struct SentryExporter {
+    url: String,
+    auth: String,
+    environment: Option<String>,
+    release: Option<String>,
+}
+
+impl SentryExporter {
+    /// Exports to the project of `dsn`, e.g. `https://<key>@o0.ingest.sentry.io/<project>`.
+    pub fn new(dsn: &str) -> worker::Result<Self> {
+        let dsn = Url::parse(dsn)?;
+        let invalid = || worker::Error::RustError(format!("invalid Sentry DSN: {dsn}"));
+        let project = dsn
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .filter(|project| !project.is_empty())
+            .ok_or_else(invalid)?;
+        if dsn.username().is_empty() {
+            return Err(invalid());
+        }
+        let host = dsn.host_str().ok_or_else(invalid)?;
+        let port = dsn
+            .port()
+            .map(|port| format!(":{port}"))
+            .unwrap_or_default();
+        Ok(Self {
+            url: format!("{}://{host}{port}/api/{project}/envelope/", dsn.scheme()),
+            auth: format!(
+                "Sentry sentry_version=7, sentry_key={}, sentry_client={CLIENT}",
+                dsn.username()
+            ),
+            environment: None,
+            release: None,
+        })
+    }
+
+    /// Reports the transactions as happening in `environment`, e.g. `production`.
+    pub fn environment(mut self, environment: impl Into<String>) -> Self {
+        self.environment = Some(environment.into());
+        self
+    }
+
+    /// Reports the transactions as happening in `release`.
+    pub fn release(mut self, release: impl Into<String>) -> Self {
+        self.release = Some(release.into());
+        self
+    }
+
+    /// The envelopes `export` sends for `records`, one per transaction.
+    pub fn encode(&self, records: &[SpanRecord]) -> Vec<Vec<u8>> {
+        super::transactions(records)
+            .into_iter()
+            .map(|(root, spans)| self.envelope(root, &spans))
+            .collect()
+    }
+
+    fn envelope(&self, root: &SpanRecord, spans: &[&SpanRecord]) -> Vec<u8> {
+        let event_id = event_id();
+        let mut transaction = json!({
+            "type": "transaction",
+            "event_id": event_id,
+            "platform": "other",
+            "transaction": root.name,
+            "start_timestamp": seconds(root.begin_time_unix_ns),
+            "timestamp": seconds(root.begin_time_unix_ns + root.duration_ns),
+            "contexts": { "trace": span(root) },
+            "spans": spans.iter().map(|record| span(record)).collect::<Vec<_>>(),
+        });
+        if let Some(object) = transaction.as_object_mut() {
+            if let Some(environment) = &self.environment {
+                object.insert("environment".into(), environment.as_str().into());
+            }
+            if let Some(release) = &self.release {
+                object.insert("release".into(), release.as_str().into());
+            }
+        }
+
+        let payload = serde_json::to_vec(&transaction).unwrap_or_default();
+        let mut envelope = json!({ "event_id": event_id }).to_string().into_bytes();
+        envelope.push(b'\n');
+        envelope.extend(
+            json!({ "type": "transaction", "length": payload.len() })
+                .to_string()
+                .into_bytes(),
+        );
+        envelope.push(b'\n');
+        envelope.extend(payload);
+        envelope.push(b'\n');
+        envelope
+    }
+}
+
+#[async_trait(?Send)]
+impl SpanExporter for SentryExporter {
+    /// Sends `records`, if there are any.
+    async fn export(&self, records: Vec<SpanRecord>) -> worker::Result<()> {
+        let headers = [("x-sentry-auth".to_owned(), self.auth.clone())];
+        for envelope in self.encode(&records) {
+            super::post(
+                &self.url,
+                &headers,
+                "application/x-sentry-envelope",
+                &envelope,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+/// A span, or the trace context of the transaction when it's the root.
+fn span(record: &SpanRecord) -> Json {
+    let data: Map<_, _> = super::attributes(record)
+        .into_iter()
+        .map(|(key, value)| (key.into_owned(), value.to_json()))
+        .collect();
+    let mut span = json!({
+        "trace_id": format!("{:032x}", record.trace_id.0),
+        "span_id": format!("{:016x}", record.span_id.0),
+        "op": op(record),
+        "description": record.name,
+        "start_timestamp": seconds(record.begin_time_unix_ns),
+        "timestamp": seconds(record.begin_time_unix_ns + record.duration_ns),
+        "status": if SpanStatus::of(record).is_error() { "internal_error" } else { "ok" },
+        "data": data,
+    });
+    if record.parent_id.0 != 0 {
+        if let Some(span) = span.as_object_mut() {
+            span.insert(
+                "parent_span_id".into(),
+                format!("{:016x}", record.parent_id.0).into(),
+            );
+        }
+    }
+    span
+}
+
+fn op(record: &SpanRecord) -> &'static str {
+    let has = |key| super::property(record, key).is_some();
+    match SpanKind::of(record) {
+        _ if has("db.system") => "db",
+        SpanKind::Server if has("http.request.method") => "http.server",
+        SpanKind::Client if has("http.request.method") => "http.client",
+        SpanKind::Producer => "queue.publish",
+        SpanKind::Consumer => "queue.process",
+        _ => "function",
+    }
+}
+
+fn seconds(unix_ns: u64) -> f64 {
+    unix_ns as f64 / 1e9
+}
+
+fn event_id() -> String {
+    let mut buf = [0; 16];
+    getrandom::getrandom(&mut buf).expect("failed to generate a random event id");
+    format!("{:032x}", u128::from_ne_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use minitrace::collector::{SpanId, TraceId};
+
+    use super::*;
+
+    /// A span of trace 1, from `begin_ms` for `duration_ms`.
+    fn record(
+        (span_id, parent_id): (u64, u64),
+        (begin_ms, duration_ms): (u64, u64),
+        name: &str,
+        properties: &[(&str, &str)],
+    ) -> SpanRecord {
+        SpanRecord {
+            trace_id: TraceId(1),
+            span_id: SpanId(span_id),
+            parent_id: SpanId(parent_id),
+            begin_time_unix_ns: begin_ms * 1_000_000,
+            duration_ns: duration_ms * 1_000_000,
+            name: name.to_owned().into(),
+            properties: properties
+                .iter()
+                .map(|(k, v)| (k.to_string().into(), v.to_string().into()))
+                .collect(),
+            events: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn encode() {
+        let records = [
+            record(
+                (2, 0),
+                (1_000, 500),
+                "GET /users",
+                &[("span.kind", "server"), ("http.request.method", "GET")],
+            ),
+            record(
+                (3, 2),
+                (1_100, 200),
+                "SELECT",
+                &[
+                    ("span.kind", "client"),
+                    ("db.system", "d1"),
+                    ("otel.status_code", "ERROR"),
+                ],
+            ),
+        ];
+        let envelopes = SentryExporter::new("https://key@o0.ingest.sentry.io/42")
+            .unwrap()
+            .environment("production")
+            .encode(&records);
+        assert_eq!(envelopes.len(), 1);
+
+        let envelope = String::from_utf8(envelopes[0].clone()).unwrap();
+        let event_id = &envelope[13..45];
+        let payload = concat!(
+            r#"{"contexts":{"trace":{"data":{"http.request.method":"GET"},"#,
+            r#""description":"GET /users","op":"http.server","span_id":"0000000000000002","#,
+            r#""start_timestamp":1.0,"status":"ok","timestamp":1.5,"#,
+            r#""trace_id":"00000000000000000000000000000001"}},"#,
+            r#""environment":"production","event_id":"{event_id}","platform":"other","#,
+            r#""spans":[{"data":{"db.system":"d1"},"description":"SELECT","op":"db","#,
+            r#""parent_span_id":"0000000000000002","span_id":"0000000000000003","#,
+            r#""start_timestamp":1.1,"status":"internal_error","timestamp":1.3,"#,
+            r#""trace_id":"00000000000000000000000000000001"}],"#,
+            r#""start_timestamp":1.0,"timestamp":1.5,"transaction":"GET /users","#,
+            r#""type":"transaction"}"#,
+        )
+        .replace("{event_id}", event_id);
+        assert_eq!(
+            envelope,
+            format!(
+                "{{\"event_id\":\"{event_id}\"}}\n{{\"length\":{},\"type\":\"transaction\"}}\n{payload}\n",
+                payload.len()
+            )
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+
+use minitrace::collector::{SpanId, SpanRecord};
+use serde_json::{json, Value as Json};
+
+// A speedscope profile (https://github.com/jlfwong/speedscope/wiki/Importing-from-custom-sources)
+// in the evented format: a profile per local root, where every span is a frame opened when it
+// starts and closed when it ends. Frames are shared by name, so the "Left Heavy" view adds up
+// the time spent in each.
+//
+// An evented profile is a single stack, spans running concurrently can't overlap in it. A
+// span starting before its previous sibling ended is shifted to start when it did, and every
+// span is cut off where its parent ends.
+
+const SCHEMA: &str = "https://www.speedscope.app/file-format-schema.json";
+
+/// Encodes `records` as a speedscope JSON file.
+pub fn encode(records: &[SpanRecord]) -> Vec<u8> {
+    let (roots, children) = super::tree(records);
+    let mut frames = Frames::default();
+    let profiles: Vec<_> = roots
+        .into_iter()
+        .map(|root| {
+            let mut events = Vec::new();
+            let start = root.begin_time_unix_ns;
+            let end = start + root.duration_ns;
+            let mut profile = Profile {
+                children: &children,
+                frames: &mut frames,
+                events: &mut events,
+                origin: start,
+            };
+            profile.span(root, start, end);
+            json!({
+                "type": "evented",
+                "name": root.name,
+                "unit": "microseconds",
+                "startValue": 0,
+                "endValue": micros(root.duration_ns),
+                "events": events,
+            })
+        })
+        .collect();
+
+    let file = json!({
+        "$schema": SCHEMA,
+        "exporter": concat!(env!("CARGO_PKG_NAME"), "@", env!("CARGO_PKG_VERSION")),
+        "shared": { "frames": frames.names.iter().map(|name| json!({ "name": name })).collect::<Vec<_>>() },
+        "profiles": profiles,
+    });
+    serde_json::to_vec(&file).unwrap_or_default()
+}
+
+#[derive(Default)]
+struct Frames {
+    names: Vec<String>,
+    indices: HashMap<String, usize>,
+}
+
+impl Frames {
+    fn index(&mut self, name: &str) -> usize {
+        if let Some(index) = self.indices.get(name) {
+            return *index;
+        }
+        self.names.push(name.to_owned());
+        self.indices.insert(name.to_owned(), self.names.len() - 1);
+        self.names.len() - 1
+    }
+}
+
+struct Profile<'a> {
+    children: &'a HashMap<SpanId, Vec<&'a SpanRecord>>,
+    frames: &'a mut Frames,
+    events: &'a mut Vec<Json>,
+    origin: u64,
+}
+
+impl Profile<'_> {
+    /// Opens `record` no earlier than `not_before`, closes it no later than `not_after`, and
+    /// returns when it was closed.
+    fn span(&mut self, record: &SpanRecord, not_before: u64, not_after: u64) -> u64 {
+        let frame = self.frames.index(&record.name);
+        let open = record.begin_time_unix_ns.clamp(not_before, not_after);
+        let close = (record.begin_time_unix_ns + record.duration_ns).clamp(open, not_after);
+        self.event("O", frame, open);
+
+        let mut cursor = open;
+        let children = self.children;
+        for child in children.get(&record.span_id).into_iter().flatten() {
+            cursor = self.span(child, cursor, close);
+        }
+
+        self.event("C", frame, close);
+        close
+    }
+
+    fn event(&mut self, kind: &str, frame: usize, unix_ns: u64) {
+        self.events.push(json!({
+            "type": kind,
+            "frame": frame,
+            "at": micros(unix_ns - self.origin),
+        }));
+    }
+}
+
+fn micros(ns: u64) -> f64 {
+    ns as f64 / 1e3
+}
@@ -0,0 +1,158 @@
+use std::{borrow::Cow, collections::BTreeMap};
+
+use async_trait::async_trait;
+use minitrace::collector::{EventRecord, SpanId, SpanRecord, TraceId};
+use serde::{Deserialize, Serialize};
+
+use super::SpanExporter;
+
+// Logs every trace as one compact JSON line, for a tail worker to pick up from the logs of
+// this one and export (see `crate::tail`, behind the `tail` feature). Logging costs next to
+// nothing, so the worker handling requests never waits on a backend, not even in
+// `wait_until`:
+//
+// ```text
+// minitrace:{"v":1,"spans":[{"t":"0af7…","s":"b7ad…","p":"0000…","b":1700000000000000000,"d":1200000,"n":"GET /","a":[["http.request.method","GET"]],"e":[]}]}
+// ```
+//
+// Ids are hex, times are nanoseconds, `a` are the properties and `e` the events, each event
+// being `{"n":name,"t":timestamp,"a":properties}`. Lines from a newer `v` than `VERSION` are
+// skipped by `decode`, so the tail worker has to be updated first.
+//
+// The runtime truncates log lines past a certain size, a trace too large for one is lost.
+
+/// What every line starts with, telling it apart from the other logs.
+pub const PREFIX: &str = "minitrace:";
+/// The version of the schema of the lines.
+pub const VERSION: u32 = 1;
+
+/// Logs records for a tail worker to export.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TailExporter;
+
+impl TailExporter {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// The lines `export` logs, one per trace.
+    pub fn encode(&self, records: &[SpanRecord]) -> Vec<String> {
+        let mut traces: BTreeMap<u128, Vec<Span>> = BTreeMap::new();
+        for record in records {
+            traces
+                .entry(record.trace_id.0)
+                .or_default()
+                .push(Span::from_record(record));
+        }
+        traces
+            .into_values()
+            .filter_map(|spans| {
+                let line = Line { v: VERSION, spans };
+                Some(format!("{PREFIX}{}", serde_json::to_string(&line).ok()?))
+            })
+            .collect()
+    }
+}
+
+#[async_trait(?Send)]
+impl SpanExporter for TailExporter {
+    /// Logs `records`, if there are any.
+    async fn export(&self, records: Vec<SpanRecord>) -> worker::Result<()> {
+        for line in self.encode(&records) {
+            worker::console_log!("{line}");
+        }
+        Ok(())
+    }
+}
+
+/// The records of a line logged by `TailExporter`, `None` if it's not one or can't be read.
+pub fn decode(line: &str) -> Option<Vec<SpanRecord>> {
+    let line: Line = serde_json::from_str(line.strip_prefix(PREFIX)?).ok()?;
+    if line.v > VERSION {
+        return None;
+    }
+    line.spans.into_iter().map(Span::into_record).collect()
+}
+
+#[derive(Serialize, Deserialize)]
+struct Line {
+    v: u32,
+    spans: Vec<Span>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Span {
+    t: String,
+    s: String,
+    p: String,
+    b: u64,
+    d: u64,
+    n: String,
+    a: Vec<(String, String)>,
+    e: Vec<Event>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Event {
+    n: String,
+    t: u64,
+    a: Vec<(String, String)>,
+}
+
+impl Span {
+    fn from_record(record: &SpanRecord) -> Self {
+        Self {
+            t: format!("{:032x}", record.trace_id.0),
+            s: format!("{:016x}", record.span_id.0),
+            p: format!("{:016x}", record.parent_id.0),
+            b: record.begin_time_unix_ns,
+            d: record.duration_ns,
+            n: record.name.to_string(),
+            a: pairs(&record.properties),
+            e: record
+                .events
+                .iter()
+                .map(|event| Event {
+                    n: event.name.to_string(),
+                    t: event.timestamp_unix_ns,
+                    a: pairs(&event.properties),
+                })
+                .collect(),
+        }
+    }
+
+    fn into_record(self) -> Option<SpanRecord> {
+        Some(SpanRecord {
+            trace_id: TraceId(u128::from_str_radix(&self.t, 16).ok()?),
+            span_id: SpanId(u64::from_str_radix(&self.s, 16).ok()?),
+            parent_id: SpanId(u64::from_str_radix(&self.p, 16).ok()?),
+            begin_time_unix_ns: self.b,
+            duration_ns: self.d,
+            name: self.n.into(),
+            properties: owned(self.a),
+            events: self
+                .e
+                .into_iter()
+                .map(|event| EventRecord {
+                    name: event.n.into(),
+                    timestamp_unix_ns: event.t,
+                    properties: owned(event.a),
+                })
+                .collect(),
+        })
+    }
+}
+
+fn pairs(properties: &[(Cow<'static, str>, Cow<'static, str>)]) -> Vec<(String, String)> {
+    properties
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+fn owned(pairs: Vec<(String, String)>) -> Vec<(Cow<'static, str>, Cow<'static, str>)> {
+    pairs
+        .into_iter()
+        .map(|(key, value)| (key.into(), value.into()))
+        .collect()
+}
@@ -0,0 +1,24 @@
+use super::{otlp::Encoding, Compression, OtlpExporter};
+
+// Grafana Cloud takes OTLP on its gateway, authenticated with the numeric id of the stack's
+// OTLP instance and an access policy token, which the gateway wants as basic auth. The
+// gateway's URL (`https://otlp-gateway-prod-<region>.grafana.net/otlp`) is on the stack's
+// OpenTelemetry page. A self-hosted Tempo takes plain OTLP, see `OtlpExporter` directly.
+//
+// ```ignore
+// let exporter = tempo::config(
+//     "https://otlp-gateway-prod-eu-west-2.grafana.net/otlp",
+//     "123456",
+//     &env.secret("GRAFANA_TOKEN")?.to_string(),
+// )
+// .service_name("my-worker");
+// ```
+
+/// An `OtlpExporter` sending gzipped protobuf to the Grafana Cloud OTLP gateway at
+/// `gateway_url`.
+pub fn config(gateway_url: &str, instance_id: &str, api_key: &str) -> OtlpExporter {
+    OtlpExporter::new(format!("{}/v1/traces", gateway_url.trim_end_matches('/')))
+        .encoding(Encoding::Protobuf)
+        .compression(Compression::Gzip)
+        .header("authorization", super::basic_auth(instance_id, api_key))
+}
@@ -0,0 +1,104 @@
+use std::collections::{BTreeMap, HashMap};
+
+use async_trait::async_trait;
+use minitrace::collector::SpanRecord;
+
+use super::{
+    fanout::{join_all, LocalBoxFuture},
+    SpanExporter,
+};
+use crate::tenant::TENANT_ID;
+
+// Sends each trace to the exporter of the tenant it's for, as recorded by `tenant::record`,
+// the traces of tenants without one of their own going to the fallback, if any, and nowhere
+// otherwise. A trace is for whichever tenant one of its spans is for: the tenant of an
+// invocation is on every span of it, so all of its records go to the same place.
+//
+// The exports of different tenants run concurrently, and fail independently, like the ones of
+// a `Fanout`.
+
+/// Exports records to the exporter of their tenant.
+#[derive(Default)]
+pub struct TenantRouter {
+    tenants: HashMap<String, Box<dyn SpanExporter>>,
+    fallback: Option<Box<dyn SpanExporter>>,
+}
+
+impl TenantRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sends the traces of `tenant` to `exporter`.
+    pub fn tenant(mut self, tenant: impl Into<String>, exporter: impl SpanExporter + 'static) -> Self {
+        self.tenants.insert(tenant.into(), Box::new(exporter));
+        self
+    }
+
+    /// Sends the traces of every other tenant, or without one, to `exporter`.
+    pub fn fallback(mut self, exporter: impl SpanExporter + 'static) -> Self {
+        self.fallback = Some(Box::new(exporter));
+        self
+    }
+}
+
+#[async_trait(?Send)]
+impl SpanExporter for TenantRouter {
+    /// Sends the traces of `records` to their tenant's exporter, each failure being logged.
+    /// Fails if any of them failed, once they're all done.
+    async fn export(&self, records: Vec<SpanRecord>) -> worker::Result<()> {
+        let mut tenants: HashMap<u128, &str> = HashMap::new();
+        for record in &records {
+            if let Some(tenant) = super::property(record, TENANT_ID) {
+                tenants.entry(record.trace_id.0).or_insert(tenant);
+            }
+        }
+        let tenants: HashMap<u128, String> = tenants
+            .into_iter()
+            .map(|(trace_id, tenant)| (trace_id, tenant.to_owned()))
+            .collect();
+
+        // `None` for the fallback.
+        let mut routes: BTreeMap<Option<&str>, Vec<SpanRecord>> = BTreeMap::new();
+        for record in records {
+            let route = tenants
+                .get(&record.trace_id.0)
+                .map(String::as_str)
+                .filter(|tenant| self.tenants.contains_key(*tenant));
+            routes.entry(route).or_default().push(record);
+        }
+
+        let mut names = Vec::new();
+        let mut exports: Vec<LocalBoxFuture<'_, worker::Result<()>>> = Vec::new();
+        for (route, records) in routes {
+            let exporter = match route {
+                Some(tenant) => &self.tenants[tenant],
+                None => match &self.fallback {
+                    Some(fallback) => fallback,
+                    None => continue,
+                },
+            };
+            names.push(route.unwrap_or("the fallback"));
+            exports.push(exporter.export(records));
+        }
+
+        let failed: Vec<_> = join_all(exports)
+            .await
+            .into_iter()
+            .zip(names)
+            .filter_map(|(res, name)| {
+                let err = res.err()?;
+                worker::console_error!("export to {name} failed: {err}");
+                Some(name)
+            })
+            .collect();
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(worker::Error::RustError(format!(
+                "export to {} failed",
+                failed.join(", ")
+            )))
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use std::{collections::HashMap, fmt::Write};
+
+use minitrace::collector::{SpanId, SpanRecord};
+
+// A waterfall of each local root and the spans below it, in plain ASCII so it survives being
+// pasted anywhere:
+//
+// ```text
+// GET /                         +0.0ms |########################################| 12.3ms
+//   route /                     +0.4ms |  ################################      | 10.1ms
+//     SELECT users              +0.9ms |    #########################         |  8.0ms
+//   render                     +10.9ms |                                 ####   |  1.2ms
+// ```
+//
+// Offsets and bars are relative to the root.
+
+/// Renders `records` as waterfalls `width` characters wide, not counting names and times.
+pub fn render(records: &[SpanRecord], width: usize) -> String {
+    let (roots, children) = super::tree(records);
+    let mut out = String::new();
+    for root in roots {
+        let mut rows = Vec::new();
+        collect(root, &children, 0, &mut rows);
+        let name_width = rows
+            .iter()
+            .map(|(depth, record)| depth * 2 + record.name.chars().count())
+            .max()
+            .unwrap_or_default();
+
+        let origin = root.begin_time_unix_ns;
+        let total = root.duration_ns.max(1);
+        for (depth, record) in rows {
+            let offset = record.begin_time_unix_ns.saturating_sub(origin);
+            let start = (offset as u128 * width as u128 / total as u128)
+                .min(width.saturating_sub(1) as u128) as usize;
+            let len = (record.duration_ns as u128 * width as u128 / total as u128)
+                .clamp(1, (width - start).max(1) as u128) as usize;
+            let name = format!("{}{}", "  ".repeat(depth), record.name);
+            let _ = writeln!(
+                out,
+                "{name:<name_width$} {:>8} |{}{}{}| {:>7}",
+                format!("+{}", super::format_duration(offset)),
+                " ".repeat(start),
+                "#".repeat(len),
+                " ".repeat(width.saturating_sub(start + len)),
+                super::format_duration(record.duration_ns),
+            );
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn collect<'a>(
+    record: &'a SpanRecord,
+    children: &HashMap<SpanId, Vec<&'a SpanRecord>>,
+    depth: usize,
+    rows: &mut Vec<(usize, &'a SpanRecord)>,
+) {
+    rows.push((depth, record));
+    for child in children.get(&record.span_id).into_iter().flatten() {
+        collect(child, children, depth + 1, rows);
+    }
+}
@@ -0,0 +1,177 @@
+use async_trait::async_trait;
+use minitrace::collector::SpanRecord;
+use serde_json::{json, Map, Value as Json};
+
+use crate::{kind::SpanKind, status::SpanStatus};
+
+use super::{auth::Credentials, SpanExporter};
+
+// Sends the records to Zipkin (or anything taking its v2 API, e.g. the OpenTelemetry
+// collector's zipkin receiver) as a list of span JSON objects
+// (https://zipkin.io/zipkin-api/#/default/post_spans). Zipkin tags are strings only, so
+// typed attributes are sent as their string form, and events become annotations, which only
+// have a name.
+
+/// Exports records to Zipkin's `/api/v2/spans`.
+#[derive(Clone, Debug)]
+pub struct ZipkinExporter {
+    url: String,
+    service_name: String,
+    headers: Vec<(String, String)>,
+}
+
+impl ZipkinExporter {
+    /// Exports to the Zipkin server at `base_url`, e.g. `http://zipkin:9411`, as
+    /// `service_name`.
+    pub fn new(base_url: &str, service_name: impl Into<String>) -> Self {
+        Self {
+            url: format!("{}/api/v2/spans", base_url.trim_end_matches('/')),
+            service_name: service_name.into(),
+            headers: Vec::new(),
+        }
+    }
+
+    /// Sends `name: value` with every export.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Sends the headers of `credentials` with every export.
+    pub fn auth(mut self, credentials: &Credentials) -> Self {
+        self.headers.extend_from_slice(credentials.headers());
+        self
+    }
+
+    /// The body `export` sends for `records`.
+    pub fn encode(&self, records: &[SpanRecord]) -> Vec<u8> {
+        let spans: Vec<_> = records.iter().map(|record| self.span(record)).collect();
+        serde_json::to_vec(&spans).unwrap_or_default()
+    }
+
+    fn span(&self, record: &SpanRecord) -> Json {
+        let mut span = Map::new();
+        span.insert(
+            "traceId".into(),
+            format!("{:032x}", record.trace_id.0).into(),
+        );
+        span.insert("id".into(), format!("{:016x}", record.span_id.0).into());
+        if record.parent_id.0 != 0 {
+            span.insert(
+                "parentId".into(),
+                format!("{:016x}", record.parent_id.0).into(),
+            );
+        }
+        span.insert("name".into(), record.name.as_ref().into());
+        if let Some(kind) = kind(SpanKind::of(record)) {
+            span.insert("kind".into(), kind.into());
+        }
+        span.insert(
+            "timestamp".into(),
+            (record.begin_time_unix_ns / 1000).into(),
+        );
+        // Zipkin reads a zero duration as unknown.
+        span.insert("duration".into(), (record.duration_ns / 1000).max(1).into());
+        span.insert(
+            "localEndpoint".into(),
+            json!({ "serviceName": self.service_name }),
+        );
+        if let Some(peer) = super::property(record, "peer.service") {
+            span.insert("remoteEndpoint".into(), json!({ "serviceName": peer }));
+        }
+
+        let annotations: Vec<_> = super::events(record)
+            .map(|event| {
+                json!({
+                    "timestamp": event.timestamp_unix_ns / 1000,
+                    "value": event.name,
+                })
+            })
+            .collect();
+        if !annotations.is_empty() {
+            span.insert("annotations".into(), annotations.into());
+        }
+
+        let mut tags: Map<_, _> = super::attributes(record)
+            .into_iter()
+            .map(|(key, value)| (key.into_owned(), value.to_string().into()))
+            .collect();
+        if let SpanStatus::Error(description) = SpanStatus::of(record) {
+            // Zipkin marks failed spans with an `error` tag, whatever its value.
+            let description = if description.is_empty() {
+                "true".into()
+            } else {
+                description
+            };
+            tags.insert("error".into(), description.as_ref().into());
+        }
+        if !tags.is_empty() {
+            span.insert("tags".into(), tags.into());
+        }
+        span.into()
+    }
+}
+
+#[async_trait(?Send)]
+impl SpanExporter for ZipkinExporter {
+    /// Sends `records`, if there are any.
+    async fn export(&self, records: Vec<SpanRecord>) -> worker::Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let body = self.encode(&records);
+        super::post(&self.url, &self.headers, "application/json", &body).await
+    }
+}
+
+fn kind(kind: SpanKind) -> Option<&'static str> {
+    match kind {
+        SpanKind::Internal => None,
+        SpanKind::Server => Some("SERVER"),
+        SpanKind::Client => Some("CLIENT"),
+        SpanKind::Producer => Some("PRODUCER"),
+        SpanKind::Consumer => Some("CONSUMER"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use minitrace::collector::{EventRecord, SpanId, TraceId};
+
+    use super::*;
+
+    #[test]
+    fn encode() {
+        let record = SpanRecord {
+            trace_id: TraceId(1),
+            span_id: SpanId(2),
+            parent_id: SpanId(3),
+            begin_time_unix_ns: 1_000_000,
+            duration_ns: 2_500,
+            name: "GET".into(),
+            properties: vec![
+                ("span.kind".into(), "client".into()),
+                ("peer.service".into(), "users".into()),
+                ("__type.code".into(), "i64".into()),
+                ("code".into(), "504".into()),
+                ("otel.status_code".into(), "ERROR".into()),
+                ("otel.status_description".into(), "timeout".into()),
+            ],
+            events: vec![EventRecord {
+                name: "retry".into(),
+                timestamp_unix_ns: 1_200_000,
+                properties: Vec::new(),
+            }],
+        };
+        let body = ZipkinExporter::new("http://zipkin:9411/", "svc").encode(&[record]);
+        let expected = concat!(
+            r#"[{"annotations":[{"timestamp":1200,"value":"retry"}],"duration":2,"#,
+            r#""id":"0000000000000002","kind":"CLIENT","localEndpoint":{"serviceName":"svc"},"#,
+            r#""name":"GET","parentId":"0000000000000003","#,
+            r#""remoteEndpoint":{"serviceName":"users"},"#,
+            r#""tags":{"code":"504","error":"timeout","peer.service":"users"},"#,
+            r#""timestamp":1000,"traceId":"00000000000000000000000000000001"}]"#,
+        );
+        assert_eq!(String::from_utf8(body).unwrap(), expected);
+    }
+}
@@ -0,0 +1,275 @@
+use std::{future::Future, time::Duration};
+
+use worker::{Delay, Fetch, Fetcher, Method, Request, Response};
+
+use crate::{
+    baggage, current, error, event,
+    kind::{SpanKind, SPAN_KIND},
+    local_future::LocalFutureExt,
+    propagation::{self, TraceContext},
+    status::{self, SpanStatus},
+};
+
+// Subrequests made through `send` get a client span of their own, and carry its context
+// (`traceparent`, `tracestate` and `baggage`) so the service on the other end can continue
+// the trace under it. Outside of a running `Collector`, the request is sent as is.
+//
+// `send` resolves as soon as the response headers are in, the body is read afterwards by the
+// caller. `send_with` reads it inside the span instead, and the `response_headers_received`
+// event tells the time to first byte apart from the time spent downloading the body. It can
+// retry on network errors and `502`/`503`/`504` too (see `Retries`), each retry being
+// recorded as an event and counted in `http.request.resend_count`. Only requests whose method
+// is idempotent are retried unless told otherwise: a `POST` that did reach the server before
+// failing would be handled twice. Retries back off like the exporters' (see `export::retry`),
+// from 100ms up to 2s, each wait shortened by a random part of up to half of it so that
+// requests failing together don't all come back together.
+//
+// Calls to other workers through a service binding go through `send_to_service`, which does
+// the same over the binding's `Fetcher`. The context travels in the same headers, so a callee
+// traced with `FetchTracing` (or anything else reading `traceparent`) continues the trace
+// under the client span, across workers.
+
+/// Sends `fetch` like `Fetch::send`, inside a client span.
+///
+/// ```ignore
+/// let response = fetch::send(Fetch::Url("https://example.com".parse()?)).await?;
+/// ```
+pub async fn send(fetch: Fetch) -> worker::Result<Response> {
+    send_with(fetch, Retries::default(), |response| async { Ok(response) }).await
+}
+
+/// How many times a request is resent when it fails, none by default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Retries {
+    max_retries: u32,
+    any_method: bool,
+}
+
+impl Retries {
+    /// Resends requests up to `max_retries` times, if their method is `GET`, `HEAD`, `OPTIONS`,
+    /// `PUT` or `DELETE`.
+    pub fn new(max_retries: u32) -> Self {
+        Self {
+            max_retries,
+            any_method: false,
+        }
+    }
+
+    /// Resends requests of any method, e.g. `POST`s to a server that tells them apart with an
+    /// idempotency key.
+    pub fn any_method(mut self) -> Self {
+        self.any_method = true;
+        self
+    }
+
+    /// How many times a request of `method` is resent.
+    fn of(&self, method: &Method) -> u32 {
+        let idempotent = matches!(
+            method,
+            Method::Get | Method::Head | Method::Options | Method::Put | Method::Delete
+        );
+        if idempotent || self.any_method {
+            self.max_retries
+        } else {
+            0
+        }
+    }
+}
+
+/// Sends `fetch`, resending it as `retries` says, and hands the response to `read`, all
+/// inside a client span.
+///
+/// ```ignore
+/// let body = fetch::send_with(fetch, Retries::new(2), |mut response| async move {
+///     response.text().await
+/// })
+/// .await?;
+/// ```
+pub async fn send_with<T, F, Fut>(fetch: Fetch, retries: Retries, read: F) -> worker::Result<T>
+where
+    F: FnOnce(Response) -> Fut,
+    Fut: Future<Output = worker::Result<T>>,
+{
+    let request = match fetch {
+        Fetch::Url(url) => Request::new(url.as_str(), Method::Get)?,
+        Fetch::Request(request) => request,
+    };
+    traced(request, None, retries, read).await
+}
+
+/// Sends `request` to the worker bound as `name` through `service`, inside a client span.
+pub async fn send_to_service(
+    service: &Fetcher,
+    name: &str,
+    request: Request,
+) -> worker::Result<Response> {
+    send_to_service_with(
+        service,
+        name,
+        request,
+        Retries::default(),
+        |response| async { Ok(response) },
+    )
+    .await
+}
+
+/// Like `send_with`, through `service`.
+pub async fn send_to_service_with<T, F, Fut>(
+    service: &Fetcher,
+    name: &str,
+    request: Request,
+    retries: Retries,
+    read: F,
+) -> worker::Result<T>
+where
+    F: FnOnce(Response) -> Fut,
+    Fut: Future<Output = worker::Result<T>>,
+{
+    traced(request, Some((service, name)), retries, read).await
+}
+
+async fn traced<T, F, Fut>(
+    mut request: Request,
+    service: Option<(&Fetcher, &str)>,
+    retries: Retries,
+    read: F,
+) -> worker::Result<T>
+where
+    F: FnOnce(Response) -> Fut,
+    Fut: Future<Output = worker::Result<T>>,
+{
+    let method = request.method();
+    let max_retries = retries.of(&method);
+    let url = request.url()?;
+    let mut properties = vec![
+        (SPAN_KIND, SpanKind::Client.as_str().to_owned()),
+        ("http.request.method", method.to_string()),
+        ("url.full", url.to_string()),
+        (
+            "server.address",
+            url.host_str().unwrap_or_default().to_owned(),
+        ),
+    ];
+    if let Some((_, name)) = service {
+        properties.push(("peer.service", name.to_owned()));
+    }
+    let service = service.map(|(service, _)| service);
+
+    async move {
+        if let Some(context) = TraceContext::current() {
+            // The headers of a request received by the worker are immutable.
+            if request.headers_mut().is_err() {
+                request = request.clone_mut()?;
+            }
+            let headers = request.headers_mut()?;
+            propagation::inject(headers, &context)?;
+            let baggage = baggage::current();
+            if !baggage.is_empty() {
+                baggage::inject(headers, &baggage)?;
+            }
+        }
+
+        let res = send_retrying(request, service, max_retries).await;
+        let response = match res {
+            Ok(response) => response,
+            Err(err) => {
+                error::record(err.to_string());
+                return Err(err);
+            }
+        };
+        let status_code = response.status_code();
+        event::record(
+            "response_headers_received",
+            &[("http.response.status_code", status_code.to_string())],
+        );
+        current::add_attribute("http.response.status_code", status_code as i64);
+        if status_code >= 400 {
+            status::set(SpanStatus::Error(status_code.to_string().into()));
+        }
+
+        let res = read(response).await;
+        if let Err(err) = &res {
+            error::record(err.to_string());
+        }
+        res
+    }
+    .in_local_span(method.to_string())
+    .with_properties(properties)
+    .await
+}
+
+async fn send_retrying(
+    request: Request,
+    service: Option<&Fetcher>,
+    max_retries: u32,
+) -> worker::Result<Response> {
+    let send = |request| async move {
+        match service {
+            Some(service) => service.fetch_request(request).await,
+            None => Fetch::Request(request).send().await,
+        }
+    };
+    let mut backoff = INITIAL_BACKOFF;
+    for retries in 0..max_retries {
+        // The body can only be sent once, every attempt but the last gets a copy.
+        let res = send(request.clone()?).await;
+        let reason = match &res {
+            Ok(response) if matches!(response.status_code(), 502..=504) => {
+                response.status_code().to_string()
+            }
+            Ok(_) => {
+                record_resend_count(retries);
+                return res;
+            }
+            Err(err) => err.to_string(),
+        };
+        event::record("retry", &[("retry.reason", reason)]);
+        Delay::from(jittered(backoff)).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+    record_resend_count(max_retries);
+    send(request).await
+}
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(2);
+
+/// `backoff` shortened by a random part of up to half of it.
+fn jittered(backoff: Duration) -> Duration {
+    let mut buf = [0; 4];
+    let random = match getrandom::getrandom(&mut buf) {
+        Ok(()) => u32::from_ne_bytes(buf) as f64 / u32::MAX as f64,
+        Err(_) => 0.0,
+    };
+    backoff.mul_f64(1.0 - random / 2.0)
+}
+
+fn record_resend_count(retries: u32) {
+    if retries > 0 {
+        current::add_attribute("http.request.resend_count", retries as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idempotent_only() {
+        let retries = Retries::new(2);
+        for method in [
+            Method::Get,
+            Method::Head,
+            Method::Options,
+            Method::Put,
+            Method::Delete,
+        ] {
+            assert_eq!(retries.of(&method), 2, "{method:?}");
+        }
+        for method in [Method::Post, Method::Patch, Method::Connect, Method::Trace] {
+            assert_eq!(retries.of(&method), 0, "{method:?}");
+        }
+        assert_eq!(retries.any_method().of(&Method::Post), 2);
+        assert_eq!(Retries::default().of(&Method::Get), 0);
+    }
+}
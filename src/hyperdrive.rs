@@ -0,0 +1,137 @@
+use std::{fmt::Display, future::Future};
+
+use worker::{
+    wasm_bindgen::{self, prelude::*, JsCast},
+    EnvBinding,
+};
+
+use crate::{binding, current, sql};
+
+// Hyperdrive hands out connection details, the connection itself and the queries are up to
+// whichever Postgres driver the worker uses. This version of `worker` doesn't have the
+// binding either, so it's bound here, and `TracedPostgres` stays driver agnostic: it traces
+// the future connecting and the futures running queries, whatever they are.
+//
+// ```ignore
+// let hyperdrive = env.get_binding::<Hyperdrive>("HYPERDRIVE")?;
+// let postgres = TracedPostgres::new(&hyperdrive);
+// let client = postgres
+//     .connect(async { connect(&hyperdrive.connection_string()).await })
+//     .await?;
+// let rows = postgres
+//     .query("SELECT * FROM users WHERE id = $1", client.query(sql, &[&id]))
+//     .await?;
+// ```
+
+const DB_SYSTEM: &str = "postgresql";
+
+#[wasm_bindgen]
+extern "C" {
+    /// A Hyperdrive binding.
+    #[derive(Clone)]
+    pub type Hyperdrive;
+
+    #[wasm_bindgen(method, getter, js_name = connectionString)]
+    pub fn connection_string(this: &Hyperdrive) -> String;
+
+    #[wasm_bindgen(method, getter)]
+    pub fn host(this: &Hyperdrive) -> String;
+
+    #[wasm_bindgen(method, getter)]
+    pub fn port(this: &Hyperdrive) -> u16;
+
+    #[wasm_bindgen(method, getter)]
+    pub fn user(this: &Hyperdrive) -> String;
+
+    #[wasm_bindgen(method, getter)]
+    pub fn database(this: &Hyperdrive) -> String;
+}
+
+impl EnvBinding for Hyperdrive {
+    const TYPE_NAME: &'static str = "Hyperdrive";
+
+    // The binding is a plain object as far as the runtime lets on.
+    fn get(val: JsValue) -> worker::Result<Self> {
+        Ok(val.unchecked_into())
+    }
+}
+
+/// What the query futures given to `TracedPostgres::query` resolve to, as far as
+/// `db.response.returned_rows` goes.
+pub trait RowCount {
+    fn row_count(&self) -> u64;
+}
+
+impl<T> RowCount for Vec<T> {
+    fn row_count(&self) -> u64 {
+        self.len() as u64
+    }
+}
+
+impl<T> RowCount for Option<T> {
+    fn row_count(&self) -> u64 {
+        self.is_some() as u64
+    }
+}
+
+/// Rows affected by a statement.
+impl RowCount for u64 {
+    fn row_count(&self) -> u64 {
+        *self
+    }
+}
+
+/// Traces the connection to and the queries run on a Postgres database.
+#[derive(Clone, Debug)]
+pub struct TracedPostgres {
+    database: String,
+    host: String,
+    port: u16,
+}
+
+impl TracedPostgres {
+    /// Traces the database `hyperdrive` connects to.
+    pub fn new(hyperdrive: &Hyperdrive) -> Self {
+        Self {
+            database: hyperdrive.database(),
+            host: hyperdrive.host(),
+            port: hyperdrive.port(),
+        }
+    }
+
+    /// Awaits `connect` inside a span for the connection acquisition.
+    pub async fn connect<C, E: Display>(
+        &self,
+        connect: impl Future<Output = Result<C, E>>,
+    ) -> Result<C, E> {
+        binding::call("connect", &self.database, self.properties(), connect).await
+    }
+
+    /// Awaits `query`, which runs `sql`, inside a span recording the sanitized statement and
+    /// the number of rows it returned.
+    pub async fn query<T: RowCount, E: Display>(
+        &self,
+        sql: &str,
+        query: impl Future<Output = Result<T, E>>,
+    ) -> Result<T, E> {
+        let operation = sql::operation(sql);
+        let mut properties = self.properties();
+        properties.push(("db.operation.name", operation.clone()));
+        properties.push(("db.query.text", sql::sanitize(sql)));
+        binding::call(&operation, &self.database, properties, async {
+            let rows = query.await?;
+            current::add_attribute("db.response.returned_rows", rows.row_count() as i64);
+            Ok(rows)
+        })
+        .await
+    }
+
+    fn properties(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("db.system", DB_SYSTEM.to_owned()),
+            ("db.namespace", self.database.clone()),
+            ("server.address", self.host.clone()),
+            ("server.port", self.port.to_string()),
+        ]
+    }
+}
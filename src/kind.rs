@@ -0,0 +1,57 @@
+use std::fmt;
+
+use minitrace::collector::SpanRecord;
+
+pub(crate) const SPAN_KIND: &str = "span.kind";
+
+/// The role a span plays in a trace, as OpenTelemetry defines it. Backends use it to tell
+/// requests served by the worker apart from the requests it makes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum SpanKind {
+    #[default]
+    Internal,
+    Server,
+    Client,
+    Producer,
+    Consumer,
+}
+
+impl SpanKind {
+    /// Reads the kind back from a collected record. Spans created without one are `Internal`.
+    pub fn of(record: &SpanRecord) -> Self {
+        record
+            .properties
+            .iter()
+            .rev()
+            .find(|(k, _)| k == SPAN_KIND)
+            .and_then(|(_, v)| Self::parse(v))
+            .unwrap_or_default()
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SpanKind::Internal => "internal",
+            SpanKind::Server => "server",
+            SpanKind::Client => "client",
+            SpanKind::Producer => "producer",
+            SpanKind::Consumer => "consumer",
+        }
+    }
+
+    fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "internal" => Some(SpanKind::Internal),
+            "server" => Some(SpanKind::Server),
+            "client" => Some(SpanKind::Client),
+            "producer" => Some(SpanKind::Producer),
+            "consumer" => Some(SpanKind::Consumer),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SpanKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
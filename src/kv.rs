@@ -0,0 +1,192 @@
+use serde::{de::DeserializeOwned, Serialize};
+use worker::kv::{KvError, KvStore, ListResponse};
+
+use crate::{binding, current};
+
+// `KvStore` doesn't know the name of its binding, so it's given along with the store. Keys
+// can hold user data (emails, tokens...), `TracedKv::hash_keys` records a hash of them
+// instead, which still tells hot keys apart.
+
+const DB_SYSTEM: &str = "cloudflare_kv";
+
+/// A `KvStore` whose operations get a client span each.
+#[derive(Clone)]
+pub struct TracedKv {
+    store: KvStore,
+    namespace: String,
+    hash_keys: bool,
+}
+
+impl TracedKv {
+    /// Traces the operations on `store`, bound as `namespace`.
+    pub fn new(store: KvStore, namespace: impl Into<String>) -> Self {
+        Self {
+            store,
+            namespace: namespace.into(),
+            hash_keys: false,
+        }
+    }
+
+    /// Traces the KV namespace bound as `binding` in `env`.
+    pub fn from_env(env: &worker::Env, binding: &str) -> worker::Result<Self> {
+        Ok(Self::new(env.kv(binding)?, binding))
+    }
+
+    /// Records a hash of the keys rather than the keys themselves.
+    pub fn hash_keys(mut self, hash_keys: bool) -> Self {
+        self.hash_keys = hash_keys;
+        self
+    }
+
+    /// The traced store.
+    pub fn inner(&self) -> &KvStore {
+        &self.store
+    }
+
+    /// Gets the value of `key` as text, recording whether it was found and its size.
+    pub async fn get_text(&self, key: &str) -> Result<Option<String>, KvError> {
+        binding::call("get", &self.namespace, self.properties("get", key), async {
+            let value = self.store.get(key).text().await?;
+            record_hit(value.as_ref().map(String::len));
+            Ok(value)
+        })
+        .await
+    }
+
+    /// Gets the value of `key` as bytes, recording whether it was found and its size.
+    pub async fn get_bytes(&self, key: &str) -> Result<Option<Vec<u8>>, KvError> {
+        binding::call("get", &self.namespace, self.properties("get", key), async {
+            let value = self.store.get(key).bytes().await?;
+            record_hit(value.as_ref().map(Vec::len));
+            Ok(value)
+        })
+        .await
+    }
+
+    /// Gets the value of `key` as JSON, recording whether it was found and its size.
+    pub async fn get_json<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, KvError> {
+        binding::call("get", &self.namespace, self.properties("get", key), async {
+            let value = self.store.get(key).text().await?;
+            record_hit(value.as_ref().map(String::len));
+            Ok(value
+                .map(|value| serde_json::from_str(&value))
+                .transpose()?)
+        })
+        .await
+    }
+
+    /// Sets `key` to `value`, optionally expiring after `expiration_ttl` seconds.
+    pub async fn put(
+        &self,
+        key: &str,
+        value: &str,
+        expiration_ttl: Option<u64>,
+    ) -> Result<(), KvError> {
+        binding::call("put", &self.namespace, self.properties("put", key), async {
+            current::add_attribute("kv.value.size", value.len() as i64);
+            let mut put = self.store.put(key, value)?;
+            if let Some(expiration_ttl) = expiration_ttl {
+                put = put.expiration_ttl(expiration_ttl);
+            }
+            put.execute().await
+        })
+        .await
+    }
+
+    /// Sets `key` to `value`, optionally expiring after `expiration_ttl` seconds.
+    pub async fn put_bytes(
+        &self,
+        key: &str,
+        value: &[u8],
+        expiration_ttl: Option<u64>,
+    ) -> Result<(), KvError> {
+        binding::call("put", &self.namespace, self.properties("put", key), async {
+            current::add_attribute("kv.value.size", value.len() as i64);
+            let mut put = self.store.put_bytes(key, value)?;
+            if let Some(expiration_ttl) = expiration_ttl {
+                put = put.expiration_ttl(expiration_ttl);
+            }
+            put.execute().await
+        })
+        .await
+    }
+
+    /// Sets `key` to `value` serialized as JSON.
+    pub async fn put_json<T: Serialize>(
+        &self,
+        key: &str,
+        value: &T,
+        expiration_ttl: Option<u64>,
+    ) -> Result<(), KvError> {
+        self.put(key, &serde_json::to_string(value)?, expiration_ttl)
+            .await
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<(), KvError> {
+        binding::call(
+            "delete",
+            &self.namespace,
+            self.properties("delete", key),
+            self.store.delete(key),
+        )
+        .await
+    }
+
+    /// Lists the keys starting with `prefix`, from `cursor` on, recording how many came back.
+    pub async fn list(
+        &self,
+        prefix: Option<String>,
+        cursor: Option<String>,
+    ) -> Result<ListResponse, KvError> {
+        let mut properties = vec![
+            ("db.system", DB_SYSTEM.to_owned()),
+            ("db.operation.name", "list".to_owned()),
+            ("db.namespace", self.namespace.clone()),
+        ];
+        if let Some(prefix) = &prefix {
+            properties.push(("kv.prefix", prefix.clone()));
+        }
+        binding::call("list", &self.namespace, properties, async {
+            let mut list = self.store.list();
+            if let Some(prefix) = prefix {
+                list = list.prefix(prefix);
+            }
+            if let Some(cursor) = cursor {
+                list = list.cursor(cursor);
+            }
+            let response = list.execute().await?;
+            current::add_attribute("kv.keys.count", response.keys.len() as i64);
+            current::add_attribute("kv.list_complete", response.list_complete);
+            Ok(response)
+        })
+        .await
+    }
+
+    fn properties(&self, operation: &'static str, key: &str) -> Vec<(&'static str, String)> {
+        let key = if self.hash_keys {
+            format!("{:016x}", fnv1a(key.as_bytes()))
+        } else {
+            key.to_owned()
+        };
+        vec![
+            ("db.system", DB_SYSTEM.to_owned()),
+            ("db.operation.name", operation.to_owned()),
+            ("db.namespace", self.namespace.clone()),
+            ("kv.key", key),
+        ]
+    }
+}
+
+fn record_hit(size: Option<usize>) {
+    current::add_attribute("kv.hit", size.is_some());
+    if let Some(size) = size {
+        current::add_attribute("kv.value.size", size as i64);
+    }
+}
+
+// Stable across builds and platforms, unlike `DefaultHasher`.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
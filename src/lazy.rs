@@ -0,0 +1,126 @@
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+};
+
+use minitrace::collector::SpanRecord;
+
+// Properties whose value is only computed for spans that actually get exported. The closure
+// is parked here and the span only carries a reserved property pointing at it, which
+// `resolve` swaps for the real value once a trace is known to be kept. Traces that are
+// dropped instead should go through `discard` so their closures don't pile up.
+
+pub(crate) const LAZY: &str = "__lazy";
+
+type Property = (Cow<'static, str>, Box<dyn FnOnce() -> Cow<'static, str>>);
+
+thread_local! {
+    static PENDING: RefCell<(u64, HashMap<u64, Property>)> = RefCell::new((0, HashMap::new()));
+}
+
+pub(crate) fn register<V, F>(key: Cow<'static, str>, value: F) -> u64
+where
+    V: Into<Cow<'static, str>>,
+    F: FnOnce() -> V + 'static,
+{
+    PENDING.with(|pending| {
+        let (next_id, pending) = &mut *pending.borrow_mut();
+        *next_id += 1;
+        pending.insert(*next_id, (key, Box::new(move || value().into())));
+        *next_id
+    })
+}
+
+/// Evaluates the lazy properties of `records`.
+pub fn resolve(records: &mut [SpanRecord]) {
+    for record in records {
+        if !record.properties.iter().any(|(k, _)| k == LAZY) {
+            continue;
+        }
+
+        let properties = std::mem::take(&mut record.properties);
+        record.properties = properties
+            .into_iter()
+            .filter_map(|(k, v)| {
+                if k != LAZY {
+                    return Some((k, v));
+                }
+                let (key, value) = take(&v)?;
+                Some((key, value()))
+            })
+            .collect();
+    }
+}
+
+/// Drops the lazy properties of `records` without evaluating them.
+pub fn discard<'a>(records: impl IntoIterator<Item = &'a SpanRecord>) {
+    for record in records {
+        for (_, v) in record.properties.iter().filter(|(k, _)| k == LAZY) {
+            take(v);
+        }
+    }
+}
+
+/// The ids of the lazy properties of `records`, see `discard_dropped`.
+pub(crate) fn ids(records: &[SpanRecord]) -> HashSet<u64> {
+    records
+        .iter()
+        .flat_map(|record| &record.properties)
+        .filter(|(k, _)| k == LAZY)
+        .filter_map(|(_, v)| v.parse().ok())
+        .collect()
+}
+
+/// Drops the lazy properties among `ids` that aren't in `records` anymore, those of the
+/// records dropped since `ids` was taken.
+pub(crate) fn discard_dropped(mut ids: HashSet<u64>, records: &[SpanRecord]) {
+    for id in self::ids(records) {
+        ids.remove(&id);
+    }
+    PENDING.with(|pending| {
+        let pending = &mut pending.borrow_mut().1;
+        for id in ids {
+            pending.remove(&id);
+        }
+    });
+}
+
+fn take(id: &str) -> Option<Property> {
+    let id = id.parse().ok()?;
+    PENDING.with(|pending| pending.borrow_mut().1.remove(&id))
+}
+
+#[cfg(test)]
+mod tests {
+    use minitrace::collector::{SpanId, TraceId};
+
+    use super::*;
+    use crate::processor::{self, Processors, TailSampling};
+
+    fn pending() -> usize {
+        PENDING.with(|pending| pending.borrow().1.len())
+    }
+
+    #[test]
+    fn dropped_trace() {
+        let record = |span_id| SpanRecord {
+            trace_id: TraceId(1),
+            span_id: SpanId(span_id),
+            parent_id: SpanId(span_id - 1),
+            properties: vec![(
+                LAZY.into(),
+                register("headers".into(), || "[]").to_string().into(),
+            )],
+            ..SpanRecord::default()
+        };
+        let mut records = vec![record(1), record(2)];
+        assert_eq!(pending(), 2);
+
+        // Nothing failed, the whole trace goes.
+        processor::set_global(Processors::default().with(TailSampling::new()));
+        processor::run(&mut records);
+        assert!(records.is_empty());
+        assert_eq!(pending(), 0);
+    }
+}
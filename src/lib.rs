@@ -1,12 +1,75 @@
-use minitrace::{
-    collector::{SpanContext, SpanId, TraceId},
-    future::FutureExt,
-    local::{LocalCollector, LocalSpan},
-    trace, Span,
-};
+use minitrace::local::LocalSpan;
 use wasm_bindgen::prelude::*;
 use worker::*;
 
+#[macro_use]
+mod macros;
+
+#[cfg(all(feature = "tracing-off", any(feature = "aggregator", feature = "tail")))]
+compile_error!("`aggregator` and `tail` export records, which `tracing-off` doesn't compile");
+
+#[cfg(all(feature = "aggregator", not(feature = "tracing-off")))]
+pub mod aggregator;
+pub mod ai;
+pub mod ambient;
+pub mod baggage;
+mod binding;
+pub mod body;
+pub mod builder;
+pub mod cache;
+pub mod cf;
+pub mod collector;
+pub mod config;
+pub mod current;
+pub mod d1;
+pub mod durable;
+pub mod email;
+pub mod error;
+pub mod event;
+pub mod export;
+pub mod fetch;
+pub mod hyperdrive;
+pub mod kind;
+pub mod kv;
+pub mod lazy;
+pub mod limits;
+pub mod link;
+pub mod local_future;
+pub mod middleware;
+pub mod processor;
+pub mod propagation;
+pub mod queue;
+pub mod r2;
+pub mod record;
+pub mod remote;
+pub mod resource;
+pub mod router;
+pub mod sampler;
+pub mod scoped_span;
+pub mod sql;
+pub mod status;
+pub mod switch;
+#[cfg(all(feature = "tail", not(feature = "tracing-off")))]
+pub mod tail;
+pub mod tenant;
+pub mod tracer;
+pub mod value;
+pub mod vectorize;
+pub mod websocket;
+
+#[cfg(not(feature = "tracing-off"))]
+use export::{ConsoleExporter, OtlpExporter, SpanExporter};
+use limits::Limits;
+use local_future::LocalFutureExt;
+use middleware::{QueueTracing, ScheduledTracing};
+use minitrace::collector::SpanRecord;
+use propagation::{B3Multi, B3Single, Propagators, TraceContext};
+use queue::Traced;
+use resource::Resource;
+use scoped_span::{ScopedSpan, SpanHandle};
+use tracer::TracingConfig;
+pub use worker_rust_macros::traced;
+
 // This is a simple reproduction for a problem I'm facing with minitrace.
 //
 // The main issue here is the inconsistency behaviour of Span and LocalSpan.
@@ -19,32 +82,64 @@ use worker::*;
 // The LocalSpan does not have `with_parent` method, and I can't use `in_span` with it.
 // So to achieve hirearchy, I must split my code to smaller functions and use `#[trace]`.
 
-// This works as expected, the function is being traced and included in SpanRecords
-// I don't need to deal with picking LocalSpan/Span and it's being picked and handlded automatically.
-#[trace]
-async fn func_with_trace() {
-    // This one is created inside this span as LocalSpan and actually works fine.
-    let _child = LocalSpan::enter_with_local_parent("child");
-
-    // Ideally, I want to create a LocalSpan instead of Span here, and use it with in_span
-    // This Span is not reported to the collector.
-    call_nested_future_ext()
-        .in_span(Span::enter_with_local_parent("in_span_async"))
-        .await;
+// `#[trace]` wraps async functions with `in_span(Span::enter_with_local_parent(..))`, so it has
+// the same problem as above. `#[traced]` polls them `in_local_span` instead, and can record
+// arguments as properties too.
+#[traced]
+async fn func_with_trace(root: Option<SpanHandle>) {
+    // Spans can't be held across an `.await`, so what runs under `child` is a future polled
+    // `in_local_span` instead.
+    async move {
+        // `in_span(Span::enter_with_local_parent(..))` is not reported to the collector,
+        // `in_local_span` enters a LocalSpan on every poll instead, so this one is.
+        call_nested_future_ext()
+            .in_local_span("in_span_async")
+            .await;
+
+        nested_wrapped(1).in_local_span("nested_wrapped").await;
+
+        // The span of a failed `#[traced(err)]` function gets an error status and an
+        // `exception` event.
+        let _ = parse_attempt("one");
 
-    {
-        let _guard = LocalSpan::enter_with_local_parent("nested_wrapped");
-        nested_wrapped().await
+        // `child` is the current local parent here, but `ScopedSpan` can be attached to an
+        // explicit parent instead. Nothing is awaited while it's held.
+        let _sibling = root.map(|root| ScopedSpan::enter_with_parent("sibling_of_child", &root));
     }
+    .in_local_span("child")
+    .await;
 }
 
 async fn call_nested_future_ext() {}
 
-async fn nested_wrapped() {}
+#[traced]
+async fn prefetch() {
+    let _guard = LocalSpan::enter_with_local_parent("prefetch_lookup");
+}
+
+#[traced(err, fields(raw))]
+fn parse_attempt(raw: &str) -> std::result::Result<u32, std::num::ParseIntError> {
+    raw.parse()
+}
+
+#[traced(fields(attempt))]
+async fn nested_wrapped(attempt: u32) {
+    current::add_property("cache", "miss");
+    current::add_attribute("response_bytes", 1024);
+    event::record("cache_miss", &[("key", format!("attempt-{attempt}"))]);
+    if let Some(context) = current::span_context() {
+        log(&format!(
+            "trace_id={:032x} span_id={:016x} cache miss",
+            context.trace_id.0, context.span_id.0
+        ));
+    }
+}
 
 #[event(start)]
 fn start() {
     std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+    // Some upstream services only speak B3.
+    propagation::set_global(Propagators::default().with(B3Single).with(B3Multi));
 }
 
 #[wasm_bindgen]
@@ -56,72 +151,235 @@ extern "C" {
 #[event(fetch)]
 async fn main(req: Request, env: Env, ctx: Context) -> Result<Response> {
     log("started");
-    let collector = LocalCollector::start();
+    // A span with more than 64 events keeps the first 64 and reports how many it dropped.
+    let limits = Limits {
+        max_events: 64,
+        ..Limits::default()
+    };
+    // Continues the caller's trace if it sent a `traceparent` (or B3 headers), and doesn't
+    // record requests it decided not to sample. `build` adds the version of the worker that's
+    // running to the service, see `Resource::version_metadata`.
+    let config = TracingConfig::new()
+        .service(Resource::new("worker-rust"))
+        .limits(limits);
+    exporters(config, &env)
+        .build(&env)
+        .fetch()
+        .echo_trace_id(true)
+        .body_sizes(true)
+        .handle(req, &ctx, handle)
+        .await
+}
 
-    {
-        let _guard = LocalSpan::enter_with_local_parent("root");
-        func_with_trace().await;
+// The spans are always logged, and go to a collector too with `OTLP_ENDPOINT` set. A
+// collector that's down doesn't keep them from the console.
+#[cfg(not(feature = "tracing-off"))]
+fn exporters(config: TracingConfig, env: &Env) -> TracingConfig {
+    let config = config.exporter("console", ConsoleExporter::new().attributes(true));
+    match env.var("OTLP_ENDPOINT") {
+        Ok(endpoint) => config.exporter("otlp", OtlpExporter::new(endpoint.to_string())),
+        Err(_) => config,
     }
+}
+
+// There's nothing to export to with `tracing-off`.
+#[cfg(feature = "tracing-off")]
+fn exporters(config: TracingConfig, _env: &Env) -> TracingConfig {
+    config
+}
+
+#[event(scheduled)]
+async fn cron(event: ScheduledEvent, _env: Env, ctx: ScheduleContext) {
+    // Every invocation is a trace of its own.
+    ScheduledTracing::new(flush)
+        .handle(event, &ctx, |_| prefetch())
+        .await;
+}
+
+#[event(queue)]
+async fn consume(batch: MessageBatch<Traced<String>>, _env: Env, ctx: Context) -> Result<()> {
+    // Each message continues the trace of whoever sent it.
+    QueueTracing::new(flush)
+        .handle(batch, &ctx, |message| async move {
+            event::record("received", &[("body", message.body)]);
+            Ok(())
+        })
+        .await
+}
 
-    ctx.wait_until(async move {
-        log("flushing in background");
-        let local_spans = collector.collect();
-        let span_context = SpanContext::new(TraceId(1), SpanId(1));
-        let span_records = local_spans.to_span_records(span_context);
-        log(format!("span_records: {:#?}", span_records).as_str());
-
-        // The output is (only spans created with `#[trace]` or manually entered are collected):
-        // TL;DR: root, child, nested_wrapped
-
-        // SpanRecord {
-        //     trace_id: TraceId(
-        //         1,
-        //     ),
-        //     span_id: SpanId(
-        //         13461468910679228417,
-        //     ),
-        //     parent_id: SpanId(
-        //         1,
-        //     ),
-        //     begin_time_unix_ns: 1707031610622070313,
-        //     duration_ns: 976562,
-        //     name: "root",
-        //     properties: [],
-        //     events: [],
-        // },
-        // SpanRecord {
-        //     trace_id: TraceId(
-        //         1,
-        //     ),
-        //     span_id: SpanId(
-        //         13461468910679228418,
-        //     ),
-        //     parent_id: SpanId(
-        //         13461468910679228417,
-        //     ),
-        //     begin_time_unix_ns: 1707031610623046875,
-        //     duration_ns: 1953125,
-        //     name: "child",
-        //     properties: [],
-        //     events: [],
-        // },
-        // SpanRecord {
-        //     trace_id: TraceId(
-        //         1,
-        //     ),
-        //     span_id: SpanId(
-        //         13461468910679228419,
-        //     ),
-        //     parent_id: SpanId(
-        //         13461468910679228418,
-        //     ),
-        //     begin_time_unix_ns: 1707031610623046875,
-        //     duration_ns: 1953125,
-        //     name: "nested_wrapped",
-        //     properties: [],
-        //     events: [],
-        // },
+async fn handle(req: Request) -> Result<Response> {
+    // Polled inside the server span created by `FetchTracing`.
+    let root = SpanHandle::current();
+    // Every span below the server span gets a `request.id` too.
+    ambient::set("request.id", "req-1");
+    // So is whatever baggage the caller sent, e.g. `baggage.tenant`.
+    baggage::record();
+    // And every span exported for the request gets where it came from: `cloudflare.colo`...
+    cf::record(&req);
+    func_with_trace(root).await;
+    current::add_property_lazy("http.request.headers", {
+        let headers = req.headers().clone();
+        move || format!("{:?}", headers.keys().collect::<Vec<_>>())
     });
+    // The prefetch is collected on its own, the spans it creates don't end up under the
+    // server span. Its records are handed back to be exported separately, they're left out
+    // here, along with their lazy properties.
+    let ((), prefetched) =
+        collector::isolate(TraceContext::new_root().span_context, prefetch()).await;
+    lazy::discard(&prefetched);
+    // The final name is only known once the request has been routed.
+    let route = traced_scope!("match_route", ["url.path" => req.path()], {
+        format!("{} {}", req.method().as_ref(), req.path())
+    });
+    current::set_name(route);
 
     Response::ok("Hello, World!")
 }
+
+// Lazy properties are resolved already.
+async fn flush(span_records: Vec<SpanRecord>) {
+    log("flushing in background");
+    // Nothing's collected with `tracing-off`, the records are always empty.
+    #[cfg(feature = "tracing-off")]
+    let _ = span_records;
+    #[cfg(not(feature = "tracing-off"))]
+    let _ = ConsoleExporter::new()
+        .attributes(true)
+        .export(span_records)
+        .await;
+
+    // The output is (only spans created with `#[traced]`, `in_local_span` or manually entered
+    // are collected, durations left out):
+    //
+    // GET / [server]
+    // ├─ worker_rust::func_with_trace
+    // │  └─ child
+    // │     ├─ in_span_async
+    // │     ├─ nested_wrapped
+    // │     │  └─ worker_rust::nested_wrapped attempt=1 cache=miss response_bytes=1024
+    // │     └─ worker_rust::parse_attempt raw=one ERROR (invalid digit found in string)
+    // ├─ sibling_of_child
+    // └─ match_route url.path=/
+}
+
+// Nothing is collected with `tracing-off`.
+#[cfg(all(test, not(feature = "tracing-off")))]
+mod tests {
+    use std::{
+        future::Future,
+        pin::pin,
+        task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+    };
+
+    use minitrace::{
+        collector::{SpanContext, SpanId, SpanRecord, TraceId},
+        local::LocalSpan,
+    };
+
+    use crate::{collector::Collector, traced};
+
+    fn noop_waker() -> Waker {
+        const VTABLE: RawWakerVTable = RawWakerVTable::new(
+            |_| RawWaker::new(std::ptr::null(), &VTABLE),
+            |_| {},
+            |_| {},
+            |_| {},
+        );
+        unsafe { Waker::from_raw(RawWaker::new(std::ptr::null(), &VTABLE)) }
+    }
+
+    fn collect(f: impl FnOnce()) -> Vec<SpanRecord> {
+        let collector = Collector::start(SpanContext::new(TraceId(1), SpanId(1)));
+        f();
+        collector.collect()
+    }
+
+    fn poll_ready<F: Future>(future: F) -> F::Output {
+        let waker = noop_waker();
+        match pin!(future).poll(&mut Context::from_waker(&waker)) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("the future didn't finish on the first poll"),
+        }
+    }
+
+    #[traced(name = "load", fields(id, name))]
+    async fn load(id: u64, name: &str) -> usize {
+        let _child = LocalSpan::enter_with_local_parent("child");
+        name.len() + id as usize
+    }
+
+    #[traced]
+    fn sync_helper() -> u32 {
+        1
+    }
+
+    #[traced(err(Debug), fields(raw))]
+    fn parse(raw: &str) -> std::result::Result<u32, std::num::ParseIntError> {
+        raw.parse()
+    }
+
+    #[test]
+    fn traced_async() {
+        let records = collect(|| assert_eq!(poll_ready(load(1, "user")), 5));
+        let [span, child] = records.as_slice() else {
+            panic!("unexpected records: {records:?}");
+        };
+        assert_eq!(span.name, "load");
+        assert_eq!(
+            span.properties,
+            [
+                ("id".into(), "1".into()),
+                ("name".into(), "\"user\"".into())
+            ]
+        );
+        assert_eq!(
+            (child.name.as_ref(), child.parent_id),
+            ("child", span.span_id)
+        );
+    }
+
+    #[test]
+    fn traced_sync() {
+        let records = collect(|| assert_eq!(sync_helper(), 1));
+        let [span] = records.as_slice() else {
+            panic!("unexpected records: {records:?}");
+        };
+        assert_eq!(span.name, "worker_rust::tests::sync_helper");
+        assert!(span.properties.is_empty());
+    }
+
+    #[test]
+    fn traced_err() {
+        let records = collect(|| {
+            assert!(parse("1").is_ok());
+            assert!(parse("one").is_err());
+        });
+        let [ok, failed] = records.as_slice() else {
+            panic!("unexpected records: {records:?}");
+        };
+        assert_eq!(ok.properties, [("raw".into(), "\"1\"".into())]);
+        assert!(ok.events.is_empty());
+        assert_eq!(
+            failed.properties,
+            [
+                ("raw".into(), "\"one\"".into()),
+                ("otel.status_code".into(), "ERROR".into()),
+                (
+                    "otel.status_description".into(),
+                    "ParseIntError { kind: InvalidDigit }".into()
+                )
+            ]
+        );
+        let [exception] = failed.events.as_slice() else {
+            panic!("unexpected events: {:?}", failed.events);
+        };
+        assert_eq!(exception.name, "exception");
+        assert_eq!(
+            exception.properties,
+            [(
+                "exception.message".into(),
+                "ParseIntError { kind: InvalidDigit }".into()
+            )]
+        );
+    }
+}
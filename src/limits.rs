@@ -0,0 +1,398 @@
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+};
+
+use minitrace::collector::{SpanId, SpanRecord};
+
+use crate::{collector, current, lazy};
+
+// Caps on how much a single span can carry, and on how many spans a trace can have, so a
+// runaway loop adding events or entering spans can't take the whole isolate down with it.
+//
+// The helpers in this crate check them when something is recorded and count what they drop
+// against the id of the current span (the closest `ScopedSpan`, `in_local_span` or
+// `#[traced]` span, see `current::span_id`). Anything added around them (plain `LocalSpan`s,
+// `Event::add_to_local_parent`) is only caught when the records are collected. Either way the
+// number of dropped items shows up on the span as `DROPPED_EVENTS` / `DROPPED_PROPERTIES`.
+//
+// Spans are counted per trace the same way: past `max_spans`, the helpers don't enter spans
+// anymore, and what still went over is dropped, latest first, when the records are collected.
+// The children of a dropped span are moved under its closest kept ancestor, and the local
+// root gets the number of spans dropped as `DROPPED_SPANS`.
+
+/// The number of events dropped from a span.
+pub const DROPPED_EVENTS: &str = "otel.dropped_events_count";
+/// The number of properties dropped from a span.
+pub const DROPPED_PROPERTIES: &str = "otel.dropped_attributes_count";
+/// The number of spans dropped from a trace, on its local root.
+pub const DROPPED_SPANS: &str = "spans_dropped";
+
+/// Per-span caps, set with `Collector::with_limits`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// Events kept per span, later ones are dropped.
+    pub max_events: usize,
+    /// Properties kept per span, not counting the ones reserved by this crate.
+    pub max_properties: usize,
+    /// Longest property value in bytes, longer ones are cut at a char boundary.
+    pub max_value_len: usize,
+    /// Spans kept per trace, later ones are dropped.
+    pub max_spans: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_events: 128,
+            max_properties: 128,
+            max_value_len: 4096,
+            max_spans: 4096,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Counts {
+    events: usize,
+    properties: usize,
+    dropped_events: usize,
+    dropped_properties: usize,
+}
+
+#[derive(Default)]
+struct SpanCounts {
+    spans: usize,
+    dropped: usize,
+}
+
+thread_local! {
+    static COUNTS: RefCell<HashMap<SpanId, Counts>> = RefCell::new(HashMap::new());
+    static SPAN_COUNTS: RefCell<HashMap<u128, SpanCounts>> = RefCell::new(HashMap::new());
+}
+
+/// Whether one more span fits in the current trace.
+pub(crate) fn admit_span() -> bool {
+    let (Some(limits), Some(context)) = (collector::active_limits(), collector::active_context())
+    else {
+        return true;
+    };
+    SPAN_COUNTS.with(|counts| {
+        let mut counts = counts.borrow_mut();
+        let counts = counts.entry(context.trace_id.0).or_default();
+        if counts.spans < limits.max_spans {
+            counts.spans += 1;
+            true
+        } else {
+            counts.dropped += 1;
+            false
+        }
+    })
+}
+
+/// Whether one more event fits on the current span.
+pub(crate) fn admit_event() -> bool {
+    let Some(limits) = collector::active_limits().filter(|_| collector::is_collecting()) else {
+        return false;
+    };
+    with_counts(current_span(), |counts| {
+        if counts.events < limits.max_events {
+            counts.events += 1;
+            true
+        } else {
+            counts.dropped_events += 1;
+            false
+        }
+    })
+}
+
+/// Keeps the properties that still fit on the current span and truncates their values.
+pub(crate) fn admit_properties(
+    properties: impl IntoIterator<Item = (Cow<'static, str>, Cow<'static, str>)>,
+) -> Vec<(Cow<'static, str>, Cow<'static, str>)> {
+    admit_properties_of(current_span(), properties)
+}
+
+/// Same as `admit_properties`, for the span pinned to `id`.
+pub(crate) fn admit_properties_of(
+    id: SpanId,
+    properties: impl IntoIterator<Item = (Cow<'static, str>, Cow<'static, str>)>,
+) -> Vec<(Cow<'static, str>, Cow<'static, str>)> {
+    let Some(limits) = collector::active_limits().filter(|_| collector::is_collecting()) else {
+        return Vec::new();
+    };
+    with_counts(id, |counts| {
+        properties
+            .into_iter()
+            .filter(|(k, _)| {
+                if is_reserved(k) {
+                    true
+                } else if counts.properties < limits.max_properties {
+                    counts.properties += 1;
+                    true
+                } else {
+                    counts.dropped_properties += 1;
+                    false
+                }
+            })
+            .map(|(k, v)| (k, truncate(v, limits.max_value_len)))
+            .collect()
+    })
+}
+
+/// Truncates `value` to the active `max_value_len`.
+pub(crate) fn truncate_value(value: Cow<'static, str>) -> Cow<'static, str> {
+    match collector::active_limits() {
+        Some(limits) => truncate(value, limits.max_value_len),
+        None => value,
+    }
+}
+
+/// Enforces `limits` on normalized records, and adds what was dropped from each span, at
+/// record time or now, as `DROPPED_EVENTS` / `DROPPED_PROPERTIES`.
+pub(crate) fn apply(records: &mut Vec<SpanRecord>, limits: Limits) {
+    apply_max_spans(records, limits.max_spans);
+    let mut counts = COUNTS.with(|counts| std::mem::take(&mut *counts.borrow_mut()));
+
+    for record in records.iter_mut() {
+        let (mut dropped_events, mut dropped_properties) = counts
+            .remove(&record.span_id)
+            .map_or((0, 0), |c| (c.dropped_events, c.dropped_properties));
+
+        if record.events.len() > limits.max_events {
+            dropped_events += record.events.len() - limits.max_events;
+            record.events.truncate(limits.max_events);
+        }
+        for event in &mut record.events {
+            truncate_all(&mut event.properties, limits.max_value_len);
+        }
+
+        let mut kept = 0;
+        record.properties.retain(|(k, _)| {
+            if is_reserved(k) {
+                return true;
+            }
+            kept += 1;
+            kept <= limits.max_properties
+        });
+        dropped_properties += kept.saturating_sub(limits.max_properties);
+        truncate_all(&mut record.properties, limits.max_value_len);
+
+        if dropped_events > 0 {
+            record
+                .properties
+                .push((DROPPED_EVENTS.into(), dropped_events.to_string().into()));
+        }
+        if dropped_properties > 0 {
+            record.properties.push((
+                DROPPED_PROPERTIES.into(),
+                dropped_properties.to_string().into(),
+            ));
+        }
+    }
+
+    // Counts of spans that weren't in these records belong to another collector.
+    if !counts.is_empty() {
+        COUNTS.with(|current| current.borrow_mut().extend(counts));
+    }
+}
+
+/// Drops the latest spans of each trace past `max_spans`, and adds how many were dropped, at
+/// record time or now, to the local root as `DROPPED_SPANS`.
+fn apply_max_spans(records: &mut Vec<SpanRecord>, max_spans: usize) {
+    let ids: HashSet<SpanId> = records.iter().map(|record| record.span_id).collect();
+    let is_root = |record: &SpanRecord| !ids.contains(&record.parent_id);
+
+    let mut order: Vec<usize> = (0..records.len()).collect();
+    order.sort_by_key(|&i| (!is_root(&records[i]), records[i].begin_time_unix_ns));
+    let mut kept: HashMap<u128, usize> = HashMap::new();
+    let mut dropped: HashMap<SpanId, SpanId> = HashMap::new();
+    for i in order {
+        let record = &records[i];
+        let count = kept.entry(record.trace_id.0).or_default();
+        if *count < max_spans || is_root(record) {
+            *count += 1;
+        } else {
+            dropped.insert(record.span_id, record.parent_id);
+        }
+    }
+
+    let mut dropped_per_trace: HashMap<u128, usize> = SPAN_COUNTS.with(|counts| {
+        let mut counts = counts.borrow_mut();
+        kept.keys()
+            .filter_map(|trace_id| Some((*trace_id, counts.remove(trace_id)?.dropped)))
+            .filter(|(_, dropped)| *dropped > 0)
+            .collect()
+    });
+    if !dropped.is_empty() {
+        for record in records.iter() {
+            if dropped.contains_key(&record.span_id) {
+                *dropped_per_trace.entry(record.trace_id.0).or_default() += 1;
+            }
+        }
+        lazy::discard(
+            records
+                .iter()
+                .filter(|record| dropped.contains_key(&record.span_id)),
+        );
+        records.retain(|record| !dropped.contains_key(&record.span_id));
+        for record in records.iter_mut() {
+            while let Some(parent) = dropped.get(&record.parent_id) {
+                record.parent_id = *parent;
+            }
+        }
+    }
+
+    for record in records.iter_mut() {
+        if !is_root(record) {
+            continue;
+        }
+        if let Some(count) = dropped_per_trace.remove(&record.trace_id.0) {
+            record
+                .properties
+                .push((DROPPED_SPANS.into(), count.to_string().into()));
+        }
+    }
+}
+
+/// Forgets about everything counted so far, once nothing is collecting anymore.
+pub(crate) fn reset() {
+    COUNTS.with(|counts| counts.borrow_mut().clear());
+    SPAN_COUNTS.with(|counts| counts.borrow_mut().clear());
+}
+
+fn current_span() -> SpanId {
+    current::span_id().unwrap_or_default()
+}
+
+fn with_counts<R>(id: SpanId, f: impl FnOnce(&mut Counts) -> R) -> R {
+    COUNTS.with(|counts| f(counts.borrow_mut().entry(id).or_default()))
+}
+
+fn is_reserved(key: &str) -> bool {
+    key.starts_with("__")
+}
+
+fn truncate_all(properties: &mut [(Cow<'static, str>, Cow<'static, str>)], max_len: usize) {
+    for (_, v) in properties {
+        if v.len() > max_len {
+            *v = truncate(std::mem::take(v), max_len);
+        }
+    }
+}
+
+fn truncate(value: Cow<'static, str>, max_len: usize) -> Cow<'static, str> {
+    if value.len() <= max_len {
+        return value;
+    }
+    let mut end = max_len;
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    match value {
+        Cow::Borrowed(v) => Cow::Borrowed(&v[..end]),
+        Cow::Owned(mut v) => {
+            v.truncate(end);
+            Cow::Owned(v)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use minitrace::collector::{EventRecord, TraceId};
+
+    use super::*;
+
+    fn record(span_id: u64, parent_id: u64, begin: u64) -> SpanRecord {
+        SpanRecord {
+            trace_id: TraceId(1),
+            span_id: SpanId(span_id),
+            parent_id: SpanId(parent_id),
+            begin_time_unix_ns: begin,
+            ..SpanRecord::default()
+        }
+    }
+
+    fn property<'a>(record: &'a SpanRecord, key: &str) -> Option<&'a str> {
+        record
+            .properties
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_ref())
+    }
+
+    #[test]
+    fn per_span() {
+        let limits = Limits {
+            max_events: 2,
+            max_properties: 2,
+            max_value_len: 4,
+            ..Limits::default()
+        };
+        let mut span = record(1, 0, 0);
+        span.events = vec![EventRecord::default(); 3];
+        span.properties = vec![
+            ("a".into(), "short".into()),
+            ("__reserved".into(), "kept".into()),
+            ("b".into(), "héllo".into()),
+            ("c".into(), "dropped".into()),
+        ];
+        let mut records = vec![span];
+
+        apply(&mut records, limits);
+        let span = &records[0];
+        assert_eq!(span.events.len(), 2);
+        assert_eq!(property(span, "a"), Some("shor"));
+        assert_eq!(property(span, "__reserved"), Some("kept"));
+        // Cut at a char boundary.
+        assert_eq!(property(span, "b"), Some("hél"));
+        assert_eq!(property(span, "c"), None);
+        assert_eq!(property(span, DROPPED_EVENTS), Some("1"));
+        assert_eq!(property(span, DROPPED_PROPERTIES), Some("1"));
+    }
+
+    #[test]
+    fn max_spans() {
+        let limits = Limits {
+            max_spans: 2,
+            ..Limits::default()
+        };
+        // root -> a -> b -> c, the latest ones go.
+        let mut records = vec![
+            record(4, 3, 30),
+            record(1, 0, 0),
+            record(2, 1, 10),
+            record(3, 2, 20),
+        ];
+
+        apply(&mut records, limits);
+        let ids: Vec<_> = records.iter().map(|r| r.span_id.0).collect();
+        assert_eq!(ids, [1, 2]);
+        assert_eq!(property(&records[0], DROPPED_SPANS), Some("2"));
+        assert_eq!(property(&records[1], DROPPED_SPANS), None);
+    }
+
+    #[test]
+    fn max_spans_reparent() {
+        let limits = Limits {
+            max_spans: 2,
+            ..Limits::default()
+        };
+        // root -> 2 and root -> 4 -> 3, 3 starting first: 2 and 4 go, 3 moves under the root.
+        let mut records = vec![
+            record(1, 0, 0),
+            record(2, 1, 10),
+            record(3, 4, 5),
+            record(4, 1, 20),
+        ];
+
+        apply(&mut records, limits);
+        let spans: Vec<_> = records
+            .iter()
+            .map(|r| (r.span_id.0, r.parent_id.0))
+            .collect();
+        assert_eq!(spans, [(1, 0), (3, 1)]);
+    }
+}
@@ -0,0 +1,72 @@
+use minitrace::{
+    collector::{EventRecord, SpanContext, SpanId, SpanRecord, TraceId},
+    Event,
+};
+
+// A link points a span at a span of another trace, e.g. a message processed in a batch
+// pointing at the trace that produced it. Links are recorded as events on the linking span
+// so they pass through the collector untouched; exporters pick them out with `Link::of`.
+
+pub(crate) const LINK_EVENT: &str = "span.link";
+const TRACE_ID: &str = "link.trace_id";
+const SPAN_ID: &str = "link.span_id";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Link {
+    pub trace_id: TraceId,
+    pub span_id: SpanId,
+}
+
+impl Link {
+    pub fn new(context: SpanContext) -> Self {
+        Self {
+            trace_id: context.trace_id,
+            span_id: context.span_id,
+        }
+    }
+
+    /// Collects the links recorded on a span.
+    pub fn of(record: &SpanRecord) -> Vec<Link> {
+        record.events.iter().filter_map(Self::from_event).collect()
+    }
+
+    /// Whether `event` is a link rather than a regular event.
+    pub fn is_link(event: &EventRecord) -> bool {
+        event.name == LINK_EVENT
+    }
+
+    fn from_event(event: &EventRecord) -> Option<Link> {
+        if !Self::is_link(event) {
+            return None;
+        }
+
+        let find = |key| {
+            event
+                .properties
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.as_ref())
+        };
+        Some(Link {
+            trace_id: TraceId(u128::from_str_radix(find(TRACE_ID)?, 16).ok()?),
+            span_id: SpanId(u64::from_str_radix(find(SPAN_ID)?, 16).ok()?),
+        })
+    }
+}
+
+impl From<SpanContext> for Link {
+    fn from(context: SpanContext) -> Self {
+        Link::new(context)
+    }
+}
+
+/// Links the current local parent to `link`.
+pub fn add(link: impl Into<Link>) {
+    let link = link.into();
+    Event::add_to_local_parent(LINK_EVENT, || {
+        [
+            (TRACE_ID.into(), format!("{:032x}", link.trace_id.0).into()),
+            (SPAN_ID.into(), format!("{:016x}", link.span_id.0).into()),
+        ]
+    });
+}
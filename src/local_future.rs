@@ -0,0 +1,121 @@
+use std::{
+    borrow::Cow,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use minitrace::{collector::SpanId, local::LocalSpan};
+
+use crate::{
+    collector, current, limits,
+    record::{self, SPAN_ID},
+    value::Value,
+};
+
+// `FutureExt::in_span` only works with a `Span`, and a `Span` created with
+// `Span::enter_with_local_parent` is a noop when the only thing collecting is a
+// `LocalCollector` (there's no collect token to attach to). So anything traced that way
+// never shows up in the records dumped in `main`.
+//
+// `InLocalSpan` sticks to `LocalSpan`s instead: a span is entered right before the inner
+// future is polled and exited right after, so whatever the future does while being polled
+// (including `#[trace]`-ed sync functions and manually entered `LocalSpan`s) is nested
+// under it and ends up in the `LocalCollector`. Every poll is pinned to the same span id,
+// so `record::normalize` merges them back into a single span covering the whole future.
+//
+// Polls made while no `Collector` is running skip all of that and just poll the inner future,
+// as do all the polls of a future whose span didn't fit in the trace (see `Limits::max_spans`).
+impl<T: Future> LocalFutureExt for T {}
+
+pub trait LocalFutureExt: Future + Sized {
+    /// Polls the future inside a `LocalSpan` named `name`.
+    #[inline]
+    fn in_local_span(self, name: impl Into<Cow<'static, str>>) -> InLocalSpan<Self> {
+        InLocalSpan {
+            inner: self,
+            id: None,
+            dropped: false,
+            name: name.into(),
+            properties: Vec::new(),
+        }
+    }
+}
+
+#[pin_project::pin_project]
+pub struct InLocalSpan<T> {
+    #[pin]
+    inner: T,
+    // Picked on the first poll that is collected.
+    id: Option<SpanId>,
+    // Whether the trace had no room left for the span.
+    dropped: bool,
+    name: Cow<'static, str>,
+    properties: Vec<(Cow<'static, str>, Cow<'static, str>)>,
+}
+
+impl<T> InLocalSpan<T> {
+    /// Adds a property to the span entered on each poll.
+    pub fn with_property(
+        mut self,
+        key: impl Into<Cow<'static, str>>,
+        value: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        self.properties.push((key.into(), value.into()));
+        self
+    }
+
+    /// Adds a typed attribute to the span entered on each poll, see [`Value`].
+    pub fn with_attribute(
+        self,
+        key: impl Into<Cow<'static, str>>,
+        value: impl Into<Value>,
+    ) -> Self {
+        let properties = value.into().to_properties(key.into());
+        self.with_properties(properties)
+    }
+
+    /// Adds several properties to the span entered on each poll.
+    pub fn with_properties<K, V>(mut self, properties: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: Into<Cow<'static, str>>,
+        V: Into<Cow<'static, str>>,
+    {
+        self.properties
+            .extend(properties.into_iter().map(|(k, v)| (k.into(), v.into())));
+        self
+    }
+}
+
+impl<T: Future> Future for InLocalSpan<T> {
+    type Output = T::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        if !collector::is_collecting() || *this.dropped {
+            return this.inner.poll(cx);
+        }
+        if this.id.is_none() && !limits::admit_span() {
+            *this.dropped = true;
+            return this.inner.poll(cx);
+        }
+
+        // The properties only go on the first fragment, the others are merged into it.
+        let (id, properties) = match *this.id {
+            Some(id) => (id, Vec::new()),
+            None => {
+                let id = record::next_span_id();
+                *this.id = Some(id);
+                let properties = std::mem::take(this.properties);
+                (id, limits::admit_properties_of(id, properties))
+            }
+        };
+        let _guard = LocalSpan::enter_with_local_parent(this.name.clone())
+            .with_property(|| (SPAN_ID, id.0.to_string()))
+            .with_properties(|| properties);
+        current::push_span(id);
+        let res = this.inner.poll(cx);
+        current::pop_span(id);
+        res
+    }
+}
@@ -0,0 +1,37 @@
+/// Runs a block inside a `ScopedSpan`, optionally with properties, and evaluates to the
+/// block's value.
+///
+/// ```ignore
+/// let body = traced_scope!("parse_body", { parse(&raw) });
+/// let body = traced_scope!("parse_body", ["content.type" => content_type], { parse(&raw) });
+/// ```
+#[cfg(not(feature = "tracing-off"))]
+#[macro_export]
+macro_rules! traced_scope {
+    ($name:expr, [$($key:expr => $value:expr),+ $(,)?], $body:block) => {{
+        let __guard__ = $crate::scoped_span::ScopedSpan::enter_with_local_parent($name)
+            .with_properties(|| {
+                [$((
+                    ::std::borrow::Cow::<'static, str>::from($key),
+                    ::std::borrow::Cow::<'static, str>::from($value),
+                )),+]
+            });
+        $body
+    }};
+    ($name:expr, $body:block) => {{
+        let __guard__ = $crate::scoped_span::ScopedSpan::enter_with_local_parent($name);
+        $body
+    }};
+}
+
+/// With `tracing-off`, `traced_scope!` is just the block.
+#[cfg(feature = "tracing-off")]
+#[macro_export]
+macro_rules! traced_scope {
+    ($name:expr, [$($key:expr => $value:expr),+ $(,)?], $body:block) => {
+        $body
+    };
+    ($name:expr, $body:block) => {
+        $body
+    };
+}
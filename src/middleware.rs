@@ -0,0 +1,481 @@
+use std::{borrow::Cow, future::Future};
+
+use minitrace::collector::SpanRecord;
+use serde::de::DeserializeOwned;
+use worker::{
+    js_sys, wasm_bindgen::JsValue, Context, Message, MessageBatch, Request, Response, ResponseBody,
+    ScheduleContext, ScheduledEvent,
+};
+
+use crate::{
+    baggage, body,
+    collector::{self, Collector},
+    current, error,
+    kind::{SpanKind, SPAN_KIND},
+    lazy,
+    limits::Limits,
+    link,
+    local_future::LocalFutureExt,
+    processor,
+    propagation::{self, TraceContext},
+    queue::{Traced, MESSAGING_SYSTEM},
+    sampler::{self, SamplingInput},
+    status::{self, SpanStatus},
+};
+
+// Everything a `#[event(fetch)]` handler needs to be traced: the caller's context is
+// extracted, a collector is started for the request, the handler is polled inside a server
+// span following the HTTP semantic conventions, and the records are collected and exported
+// in the background once the response is ready. Scheduled handlers get the same treatment,
+// minus the context extraction: every invocation starts a new trace. So do queue batches,
+// with a trace per message next to it.
+//
+// ```ignore
+// #[event(fetch)]
+// async fn main(req: Request, _env: Env, ctx: Context) -> Result<Response> {
+//     FetchTracing::new(|records| async move { /* export them */ })
+//         .handle(req, &ctx, handle)
+//         .await
+// }
+// ```
+
+/// Traces a fetch handler, see [`FetchTracing::handle`].
+#[must_use]
+pub struct FetchTracing<E> {
+    export: E,
+    limits: Limits,
+    resource: Vec<Property>,
+    honor_sampled: bool,
+    echo_trace_id: bool,
+    echo_traceparent: bool,
+    body_sizes: bool,
+}
+
+impl<E, Fut> FetchTracing<E>
+where
+    E: FnOnce(Vec<SpanRecord>) -> Fut + 'static,
+    Fut: Future<Output = ()> + 'static,
+{
+    /// Traces the request and hands its records to `export` when it's done.
+    pub fn new(export: E) -> Self {
+        Self {
+            export,
+            limits: Limits::default(),
+            resource: Vec::new(),
+            honor_sampled: true,
+            echo_trace_id: false,
+            echo_traceparent: false,
+            body_sizes: false,
+        }
+    }
+
+    /// Caps what each span can carry, see [`Limits`].
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Adds resource attributes to every span, see `Collector::with_resource`.
+    pub fn resource<K, V>(mut self, attributes: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: Into<Cow<'static, str>>,
+        V: Into<Cow<'static, str>>,
+    {
+        self.resource
+            .extend(attributes.into_iter().map(|(k, v)| (k.into(), v.into())));
+        self
+    }
+
+    /// Whether requests the caller didn't sample are left unrecorded, which is the default.
+    /// See `Collector::start_remote`.
+    pub fn honor_sampled(mut self, honor_sampled: bool) -> Self {
+        self.honor_sampled = honor_sampled;
+        self
+    }
+
+    /// Sets `x-trace-id` on every response, and `traceparent` too with `with_traceparent`.
+    /// See `propagation::echo`.
+    pub fn echo_trace_id(mut self, with_traceparent: bool) -> Self {
+        self.echo_trace_id = true;
+        self.echo_traceparent = with_traceparent;
+        self
+    }
+
+    /// Records `http.request.body.size` and `http.response.body.size`, see `body`. The size of
+    /// a streamed response is only known once it's been sent, so its records are exported
+    /// after that.
+    pub fn body_sizes(mut self, body_sizes: bool) -> Self {
+        self.body_sizes = body_sizes;
+        self
+    }
+
+    /// Runs `handler` on `req` inside a server span.
+    ///
+    /// An error returned by `handler` is recorded on the span and turned into a `500`
+    /// response.
+    pub async fn handle<H, HFut>(
+        self,
+        req: Request,
+        ctx: &Context,
+        handler: H,
+    ) -> worker::Result<Response>
+    where
+        H: FnOnce(Request) -> HFut,
+        HFut: Future<Output = worker::Result<Response>>,
+    {
+        let mut collector =
+            start_collector(&req, self.honor_sampled, self.limits).with_resource(self.resource);
+        let (body_sizes, echo_trace_id, echo_traceparent) =
+            (self.body_sizes, self.echo_trace_id, self.echo_traceparent);
+        let (res, finished) = collector
+            .run(async move {
+                match serve(req, body_sizes, handler).await {
+                    Ok((res, finished, Some(context))) if echo_trace_id => {
+                        (propagation::echo(res, &context, echo_traceparent), finished)
+                    }
+                    Ok((res, finished, _)) => (Ok(res), finished),
+                    Err(err) => (Err(err), None),
+                }
+            })
+            .await;
+
+        ctx.wait_until(async move {
+            if let Some(finished) = finished {
+                finished.await;
+            }
+            flush(collector, Vec::new(), self.export).await;
+        });
+        res
+    }
+}
+
+/// Traces a scheduled (cron) handler, see [`ScheduledTracing::handle`].
+#[must_use]
+pub struct ScheduledTracing<E> {
+    export: E,
+    limits: Limits,
+    resource: Vec<Property>,
+}
+
+impl<E, Fut> ScheduledTracing<E>
+where
+    E: FnOnce(Vec<SpanRecord>) -> Fut + 'static,
+    Fut: Future<Output = ()> + 'static,
+{
+    /// Traces the invocation and hands its records to `export` when it's done.
+    pub fn new(export: E) -> Self {
+        Self {
+            export,
+            limits: Limits::default(),
+            resource: Vec::new(),
+        }
+    }
+
+    /// Caps what each span can carry, see [`Limits`].
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Adds resource attributes to every span, see `Collector::with_resource`.
+    pub fn resource<K, V>(mut self, attributes: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: Into<Cow<'static, str>>,
+        V: Into<Cow<'static, str>>,
+    {
+        self.resource
+            .extend(attributes.into_iter().map(|(k, v)| (k.into(), v.into())));
+        self
+    }
+
+    /// Runs `handler` on `event` inside a span of a new trace.
+    pub async fn handle<H, HFut>(self, event: ScheduledEvent, ctx: &ScheduleContext, handler: H)
+    where
+        H: FnOnce(ScheduledEvent) -> HFut,
+        HFut: Future<Output = ()>,
+    {
+        let parent = TraceContext::new_root();
+        let mut collector = Collector::start_remote(&parent, false)
+            .with_limits(self.limits)
+            .with_resource(self.resource);
+
+        let cron = event.cron();
+        let properties = [
+            ("faas.trigger", "timer".to_owned()),
+            ("faas.cron", cron.clone()),
+            ("faas.time", to_iso_string(event.schedule())),
+        ];
+        let scheduled = handler(event)
+            .in_local_span(if cron.is_empty() {
+                "scheduled".to_owned()
+            } else {
+                cron
+            })
+            .with_properties(properties);
+        collector.run(scheduled).await;
+
+        ctx.wait_until(flush(collector, Vec::new(), self.export));
+    }
+}
+
+/// Traces a queue consumer, see [`QueueTracing::handle`].
+#[must_use]
+pub struct QueueTracing<E> {
+    export: E,
+    limits: Limits,
+    resource: Vec<Property>,
+}
+
+impl<E, Fut> QueueTracing<E>
+where
+    E: FnOnce(Vec<SpanRecord>) -> Fut + 'static,
+    Fut: Future<Output = ()> + 'static,
+{
+    /// Traces the batch and hands its records to `export` when it's done.
+    pub fn new(export: E) -> Self {
+        Self {
+            export,
+            limits: Limits::default(),
+            resource: Vec::new(),
+        }
+    }
+
+    /// Caps what each span can carry, see [`Limits`].
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Adds resource attributes to every span, see `Collector::with_resource`.
+    pub fn resource<K, V>(mut self, attributes: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: Into<Cow<'static, str>>,
+        V: Into<Cow<'static, str>>,
+    {
+        self.resource
+            .extend(attributes.into_iter().map(|(k, v)| (k.into(), v.into())));
+        self
+    }
+
+    /// Runs `handler` on every message of `batch`, one after the other.
+    ///
+    /// The batch gets a span in a new trace, linked to the trace of each message's producer.
+    /// Each message is processed in a span of its own that continues its producer's trace
+    /// (see `queue::Traced`), or starts a new one if the producer didn't send its context.
+    /// Stops at the first message `handler` fails on, and returns its error.
+    pub async fn handle<T, H, HFut>(
+        self,
+        batch: MessageBatch<Traced<T>>,
+        ctx: &Context,
+        mut handler: H,
+    ) -> worker::Result<()>
+    where
+        T: DeserializeOwned,
+        H: FnMut(Message<T>) -> HFut,
+        HFut: Future<Output = worker::Result<()>>,
+    {
+        let mut collector = Collector::start_remote(&TraceContext::new_root(), false)
+            .with_limits(self.limits)
+            .with_resource(self.resource);
+        let queue = batch.queue();
+        let messages = batch.messages()?;
+        let properties = [
+            (SPAN_KIND, SpanKind::Consumer.as_str().to_owned()),
+            ("messaging.system", MESSAGING_SYSTEM.to_owned()),
+            ("messaging.operation", "process".to_owned()),
+            ("messaging.destination.name", queue.clone()),
+        ];
+        let message_count = messages.len() as i64;
+
+        let process = async {
+            let mut records = Vec::new();
+            for message in messages {
+                let context = message.body.context();
+                if let Some(context) = &context {
+                    link::add(context.span_context);
+                }
+                let properties = [
+                    (SPAN_KIND, SpanKind::Consumer.as_str().to_owned()),
+                    ("messaging.system", MESSAGING_SYSTEM.to_owned()),
+                    ("messaging.operation", "process".to_owned()),
+                    ("messaging.destination.name", queue.clone()),
+                    ("messaging.message.id", message.id.clone()),
+                ];
+                let message = Message {
+                    body: message.body.body,
+                    timestamp: message.timestamp,
+                    id: message.id,
+                };
+                let parent = context.unwrap_or_else(TraceContext::new_root);
+                let process = async {
+                    let res = handler(message).await;
+                    if let Err(err) = &res {
+                        error::record(err.to_string());
+                    }
+                    res
+                }
+                .in_local_span(format!("{queue} process"))
+                .with_properties(properties);
+                let (res, message_records) = collector::isolate(parent.span_context, process).await;
+                records.extend(message_records);
+                if let Err(err) = res {
+                    error::record(err.to_string());
+                    return (Err(err), records);
+                }
+            }
+            (Ok(()), records)
+        }
+        .in_local_span(format!("{queue} process"))
+        .with_properties(properties)
+        .with_attribute("messaging.batch.message_count", message_count);
+        let (res, records) = collector.run(process).await;
+
+        ctx.wait_until(flush(collector, records, self.export));
+        res
+    }
+}
+
+type Property = (Cow<'static, str>, Cow<'static, str>);
+
+/// Starts collecting for `req`, continuing the trace of its caller if it sent one, if the
+/// global sampler samples it or the debug header forces it to be.
+pub(crate) fn start_collector(req: &Request, honor_sampled: bool, limits: Limits) -> Collector {
+    let remote = propagation::extract(req.headers());
+    let mut parent = remote.clone().unwrap_or_else(TraceContext::new_root);
+    parent.sampled = sampler::is_forced(req)
+        || ((!honor_sampled || parent.sampled)
+            && sampler::should_sample(&SamplingInput {
+                trace_id: parent.span_context.trace_id,
+                parent: remote.as_ref(),
+                request: req,
+            }));
+    Collector::start_remote(&parent, true)
+        .with_baggage(baggage::extract(req.headers()))
+        .with_limits(limits)
+}
+
+/// Runs `handler` on `req` inside a server span, turning errors into `500` responses.
+///
+/// With `body_sizes`, the size of the response body is recorded too, along with the request's
+/// when it has a `content-length`. The returned future resolves once a streamed response body
+/// ended, and its size is known. The context of the server span comes back too, to echo it.
+pub(crate) async fn serve<H, HFut>(
+    req: Request,
+    body_sizes: bool,
+    handler: H,
+) -> worker::Result<(Response, Option<body::Finished>, Option<TraceContext>)>
+where
+    H: FnOnce(Request) -> HFut,
+    HFut: Future<Output = worker::Result<Response>>,
+{
+    let method = req.method();
+    let mut properties = vec![
+        (SPAN_KIND, SpanKind::Server.as_str().to_owned()),
+        ("http.request.method", method.to_string()),
+        ("url.path", normalize_path(&req.path())),
+    ];
+    if let Some(user_agent) = req.headers().get("user-agent").ok().flatten() {
+        properties.push(("user_agent.original", user_agent));
+    }
+    if sampler::is_forced(&req) {
+        properties.push((sampler::FORCED, "true".to_owned()));
+    }
+    let content_length = req
+        .headers()
+        .get("content-length")
+        .ok()
+        .flatten()
+        .and_then(|length| length.parse::<i64>().ok());
+
+    async move {
+        // Taken in the server span, the response is only echoed once it's been left.
+        let context = TraceContext::current();
+        if let (true, Some(content_length)) = (body_sizes, content_length) {
+            current::add_attribute(body::REQUEST_BODY_SIZE, content_length);
+        }
+        let (res, failed) = match handler(req).await {
+            Ok(response) => (response, false),
+            Err(err) => {
+                error::record(err.to_string());
+                (Response::error("Internal Server Error", 500)?, true)
+            }
+        };
+        record_outcome(&res, failed);
+        let (res, finished) = if body_sizes {
+            body::count_response(res)?
+        } else {
+            (res, None)
+        };
+        Ok((res, finished, context))
+    }
+    .in_local_span(method.to_string())
+    .with_properties(properties)
+    .await
+}
+
+/// Records how the request went on the current (server) span: the status code and its class,
+/// where a redirect points to, and whether the response is an error page, either the `500`
+/// standing in for a `failed` handler or one made with `Response::error`.
+fn record_outcome(res: &Response, failed: bool) {
+    let status_code = res.status_code();
+    current::add_attribute("http.response.status_code", status_code as i64);
+    current::add_property(
+        "http.response.status_class",
+        format!("{}xx", status_code / 100),
+    );
+    if (300..400).contains(&status_code) {
+        if let Some(location) = res.headers().get("location").ok().flatten() {
+            current::add_property("http.response.redirect.location", location);
+        }
+    }
+    // `Response::error` is the only constructor leaving an error page without a content type.
+    let error_page = failed
+        || (status_code >= 400
+            && matches!(res.body(), ResponseBody::Body(_))
+            && !res.headers().has("content-type").unwrap_or(true));
+    current::add_attribute("http.response.error_page", error_page);
+    // 4xx are the client's fault, the server span only errors on 5xx. A failed handler already
+    // set a more telling status.
+    if status_code >= 500 && !failed {
+        status::set(SpanStatus::Error(status_code.to_string().into()));
+    }
+}
+
+/// Collects `collector` and hands the records to `export` along with `other` records collected
+/// separately, meant to be run in the background once the handler is done.
+pub(crate) async fn flush<E, Fut>(collector: Collector, other: Vec<SpanRecord>, export: E)
+where
+    E: FnOnce(Vec<SpanRecord>) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let mut records = collector.collect();
+    records.extend(other);
+    if records.is_empty() {
+        return;
+    }
+    processor::run(&mut records);
+    if records.is_empty() {
+        return;
+    }
+    lazy::resolve(&mut records);
+    export(records).await;
+}
+
+fn to_iso_string(unix_ms: f64) -> String {
+    js_sys::Date::new(&JsValue::from_f64(unix_ms))
+        .to_iso_string()
+        .into()
+}
+
+/// `path` without repeated or trailing slashes.
+fn normalize_path(path: &str) -> String {
+    let mut normalized = String::with_capacity(path.len() + 1);
+    for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+        normalized.push('/');
+        normalized.push_str(segment);
+    }
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+    normalized
+}
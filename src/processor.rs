@@ -0,0 +1,377 @@
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+    time::Duration,
+};
+
+use minitrace::collector::{SpanId, SpanRecord};
+
+use crate::{export, kv, lazy, sampler, status::SpanStatus};
+
+// The records of every invocation go through the global processors once they're collected,
+// before the middlewares hand them to the exporters. Processors can change, add or drop
+// records, or the whole trace: whatever they leave is what gets exported, and nothing is
+// exported when they leave nothing. Lazy properties are only resolved for what they leave, so
+// they still hold the reserved property pointing at the closure (see `lazy`) when processors
+// see them.
+//
+// ```ignore
+// let tail_sampling = TailSampling::new().slower_than(Duration::from_millis(500));
+// processor::set_global(Processors::default().with(tail_sampling));
+// ```
+//
+// Processors are `Fn(&mut Vec<SpanRecord>)` too, for one-off changes. The records handed to
+// them can be of several traces, e.g. for queue batches.
+
+/// Changes the records of an invocation before they're exported.
+pub trait Processor {
+    fn process(&self, records: &mut Vec<SpanRecord>);
+}
+
+impl<F: Fn(&mut Vec<SpanRecord>)> Processor for F {
+    fn process(&self, records: &mut Vec<SpanRecord>) {
+        self(records)
+    }
+}
+
+/// A list of processors, run in the order they were added. The default has none.
+#[derive(Default)]
+pub struct Processors(Vec<Box<dyn Processor>>);
+
+impl Processors {
+    /// Adds a processor, run after the ones added before it.
+    pub fn with(mut self, processor: impl Processor + 'static) -> Self {
+        self.0.push(Box::new(processor));
+        self
+    }
+}
+
+impl Processor for Processors {
+    fn process(&self, records: &mut Vec<SpanRecord>) {
+        for processor in &self.0 {
+            if records.is_empty() {
+                return;
+            }
+            processor.process(records);
+        }
+    }
+}
+
+/// Keeps only the traces worth looking at once they're done: those with a span that failed,
+/// and with `slower_than`, those whose local root took at least that long, along with those
+/// forced with the debug header (see `sampler::set_debug_header`). The others are dropped
+/// whole.
+///
+/// Unlike a sampler it sees the whole trace before deciding, but by then the trace has been
+/// recorded, so the two go well together: a sampler to keep the recording cheap, and
+/// `TailSampling` to only pay for storing what's left when it's interesting.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TailSampling {
+    slower_than: Option<Duration>,
+}
+
+impl TailSampling {
+    /// Keeps the traces with a failed span.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps the traces whose root took at least `duration` too.
+    pub fn slower_than(mut self, duration: Duration) -> Self {
+        self.slower_than = Some(duration);
+        self
+    }
+
+    /// Whether the trace of `records` is kept.
+    pub fn keeps(&self, records: &[&SpanRecord]) -> bool {
+        let is_local_root = |record: &SpanRecord| {
+            record.parent_id.0 == 0 || !records.iter().any(|r| r.span_id == record.parent_id)
+        };
+        records.iter().any(|record| {
+            SpanStatus::of(record).is_error()
+                || export::property(record, sampler::FORCED) == Some("true")
+                || self.slower_than.is_some_and(|threshold| {
+                    is_local_root(record) && record.duration_ns as u128 >= threshold.as_nanos()
+                })
+        })
+    }
+}
+
+impl Processor for TailSampling {
+    fn process(&self, records: &mut Vec<SpanRecord>) {
+        let mut traces: HashMap<u128, Vec<&SpanRecord>> = HashMap::new();
+        for record in records.iter() {
+            traces.entry(record.trace_id.0).or_default().push(record);
+        }
+        let kept: Vec<u128> = traces
+            .into_iter()
+            .filter(|(_, trace)| self.keeps(trace))
+            .map(|(trace_id, _)| trace_id)
+            .collect();
+        records.retain(|record| kept.contains(&record.trace_id.0));
+    }
+}
+
+/// Keeps only the local roots, the spans whose parent isn't in the records: the server span
+/// of a request, say, without anything under it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LocalRoots;
+
+impl Processor for LocalRoots {
+    fn process(&self, records: &mut Vec<SpanRecord>) {
+        let ids: HashSet<SpanId> = records.iter().map(|record| record.span_id).collect();
+        records.retain(|record| !ids.contains(&record.parent_id));
+    }
+}
+
+/// Drops the spans whose names match a denylist, or don't match an allowlist, and moves their
+/// children under the closest ancestor that's kept. Names are matched against patterns where
+/// `*` stands for anything, e.g. `worker_rust::helpers::*`. Local roots are always kept, as
+/// are the spans that failed, and the events of dropped spans are dropped with them.
+#[derive(Clone, Debug)]
+pub struct NameFilter {
+    patterns: Vec<String>,
+    allow: bool,
+}
+
+impl NameFilter {
+    /// Drops the spans matching any of `patterns`.
+    pub fn deny<S: Into<String>>(patterns: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            patterns: patterns.into_iter().map(Into::into).collect(),
+            allow: false,
+        }
+    }
+
+    /// Only keeps the spans matching any of `patterns`.
+    pub fn allow<S: Into<String>>(patterns: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            allow: true,
+            ..Self::deny(patterns)
+        }
+    }
+
+    fn drops(&self, record: &SpanRecord) -> bool {
+        let matches = self
+            .patterns
+            .iter()
+            .any(|pattern| glob(pattern, &record.name));
+        matches != self.allow && !SpanStatus::of(record).is_error()
+    }
+}
+
+impl Processor for NameFilter {
+    fn process(&self, records: &mut Vec<SpanRecord>) {
+        let ids: HashSet<SpanId> = records.iter().map(|record| record.span_id).collect();
+        let dropped: HashMap<SpanId, SpanId> = records
+            .iter()
+            .filter(|record| ids.contains(&record.parent_id) && self.drops(record))
+            .map(|record| (record.span_id, record.parent_id))
+            .collect();
+        if dropped.is_empty() {
+            return;
+        }
+        records.retain(|record| !dropped.contains_key(&record.span_id));
+        for record in records {
+            while let Some(parent) = dropped.get(&record.parent_id) {
+                record.parent_id = *parent;
+            }
+        }
+    }
+}
+
+/// Set by `MinDuration` on the spans whose children it dropped, to how many.
+pub const FOLDED_SPANS: &str = "spans_folded";
+
+/// Drops the leaf spans, those with no children, that took less than a threshold: think one
+/// span per item of a loop, each a few µs. Their parent gets the number of them as
+/// `FOLDED_SPANS`, so the trace still shows how many there were. Spans that failed are kept,
+/// and so are local roots, whatever they took.
+#[derive(Clone, Copy, Debug)]
+pub struct MinDuration {
+    threshold: Duration,
+}
+
+impl MinDuration {
+    /// Drops the leaf spans shorter than `threshold`, e.g. `Duration::from_micros(50)`.
+    pub fn new(threshold: Duration) -> Self {
+        Self { threshold }
+    }
+}
+
+impl Processor for MinDuration {
+    fn process(&self, records: &mut Vec<SpanRecord>) {
+        let ids: HashSet<SpanId> = records.iter().map(|record| record.span_id).collect();
+        let parents: HashSet<SpanId> = records.iter().map(|record| record.parent_id).collect();
+        let mut folded: HashMap<SpanId, usize> = HashMap::new();
+        records.retain(|record| {
+            let drops = ids.contains(&record.parent_id)
+                && !parents.contains(&record.span_id)
+                && (record.duration_ns as u128) < self.threshold.as_nanos()
+                && !SpanStatus::of(record).is_error();
+            if drops {
+                *folded.entry(record.parent_id).or_default() += 1;
+            }
+            !drops
+        });
+        for record in records {
+            let Some(count) = folded.remove(&record.span_id) else {
+                continue;
+            };
+            match record
+                .properties
+                .iter_mut()
+                .find(|(k, _)| k == FOLDED_SPANS)
+            {
+                Some((_, value)) => {
+                    let before: usize = value.parse().unwrap_or_default();
+                    *value = (before + count).to_string().into();
+                }
+                None => record
+                    .properties
+                    .push((FOLDED_SPANS.into(), count.to_string().into())),
+            }
+        }
+    }
+}
+
+/// What `Redact` replaces the values it redacts with, unless it hashes them.
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Redacts the values of the properties, of spans and events alike, whose keys match any of a
+/// list of patterns (checked like `NameFilter`'s, ignoring case), along with the values that
+/// look like email addresses whatever their key. The default patterns catch credentials and
+/// cookies: `*authorization*`, `*cookie*`, `*password*`, `*secret*`, `*token*`, `*api_key*`
+/// and `*api-key*`.
+///
+/// With `hash`, values are replaced with a hash instead, which still tells them apart (to see
+/// that it's always the same user, say) but is no protection against guessing: anyone can
+/// hash every address of a list and compare.
+#[derive(Clone, Debug)]
+pub struct Redact {
+    keys: Vec<String>,
+    emails: bool,
+    hash: bool,
+}
+
+impl Default for Redact {
+    fn default() -> Self {
+        Self {
+            keys: [
+                "*authorization*",
+                "*cookie*",
+                "*password*",
+                "*secret*",
+                "*token*",
+                "*api_key*",
+                "*api-key*",
+            ]
+            .map(String::from)
+            .to_vec(),
+            emails: true,
+            hash: false,
+        }
+    }
+}
+
+impl Redact {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Redacts the values of the keys matching `pattern` too.
+    pub fn key(mut self, pattern: impl Into<String>) -> Self {
+        self.keys.push(pattern.into().to_ascii_lowercase());
+        self
+    }
+
+    /// Whether values looking like email addresses are redacted, which is the default.
+    pub fn emails(mut self, enabled: bool) -> Self {
+        self.emails = enabled;
+        self
+    }
+
+    /// Whether values are replaced with a hash of them rather than `REDACTED`.
+    pub fn hash(mut self, enabled: bool) -> Self {
+        self.hash = enabled;
+        self
+    }
+
+    fn redact(&self, properties: &mut [(Cow<'static, str>, Cow<'static, str>)]) {
+        for (key, value) in properties {
+            let key = key.to_ascii_lowercase();
+            let sensitive = self.keys.iter().any(|pattern| glob(pattern, &key))
+                || (self.emails && value.split_whitespace().any(is_email));
+            if !sensitive {
+                continue;
+            }
+            *value = if self.hash {
+                format!("{:016x}", kv::fnv1a(value.as_bytes())).into()
+            } else {
+                REDACTED.into()
+            };
+        }
+    }
+}
+
+impl Processor for Redact {
+    fn process(&self, records: &mut Vec<SpanRecord>) {
+        for record in records {
+            self.redact(&mut record.properties);
+            for event in &mut record.events {
+                self.redact(&mut event.properties);
+            }
+        }
+    }
+}
+
+/// Whether `word` reads like an email address: something, an `@`, and a domain with a dot.
+fn is_email(word: &str) -> bool {
+    let word = word.trim_matches(|c: char| !c.is_alphanumeric());
+    let Some((local, domain)) = word.split_once('@') else {
+        return false;
+    };
+    !local.is_empty()
+        && domain
+            .split_once('.')
+            .is_some_and(|(name, tld)| !name.is_empty() && !tld.is_empty())
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any run of characters.
+fn glob(pattern: &str, name: &str) -> bool {
+    let Some((first, rest)) = pattern.split_once('*') else {
+        return pattern == name;
+    };
+    let Some(mut name) = name.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<_> = rest.split('*').collect();
+    let last = parts.pop().unwrap_or_default();
+    for part in parts {
+        match name.find(part) {
+            Some(at) => name = &name[at + part.len()..],
+            None => return false,
+        }
+    }
+    name.len() >= last.len() && name.ends_with(last)
+}
+
+thread_local! {
+    static GLOBAL: RefCell<Rc<Processors>> = RefCell::new(Rc::new(Processors::default()));
+}
+
+/// Sets the processors the records of every invocation go through.
+pub fn set_global(processors: Processors) {
+    GLOBAL.with(|global| *global.borrow_mut() = Rc::new(processors));
+}
+
+/// Runs the global processors on `records`, and discards the lazy properties of the records
+/// they dropped.
+pub fn run(records: &mut Vec<SpanRecord>) {
+    let processors = GLOBAL.with(|global| global.borrow().clone());
+    let lazy = lazy::ids(records);
+    processors.process(records);
+    lazy::discard_dropped(lazy, records);
+}
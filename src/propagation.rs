@@ -0,0 +1,398 @@
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use minitrace::collector::{SpanContext, SpanId, TraceId};
+use worker::{Headers, Response};
+
+use crate::{collector, current};
+
+pub mod b3;
+pub mod gcp;
+pub mod xray;
+
+pub use b3::{B3Multi, B3Single};
+pub use gcp::Gcp;
+pub use xray::XRay;
+
+// Continues traces started by another service. The caller's context comes in as a W3C
+// `traceparent` header (https://www.w3.org/TR/trace-context/): its trace id is used for the
+// whole request, and its span id becomes the parent of the worker's top-level spans.
+//
+// The `tracestate` header next to it carries vendor specific state (sampling decisions and
+// the like) that the worker doesn't interpret, only passes on: to subrequests, through
+// `inject`, and to exporters, as the `TRACE_STATE` property of every collected record.
+//
+// Services that don't speak W3C are supported by other `Propagator`s, see `set_global`.
+
+pub const TRACEPARENT: &str = "traceparent";
+pub const TRACESTATE: &str = "tracestate";
+/// The response header `echo` sets to the trace id.
+pub const X_TRACE_ID: &str = "x-trace-id";
+/// The property the `tracestate` of the trace is recorded as.
+pub const TRACE_STATE: &str = "w3c.tracestate";
+
+/// The most list members a `tracestate` can have, the spec says to drop the rest.
+const MAX_TRACE_STATE_MEMBERS: usize = 32;
+
+/// The context of a span in another service, and whether it was sampled there.
+#[derive(Clone, Debug)]
+pub struct TraceContext {
+    pub span_context: SpanContext,
+    pub sampled: bool,
+    pub trace_state: TraceState,
+}
+
+impl TraceContext {
+    /// The context of a new trace, for requests that didn't come with one.
+    pub fn new_root() -> Self {
+        let mut buf = [0; 16];
+        getrandom::getrandom(&mut buf).expect("failed to generate a random trace id");
+        Self {
+            span_context: SpanContext::new(TraceId(u128::from_ne_bytes(buf)), SpanId::default()),
+            sampled: true,
+            trace_state: TraceState::default(),
+        }
+    }
+
+    /// The context of the current span, to propagate it to a subrequest. When nothing is
+    /// recorded, that's the context the collector was started with.
+    pub fn current() -> Option<Self> {
+        let context = collector::active_context()?;
+        let span_id = current::span_id().unwrap_or(context.span_id);
+        if span_id == SpanId::default() {
+            return None;
+        }
+        Some(Self {
+            span_context: SpanContext::new(context.trace_id, span_id),
+            sampled: collector::active_sampled().unwrap_or(true),
+            trace_state: current::trace_state().unwrap_or_default(),
+        })
+    }
+
+    /// Parses a `traceparent` header value, `None` if it isn't valid.
+    pub fn parse_traceparent(value: &str) -> Option<Self> {
+        let mut parts = value.trim().split('-');
+        let version = parts.next().filter(|v| is_hex(v, 2) && *v != "ff")?;
+        let trace_id = parts.next().filter(|v| is_hex(v, 32))?;
+        let span_id = parts.next().filter(|v| is_hex(v, 16))?;
+        let flags = parts.next().filter(|v| is_hex(v, 2))?;
+        // Later versions may append fields, version 00 can't.
+        if version == "00" && parts.next().is_some() {
+            return None;
+        }
+
+        let trace_id = u128::from_str_radix(trace_id, 16)
+            .ok()
+            .filter(|id| *id != 0)?;
+        let span_id = u64::from_str_radix(span_id, 16)
+            .ok()
+            .filter(|id| *id != 0)?;
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+        Some(Self {
+            span_context: SpanContext::new(TraceId(trace_id), SpanId(span_id)),
+            sampled: flags & 1 == 1,
+            trace_state: TraceState::default(),
+        })
+    }
+
+    /// Formats the context as a `traceparent` header value.
+    pub fn to_traceparent(&self) -> String {
+        self.span_context
+            .encode_w3c_traceparent_with_sampled(self.sampled)
+    }
+}
+
+/// The `tracestate` header: a list of `key=value` members, most recently updated first.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceState(Vec<(String, String)>);
+
+impl TraceState {
+    /// Parses a `tracestate` header value. Invalid members are skipped, as are duplicate
+    /// keys after their first occurrence.
+    pub fn parse(value: &str) -> Self {
+        let mut members: Vec<(String, String)> = Vec::new();
+        for member in value.split(',').map(str::trim).filter(|m| !m.is_empty()) {
+            let Some((key, value)) = member.split_once('=') else {
+                continue;
+            };
+            if !is_valid_key(key) || !is_valid_value(value) || members.iter().any(|(k, _)| k == key)
+            {
+                continue;
+            }
+            members.push((key.to_owned(), value.to_owned()));
+        }
+        members.truncate(MAX_TRACE_STATE_MEMBERS);
+        Self(members)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Sets `key`, moving it to the front as the spec requires for updated members. Invalid
+    /// keys or values are ignored.
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        let (key, value) = (key.into(), value.into());
+        if !is_valid_key(&key) || !is_valid_value(&value) {
+            return;
+        }
+        self.remove(&key);
+        self.0.insert(0, (key, value));
+        self.0.truncate(MAX_TRACE_STATE_MEMBERS);
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let index = self.0.iter().position(|(k, _)| k == key)?;
+        Some(self.0.remove(index).1)
+    }
+}
+
+impl fmt::Display for TraceState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (k, v)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{k}={v}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A header format the context can be propagated in.
+///
+/// ```ignore
+/// struct Internal;
+///
+/// impl Propagator for Internal {
+///     fn extract(&self, headers: &Headers) -> Option<TraceContext> { ... }
+///     fn inject(&self, headers: &mut Headers, context: &TraceContext) -> worker::Result<()> { ... }
+/// }
+///
+/// propagation::set_global(Propagators::default().with(Internal));
+/// ```
+pub trait Propagator {
+    /// Reads the context propagated in `headers`, `None` if there's none or it's invalid.
+    fn extract(&self, headers: &Headers) -> Option<TraceContext>;
+
+    /// Propagates `context` in `headers`.
+    fn inject(&self, headers: &mut Headers, context: &TraceContext) -> worker::Result<()>;
+}
+
+/// `traceparent` and `tracestate`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct W3c;
+
+impl Propagator for W3c {
+    fn extract(&self, headers: &Headers) -> Option<TraceContext> {
+        let traceparent = headers.get(TRACEPARENT).ok().flatten()?;
+        let mut context = TraceContext::parse_traceparent(&traceparent)?;
+        // A `tracestate` without a valid `traceparent` is meaningless, so it's only read here.
+        if let Some(trace_state) = headers.get(TRACESTATE).ok().flatten() {
+            context.trace_state = TraceState::parse(&trace_state);
+        }
+        Some(context)
+    }
+
+    fn inject(&self, headers: &mut Headers, context: &TraceContext) -> worker::Result<()> {
+        headers.set(TRACEPARENT, &context.to_traceparent())?;
+        if context.trace_state.is_empty() {
+            headers.delete(TRACESTATE)
+        } else {
+            headers.set(TRACESTATE, &context.trace_state.to_string())
+        }
+    }
+}
+
+/// A list of propagators: the context is extracted with the first one that finds it, and
+/// injected with all of them. The default is [`W3c`] alone.
+pub struct Propagators(Vec<Box<dyn Propagator>>);
+
+impl Propagators {
+    /// No propagators at all, so traces are never continued nor propagated.
+    pub fn none() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Adds a propagator, tried after the ones added before it.
+    pub fn with(mut self, propagator: impl Propagator + 'static) -> Self {
+        self.0.push(Box::new(propagator));
+        self
+    }
+}
+
+impl Default for Propagators {
+    fn default() -> Self {
+        Self::none().with(W3c)
+    }
+}
+
+impl Propagator for Propagators {
+    fn extract(&self, headers: &Headers) -> Option<TraceContext> {
+        self.0
+            .iter()
+            .find_map(|propagator| propagator.extract(headers))
+    }
+
+    fn inject(&self, headers: &mut Headers, context: &TraceContext) -> worker::Result<()> {
+        self.0
+            .iter()
+            .try_for_each(|propagator| propagator.inject(headers, context))
+    }
+}
+
+thread_local! {
+    static GLOBAL: RefCell<Rc<Propagators>> = RefCell::new(Rc::new(Propagators::default()));
+}
+
+/// Sets the propagators used by `extract` and `inject`, and so by `fetch::send`.
+pub fn set_global(propagators: Propagators) {
+    GLOBAL.with(|global| *global.borrow_mut() = Rc::new(propagators));
+}
+
+/// Reads the context propagated in `headers` with the global propagators, if any.
+pub fn extract(headers: &Headers) -> Option<TraceContext> {
+    global().extract(headers)
+}
+
+/// Propagates `context` in `headers` with the global propagators.
+pub fn inject(headers: &mut Headers, context: &TraceContext) -> worker::Result<()> {
+    global().inject(headers, context)
+}
+
+/// Sets `x-trace-id` on `response` to the trace id of `context`, that of the server span, so
+/// clients (and whoever they report a failed call to) can look the trace up. With
+/// `with_traceparent`, `context` is added as `traceparent` too.
+pub fn echo(
+    mut response: Response,
+    context: &TraceContext,
+    with_traceparent: bool,
+) -> worker::Result<Response> {
+    // The headers of a response received from a subrequest are immutable.
+    let mut headers = response.headers().clone();
+    headers.set(
+        X_TRACE_ID,
+        &format!("{:032x}", context.span_context.trace_id.0),
+    )?;
+    if with_traceparent {
+        headers.set(TRACEPARENT, &context.to_traceparent())?;
+    }
+    response = response.with_headers(headers);
+    Ok(response)
+}
+
+fn global() -> Rc<Propagators> {
+    GLOBAL.with(|global| global.borrow().clone())
+}
+
+// `key` is `[a-z0-9][a-z0-9_*/-]*`, optionally followed by `@vendor` (multi-tenant keys).
+fn is_valid_key(key: &str) -> bool {
+    let valid = |part: &str, max_len| {
+        let mut bytes = part.bytes();
+        part.len() <= max_len
+            && bytes
+                .next()
+                .is_some_and(|b| b.is_ascii_lowercase() || b.is_ascii_digit())
+            && bytes.all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b"_-*/".contains(&b))
+    };
+    match key.split_once('@') {
+        Some((tenant, vendor)) => valid(tenant, 241) && valid(vendor, 14),
+        None => valid(key, 256),
+    }
+}
+
+// Printable ASCII except `,` and `=`, not ending with a space.
+fn is_valid_value(value: &str) -> bool {
+    !value.is_empty()
+        && value.len() <= 256
+        && !value.ends_with(' ')
+        && value
+            .bytes()
+            .all(|b| (0x20..=0x7e).contains(&b) && b != b',' && b != b'=')
+}
+
+fn is_hex(value: &str, len: usize) -> bool {
+    value.len() == len
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn traceparent() {
+        let value = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let context = TraceContext::parse_traceparent(value).unwrap();
+        assert_eq!(
+            context.span_context.trace_id,
+            TraceId(0x4bf92f3577b34da6a3ce929d0e0e4736)
+        );
+        assert_eq!(context.span_context.span_id, SpanId(0x00f067aa0ba902b7));
+        assert!(context.sampled);
+        assert_eq!(context.to_traceparent(), value);
+
+        let context = TraceContext::parse_traceparent(
+            " 00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00 ",
+        )
+        .unwrap();
+        assert!(!context.sampled);
+        // Later versions may have more fields.
+        assert!(TraceContext::parse_traceparent(
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-what-comes-next"
+        )
+        .is_some());
+    }
+
+    #[test]
+    fn traceparent_invalid() {
+        for value in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-1",
+        ] {
+            assert!(TraceContext::parse_traceparent(value).is_none(), "{value}");
+        }
+    }
+
+    #[test]
+    fn trace_state() {
+        let mut trace_state =
+            TraceState::parse("congo=t61rcWkgMzE, rojo=00f067aa0ba902b7,invalid,Upper=1,congo=dup");
+        assert_eq!(
+            trace_state.to_string(),
+            "congo=t61rcWkgMzE,rojo=00f067aa0ba902b7"
+        );
+        assert_eq!(trace_state.get("rojo"), Some("00f067aa0ba902b7"));
+
+        trace_state.insert("rojo", "updated");
+        trace_state.insert("tenant@vendor", "x");
+        trace_state.insert("bad key", "x");
+        trace_state.insert("key", "bad,value");
+        assert_eq!(
+            trace_state.to_string(),
+            "tenant@vendor=x,rojo=updated,congo=t61rcWkgMzE"
+        );
+        assert_eq!(trace_state.remove("congo").as_deref(), Some("t61rcWkgMzE"));
+
+        let members = (0..40).map(|i| format!("k{i}=v")).collect::<Vec<_>>();
+        assert_eq!(
+            TraceState::parse(&members.join(",")).0.len(),
+            MAX_TRACE_STATE_MEMBERS
+        );
+    }
+}
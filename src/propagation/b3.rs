@@ -0,0 +1,209 @@
+use minitrace::collector::{SpanContext, SpanId, TraceId};
+use worker::Headers;
+
+use super::{is_hex, Propagator, TraceContext, TraceState};
+
+// Zipkin's B3 headers (https://github.com/openzipkin/b3-propagation), either all in a single
+// `b3` header (`{trace id}-{span id}-{sampled}-{parent span id}`, the last two optional) or
+// spread over one `X-B3-*` header per field. Trace ids can be 64 or 128 bits.
+
+pub const B3: &str = "b3";
+pub const TRACE_ID: &str = "x-b3-traceid";
+pub const SPAN_ID: &str = "x-b3-spanid";
+pub const SAMPLED: &str = "x-b3-sampled";
+pub const FLAGS: &str = "x-b3-flags";
+pub const PARENT_SPAN_ID: &str = "x-b3-parentspanid";
+
+/// A single `b3` header.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct B3Single;
+
+/// `X-B3-TraceId`, `X-B3-SpanId` and `X-B3-Sampled`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct B3Multi;
+
+impl Propagator for B3Single {
+    fn extract(&self, headers: &Headers) -> Option<TraceContext> {
+        extract_single(headers)
+    }
+
+    fn inject(&self, headers: &mut Headers, context: &TraceContext) -> worker::Result<()> {
+        inject_single(headers, context)
+    }
+}
+
+impl Propagator for B3Multi {
+    fn extract(&self, headers: &Headers) -> Option<TraceContext> {
+        extract_multi(headers)
+    }
+
+    fn inject(&self, headers: &mut Headers, context: &TraceContext) -> worker::Result<()> {
+        inject_multi(headers, context)
+    }
+}
+
+fn extract_single(headers: &Headers) -> Option<TraceContext> {
+    parse_single(&headers.get(B3).ok().flatten()?)
+}
+
+fn extract_multi(headers: &Headers) -> Option<TraceContext> {
+    parse_multi(|name| headers.get(name).ok().flatten())
+}
+
+fn parse_single(value: &str) -> Option<TraceContext> {
+    let mut parts = value.trim().split('-');
+    let trace_id = parse_trace_id(parts.next()?)?;
+    let span_id = parse_span_id(parts.next()?)?;
+    // A missing sampling state defers the decision to us.
+    let sampled = match parts.next() {
+        Some(sampled) => parse_sampled(sampled)?,
+        None => true,
+    };
+    Some(context(trace_id, span_id, sampled))
+}
+
+fn parse_multi(get: impl Fn(&str) -> Option<String>) -> Option<TraceContext> {
+    let trace_id = parse_trace_id(&get(TRACE_ID)?)?;
+    let span_id = parse_span_id(&get(SPAN_ID)?)?;
+    // The debug flag implies the span is sampled.
+    let sampled = match (get(FLAGS).as_deref(), get(SAMPLED)) {
+        (Some("1"), _) => true,
+        (_, Some(sampled)) => parse_sampled(&sampled)?,
+        (_, None) => true,
+    };
+    Some(context(trace_id, span_id, sampled))
+}
+
+fn inject_single(headers: &mut Headers, context: &TraceContext) -> worker::Result<()> {
+    let span_context = context.span_context;
+    headers.set(
+        B3,
+        &format!(
+            "{:032x}-{:016x}-{}",
+            span_context.trace_id.0, span_context.span_id.0, context.sampled as u8
+        ),
+    )
+}
+
+fn inject_multi(headers: &mut Headers, context: &TraceContext) -> worker::Result<()> {
+    let span_context = context.span_context;
+    headers.set(TRACE_ID, &format!("{:032x}", span_context.trace_id.0))?;
+    headers.set(SPAN_ID, &format!("{:016x}", span_context.span_id.0))?;
+    headers.set(SAMPLED, if context.sampled { "1" } else { "0" })
+}
+
+fn context(trace_id: TraceId, span_id: SpanId, sampled: bool) -> TraceContext {
+    TraceContext {
+        span_context: SpanContext::new(trace_id, span_id),
+        sampled,
+        trace_state: TraceState::default(),
+    }
+}
+
+fn parse_trace_id(value: &str) -> Option<TraceId> {
+    if !is_hex(value, 16) && !is_hex(value, 32) {
+        return None;
+    }
+    let id = u128::from_str_radix(value, 16).ok().filter(|id| *id != 0)?;
+    Some(TraceId(id))
+}
+
+fn parse_span_id(value: &str) -> Option<SpanId> {
+    if !is_hex(value, 16) {
+        return None;
+    }
+    let id = u64::from_str_radix(value, 16).ok().filter(|id| *id != 0)?;
+    Some(SpanId(id))
+}
+
+fn parse_sampled(value: &str) -> Option<bool> {
+    match value {
+        "1" | "d" | "true" => Some(true),
+        "0" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACE_ID_128: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    fn ids(context: &TraceContext) -> (u128, u64, bool) {
+        let span_context = context.span_context;
+        (
+            span_context.trace_id.0,
+            span_context.span_id.0,
+            context.sampled,
+        )
+    }
+
+    #[test]
+    fn single() {
+        let context = parse_single(&format!("{TRACE_ID_128}-00f067aa0ba902b7-1")).unwrap();
+        assert_eq!(
+            ids(&context),
+            (0x4bf92f3577b34da6a3ce929d0e0e4736, 0x00f067aa0ba902b7, true)
+        );
+        // 64 bit trace ids, a parent span id, and a missing sampling state.
+        let context = parse_single("a3ce929d0e0e4736-00f067aa0ba902b7-0-05e3ac9a4f6e3b90").unwrap();
+        assert_eq!(
+            ids(&context),
+            (0xa3ce929d0e0e4736, 0x00f067aa0ba902b7, false)
+        );
+        assert!(
+            parse_single("a3ce929d0e0e4736-00f067aa0ba902b7")
+                .unwrap()
+                .sampled
+        );
+        assert!(
+            parse_single(&format!("{TRACE_ID_128}-00f067aa0ba902b7-d"))
+                .unwrap()
+                .sampled
+        );
+    }
+
+    #[test]
+    fn single_invalid() {
+        for value in [
+            "",
+            "0",
+            "a3ce929d0e0e4736",
+            "a3ce929d0e0e4736-00f067aa0ba902b7-2",
+            "a3ce929d0e0e47-00f067aa0ba902b7",
+            "a3ce929d0e0e4736-0000000000000000",
+            "0000000000000000-00f067aa0ba902b7",
+            "A3CE929D0E0E4736-00f067aa0ba902b7",
+        ] {
+            assert!(parse_single(value).is_none(), "{value}");
+        }
+    }
+
+    #[test]
+    fn multi() {
+        let headers = |sampled: Option<&'static str>, flags: Option<&'static str>| {
+            move |name: &str| match name {
+                TRACE_ID => Some(TRACE_ID_128.to_owned()),
+                SPAN_ID => Some("00f067aa0ba902b7".to_owned()),
+                SAMPLED => sampled.map(str::to_owned),
+                FLAGS => flags.map(str::to_owned),
+                _ => None,
+            }
+        };
+        let context = parse_multi(headers(Some("0"), None)).unwrap();
+        assert_eq!(
+            ids(&context),
+            (
+                0x4bf92f3577b34da6a3ce929d0e0e4736,
+                0x00f067aa0ba902b7,
+                false
+            )
+        );
+        assert!(parse_multi(headers(None, None)).unwrap().sampled);
+        // Debug implies sampled.
+        assert!(parse_multi(headers(Some("0"), Some("1"))).unwrap().sampled);
+        assert!(parse_multi(headers(Some("maybe"), None)).is_none());
+        assert!(parse_multi(|name| (name == TRACE_ID).then(|| TRACE_ID_128.to_owned())).is_none());
+    }
+}
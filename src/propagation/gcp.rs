@@ -0,0 +1,91 @@
+use minitrace::collector::{SpanContext, SpanId, TraceId};
+use worker::Headers;
+
+use super::{is_hex, Propagator, TraceContext, TraceState};
+
+// Google Cloud Trace's `X-Cloud-Trace-Context` header: `{trace id}/{span id};o={0|1}`, with
+// a 32 hex digit trace id and a decimal span id. `o=1` means the request is traced, a missing
+// `o` leaves the decision to us, like a `traceparent` with the sampled flag set.
+
+pub const X_CLOUD_TRACE_CONTEXT: &str = "x-cloud-trace-context";
+
+/// `X-Cloud-Trace-Context`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Gcp;
+
+impl Propagator for Gcp {
+    fn extract(&self, headers: &Headers) -> Option<TraceContext> {
+        parse(&headers.get(X_CLOUD_TRACE_CONTEXT).ok().flatten()?)
+    }
+
+    fn inject(&self, headers: &mut Headers, context: &TraceContext) -> worker::Result<()> {
+        let SpanContext { trace_id, span_id } = context.span_context;
+        headers.set(
+            X_CLOUD_TRACE_CONTEXT,
+            &format!(
+                "{:032x}/{};o={}",
+                trace_id.0, span_id.0, context.sampled as u8
+            ),
+        )
+    }
+}
+
+fn parse(value: &str) -> Option<TraceContext> {
+    let (ids, options) = value.trim().split_once(';').unwrap_or((value.trim(), ""));
+    let (trace_id, span_id) = ids.split_once('/')?;
+
+    let trace_id = trace_id.to_ascii_lowercase();
+    if !is_hex(&trace_id, 32) {
+        return None;
+    }
+    let trace_id = u128::from_str_radix(&trace_id, 16)
+        .ok()
+        .filter(|id| *id != 0)?;
+    let span_id = span_id.parse::<u64>().ok().filter(|id| *id != 0)?;
+    let sampled = !options.split(';').any(|option| option.trim() == "o=0");
+
+    Some(TraceContext {
+        span_context: SpanContext::new(TraceId(trace_id), SpanId(span_id)),
+        sampled,
+        trace_state: TraceState::default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header() {
+        let context = parse("4BF92F3577B34DA6A3CE929D0E0E4736/123456789;o=1").unwrap();
+        assert_eq!(
+            context.span_context.trace_id,
+            TraceId(0x4bf92f3577b34da6a3ce929d0e0e4736)
+        );
+        assert_eq!(context.span_context.span_id, SpanId(123456789));
+        assert!(context.sampled);
+        // A missing option leaves the decision to us.
+        assert!(parse("4bf92f3577b34da6a3ce929d0e0e4736/1").unwrap().sampled);
+        assert!(
+            !parse("4bf92f3577b34da6a3ce929d0e0e4736/1;o=0")
+                .unwrap()
+                .sampled
+        );
+    }
+
+    #[test]
+    fn header_invalid() {
+        for value in [
+            "",
+            "4bf92f3577b34da6a3ce929d0e0e4736",
+            "4bf92f3577b34da6a3ce929d0e0e4736/",
+            "4bf92f3577b34da6a3ce929d0e0e473/1",
+            "00000000000000000000000000000000/1",
+            "4bf92f3577b34da6a3ce929d0e0e4736/0",
+            "4bf92f3577b34da6a3ce929d0e0e4736/00f067aa0ba902b7",
+            "4bf92f3577b34da6a3ce929d0e0e4736/18446744073709551616",
+        ] {
+            assert!(parse(value).is_none(), "{value}");
+        }
+    }
+}
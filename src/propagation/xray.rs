@@ -0,0 +1,128 @@
+use minitrace::collector::{SpanContext, SpanId, TraceId};
+use worker::Headers;
+
+use super::{is_hex, Propagator, TraceContext, TraceState};
+
+// AWS X-Ray's `X-Amzn-Trace-Id` header, as set by ALBs and API Gateway:
+// `Root=1-{epoch seconds, 8 hex}-{96 random bits, 24 hex};Parent={span id};Sampled={0|1}`.
+//
+// Both parts of the root make up the 128 bit trace id, so the conversion is lossless. X-Ray
+// itself rejects trace ids whose first 32 bits aren't a recent timestamp though, which random
+// W3C trace ids usually aren't: for traces that start in the worker and end up in X-Ray, see
+// `XRay::new_root`.
+
+pub const X_AMZN_TRACE_ID: &str = "x-amzn-trace-id";
+
+/// `X-Amzn-Trace-Id`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct XRay;
+
+impl XRay {
+    /// The context of a new trace whose id is a valid X-Ray trace id.
+    pub fn new_root() -> TraceContext {
+        let mut context = TraceContext::new_root();
+        let epoch = (worker::Date::now().as_millis() / 1000) as u128;
+        let random = context.span_context.trace_id.0 & ((1 << 96) - 1);
+        context.span_context.trace_id = TraceId((epoch << 96) | random);
+        context
+    }
+}
+
+impl Propagator for XRay {
+    fn extract(&self, headers: &Headers) -> Option<TraceContext> {
+        parse(&headers.get(X_AMZN_TRACE_ID).ok().flatten()?)
+    }
+
+    fn inject(&self, headers: &mut Headers, context: &TraceContext) -> worker::Result<()> {
+        let SpanContext { trace_id, span_id } = context.span_context;
+        headers.set(
+            X_AMZN_TRACE_ID,
+            &format!(
+                "Root=1-{:08x}-{:024x};Parent={:016x};Sampled={}",
+                trace_id.0 >> 96,
+                trace_id.0 & ((1 << 96) - 1),
+                span_id.0,
+                context.sampled as u8
+            ),
+        )
+    }
+}
+
+fn parse(value: &str) -> Option<TraceContext> {
+    let (mut trace_id, mut span_id, mut sampled) = (None, None, true);
+    for field in value.split(';') {
+        match field.trim().split_once('=') {
+            Some(("Root", root)) => trace_id = parse_root(root),
+            Some(("Parent", parent)) if is_hex(parent, 16) => {
+                span_id = u64::from_str_radix(parent, 16).ok().filter(|id| *id != 0);
+            }
+            // `?` means the caller left the decision to us.
+            Some(("Sampled", "0")) => sampled = false,
+            _ => {}
+        }
+    }
+
+    Some(TraceContext {
+        span_context: SpanContext::new(TraceId(trace_id?), SpanId(span_id?)),
+        sampled,
+        trace_state: TraceState::default(),
+    })
+}
+
+fn parse_root(root: &str) -> Option<u128> {
+    let mut parts = root.split('-');
+    let (Some("1"), Some(epoch), Some(random), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+    if !is_hex(epoch, 8) || !is_hex(random, 24) {
+        return None;
+    }
+    let epoch = u128::from_str_radix(epoch, 16).ok()?;
+    let random = u128::from_str_radix(random, 16).ok()?;
+    Some((epoch << 96) | random).filter(|id| *id != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header() {
+        let context =
+            parse("Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=1")
+                .unwrap();
+        assert_eq!(
+            context.span_context.trace_id,
+            TraceId(0x5759e988_bd862e3fe1be46a994272793)
+        );
+        assert_eq!(context.span_context.span_id, SpanId(0x53995c3f42cd8ad8));
+        assert!(context.sampled);
+        // Fields in any order, and a decision left to us.
+        let context =
+            parse("Sampled=?; Parent=53995c3f42cd8ad8; Root=1-5759e988-bd862e3fe1be46a994272793")
+                .unwrap();
+        assert!(context.sampled);
+        let context =
+            parse("Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=0")
+                .unwrap();
+        assert!(!context.sampled);
+    }
+
+    #[test]
+    fn header_invalid() {
+        for value in [
+            "",
+            // API Gateway only sends the root, there's no span to continue.
+            "Root=1-5759e988-bd862e3fe1be46a994272793",
+            "Root=2-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8",
+            "Root=1-5759e98-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8",
+            "Root=1-5759e988-bd862e3fe1be46a994272793-00;Parent=53995c3f42cd8ad8",
+            "Root=1-5759e988-bd862e3fe1be46a994272793;Parent=0000000000000000",
+            "Root=1-00000000-000000000000000000000000;Parent=53995c3f42cd8ad8",
+        ] {
+            assert!(parse(value).is_none(), "{value}");
+        }
+    }
+}
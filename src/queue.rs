@@ -0,0 +1,138 @@
+use serde::{Deserialize, Serialize};
+use worker::{
+    d1::serde_wasm_bindgen,
+    js_sys::{Array, Function, Object, Promise, Reflect},
+    wasm_bindgen::{JsCast, JsValue},
+    wasm_bindgen_futures::JsFuture,
+    Queue,
+};
+
+use crate::{
+    error,
+    kind::{SpanKind, SPAN_KIND},
+    local_future::LocalFutureExt,
+    propagation::{TraceContext, TraceState},
+};
+
+/// `messaging.system` of Cloudflare Queues.
+pub(crate) const MESSAGING_SYSTEM: &str = "cloudflare_queues";
+
+// Queue messages don't have headers to carry a trace context in, so it travels in the body
+// instead: messages are wrapped in a `Traced` envelope holding the body and the `traceparent`
+// (and `tracestate`) of the span that sent it. `QueueTracing` unwraps it on the consumer
+// side so the processing of each message continues the trace of its producer.
+//
+// `TracedQueue` does the wrapping on the producer side, from inside a producer span, so the
+// consumer's spans end up under it.
+
+/// A message body along with the context of its producer.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Traced<T> {
+    pub body: T,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub traceparent: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tracestate: Option<String>,
+}
+
+impl<T> Traced<T> {
+    /// Wraps `body` with the context of the current span, if any.
+    pub fn new(body: T) -> Self {
+        let context = TraceContext::current();
+        Self {
+            body,
+            traceparent: context.as_ref().map(TraceContext::to_traceparent),
+            tracestate: context
+                .filter(|context| !context.trace_state.is_empty())
+                .map(|context| context.trace_state.to_string()),
+        }
+    }
+
+    /// The context of the producer, if it sent a valid one.
+    pub fn context(&self) -> Option<TraceContext> {
+        let mut context = TraceContext::parse_traceparent(self.traceparent.as_deref()?)?;
+        if let Some(tracestate) = &self.tracestate {
+            context.trace_state = TraceState::parse(tracestate);
+        }
+        Some(context)
+    }
+}
+
+/// A `Queue` sending `Traced` messages, inside a producer span.
+pub struct TracedQueue {
+    queue: Queue,
+    name: String,
+}
+
+impl TracedQueue {
+    /// Traces the messages sent to `queue`, bound as `name`.
+    pub fn new(queue: Queue, name: impl Into<String>) -> Self {
+        Self {
+            queue,
+            name: name.into(),
+        }
+    }
+
+    /// Traces the queue bound as `binding` in `env`.
+    pub fn from_env(env: &worker::Env, binding: &str) -> worker::Result<Self> {
+        Ok(Self::new(env.queue(binding)?, binding))
+    }
+
+    /// Sends `body`, wrapped in a `Traced` envelope.
+    pub async fn send<T: Serialize>(&self, body: T) -> worker::Result<()> {
+        self.traced(1, async { self.queue.send(&Traced::new(body)).await })
+            .await
+    }
+
+    /// Sends all of `bodies` at once, each wrapped in a `Traced` envelope.
+    ///
+    /// This version of `worker` has no `Queue::send_batch`, the binding's `sendBatch` is called
+    /// directly.
+    pub async fn send_batch<T: Serialize>(
+        &self,
+        bodies: impl IntoIterator<Item = T>,
+    ) -> worker::Result<()> {
+        let bodies = bodies.into_iter().collect::<Vec<_>>();
+        self.traced(bodies.len(), async {
+            let messages = Array::new();
+            for body in bodies {
+                let message = Object::new();
+                let body = serde_wasm_bindgen::to_value(&Traced::new(body))?;
+                Reflect::set(&message, &"body".into(), &body)?;
+                messages.push(&message);
+            }
+            let queue: &JsValue = self.queue.as_ref();
+            let send_batch: Function = Reflect::get(queue, &"sendBatch".into())?.dyn_into()?;
+            let promise: Promise = send_batch.call1(queue, &messages)?.dyn_into()?;
+            JsFuture::from(promise).await?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn traced(
+        &self,
+        count: usize,
+        send: impl std::future::Future<Output = worker::Result<()>>,
+    ) -> worker::Result<()> {
+        let properties = [
+            (SPAN_KIND, SpanKind::Producer.as_str().to_owned()),
+            ("messaging.system", MESSAGING_SYSTEM.to_owned()),
+            ("messaging.operation", "publish".to_owned()),
+            ("messaging.destination.name", self.name.clone()),
+        ];
+        let mut publish = async {
+            let res = send.await;
+            if let Err(err) = &res {
+                error::record(err.to_string());
+            }
+            res
+        }
+        .in_local_span(format!("{} publish", self.name))
+        .with_properties(properties);
+        if count != 1 {
+            publish = publish.with_attribute("messaging.batch.message_count", count as i64);
+        }
+        publish.await
+    }
+}
@@ -0,0 +1,255 @@
+use std::future::Future;
+
+use worker::{Bucket, Data, MultipartUpload, Object, Objects, Range, UploadedPart};
+
+use crate::{binding, current};
+
+// `Bucket` doesn't know the name of its binding either, see `kv`. Multipart uploads span
+// several calls, so `TracedBucket::multipart_upload` takes the code driving the upload and
+// runs it inside a span of its own, under which the creation of the upload and every part
+// get their own span.
+
+/// A `Bucket` whose operations get a client span each.
+pub struct TracedBucket {
+    bucket: Bucket,
+    name: String,
+}
+
+/// A multipart upload started by `TracedBucket::multipart_upload`.
+pub struct TracedMultipartUpload {
+    upload: MultipartUpload,
+    bucket: String,
+    key: String,
+}
+
+impl TracedBucket {
+    /// Traces the operations on `bucket`, bound as `name`.
+    pub fn new(bucket: Bucket, name: impl Into<String>) -> Self {
+        Self {
+            bucket,
+            name: name.into(),
+        }
+    }
+
+    /// Traces the bucket bound as `binding` in `env`.
+    pub fn from_env(env: &worker::Env, binding: &str) -> worker::Result<Self> {
+        Ok(Self::new(env.bucket(binding)?, binding))
+    }
+
+    /// The traced bucket.
+    pub fn inner(&self) -> &Bucket {
+        &self.bucket
+    }
+
+    /// Gets the metadata of `key`, see `Bucket::head`.
+    pub async fn head(&self, key: &str) -> worker::Result<Option<Object>> {
+        binding::call("head", &self.name, self.properties(key), async {
+            let object = self.bucket.head(key).await?;
+            record_object(object.as_ref());
+            Ok(object)
+        })
+        .await
+    }
+
+    /// Gets `key`, or the `range` of it.
+    pub async fn get(&self, key: &str, range: Option<Range>) -> worker::Result<Option<Object>> {
+        let mut properties = self.properties(key);
+        if let Some(range) = &range {
+            properties.push(("r2.range", format_range(range)));
+        }
+        binding::call("get", &self.name, properties, async {
+            let mut get = self.bucket.get(key);
+            if let Some(range) = range {
+                get = get.range(range);
+            }
+            let object = get.execute().await?;
+            record_object(object.as_ref());
+            Ok(object)
+        })
+        .await
+    }
+
+    /// Sets `key` to `value`. The size of a streamed `value` isn't recorded.
+    pub async fn put(&self, key: &str, value: impl Into<Data>) -> worker::Result<Object> {
+        let value = value.into();
+        let size = data_size(&value);
+        binding::call("put", &self.name, self.properties(key), async {
+            if let Some(size) = size {
+                current::add_attribute("r2.object.size", size as i64);
+            }
+            self.bucket.put(key, value).execute().await
+        })
+        .await
+    }
+
+    pub async fn delete(&self, key: &str) -> worker::Result<()> {
+        binding::call(
+            "delete",
+            &self.name,
+            self.properties(key),
+            self.bucket.delete(key),
+        )
+        .await
+    }
+
+    /// Lists the objects starting with `prefix`, from `cursor` on.
+    pub async fn list(
+        &self,
+        prefix: Option<String>,
+        cursor: Option<String>,
+    ) -> worker::Result<Objects> {
+        let mut properties = vec![("r2.bucket", self.name.clone())];
+        if let Some(prefix) = &prefix {
+            properties.push(("r2.prefix", prefix.clone()));
+        }
+        binding::call("list", &self.name, properties, async {
+            let mut list = self.bucket.list();
+            if let Some(prefix) = prefix {
+                list = list.prefix(prefix);
+            }
+            if let Some(cursor) = cursor {
+                list = list.cursor(cursor);
+            }
+            let objects = list.execute().await?;
+            current::add_attribute("r2.objects.count", objects.objects().len() as i64);
+            current::add_attribute("r2.truncated", objects.truncated());
+            Ok(objects)
+        })
+        .await
+    }
+
+    /// Creates a multipart upload of `key` and hands it to `upload`, all inside one span.
+    ///
+    /// ```ignore
+    /// let object = bucket
+    ///     .multipart_upload("video.mp4", |upload| async move {
+    ///         let mut parts = Vec::new();
+    ///         for (i, chunk) in chunks.into_iter().enumerate() {
+    ///             parts.push(upload.upload_part(i as u16 + 1, chunk).await?);
+    ///         }
+    ///         upload.complete(parts).await
+    ///     })
+    ///     .await?;
+    /// ```
+    pub async fn multipart_upload<F, Fut, T>(&self, key: &str, upload: F) -> worker::Result<T>
+    where
+        F: FnOnce(TracedMultipartUpload) -> Fut,
+        Fut: Future<Output = worker::Result<T>>,
+    {
+        binding::call(
+            "multipart_upload",
+            &self.name,
+            self.properties(key),
+            async {
+                let created = binding::call(
+                    "create_multipart_upload",
+                    &self.name,
+                    self.properties(key),
+                    self.bucket.create_multipart_upload(key).execute(),
+                )
+                .await?;
+                upload(TracedMultipartUpload {
+                    upload: created,
+                    bucket: self.name.clone(),
+                    key: key.to_owned(),
+                })
+                .await
+            },
+        )
+        .await
+    }
+
+    fn properties(&self, key: &str) -> Vec<(&'static str, String)> {
+        vec![("r2.bucket", self.name.clone()), ("r2.key", key.to_owned())]
+    }
+}
+
+impl TracedMultipartUpload {
+    /// Uploads part `part_number`, see `MultipartUpload::upload_part`.
+    pub async fn upload_part(
+        &self,
+        part_number: u16,
+        value: impl Into<Data>,
+    ) -> worker::Result<UploadedPart> {
+        let value = value.into();
+        let size = data_size(&value);
+        binding::call("upload_part", &self.bucket, self.properties(), async {
+            current::add_attribute("r2.part_number", part_number as i64);
+            if let Some(size) = size {
+                current::add_attribute("r2.part.size", size as i64);
+            }
+            self.upload.upload_part(part_number, value).await
+        })
+        .await
+    }
+
+    pub async fn abort(&self) -> worker::Result<()> {
+        binding::call(
+            "abort",
+            &self.bucket,
+            self.properties(),
+            self.upload.abort(),
+        )
+        .await
+    }
+
+    /// Completes the upload with `parts`, recording the size of the resulting object.
+    pub async fn complete(
+        self,
+        parts: impl IntoIterator<Item = UploadedPart>,
+    ) -> worker::Result<Object> {
+        let parts = parts.into_iter().collect::<Vec<_>>();
+        let properties = self.properties();
+        let bucket = self.bucket.clone();
+        binding::call("complete", &bucket, properties, async move {
+            current::add_attribute("r2.parts.count", parts.len() as i64);
+            let object = self.upload.complete(parts).await?;
+            record_object(Some(&object));
+            Ok(object)
+        })
+        .await
+    }
+
+    fn properties(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("r2.bucket", self.bucket.clone()),
+            ("r2.key", self.key.clone()),
+        ]
+    }
+}
+
+fn record_object(object: Option<&Object>) {
+    current::add_attribute("r2.hit", object.is_some());
+    if let Some(object) = object {
+        current::add_attribute("r2.object.size", object.size() as i64);
+    }
+}
+
+fn data_size(data: &Data) -> Option<usize> {
+    match data {
+        Data::Text(text) => Some(text.len()),
+        Data::Bytes(bytes) => Some(bytes.len()),
+        Data::Empty => Some(0),
+        Data::Stream(_) => None,
+    }
+}
+
+/// `range` the way a `Range` header would put it.
+fn format_range(range: &Range) -> String {
+    match *range {
+        Range::OffsetWithLength { offset, length }
+        | Range::OffsetWithOptionalLength {
+            offset,
+            length: Some(length),
+        } => format!("bytes={offset}-{}", (offset + length).saturating_sub(1)),
+        Range::OffsetWithOptionalLength {
+            offset,
+            length: None,
+        } => format!("bytes={offset}-"),
+        Range::OptionalOffsetWithLength { offset, length } => {
+            let offset = offset.unwrap_or_default();
+            format!("bytes={offset}-{}", (offset + length).saturating_sub(1))
+        }
+        Range::Suffix { suffix } => format!("bytes=-{suffix}"),
+    }
+}
@@ -0,0 +1,335 @@
+use std::{borrow::Cow, cell::Cell, collections::HashMap};
+
+use minitrace::collector::{SpanId, SpanRecord};
+
+// The local span stack only knows about nesting: a `LocalSpan`'s parent is whatever was on
+// top of the stack when it was entered, and its id is handed out internally. The helpers in
+// this crate need a bit more than that, so they carry it through the collector as reserved
+// properties and events, which `normalize` resolves (and strips) once the spans are collected.
+//
+// Every key starting with `__` is reserved for this and never meant to be exported.
+
+/// Pins the span to a `SpanId` chosen by this crate instead of the one the stack assigned.
+/// Several records pinned to the same id are merged into one.
+pub(crate) const SPAN_ID: &str = "__span_id";
+/// Marks a record that only re-enters a span pinned elsewhere, so it doesn't get to decide
+/// the name or parent of the merged record.
+pub(crate) const FRAGMENT: &str = "__fragment";
+/// An event whose properties are moved onto the span it was added to. This is the only way
+/// to add properties to a span without holding its guard.
+pub(crate) const PROPERTY_EVENT: &str = "__property";
+/// Overrides the timestamp of the event carrying it.
+pub(crate) const TIMESTAMP: &str = "__timestamp_unix_ns";
+/// Renames the span carrying it. When there are several, the last one wins.
+pub(crate) const NAME: &str = "__name";
+/// An event whose properties are copied onto the span it was added to and all of the spans
+/// below it.
+pub(crate) const AMBIENT_EVENT: &str = "__ambient";
+
+pub(crate) type Properties = Vec<(Cow<'static, str>, Cow<'static, str>)>;
+
+thread_local! {
+    static NEXT_SPAN_ID: Cell<(u32, u32)> = Cell::new((random_u32(), 0));
+}
+
+fn random_u32() -> u32 {
+    let mut buf = [0; 4];
+    getrandom::getrandom(&mut buf).expect("failed to generate a random span id prefix");
+    u32::from_ne_bytes(buf)
+}
+
+pub(crate) fn next_span_id() -> SpanId {
+    NEXT_SPAN_ID.with(|next| {
+        let (prefix, suffix) = next.get();
+        let suffix = suffix.wrapping_add(1);
+        next.set((prefix, suffix));
+        SpanId(((prefix as u64) << 32) | suffix as u64)
+    })
+}
+
+/// Resolves the reserved properties set by this crate's span helpers.
+///
+/// Call this on the output of `LocalSpans::to_span_records` before handing the records to
+/// anything else.
+pub fn normalize(records: &mut Vec<SpanRecord>) {
+    // stack-assigned id -> pinned id
+    let mut pinned = HashMap::new();
+    // pinned id -> (index in `merged`, whether that record is only a fragment)
+    let mut slots: HashMap<SpanId, (usize, bool)> = HashMap::new();
+    let mut merged = Vec::with_capacity(records.len());
+
+    for mut record in records.drain(..) {
+        let fragment = take_property(&mut record, FRAGMENT).is_some();
+        let Some(id) = take_property(&mut record, SPAN_ID).and_then(|id| id.parse().ok()) else {
+            merged.push(record);
+            continue;
+        };
+        let id = SpanId(id);
+
+        pinned.insert(record.span_id, id);
+        record.span_id = id;
+
+        match slots.get_mut(&id) {
+            Some((index, is_fragment)) => {
+                let target: &mut SpanRecord = &mut merged[*index];
+                if *is_fragment && !fragment {
+                    target.name = record.name.clone();
+                    target.parent_id = record.parent_id;
+                    *is_fragment = false;
+                }
+                merge(target, record);
+            }
+            None => {
+                slots.insert(id, (merged.len(), fragment));
+                merged.push(record);
+            }
+        }
+    }
+
+    for record in &mut merged {
+        if let Some(id) = pinned.get(&record.parent_id) {
+            record.parent_id = *id;
+        }
+        fold_property_events(record);
+        apply_event_timestamps(record);
+        if let Some(name) = take_last(&mut record.properties, NAME) {
+            record.name = name;
+        }
+    }
+    inherit_ambient_properties(&mut merged);
+
+    *records = merged;
+}
+
+fn inherit_ambient_properties(records: &mut [SpanRecord]) {
+    let mut ambient: HashMap<SpanId, Properties> = HashMap::new();
+    for record in records.iter_mut() {
+        if !record
+            .events
+            .iter()
+            .any(|event| event.name == AMBIENT_EVENT)
+        {
+            continue;
+        }
+        let (properties, events) = std::mem::take(&mut record.events)
+            .into_iter()
+            .partition::<Vec<_>, _>(|event| event.name == AMBIENT_EVENT);
+        record.events = events;
+        ambient.insert(
+            record.span_id,
+            properties
+                .into_iter()
+                .flat_map(|event| event.properties)
+                .collect(),
+        );
+    }
+    if ambient.is_empty() {
+        return;
+    }
+
+    let parents: HashMap<SpanId, SpanId> = records
+        .iter()
+        .map(|record| (record.span_id, record.parent_id))
+        .collect();
+    for record in records.iter_mut() {
+        // Walk up to the root, the closest ancestor wins when a key is set more than once.
+        let mut id = Some(record.span_id);
+        while let Some(current) = id {
+            for (k, v) in ambient.get(&current).into_iter().flatten() {
+                if !record.properties.iter().any(|(key, _)| key == k) {
+                    record.properties.push((k.clone(), v.clone()));
+                }
+            }
+            id = parents
+                .get(&current)
+                .copied()
+                .filter(|parent| *parent != current);
+        }
+    }
+}
+
+fn merge(target: &mut SpanRecord, other: SpanRecord) {
+    let end = (target.begin_time_unix_ns + target.duration_ns)
+        .max(other.begin_time_unix_ns + other.duration_ns);
+    target.begin_time_unix_ns = target.begin_time_unix_ns.min(other.begin_time_unix_ns);
+    target.duration_ns = end - target.begin_time_unix_ns;
+    target.properties.extend(other.properties);
+    target.events.extend(other.events);
+}
+
+fn fold_property_events(record: &mut SpanRecord) {
+    if !record
+        .events
+        .iter()
+        .any(|event| event.name == PROPERTY_EVENT)
+    {
+        return;
+    }
+
+    let (properties, events) = std::mem::take(&mut record.events)
+        .into_iter()
+        .partition::<Vec<_>, _>(|event| event.name == PROPERTY_EVENT);
+    record.events = events;
+    record
+        .properties
+        .extend(properties.into_iter().flat_map(|event| event.properties));
+}
+
+fn apply_event_timestamps(record: &mut SpanRecord) {
+    let mut reordered = false;
+    for event in &mut record.events {
+        if let Some(timestamp) = take(&mut event.properties, TIMESTAMP).and_then(|t| t.parse().ok())
+        {
+            event.timestamp_unix_ns = timestamp;
+            reordered = true;
+        }
+    }
+    if reordered {
+        record.events.sort_by_key(|event| event.timestamp_unix_ns);
+    }
+}
+
+fn take_property(record: &mut SpanRecord, key: &str) -> Option<Cow<'static, str>> {
+    take(&mut record.properties, key)
+}
+
+fn take_last(properties: &mut Properties, key: &str) -> Option<Cow<'static, str>> {
+    let mut last = None;
+    properties.retain(|(k, v)| {
+        if k == key {
+            last = Some(v.clone());
+            false
+        } else {
+            true
+        }
+    });
+    last
+}
+
+fn take(properties: &mut Properties, key: &str) -> Option<Cow<'static, str>> {
+    let index = properties.iter().position(|(k, _)| k == key)?;
+    Some(properties.remove(index).1)
+}
+
+#[cfg(test)]
+mod tests {
+    use minitrace::collector::EventRecord;
+
+    use super::*;
+
+    fn record(
+        span_id: u64,
+        parent_id: u64,
+        name: &'static str,
+        properties: Properties,
+    ) -> SpanRecord {
+        SpanRecord {
+            span_id: SpanId(span_id),
+            parent_id: SpanId(parent_id),
+            name: name.into(),
+            properties,
+            ..SpanRecord::default()
+        }
+    }
+
+    fn event(name: &'static str, properties: Properties) -> EventRecord {
+        EventRecord {
+            name: name.into(),
+            properties,
+            ..EventRecord::default()
+        }
+    }
+
+    #[test]
+    fn merge_pinned() {
+        // Two polls of a future pinned to 100, the second one renaming it.
+        let mut first = record(1, 0, "GET", vec![(SPAN_ID.into(), "100".into())]);
+        first.duration_ns = 10;
+        let mut second = record(
+            3,
+            0,
+            "GET",
+            vec![
+                (SPAN_ID.into(), "100".into()),
+                (NAME.into(), "GET /users".into()),
+            ],
+        );
+        second.begin_time_unix_ns = 20;
+        second.duration_ns = 5;
+        let mut records = vec![first, record(2, 1, "child", Vec::new()), second];
+
+        normalize(&mut records);
+        assert_eq!(records.len(), 2);
+        let server = &records[0];
+        assert_eq!(
+            (server.span_id, server.name.as_ref(), server.duration_ns),
+            (SpanId(100), "GET /users", 25)
+        );
+        assert!(server.properties.is_empty());
+        assert_eq!(records[1].parent_id, SpanId(100));
+    }
+
+    #[test]
+    fn fragment_first() {
+        // A child entered under an explicit parent before the parent's own record shows up.
+        let mut records = vec![
+            record(
+                1,
+                7,
+                "",
+                vec![(SPAN_ID.into(), "200".into()), (FRAGMENT.into(), "".into())],
+            ),
+            record(2, 1, "child", vec![(SPAN_ID.into(), "201".into())]),
+            record(3, 0, "parent", vec![(SPAN_ID.into(), "200".into())]),
+        ];
+
+        normalize(&mut records);
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            (
+                records[0].span_id,
+                records[0].parent_id,
+                records[0].name.as_ref()
+            ),
+            (SpanId(200), SpanId(0), "parent")
+        );
+        assert_eq!(
+            (records[1].span_id, records[1].parent_id),
+            (SpanId(201), SpanId(200))
+        );
+    }
+
+    #[test]
+    fn events() {
+        let mut root = record(1, 0, "root", Vec::new());
+        root.events = vec![
+            event(AMBIENT_EVENT, vec![("tenant".into(), "acme".into())]),
+            event(PROPERTY_EVENT, vec![("cache".into(), "miss".into())]),
+            event("late", vec![(TIMESTAMP.into(), "50".into())]),
+            event("early", vec![(TIMESTAMP.into(), "10".into())]),
+        ];
+        let child = record(2, 1, "child", vec![("tenant".into(), "own".into())]);
+        let grandchild = record(3, 2, "grandchild", Vec::new());
+        let mut records = vec![root, child, grandchild];
+
+        normalize(&mut records);
+        let root = &records[0];
+        assert_eq!(
+            root.properties,
+            [
+                ("cache".into(), "miss".into()),
+                ("tenant".into(), "acme".into())
+            ]
+        );
+        let events: Vec<_> = root
+            .events
+            .iter()
+            .map(|event| (event.name.as_ref(), event.timestamp_unix_ns))
+            .collect();
+        assert_eq!(events, [("early", 10), ("late", 50)]);
+        assert!(root.events.iter().all(|event| event.properties.is_empty()));
+        // A span's own value wins over an ancestor's.
+        assert_eq!(records[1].properties, [("tenant".into(), "own".into())]);
+        assert_eq!(records[2].properties, [("tenant".into(), "acme".into())]);
+    }
+}
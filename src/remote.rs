@@ -0,0 +1,262 @@
+use std::{cell::RefCell, rc::Rc, time::Duration};
+
+use minitrace::collector::SpanRecord;
+use serde::Deserialize;
+use worker::{Cache, Date, Env, Fetch, Headers, Response};
+
+use crate::{
+    processor::{MinDuration, NameFilter, Processor, Processors},
+    sampler::{self, Ratio, Rule, Sampler, SamplingInput},
+};
+
+// Sampling and filtering can be left to a JSON document, fetched from a URL or read from KV,
+// so that rates can be changed without redeploying the worker:
+//
+// ```json
+// {
+//   "ratio": 0.1,
+//   "rules": [{ "path": "/checkout", "method": "POST", "ratio": 1.0 }],
+//   "deny": ["worker_rust::helpers::*"],
+//   "min_duration_us": 50
+// }
+// ```
+//
+// Every field is optional. `ratio` and `rules` are what `remote.sampler` decides with (the
+// rules like `Rule`'s, the first one matching winning), and `deny`, `allow` and
+// `min_duration_us` are what `remote.filter` applies, like `NameFilter` and `MinDuration`.
+//
+// ```ignore
+// let remote = RemoteConfig::url("https://config.example.com/tracing.json");
+// sampler::set_global(remote.sampler(Ratio::new(0.1)));
+// processor::set_global(Processors::default().with(remote.filter()));
+//
+// // In the fetch handler, before the middleware:
+// if let Err(err) = remote.refresh(&env).await {
+//     console_error!("{err}");
+// }
+// ```
+//
+// `refresh` only loads the document once it's older than `ttl`, going through the Cache API
+// first, so that a new isolate doesn't have to fetch it either while it's cached in its
+// location. Until a document is loaded, the sampler decides with the fallback it was given
+// and the filter keeps everything. A document that fails to load or parse leaves the last
+// one in place, and isn't tried again before another `ttl`. There's one document per isolate,
+// whatever `RemoteConfig` loaded it.
+
+thread_local! {
+    static CURRENT: RefCell<Current> = RefCell::default();
+}
+
+#[derive(Default)]
+struct Current {
+    config: Option<Rc<Config>>,
+    // When the document should be loaded again, in ms since the epoch.
+    expires: u64,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct Document {
+    ratio: Option<f64>,
+    rules: Vec<RuleDocument>,
+    deny: Vec<String>,
+    allow: Vec<String>,
+    min_duration_us: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct RuleDocument {
+    path: String,
+    method: Option<String>,
+    ratio: f64,
+}
+
+// A document, ready to be applied.
+struct Config {
+    ratio: Option<Ratio>,
+    rules: Vec<Rule>,
+    filter: Processors,
+}
+
+impl Config {
+    fn parse(text: &str) -> worker::Result<Self> {
+        let document: Document = serde_json::from_str(text)
+            .map_err(|err| worker::Error::RustError(format!("invalid remote config: {err}")))?;
+        let rules = document
+            .rules
+            .into_iter()
+            .map(|rule| {
+                let sampled = Rule::new(rule.path, Ratio::new(rule.ratio));
+                let Some(method) = rule.method else {
+                    return Ok(sampled);
+                };
+                let parsed = sampler::parse_method(&method).ok_or_else(|| {
+                    worker::Error::RustError(format!(
+                        "invalid remote config: unknown method {method}"
+                    ))
+                })?;
+                Ok(sampled.method(parsed))
+            })
+            .collect::<worker::Result<_>>()?;
+        let mut filter = Processors::default();
+        if !document.deny.is_empty() {
+            filter = filter.with(NameFilter::deny(document.deny));
+        }
+        if !document.allow.is_empty() {
+            filter = filter.with(NameFilter::allow(document.allow));
+        }
+        if let Some(us) = document.min_duration_us {
+            filter = filter.with(MinDuration::new(Duration::from_micros(us)));
+        }
+        Ok(Self {
+            ratio: document.ratio.map(Ratio::new),
+            rules,
+            filter,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Source {
+    Url(String),
+    Kv { binding: String, key: String },
+}
+
+/// Where the sampling and filtering document is loaded from, and how often.
+#[derive(Clone, Debug)]
+pub struct RemoteConfig {
+    source: Source,
+    ttl: Duration,
+}
+
+impl RemoteConfig {
+    /// Fetches the document from `url`, every minute at most.
+    pub fn url(url: impl Into<String>) -> Self {
+        Self {
+            source: Source::Url(url.into()),
+            ttl: Duration::from_secs(60),
+        }
+    }
+
+    /// Reads the document from `key`, in the KV namespace bound as `binding`, every minute at
+    /// most.
+    pub fn kv(binding: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            source: Source::Kv {
+                binding: binding.into(),
+                key: key.into(),
+            },
+            ttl: Duration::from_secs(60),
+        }
+    }
+
+    /// How long a document is used, and cached, before it's loaded again.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Samples with the loaded document, and with `fallback` until one is loaded or when it
+    /// has no `ratio` and none of its rules match.
+    pub fn sampler(&self, fallback: impl Sampler + 'static) -> RemoteSampler {
+        RemoteSampler {
+            fallback: Box::new(fallback),
+        }
+    }
+
+    /// Filters with the loaded document.
+    pub fn filter(&self) -> RemoteFilter {
+        RemoteFilter
+    }
+
+    /// Loads the document again if it's older than `ttl`.
+    pub async fn refresh(&self, env: &Env) -> worker::Result<()> {
+        let now = Date::now().as_millis();
+        if CURRENT.with(|current| current.borrow().expires > now) {
+            return Ok(());
+        }
+        let expires = now + self.ttl.as_millis() as u64;
+        CURRENT.with(|current| current.borrow_mut().expires = expires);
+        let config = Config::parse(&self.load(env).await?)?;
+        CURRENT.with(|current| current.borrow_mut().config = Some(Rc::new(config)));
+        Ok(())
+    }
+
+    async fn load(&self, env: &Env) -> worker::Result<String> {
+        let cache = Cache::default();
+        let key = self.cache_key();
+        if let Some(mut cached) = cache.get(key.as_str(), false).await? {
+            return cached.text().await;
+        }
+        let text = match &self.source {
+            Source::Url(url) => {
+                let mut response = Fetch::Url(url.parse()?).send().await?;
+                match response.status_code() {
+                    200..=299 => response.text().await?,
+                    status => {
+                        return Err(worker::Error::RustError(format!(
+                            "{url} answered with {status}"
+                        )))
+                    }
+                }
+            }
+            Source::Kv { binding, key } => {
+                env.kv(binding)?.get(key).text().await?.ok_or_else(|| {
+                    worker::Error::RustError(format!("no remote config at {key} in {binding}"))
+                })?
+            }
+        };
+        let mut headers = Headers::new();
+        headers.set("content-type", "application/json")?;
+        headers.set(
+            "cache-control",
+            &format!("max-age={}", self.ttl.as_secs().max(1)),
+        )?;
+        cache
+            .put(key.as_str(), Response::ok(&text)?.with_headers(headers))
+            .await?;
+        Ok(text)
+    }
+
+    // The Cache API only takes URLs, so documents in KV get one of their own.
+    fn cache_key(&self) -> String {
+        match &self.source {
+            Source::Url(url) => url.clone(),
+            Source::Kv { binding, key } => {
+                format!("https://remote-config.minitrace/{binding}/{key}")
+            }
+        }
+    }
+}
+
+fn current() -> Option<Rc<Config>> {
+    CURRENT.with(|current| current.borrow().config.clone())
+}
+
+/// Samples with the document loaded by a `RemoteConfig`.
+pub struct RemoteSampler {
+    fallback: Box<dyn Sampler>,
+}
+
+impl Sampler for RemoteSampler {
+    fn should_sample(&self, input: &SamplingInput<'_>) -> bool {
+        let Some(config) = current() else {
+            return self.fallback.should_sample(input);
+        };
+        sampler::decide(&config.rules, input)
+            .or_else(|| config.ratio.map(|ratio| ratio.samples(input.trace_id)))
+            .unwrap_or_else(|| self.fallback.should_sample(input))
+    }
+}
+
+/// Filters with the document loaded by a `RemoteConfig`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RemoteFilter;
+
+impl Processor for RemoteFilter {
+    fn process(&self, records: &mut Vec<SpanRecord>) {
+        if let Some(config) = current() {
+            config.filter.process(records);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use std::{borrow::Cow, cell::RefCell, rc::Rc};
+
+use worker::{
+    wasm_bindgen::{self, prelude::*, JsCast},
+    Env, EnvBinding,
+};
+
+use crate::value::Value;
+
+// The resource is what a worker's spans are exported under: the service they come from, its
+// version, the environment it's deployed to. It's the same for every span, so it's set once
+// per isolate, e.g. in `#[event(start)]`, rather than on every exporter:
+//
+// ```ignore
+// resource::set_global(
+//     Resource::new("checkout")
+//         .namespace("shop")
+//         .environment("production")
+//         .version(env!("CARGO_PKG_VERSION")),
+// );
+// ```
+//
+// OTLP exporters send the global resource with every batch, along with the attributes set
+// on the exporter itself with `OtlpExporter::resource`, which win over the global ones.
+// Without a `service.name` from either, backends file the spans under `unknown_service`.
+//
+// `version_metadata` adds which version of the worker is running, so that a regression can be
+// tied to the deploy that brought it: `deployment.id` is the version id, `service.version` its
+// tag, and `cloudflare.version.timestamp` when it was uploaded. They come from the version
+// metadata binding, which `TracingConfig::build` reads from `CF_VERSION_METADATA` on its
+// own:
+//
+// ```toml
+// [version_metadata]
+// binding = "CF_VERSION_METADATA"
+// ```
+//
+// Without the binding, they come from the `WORKER_VERSION_ID` and `WORKER_VERSION_TAG`
+// environment variables at build time instead, if they were set. This version of `worker` has
+// no binding for the metadata, so it's bound here.
+
+pub const SERVICE_NAME: &str = "service.name";
+pub const SERVICE_NAMESPACE: &str = "service.namespace";
+pub const SERVICE_VERSION: &str = "service.version";
+pub const DEPLOYMENT_ENVIRONMENT: &str = "deployment.environment";
+pub const DEPLOYMENT_ID: &str = "deployment.id";
+pub const VERSION_TIMESTAMP: &str = "cloudflare.version.timestamp";
+
+/// Where `TracingConfig::build` reads the version metadata from.
+pub const VERSION_METADATA_BINDING: &str = "CF_VERSION_METADATA";
+
+#[wasm_bindgen]
+extern "C" {
+    /// A version metadata binding.
+    #[derive(Clone)]
+    pub type VersionMetadata;
+
+    #[wasm_bindgen(method, getter)]
+    fn id(this: &VersionMetadata) -> Option<String>;
+
+    #[wasm_bindgen(method, getter)]
+    fn tag(this: &VersionMetadata) -> Option<String>;
+
+    #[wasm_bindgen(method, getter)]
+    fn timestamp(this: &VersionMetadata) -> Option<String>;
+}
+
+impl EnvBinding for VersionMetadata {
+    const TYPE_NAME: &'static str = "Object";
+
+    // It's a plain object, so anything but a missing binding goes.
+    fn get(val: JsValue) -> worker::Result<Self> {
+        if val.is_undefined() || val.is_null() {
+            return Err(worker::Error::RustError(
+                "no version metadata binding".to_owned(),
+            ));
+        }
+        Ok(val.unchecked_into())
+    }
+}
+
+/// The attributes describing where spans come from.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Resource {
+    attributes: Vec<(Cow<'static, str>, Value)>,
+}
+
+impl Resource {
+    /// The resource of the service named `service_name`.
+    pub fn new(service_name: impl Into<Cow<'static, str>>) -> Self {
+        Self::default().attribute(SERVICE_NAME, Value::String(service_name.into()))
+    }
+
+    /// Sets `service.namespace`, the group of services this one is part of.
+    pub fn namespace(self, namespace: impl Into<Cow<'static, str>>) -> Self {
+        self.attribute(SERVICE_NAMESPACE, Value::String(namespace.into()))
+    }
+
+    /// Sets `deployment.environment`, e.g. `production` or `staging`.
+    pub fn environment(self, environment: impl Into<Cow<'static, str>>) -> Self {
+        self.attribute(DEPLOYMENT_ENVIRONMENT, Value::String(environment.into()))
+    }
+
+    /// Sets `service.version`.
+    pub fn version(self, version: impl Into<Cow<'static, str>>) -> Self {
+        self.attribute(SERVICE_VERSION, Value::String(version.into()))
+    }
+
+    /// Sets `key`, replacing whatever it was set to before.
+    pub fn attribute(mut self, key: impl Into<Cow<'static, str>>, value: impl Into<Value>) -> Self {
+        let key = key.into();
+        self.attributes.retain(|(k, _)| *k != key);
+        self.attributes.push((key, value.into()));
+        self
+    }
+
+    /// Sets `deployment.id`, `service.version` and `cloudflare.version.timestamp` from the
+    /// version metadata bound as `binding`, or from the build, leaving those already set as
+    /// they are.
+    pub fn version_metadata(mut self, env: &Env, binding: &str) -> Self {
+        let (id, tag, timestamp) = match env.get_binding::<VersionMetadata>(binding) {
+            Ok(metadata) => (metadata.id(), metadata.tag(), metadata.timestamp()),
+            Err(_) => (
+                option_env!("WORKER_VERSION_ID").map(str::to_owned),
+                option_env!("WORKER_VERSION_TAG").map(str::to_owned),
+                None,
+            ),
+        };
+        for (key, value) in [
+            (DEPLOYMENT_ID, id),
+            (SERVICE_VERSION, tag),
+            (VERSION_TIMESTAMP, timestamp),
+        ] {
+            if let Some(value) = value.filter(|value| !value.is_empty()) {
+                if self.get(key).is_none() {
+                    self = self.attribute(key, value);
+                }
+            }
+        }
+        self
+    }
+
+    /// The value of `key`, if it's set.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.attributes
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value)
+    }
+
+    pub fn attributes(&self) -> &[(Cow<'static, str>, Value)] {
+        &self.attributes
+    }
+}
+
+thread_local! {
+    static GLOBAL: RefCell<Rc<Resource>> = RefCell::default();
+}
+
+/// Sets the resource the spans of this isolate are exported under.
+pub fn set_global(resource: Resource) {
+    GLOBAL.with(|global| *global.borrow_mut() = Rc::new(resource));
+}
+
+/// The resource the spans of this isolate are exported under, empty unless set.
+pub fn global() -> Rc<Resource> {
+    GLOBAL.with(|global| global.borrow().clone())
+}
@@ -0,0 +1,165 @@
+use std::{collections::HashMap, future::Future};
+
+use matchit::Node;
+use worker::{Env, Method, Request, Response, RouteContext, Router};
+
+use crate::{current, local_future::LocalFutureExt};
+
+// Naming server spans after the raw path gives backends one span name per user id, which
+// doesn't scale. `TracedRouter` is a `Router` that also remembers the pattern of every route
+// it's given, so that once the route of a request is known the current (server) span is
+// renamed after it, `GET /users/:id`, and the handler runs in a child span of its own.
+//
+// `Router` doesn't tell which route it picked, so the patterns are matched again with the
+// same rules, and `Router`'s handlers being plain `fn`s rules out wrapping them instead.
+
+/// `http.route` of the matched route.
+const HTTP_ROUTE: &str = "http.route";
+
+/// A `worker::Router` naming the current span after the matched route.
+pub struct TracedRouter<'a, D> {
+    router: Router<'a, D>,
+    routes: HashMap<Method, Node<String>>,
+    or_else_any_method: Node<String>,
+}
+
+macro_rules! routes {
+    ($($method:ident, $method_async:ident => $methods:expr;)+) => {
+        $(
+            /// Like the `Router` method of the same name.
+            pub fn $method(
+                mut self,
+                pattern: &str,
+                func: fn(Request, RouteContext<D>) -> worker::Result<Response>,
+            ) -> Self {
+                self.router = self.router.$method(pattern, func);
+                self.add(pattern, $methods);
+                self
+            }
+
+            /// Like the `Router` method of the same name.
+            pub fn $method_async<T>(
+                mut self,
+                pattern: &str,
+                func: fn(Request, RouteContext<D>) -> T,
+            ) -> Self
+            where
+                T: Future<Output = worker::Result<Response>> + 'a,
+            {
+                self.router = self.router.$method_async(pattern, func);
+                self.add(pattern, $methods);
+                self
+            }
+        )+
+    };
+}
+
+impl<'a> TracedRouter<'a, ()> {
+    pub fn new() -> Self {
+        Self::with_data(())
+    }
+}
+
+impl<'a> Default for TracedRouter<'a, ()> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, D: 'a> TracedRouter<'a, D> {
+    pub fn with_data(data: D) -> Self {
+        Self {
+            router: Router::with_data(data),
+            routes: HashMap::new(),
+            or_else_any_method: Node::new(),
+        }
+    }
+
+    routes! {
+        head, head_async => vec![Method::Head];
+        get, get_async => vec![Method::Get];
+        post, post_async => vec![Method::Post];
+        put, put_async => vec![Method::Put];
+        patch, patch_async => vec![Method::Patch];
+        delete, delete_async => vec![Method::Delete];
+        options, options_async => vec![Method::Options];
+        on, on_async => Method::all();
+    }
+
+    /// Like the `Router` method of the same name.
+    pub fn or_else_any_method(
+        mut self,
+        pattern: &str,
+        func: fn(Request, RouteContext<D>) -> worker::Result<Response>,
+    ) -> Self {
+        self.router = self.router.or_else_any_method(pattern, func);
+        // `Router` already panicked on a conflicting pattern.
+        let _ = self.or_else_any_method.insert(pattern, pattern.to_owned());
+        self
+    }
+
+    /// Like the `Router` method of the same name.
+    pub fn or_else_any_method_async<T>(
+        mut self,
+        pattern: &str,
+        func: fn(Request, RouteContext<D>) -> T,
+    ) -> Self
+    where
+        T: Future<Output = worker::Result<Response>> + 'a,
+    {
+        self.router = self.router.or_else_any_method_async(pattern, func);
+        let _ = self.or_else_any_method.insert(pattern, pattern.to_owned());
+        self
+    }
+
+    /// Runs the route matching `req` like `Router::run`, in a span named after its pattern.
+    ///
+    /// The current span is renamed `{method} {pattern}` and gets an `http.route` property.
+    /// Requests matching no route are left alone.
+    pub async fn run(self, req: Request, env: Env) -> worker::Result<Response> {
+        let method = req.method();
+        let Some(pattern) = self.route(&method, &req.path()) else {
+            return self.router.run(req, env).await;
+        };
+
+        current::set_name(format!("{} {pattern}", method.as_ref()));
+        current::add_property(HTTP_ROUTE, pattern.clone());
+        self.router
+            .run(req, env)
+            .in_local_span(format!("route {pattern}"))
+            .with_property(HTTP_ROUTE, pattern)
+            .await
+    }
+
+    fn add(&mut self, pattern: &str, methods: Vec<Method>) {
+        for method in methods {
+            let _ = self
+                .routes
+                .entry(method)
+                .or_default()
+                .insert(pattern, pattern.to_owned());
+        }
+    }
+
+    // The pattern `Router::run` picks for `method` and `path`, if any. A path matching a route
+    // of another method only gets a `405` and isn't named after it.
+    fn route(&self, method: &Method, path: &str) -> Option<String> {
+        if let Some(routes) = self.routes.get(method) {
+            if let Ok(found) = routes.at(path) {
+                return Some(found.value.clone());
+            }
+        }
+        let other_method = Method::all()
+            .into_iter()
+            .filter(|method| !matches!(method, Method::Head | Method::Options | Method::Trace))
+            .filter_map(|method| self.routes.get(&method))
+            .any(|routes| routes.at(path).is_ok());
+        if other_method {
+            return None;
+        }
+        self.or_else_any_method
+            .at(path)
+            .ok()
+            .map(|found| found.value.clone())
+    }
+}
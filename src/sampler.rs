@@ -0,0 +1,394 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use minitrace::collector::TraceId;
+use worker::{Date, Method, Request};
+
+use crate::propagation::TraceContext;
+
+// Whether a request is recorded is decided once, when its collector starts, by the global
+// sampler. A request that isn't sampled records nothing and exports nothing, but still
+// propagates its context (as not sampled), so the services it calls can do the same. The
+// default samples everything, and the middlewares still leave out the requests the caller
+// didn't sample unless told otherwise (see `FetchTracing::honor_sampled`).
+//
+// ```ignore
+// sampler::set_global(ParentBased::new(Ratio::new(0.1)));
+// ```
+//
+// Samplers are `Fn(&SamplingInput) -> bool` too, for one-off decisions.
+//
+// A request with the debug header (see `set_debug_header`) is recorded whatever the sampler
+// or its caller decided, and its server span gets `sampling.forced`, which `TailSampling`
+// keeps too: that's how to get the trace of a given request out of production.
+
+/// Set on the server span of a request forced to be sampled with the debug header.
+pub const FORCED: &str = "sampling.forced";
+
+/// What a sampler decides on.
+pub struct SamplingInput<'a> {
+    /// The trace the request is part of.
+    pub trace_id: TraceId,
+    /// The context the caller sent, if any.
+    pub parent: Option<&'a TraceContext>,
+    /// The request being decided on.
+    pub request: &'a Request,
+}
+
+/// Decides which requests are recorded.
+pub trait Sampler {
+    fn should_sample(&self, input: &SamplingInput<'_>) -> bool;
+}
+
+impl<F: Fn(&SamplingInput<'_>) -> bool> Sampler for F {
+    fn should_sample(&self, input: &SamplingInput<'_>) -> bool {
+        self(input)
+    }
+}
+
+/// Samples everything.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AlwaysOn;
+
+impl Sampler for AlwaysOn {
+    fn should_sample(&self, _: &SamplingInput<'_>) -> bool {
+        true
+    }
+}
+
+/// Samples nothing.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AlwaysOff;
+
+impl Sampler for AlwaysOff {
+    fn should_sample(&self, _: &SamplingInput<'_>) -> bool {
+        false
+    }
+}
+
+/// Samples a share of the traces, like OpenTelemetry's `TraceIdRatioBased`: the decision only
+/// depends on the trace id, so every service sampling the same ratio this way keeps the same
+/// traces.
+#[derive(Clone, Copy, Debug)]
+pub struct Ratio {
+    // Traces whose lower 64 bits are below this are sampled.
+    bound: u64,
+    ratio: f64,
+}
+
+impl Ratio {
+    /// Samples `ratio` of the traces, between `0.0` (none) and `1.0` (all).
+    pub fn new(ratio: f64) -> Self {
+        let ratio = if ratio.is_nan() {
+            0.0
+        } else {
+            ratio.clamp(0.0, 1.0)
+        };
+        Self {
+            bound: (ratio * u64::MAX as f64) as u64,
+            ratio,
+        }
+    }
+
+    pub fn ratio(&self) -> f64 {
+        self.ratio
+    }
+
+    /// Whether the trace `trace_id` is sampled.
+    pub fn samples(&self, trace_id: TraceId) -> bool {
+        self.ratio >= 1.0 || (trace_id.0 as u64) < self.bound
+    }
+}
+
+impl Sampler for Ratio {
+    fn should_sample(&self, input: &SamplingInput<'_>) -> bool {
+        self.samples(input.trace_id)
+    }
+}
+
+/// Follows the caller's decision when it sent a context, like OpenTelemetry's `ParentBased`,
+/// and leaves traces starting here to `root`. Unlike `FetchTracing::honor_sampled`, a caller
+/// that sampled the trace has it recorded here too, whatever `root` would have decided.
+pub struct ParentBased {
+    root: Box<dyn Sampler>,
+}
+
+impl ParentBased {
+    pub fn new(root: impl Sampler + 'static) -> Self {
+        Self {
+            root: Box::new(root),
+        }
+    }
+}
+
+impl Sampler for ParentBased {
+    fn should_sample(&self, input: &SamplingInput<'_>) -> bool {
+        match input.parent {
+            Some(parent) => parent.sampled,
+            None => self.root.should_sample(input),
+        }
+    }
+}
+
+/// Samples at most `per_second` traces a second in each isolate, whatever the traffic, with a
+/// token bucket: up to `per_second` tokens are saved up while it's quiet, so a burst after a
+/// quiet spell is sampled in full until they run out.
+///
+/// The bucket is global to the isolate and keyed by `name`, like the circuits of
+/// `CircuitBreaker`, so the rate holds across requests even though the sampler may be built
+/// for each.
+pub struct RateLimited {
+    name: String,
+    per_second: f64,
+}
+
+#[derive(Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    // When the tokens were last counted, in ms since the epoch.
+    updated: u64,
+}
+
+thread_local! {
+    static BUCKETS: RefCell<HashMap<String, Bucket>> = RefCell::default();
+}
+
+impl RateLimited {
+    pub fn new(name: impl Into<String>, per_second: f64) -> Self {
+        Self {
+            name: name.into(),
+            per_second: per_second.max(0.0),
+        }
+    }
+
+    fn should_sample_at(&self, now: u64) -> bool {
+        BUCKETS.with(|buckets| {
+            let mut buckets = buckets.borrow_mut();
+            let bucket = buckets.entry(self.name.clone()).or_insert(Bucket {
+                tokens: self.per_second,
+                updated: now,
+            });
+            let elapsed = now.saturating_sub(bucket.updated) as f64 / 1000.0;
+            bucket.tokens =
+                (bucket.tokens + elapsed * self.per_second).min(self.per_second.max(1.0));
+            bucket.updated = now;
+            let sampled = bucket.tokens >= 1.0;
+            if sampled {
+                bucket.tokens -= 1.0;
+            }
+            sampled
+        })
+    }
+}
+
+impl Sampler for RateLimited {
+    fn should_sample(&self, _: &SamplingInput<'_>) -> bool {
+        self.should_sample_at(Date::now().as_millis())
+    }
+}
+
+/// Decides with the sampler of the first rule matching the request, or `fallback` if none
+/// does.
+///
+/// ```ignore
+/// sampler::set_global(
+///     Rules::new(Ratio::new(0.1))
+///         .rule(Rule::new("/checkout", AlwaysOn).method(Method::Post))
+///         .rule(Rule::new("/healthz", Ratio::new(0.01)))
+///         .rule(Rule::new("/assets/*", AlwaysOff)),
+/// );
+/// ```
+pub struct Rules {
+    rules: Vec<Rule>,
+    fallback: Box<dyn Sampler>,
+}
+
+impl Rules {
+    pub fn new(fallback: impl Sampler + 'static) -> Self {
+        Self {
+            rules: Vec::new(),
+            fallback: Box::new(fallback),
+        }
+    }
+
+    /// Adds `rule`, tried after the ones added before it.
+    pub fn rule(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+}
+
+impl Sampler for Rules {
+    fn should_sample(&self, input: &SamplingInput<'_>) -> bool {
+        decide(&self.rules, input).unwrap_or_else(|| self.fallback.should_sample(input))
+    }
+}
+
+/// What the first of `rules` matching the request decides, if any does.
+pub(crate) fn decide(rules: &[Rule], input: &SamplingInput<'_>) -> Option<bool> {
+    let method = input.request.method();
+    let path = input.request.path();
+    rules
+        .iter()
+        .find(|rule| rule.matches(&method, &path))
+        .map(|rule| rule.sampler.should_sample(input))
+}
+
+/// Requests to a path, and optionally with a method, and how they're sampled.
+///
+/// Paths are matched segment by segment: a `:name` segment matches any segment, and a
+/// trailing `*` matches whatever is left, nothing included. So `/users/:id` matches
+/// `/users/42`, and `/assets/*` matches `/assets` and everything under it.
+pub struct Rule {
+    method: Option<Method>,
+    path: String,
+    sampler: Box<dyn Sampler>,
+}
+
+impl Rule {
+    /// Samples the requests to `path` with `sampler`.
+    pub fn new(path: impl Into<String>, sampler: impl Sampler + 'static) -> Self {
+        Self {
+            method: None,
+            path: path.into(),
+            sampler: Box::new(sampler),
+        }
+    }
+
+    /// Only matches requests with `method`.
+    pub fn method(mut self, method: Method) -> Self {
+        self.method = Some(method);
+        self
+    }
+
+    fn matches(&self, method: &Method, path: &str) -> bool {
+        if self.method.as_ref().is_some_and(|m| m != method) {
+            return false;
+        }
+        let mut segments = path.split('/').filter(|segment| !segment.is_empty());
+        for pattern in self.path.split('/').filter(|segment| !segment.is_empty()) {
+            if pattern == "*" {
+                return true;
+            }
+            match segments.next() {
+                Some(segment) if pattern.starts_with(':') || pattern == segment => {}
+                _ => return false,
+            }
+        }
+        segments.next().is_none()
+    }
+}
+
+/// The method named `name`, whatever its case, if there's one.
+pub(crate) fn parse_method(name: &str) -> Option<Method> {
+    Method::all()
+        .into_iter()
+        .find(|method| method.as_ref().eq_ignore_ascii_case(name))
+}
+
+thread_local! {
+    static GLOBAL: RefCell<Rc<dyn Sampler>> = RefCell::new(Rc::new(AlwaysOn));
+    static DEBUG_HEADER: RefCell<Option<(String, String)>> = const { RefCell::new(None) };
+}
+
+/// Sets the sampler deciding which requests the middlewares record.
+pub fn set_global(sampler: impl Sampler + 'static) {
+    GLOBAL.with(|global| *global.borrow_mut() = Rc::new(sampler));
+}
+
+/// Whether the global sampler samples `input`.
+pub fn should_sample(input: &SamplingInput<'_>) -> bool {
+    let sampler = GLOBAL.with(|global| global.borrow().clone());
+    sampler.should_sample(input)
+}
+
+/// Records every request whose header `name` is `secret`, e.g. `x-trace-debug`, whatever the
+/// sampler decides.
+pub fn set_debug_header(name: impl Into<String>, secret: impl Into<String>) {
+    DEBUG_HEADER.with(|header| *header.borrow_mut() = Some((name.into(), secret.into())));
+}
+
+/// Whether `req` has the debug header, and so is recorded whatever the sampler decides.
+pub fn is_forced(req: &Request) -> bool {
+    DEBUG_HEADER.with(|header| {
+        let header = header.borrow();
+        let Some((name, secret)) = header.as_ref() else {
+            return false;
+        };
+        req.headers()
+            .get(name)
+            .ok()
+            .flatten()
+            .is_some_and(|value| constant_time_eq(value.as_bytes(), secret.as_bytes()))
+    })
+}
+
+// Doesn't tell how much of the secret a guess got right by how long it takes to reject it.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ratio() {
+        let half = Ratio::new(0.5);
+        assert!(half.samples(TraceId(0)));
+        assert!(half.samples(TraceId(u64::MAX as u128 / 2 - 1)));
+        assert!(!half.samples(TraceId(u64::MAX as u128 / 2 + 1)));
+        // Only the lower 64 bits count.
+        assert!(half.samples(TraceId(u128::MAX << 64 | 1)));
+
+        assert!(Ratio::new(1.0).samples(TraceId(u128::MAX)));
+        assert!(!Ratio::new(0.0).samples(TraceId(0)));
+        assert_eq!(Ratio::new(2.0).ratio(), 1.0);
+        assert_eq!(Ratio::new(-1.0).ratio(), 0.0);
+        assert_eq!(Ratio::new(f64::NAN).ratio(), 0.0);
+    }
+
+    #[test]
+    fn rule_matches() {
+        let rule = Rule::new("/users/:id", AlwaysOn);
+        assert!(rule.matches(&Method::Get, "/users/42"));
+        assert!(rule.matches(&Method::Post, "//users/42/"));
+        assert!(!rule.matches(&Method::Get, "/users"));
+        assert!(!rule.matches(&Method::Get, "/users/42/posts"));
+        assert!(!rule.matches(&Method::Get, "/groups/42"));
+
+        let rule = Rule::new("/assets/*", AlwaysOff);
+        assert!(rule.matches(&Method::Get, "/assets"));
+        assert!(rule.matches(&Method::Get, "/assets/css/site.css"));
+        assert!(!rule.matches(&Method::Get, "/asset"));
+
+        let rule = Rule::new("/", AlwaysOn);
+        assert!(rule.matches(&Method::Get, "/"));
+        assert!(!rule.matches(&Method::Get, "/checkout"));
+
+        let rule = Rule::new("/checkout", AlwaysOn).method(Method::Post);
+        assert!(rule.matches(&Method::Post, "/checkout"));
+        assert!(!rule.matches(&Method::Get, "/checkout"));
+    }
+
+    #[test]
+    fn methods() {
+        assert_eq!(parse_method("POST"), Some(Method::Post));
+        assert_eq!(parse_method("delete"), Some(Method::Delete));
+        assert_eq!(parse_method("GETT"), None);
+        assert_eq!(parse_method(""), None);
+    }
+
+    #[test]
+    fn rate_limited_rebuilt() {
+        let now = 1_000_000;
+        let sampled = (0..5)
+            .filter(|_| RateLimited::new("rebuilt", 2.0).should_sample_at(now))
+            .count();
+        assert_eq!(sampled, 2);
+        // Refilled at `per_second`, for the next sampler built just the same.
+        assert!(RateLimited::new("rebuilt", 2.0).should_sample_at(now + 500));
+        assert!(!RateLimited::new("rebuilt", 2.0).should_sample_at(now + 500));
+        // Other names have buckets of their own.
+        assert!(RateLimited::new("other", 2.0).should_sample_at(now));
+    }
+}
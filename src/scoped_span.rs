@@ -0,0 +1,190 @@
+use std::borrow::Cow;
+
+use minitrace::{collector::SpanId, local::LocalSpan};
+
+use crate::{
+    collector, current,
+    lazy::{self, LAZY},
+    limits,
+    record::{self, FRAGMENT, NAME, SPAN_ID},
+    value::Value,
+};
+
+// A `LocalSpan` can only be created under "the current local parent", so the only way to
+// get a hierarchy is to nest guards (or `#[trace]` functions) the same way the spans should
+// nest. `ScopedSpan` pins itself to an id it picks up front, which lets a child point at it
+// explicitly: `enter_with_parent` re-enters the parent (a fragment pinned to the same id)
+// and nests the child in there. `record::normalize` later merges the fragments back into
+// the parent's record.
+
+/// A handle to a [`ScopedSpan`] that can be used as an explicit parent.
+///
+/// The handle can outlive the span, children created afterwards are still attached to it.
+#[derive(Clone, Debug)]
+pub struct SpanHandle {
+    id: SpanId,
+    name: Cow<'static, str>,
+}
+
+impl SpanHandle {
+    /// A handle to the current span, see `current::span_id`. That's how an explicit parent is
+    /// obtained from within `in_local_span` or `#[traced]`, which don't hand out a handle.
+    pub fn current() -> Option<Self> {
+        Some(Self {
+            id: current::span_id()?,
+            // Only used for the fragments, which don't get to name the span.
+            name: Cow::Borrowed(""),
+        })
+    }
+
+    /// The id the span will be reported with after `record::normalize`.
+    pub fn span_id(&self) -> SpanId {
+        self.id
+    }
+}
+
+/// A `LocalSpan` that can be used as an explicit parent via its [`SpanHandle`].
+///
+/// Outside of a running `Collector` this is a noop guard: no id is picked, nothing is
+/// entered and no property closure is ever called.
+#[must_use]
+pub struct ScopedSpan {
+    inner: Option<Inner>,
+}
+
+struct Inner {
+    // Field order matters: `span` has to be exited before the `_fragment` it's nested in.
+    span: LocalSpan,
+    _fragment: Option<LocalSpan>,
+    handle: SpanHandle,
+}
+
+impl ScopedSpan {
+    /// Enters a span under the current local parent, same as `LocalSpan::enter_with_local_parent`.
+    #[inline]
+    pub fn enter_with_local_parent(name: impl Into<Cow<'static, str>>) -> Self {
+        if !collector::is_collecting() || !limits::admit_span() {
+            return Self { inner: None };
+        }
+        Self::enter(name.into(), None)
+    }
+
+    /// Enters a span under `parent`, regardless of what the current local parent is.
+    #[inline]
+    pub fn enter_with_parent(name: impl Into<Cow<'static, str>>, parent: &SpanHandle) -> Self {
+        if !collector::is_collecting() || !limits::admit_span() {
+            return Self { inner: None };
+        }
+        // The handle of a noop span, there's nothing to re-enter.
+        if parent.id == SpanId::default() {
+            return Self::enter(name.into(), None);
+        }
+
+        let fragment =
+            LocalSpan::enter_with_local_parent(parent.name.clone()).with_properties(|| {
+                [
+                    (SPAN_ID, parent.id.0.to_string()),
+                    (FRAGMENT, String::new()),
+                ]
+            });
+        Self::enter(name.into(), Some(fragment))
+    }
+
+    fn enter(name: Cow<'static, str>, fragment: Option<LocalSpan>) -> Self {
+        let handle = SpanHandle {
+            id: record::next_span_id(),
+            name,
+        };
+        let span = LocalSpan::enter_with_local_parent(handle.name.clone())
+            .with_property(|| (SPAN_ID, handle.id.0.to_string()));
+        current::push_span(handle.id);
+
+        Self {
+            inner: Some(Inner {
+                span,
+                _fragment: fragment,
+                handle,
+            }),
+        }
+    }
+
+    /// Adds a typed attribute, see [`Value`].
+    pub fn with_attribute(
+        self,
+        key: impl Into<Cow<'static, str>>,
+        value: impl Into<Value>,
+    ) -> Self {
+        let properties = value.into().to_properties(key.into());
+        self.with_properties(|| properties)
+    }
+
+    /// Adds a property whose value is only computed if the span ends up being exported, see
+    /// `lazy::resolve`.
+    pub fn with_property_lazy<V, F>(self, key: impl Into<Cow<'static, str>>, value: F) -> Self
+    where
+        V: Into<Cow<'static, str>>,
+        F: FnOnce() -> V + 'static,
+    {
+        let key = key.into();
+        self.with_property(|| (LAZY, lazy::register(key, value).to_string()))
+    }
+
+    /// Ends the span now rather than at the end of the scope.
+    ///
+    /// Like dropping it, spans nested in it have to be ended first.
+    pub fn end(self) {
+        drop(self);
+    }
+
+    /// Renames the span, e.g. once the route of a request is known.
+    pub fn set_name(&mut self, name: impl Into<Cow<'static, str>>) {
+        if let Some(inner) = &mut self.inner {
+            let name = name.into();
+            inner.span = std::mem::take(&mut inner.span).with_property(|| (NAME, name));
+        }
+    }
+
+    pub fn handle(&self) -> SpanHandle {
+        match &self.inner {
+            Some(inner) => inner.handle.clone(),
+            None => SpanHandle {
+                id: SpanId::default(),
+                name: Cow::Borrowed(""),
+            },
+        }
+    }
+
+    #[inline]
+    pub fn with_property<K, V, F>(self, property: F) -> Self
+    where
+        K: Into<Cow<'static, str>>,
+        V: Into<Cow<'static, str>>,
+        F: FnOnce() -> (K, V),
+    {
+        self.with_properties(|| [property()])
+    }
+
+    #[inline]
+    pub fn with_properties<K, V, I, F>(mut self, properties: F) -> Self
+    where
+        K: Into<Cow<'static, str>>,
+        V: Into<Cow<'static, str>>,
+        I: IntoIterator<Item = (K, V)>,
+        F: FnOnce() -> I,
+    {
+        if let Some(inner) = &mut self.inner {
+            let id = inner.handle.id;
+            inner.span = std::mem::take(&mut inner.span).with_properties(|| {
+                let properties = properties().into_iter().map(|(k, v)| (k.into(), v.into()));
+                limits::admit_properties_of(id, properties)
+            });
+        }
+        self
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        current::pop_span(self.handle.id);
+    }
+}
@@ -0,0 +1,45 @@
+// SQL goes into spans without the values it was written with: string and number literals
+// are replaced with `?`, which also keeps statements that only differ by their values under
+// the same text. Bound parameters are never recorded in the first place.
+
+/// `sql` with its literals replaced with `?`.
+pub fn sanitize(sql: &str) -> String {
+    let mut sanitized = String::with_capacity(sql.len());
+    let mut chars = sql.chars().peekable();
+    // Whether the previous char continues an identifier, so `t1` keeps its `1`.
+    let mut in_word = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                // `''` escapes a quote inside a string.
+                while let Some(c) = chars.next() {
+                    if c == '\'' && chars.next_if_eq(&'\'').is_none() {
+                        break;
+                    }
+                }
+                sanitized.push('?');
+                in_word = false;
+            }
+            '0'..='9' if !in_word => {
+                while chars
+                    .next_if(|c| c.is_ascii_alphanumeric() || *c == '.')
+                    .is_some()
+                {}
+                sanitized.push('?');
+            }
+            c => {
+                in_word = c.is_alphanumeric() || c == '_' || c == '"' || c == '`';
+                sanitized.push(c);
+            }
+        }
+    }
+    sanitized
+}
+
+/// The keyword `sql` starts with, upper-cased (`SELECT`, `INSERT`...).
+pub fn operation(sql: &str) -> String {
+    sql.split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_ascii_uppercase()
+}
@@ -0,0 +1,78 @@
+use std::{borrow::Cow, fmt};
+
+use minitrace::collector::SpanRecord;
+
+use crate::current;
+
+// Status is carried as regular properties, using the OpenTelemetry attribute names that
+// exporters already understand. When it's set more than once the last one wins.
+
+pub(crate) const STATUS_CODE: &str = "otel.status_code";
+pub(crate) const STATUS_DESCRIPTION: &str = "otel.status_description";
+
+/// The outcome of the operation a span covers.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum SpanStatus {
+    #[default]
+    Unset,
+    Ok,
+    Error(Cow<'static, str>),
+}
+
+impl SpanStatus {
+    /// Reads the status back from a collected record.
+    pub fn of(record: &SpanRecord) -> Self {
+        let code = record
+            .properties
+            .iter()
+            .rev()
+            .find(|(k, _)| k == STATUS_CODE)
+            .map(|(_, v)| v.as_ref());
+
+        match code {
+            Some("OK") => SpanStatus::Ok,
+            Some("ERROR") => {
+                let description = record
+                    .properties
+                    .iter()
+                    .rev()
+                    .find(|(k, _)| k == STATUS_DESCRIPTION)
+                    .map(|(_, v)| v.clone())
+                    .unwrap_or_default();
+                SpanStatus::Error(description)
+            }
+            _ => SpanStatus::Unset,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, SpanStatus::Error(_))
+    }
+
+    pub(crate) fn properties(&self) -> Vec<(Cow<'static, str>, Cow<'static, str>)> {
+        match self {
+            SpanStatus::Unset => vec![(STATUS_CODE.into(), "UNSET".into())],
+            SpanStatus::Ok => vec![(STATUS_CODE.into(), "OK".into())],
+            SpanStatus::Error(description) => vec![
+                (STATUS_CODE.into(), "ERROR".into()),
+                (STATUS_DESCRIPTION.into(), description.clone()),
+            ],
+        }
+    }
+}
+
+impl fmt::Display for SpanStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpanStatus::Unset => f.write_str("UNSET"),
+            SpanStatus::Ok => f.write_str("OK"),
+            SpanStatus::Error(description) if description.is_empty() => f.write_str("ERROR"),
+            SpanStatus::Error(description) => write!(f, "ERROR ({description})"),
+        }
+    }
+}
+
+/// Sets the status of the current local parent.
+pub fn set(status: SpanStatus) {
+    current::add_properties(status.properties());
+}
@@ -0,0 +1,117 @@
+use std::{cell::Cell, time::Duration};
+
+use worker::{Date, Env};
+
+// Tracing can be turned off while the worker is running, e.g. during an incident where
+// exports are the last thing anyone needs. While it's off, every collector started (by the
+// middlewares or by hand) is started as not sampled: the span helpers are noops, nothing is
+// exported, and contexts are still propagated, as not sampled.
+//
+// The flag is global to the isolate, and read whenever a collector starts. `set_enabled`
+// sets it from code, and a `KillSwitch` from a var or a KV key, checked at the start of
+// each invocation:
+//
+// ```ignore
+// // In the fetch handler, before the middleware:
+// KillSwitch::kv("CONFIG", "tracing").refresh(&env).await;
+// ```
+//
+// Tracing is off when the value is `off`, `false`, `0` or `disabled` (whatever the case),
+// and on otherwise, a missing value included: a switch that can't be read doesn't turn
+// tracing off. KV is only read again once the last value is older than `ttl`, so turning
+// tracing back off takes up to that long, on top of KV's own propagation.
+
+thread_local! {
+    static ENABLED: Cell<bool> = const { Cell::new(true) };
+    // When the switch should be read again, in ms since the epoch.
+    static EXPIRES: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Turns tracing on or off in this isolate.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.with(|flag| flag.set(enabled));
+}
+
+/// Whether tracing is on in this isolate, which is the default. It never is with the
+/// `tracing-off` feature.
+pub fn is_enabled() -> bool {
+    !cfg!(feature = "tracing-off") && ENABLED.with(Cell::get)
+}
+
+#[derive(Clone, Debug)]
+enum Source {
+    Var(String),
+    Kv { binding: String, key: String },
+}
+
+/// Where tracing is turned on or off from.
+#[derive(Clone, Debug)]
+pub struct KillSwitch {
+    source: Source,
+    ttl: Duration,
+}
+
+impl KillSwitch {
+    /// Reads the switch from the var or secret `name`.
+    pub fn var(name: impl Into<String>) -> Self {
+        Self {
+            source: Source::Var(name.into()),
+            ttl: Duration::ZERO,
+        }
+    }
+
+    /// Reads the switch from `key`, in the KV namespace bound as `binding`, every 10 seconds
+    /// at most.
+    pub fn kv(binding: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            source: Source::Kv {
+                binding: binding.into(),
+                key: key.into(),
+            },
+            ttl: Duration::from_secs(10),
+        }
+    }
+
+    /// How long a value read from KV is used before reading it again.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Turns tracing on or off as the switch says, reading it again if it's due. A switch
+    /// that fails to read leaves tracing as it was, and is logged.
+    pub async fn refresh(&self, env: &Env) {
+        let now = Date::now().as_millis();
+        if EXPIRES.with(Cell::get) > now {
+            return;
+        }
+        EXPIRES.with(|expires| expires.set(now + self.ttl.as_millis() as u64));
+        let value = match &self.source {
+            Source::Var(name) => env
+                .var(name)
+                .map(|var| var.to_string())
+                .or_else(|_| env.secret(name).map(|secret| secret.to_string()))
+                .ok(),
+            Source::Kv { binding, key } => {
+                let value = match env.kv(binding) {
+                    Ok(store) => store.get(key).text().await.map_err(worker::Error::from),
+                    Err(err) => Err(err),
+                };
+                match value {
+                    Ok(value) => value,
+                    Err(err) => {
+                        worker::console_error!("failed to read the kill switch: {err}");
+                        return;
+                    }
+                }
+            }
+        };
+        set_enabled(value.as_deref().map_or(true, is_on));
+    }
+}
+
+fn is_on(value: &str) -> bool {
+    !["off", "false", "0", "disabled"]
+        .iter()
+        .any(|off| value.trim().eq_ignore_ascii_case(off))
+}
@@ -0,0 +1,49 @@
+use serde::Deserialize;
+use worker::{d1::serde_wasm_bindgen, wasm_bindgen::JsValue};
+
+use crate::export::{tail, SpanExporter};
+
+// The other half of `TailExporter`: a tail worker gets the logs of the workers it tails, and
+// `forward` exports the traces they logged. The version of `worker` this crate is built on
+// has no `tail` event, so the handler is exported by hand, and added to the default export
+// of the shim next to `fetch`:
+//
+// ```ignore
+// #[wasm_bindgen]
+// pub async fn tail(events: JsValue, env: Env, _ctx: JsValue) -> Result<(), JsValue> {
+//     let endpoint = env.secret("OTLP_ENDPOINT")?.to_string();
+//     tail::forward(events, &OtlpExporter::new(endpoint).service_name("my-worker")).await?;
+//     Ok(())
+// }
+// ```
+//
+// Everything else the tailed workers log is ignored, as are lines that can't be read.
+
+#[derive(Deserialize)]
+struct TraceItem {
+    #[serde(default)]
+    logs: Vec<Log>,
+}
+
+#[derive(Deserialize)]
+struct Log {
+    #[serde(default)]
+    message: Vec<serde_json::Value>,
+}
+
+/// Exports the traces logged by `TailExporter` in `events`, the `TraceItem`s a tail worker is
+/// handed, all at once.
+pub async fn forward(events: JsValue, exporter: &impl SpanExporter) -> worker::Result<()> {
+    let items: Vec<TraceItem> = serde_wasm_bindgen::from_value(events)?;
+    let records: Vec<_> = items
+        .iter()
+        .flat_map(|item| &item.logs)
+        .flat_map(|log| &log.message)
+        .filter_map(|message| tail::decode(message.as_str()?))
+        .flatten()
+        .collect();
+    if records.is_empty() {
+        return Ok(());
+    }
+    exporter.export(records).await
+}
@@ -0,0 +1,69 @@
+use worker::Request;
+
+use crate::collector;
+
+// Multi-tenant workers often owe each tenant their traces, in the tenant's own backend. The
+// tenant a request is for is recorded as `tenant.id`, on every span of the invocation (see
+// `Collector::with_resource`), and `export::TenantRouter` sends each trace to the exporter
+// of its tenant:
+//
+// ```ignore
+// // In the handler:
+// tenant::record(&req, &TenantFrom::Subdomain);
+//
+// // With the exporters:
+// let router = TenantRouter::new()
+//     .tenant("acme", OtlpExporter::new("https://otlp.acme.example/v1/traces"))
+//     .tenant("globex", HoneycombExporter::new(dataset, api_key, "my-worker"))
+//     .fallback(OtlpExporter::new(our_endpoint));
+// ```
+
+/// Set on every span of an invocation to the tenant it's for.
+pub const TENANT_ID: &str = "tenant.id";
+
+/// Where the tenant of a request is read from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TenantFrom {
+    /// The value of a request header, e.g. `x-tenant-id`.
+    Header(String),
+    /// The first label of the hostname, `acme` for `acme.example.com`, when there are at
+    /// least three.
+    Subdomain,
+    /// The whole hostname, for tenants with domains of their own.
+    Host,
+}
+
+impl TenantFrom {
+    /// The tenant `req` is for, if it says.
+    pub fn extract(&self, req: &Request) -> Option<String> {
+        let tenant = match self {
+            TenantFrom::Header(name) => req.headers().get(name).ok().flatten()?,
+            TenantFrom::Subdomain => {
+                let host = req.url().ok()?.host_str()?.to_ascii_lowercase();
+                let mut labels = host.split('.');
+                let first = labels.next()?.to_owned();
+                if labels.count() < 2 {
+                    return None;
+                }
+                first
+            }
+            TenantFrom::Host => req.url().ok()?.host_str()?.to_ascii_lowercase(),
+        };
+        let tenant = tenant.trim();
+        (!tenant.is_empty()).then(|| tenant.to_owned())
+    }
+}
+
+/// Records the tenant of `req` on every span collected by the current collector, and returns
+/// it.
+pub fn record(req: &Request, from: &TenantFrom) -> Option<String> {
+    let tenant = from.extract(req)?;
+    if collector::is_collecting() {
+        let value = tenant.clone();
+        collector::update_active_resource(|resource| {
+            resource.retain(|(k, _)| k != TENANT_ID);
+            resource.push((TENANT_ID.into(), value.into()));
+        });
+    }
+    Some(tenant)
+}
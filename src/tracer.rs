@@ -0,0 +1,376 @@
+use std::{borrow::Cow, cell::Cell, future::Future, pin::Pin, rc::Rc};
+
+use minitrace::collector::SpanRecord;
+use serde::de::DeserializeOwned;
+use worker::{
+    Context, Env, Message, MessageBatch, Request, Response, ScheduleContext, ScheduledEvent,
+};
+
+use crate::{
+    export::{self, Fanout, SpanExporter},
+    limits::Limits,
+    middleware::{FetchTracing, QueueTracing, ScheduledTracing},
+    processor::{self, Processor, Processors},
+    propagation::{self, Propagators},
+    queue::Traced,
+    resource::{self, Resource},
+    sampler::{self, Sampler, SamplingInput},
+};
+
+// Tracing a worker takes a few pieces that are set up on their own otherwise: the propagators,
+// the sampler and the processors are globals, the resource attributes and the limits go on
+// each middleware, and the exporters are put together in a `Fanout`. `TracingConfig` takes
+// all of them in one place, and `build` installs the globals and returns the `Tracer` that
+// instruments the handlers with the rest.
+//
+// ```ignore
+// #[event(fetch)]
+// async fn main(req: Request, env: Env, ctx: Context) -> Result<Response> {
+//     let tracer = TracingConfig::new()
+//         .sampler(ParentBased::new(Ratio::new(0.1)))
+//         .processor(TailSampling::new().slower_than(Duration::from_millis(500)))
+//         .resource("service.name", "checkout")
+//         .exporter("otlp", OtlpExporter::new(env.var("OTLP_ENDPOINT")?.to_string()))
+//         .build(&env);
+//     tracer.instrument_fetch(req, &ctx, handle).await
+// }
+// ```
+//
+// Exporters usually need the `Env`, so the config is meant to be built in the handler, for
+// every invocation. The globals are only installed by the first `build` in the isolate
+// though, so whatever state the sampler and the processors keep (a tail sampling buffer,
+// say) carries over from one invocation to the next, and later configs can't change them.
+// Whatever isn't configured keeps its default: building one doesn't undo propagators set
+// with `propagation::set_global` in `#[event(start)]`, say, unless it has its own.
+//
+// `from_env` reads a config from the `[vars]` and secrets of the worker instead, each of them
+// being a var or a secret alike:
+//
+// - `TRACE_ENDPOINT`, the OTLP/HTTP traces endpoint, the spans are only logged without it
+// - `TRACE_API_KEY`, optional, sent as `authorization: Bearer {key}`
+// - `TRACE_HEADERS`, optional, `name=value` pairs separated by commas
+// - `TRACE_PROTOCOL`, optional, `http/protobuf` (the default) or `http/json`
+// - `TRACE_SERVICE_NAME`, optional, `worker` otherwise
+// - `TRACE_SERVICE_VERSION` and `TRACE_ENVIRONMENT`, optional, see `Resource`, the version
+//   metadata being read too
+// - `TRACE_SAMPLE_RATE`, optional, the share of the traces starting here that are recorded,
+//   between `0` and `1`, `1` otherwise. Callers' decisions are followed, see `ParentBased`
+//
+// Values that don't make sense fail `from_env`, all of them in one error, so that a typo
+// shows on the first request rather than as traces quietly missing.
+
+type Export = Pin<Box<dyn Future<Output = ()>>>;
+
+thread_local! {
+    // Whether a config installed the globals already.
+    static INSTALLED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Everything tracing is set up with, see [`TracingConfig::build`].
+#[must_use]
+pub struct TracingConfig {
+    propagators: Option<Propagators>,
+    sampler: Option<Box<dyn Sampler>>,
+    processors: Option<Processors>,
+    service: Option<Resource>,
+    resource: Vec<(Cow<'static, str>, Cow<'static, str>)>,
+    exporters: Fanout,
+    limits: Limits,
+    honor_sampled: bool,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        Self {
+            propagators: None,
+            sampler: None,
+            processors: None,
+            service: None,
+            resource: Vec::new(),
+            exporters: Fanout::new(),
+            limits: Limits::default(),
+            honor_sampled: true,
+        }
+    }
+}
+
+impl TracingConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the config from the vars and secrets of `env`, see the module docs.
+    #[cfg(not(feature = "tracing-off"))]
+    pub fn from_env(env: &Env) -> worker::Result<Self> {
+        use crate::{
+            export::{ConsoleExporter, Encoding, OtlpExporter},
+            sampler::{ParentBased, Ratio},
+        };
+        use worker::Url;
+
+        let text = |name: &str| {
+            env.var(name)
+                .map(|var| var.to_string())
+                .or_else(|_| env.secret(name).map(|secret| secret.to_string()))
+                .ok()
+                .filter(|value| !value.trim().is_empty())
+        };
+        let mut errors = Vec::new();
+
+        let mut service =
+            Resource::new(text("TRACE_SERVICE_NAME").unwrap_or_else(|| "worker".to_owned()));
+        if let Some(version) = text("TRACE_SERVICE_VERSION") {
+            service = service.version(version);
+        }
+        if let Some(environment) = text("TRACE_ENVIRONMENT") {
+            service = service.environment(environment);
+        }
+        let encoding = match text("TRACE_PROTOCOL").as_deref().map(str::trim) {
+            None | Some("http/protobuf") => Encoding::Protobuf,
+            Some("http/json") => Encoding::Json,
+            Some(other) => {
+                errors.push(format!(
+                    "TRACE_PROTOCOL must be http/protobuf or http/json, not {other:?}"
+                ));
+                Encoding::Protobuf
+            }
+        };
+        let mut headers = Vec::new();
+        for header in text("TRACE_HEADERS").iter().flat_map(|h| h.split(',')) {
+            match header.split_once('=') {
+                Some((name, value)) if !name.trim().is_empty() => {
+                    headers.push((name.trim().to_owned(), value.trim().to_owned()))
+                }
+                _ => errors.push(format!(
+                    "TRACE_HEADERS must be name=value pairs, not {:?}",
+                    header.trim()
+                )),
+            }
+        }
+        if let Some(key) = text("TRACE_API_KEY") {
+            headers.push(("authorization".to_owned(), format!("Bearer {}", key.trim())));
+        }
+        let sample_rate =
+            text("TRACE_SAMPLE_RATE").and_then(|rate| match rate.trim().parse::<f64>() {
+                Ok(rate) if (0.0..=1.0).contains(&rate) => Some(rate),
+                _ => {
+                    errors.push(format!(
+                        "TRACE_SAMPLE_RATE must be a number between 0 and 1, not {rate:?}"
+                    ));
+                    None
+                }
+            });
+        let endpoint = text("TRACE_ENDPOINT");
+        if let Some(endpoint) = &endpoint {
+            if Url::parse(endpoint.trim()).is_err() {
+                errors.push(format!("TRACE_ENDPOINT must be a URL, not {endpoint:?}"));
+            }
+        }
+        if !errors.is_empty() {
+            return Err(worker::Error::RustError(format!(
+                "invalid tracing config: {}",
+                errors.join(", ")
+            )));
+        }
+
+        let mut config = Self::new().service(service);
+        config = match endpoint {
+            Some(endpoint) => {
+                let mut otlp = OtlpExporter::new(endpoint.trim()).encoding(encoding);
+                for (name, value) in headers {
+                    otlp = otlp.header(name, value);
+                }
+                config.exporter("otlp", otlp)
+            }
+            None => config.exporter("console", ConsoleExporter::new()),
+        };
+        if let Some(rate) = sample_rate.filter(|rate| *rate < 1.0) {
+            config = config.sampler(ParentBased::new(Ratio::new(rate)));
+        }
+        Ok(config)
+    }
+
+    /// With `tracing-off`, there's nothing to read: the config is empty.
+    #[cfg(feature = "tracing-off")]
+    pub fn from_env(_env: &Env) -> worker::Result<Self> {
+        Ok(Self::new())
+    }
+
+    /// Extracts and injects contexts with `propagators`, see `propagation::set_global`.
+    pub fn propagators(mut self, propagators: Propagators) -> Self {
+        self.propagators = Some(propagators);
+        self
+    }
+
+    /// Decides which requests are recorded with `sampler`, see `sampler::set_global`.
+    pub fn sampler(mut self, sampler: impl Sampler + 'static) -> Self {
+        self.sampler = Some(Box::new(sampler));
+        self
+    }
+
+    /// Adds `processor`, run after the ones added before it, see `processor::set_global`.
+    pub fn processor(mut self, processor: impl Processor + 'static) -> Self {
+        self.processors = Some(self.processors.unwrap_or_default().with(processor));
+        self
+    }
+
+    /// Exports the spans under `resource`, see `resource::set_global`.
+    pub fn service(mut self, resource: Resource) -> Self {
+        self.service = Some(resource);
+        self
+    }
+
+    /// Sets `key` on every span, see `Collector::with_resource`.
+    pub fn resource(
+        mut self,
+        key: impl Into<Cow<'static, str>>,
+        value: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        self.resource.push((key.into(), value.into()));
+        self
+    }
+
+    /// Exports the records with `exporter` too, named `name` in errors.
+    pub fn exporter(
+        mut self,
+        name: impl Into<String>,
+        exporter: impl SpanExporter + 'static,
+    ) -> Self {
+        self.exporters = self.exporters.with(name, exporter);
+        self
+    }
+
+    /// Exports the records `filter` leaves with `exporter` too, see `Fanout::with_filter`.
+    pub fn exporter_with_filter(
+        mut self,
+        name: impl Into<String>,
+        filter: impl Processor + 'static,
+        exporter: impl SpanExporter + 'static,
+    ) -> Self {
+        self.exporters = self.exporters.with_filter(name, filter, exporter);
+        self
+    }
+
+    /// Caps what each span can carry, see [`Limits`].
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Whether requests the caller didn't sample are left unrecorded, which is the default.
+    /// See `FetchTracing::honor_sampled`.
+    pub fn honor_sampled(mut self, honor_sampled: bool) -> Self {
+        self.honor_sampled = honor_sampled;
+        self
+    }
+
+    /// Installs the propagators, the sampler, the processors and the resource, if any, as the
+    /// globals, unless a config was built in this isolate already.
+    ///
+    /// The resource gets the version metadata of the worker bound in `env`, for whatever it
+    /// doesn't set itself, see `Resource::version_metadata`.
+    pub fn build(self, env: &Env) -> Tracer {
+        if !INSTALLED.with(|installed| installed.replace(true)) {
+            if let Some(propagators) = self.propagators {
+                propagation::set_global(propagators);
+            }
+            if let Some(sampler) = self.sampler {
+                sampler::set_global(move |input: &SamplingInput<'_>| sampler.should_sample(input));
+            }
+            if let Some(processors) = self.processors {
+                processor::set_global(processors);
+            }
+            if let Some(resource) = self.service {
+                resource::set_global(
+                    resource.version_metadata(env, resource::VERSION_METADATA_BINDING),
+                );
+            }
+        }
+        Tracer {
+            exporter: Rc::new(self.exporters),
+            resource: self.resource,
+            limits: self.limits,
+            honor_sampled: self.honor_sampled,
+        }
+    }
+}
+
+/// Instruments handlers as configured by a [`TracingConfig`].
+#[derive(Clone)]
+pub struct Tracer {
+    exporter: Rc<Fanout>,
+    resource: Vec<(Cow<'static, str>, Cow<'static, str>)>,
+    limits: Limits,
+    honor_sampled: bool,
+}
+
+impl Tracer {
+    /// The export callback sending the records to the configured exporters.
+    pub fn export(&self) -> impl Fn(Vec<SpanRecord>) -> Export + Clone {
+        export::to(self.exporter.clone())
+    }
+
+    /// A `FetchTracing` as configured, for the options it has on top, e.g. `body_sizes`.
+    pub fn fetch(&self) -> FetchTracing<impl FnOnce(Vec<SpanRecord>) -> Export> {
+        FetchTracing::new(self.export())
+            .limits(self.limits)
+            .honor_sampled(self.honor_sampled)
+            .resource(self.resource.clone())
+    }
+
+    /// A `ScheduledTracing` as configured.
+    pub fn scheduled(&self) -> ScheduledTracing<impl FnOnce(Vec<SpanRecord>) -> Export> {
+        ScheduledTracing::new(self.export())
+            .limits(self.limits)
+            .resource(self.resource.clone())
+    }
+
+    /// A `QueueTracing` as configured.
+    pub fn queue(&self) -> QueueTracing<impl FnOnce(Vec<SpanRecord>) -> Export> {
+        QueueTracing::new(self.export())
+            .limits(self.limits)
+            .resource(self.resource.clone())
+    }
+
+    /// Runs `handler` on `req`, see `FetchTracing::handle`.
+    pub async fn instrument_fetch<H, HFut>(
+        &self,
+        req: Request,
+        ctx: &Context,
+        handler: H,
+    ) -> worker::Result<Response>
+    where
+        H: FnOnce(Request) -> HFut,
+        HFut: Future<Output = worker::Result<Response>>,
+    {
+        self.fetch().handle(req, ctx, handler).await
+    }
+
+    /// Runs `handler` on `event`, see `ScheduledTracing::handle`.
+    pub async fn instrument_scheduled<H, HFut>(
+        &self,
+        event: ScheduledEvent,
+        ctx: &ScheduleContext,
+        handler: H,
+    ) where
+        H: FnOnce(ScheduledEvent) -> HFut,
+        HFut: Future<Output = ()>,
+    {
+        self.scheduled().handle(event, ctx, handler).await
+    }
+
+    /// Runs `handler` on every message of `batch`, see `QueueTracing::handle`.
+    pub async fn instrument_queue<T, H, HFut>(
+        &self,
+        batch: MessageBatch<Traced<T>>,
+        ctx: &Context,
+        handler: H,
+    ) -> worker::Result<()>
+    where
+        T: DeserializeOwned,
+        H: FnMut(Message<T>) -> HFut,
+        HFut: Future<Output = worker::Result<()>>,
+    {
+        self.queue().handle(batch, ctx, handler).await
+    }
+}
@@ -0,0 +1,224 @@
+use std::{borrow::Cow, fmt};
+
+use minitrace::collector::SpanRecord;
+
+// Properties can only be strings, so a typed attribute is stored as its string form plus a
+// reserved `__type.<key>` property naming the type. `attributes` puts the two back together
+// for exporters; anyone looking at the raw properties still sees a readable value. Arrays
+// are stored as JSON.
+
+pub(crate) const TYPE_PREFIX: &str = "__type.";
+
+/// The value of a typed attribute.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    I64(i64),
+    F64(f64),
+    Bool(bool),
+    String(Cow<'static, str>),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    fn type_name(&self) -> Option<&'static str> {
+        match self {
+            Value::I64(_) => Some("i64"),
+            Value::F64(_) => Some("f64"),
+            Value::Bool(_) => Some("bool"),
+            Value::String(_) => None,
+            Value::Array(_) => Some("array"),
+        }
+    }
+
+    fn parse(type_name: &str, value: &str) -> Option<Value> {
+        match type_name {
+            "i64" => value.parse().ok().map(Value::I64),
+            "f64" => value.parse().ok().map(Value::F64),
+            "bool" => value.parse().ok().map(Value::Bool),
+            "array" => Self::from_json(serde_json::from_str(value).ok()?),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        match self {
+            Value::I64(v) => (*v).into(),
+            Value::F64(v) => (*v).into(),
+            Value::Bool(v) => (*v).into(),
+            Value::String(v) => v.as_ref().into(),
+            Value::Array(v) => v.iter().map(Value::to_json).collect(),
+        }
+    }
+
+    fn from_json(json: serde_json::Value) -> Option<Value> {
+        match json {
+            serde_json::Value::Bool(v) => Some(Value::Bool(v)),
+            serde_json::Value::Number(v) => v
+                .as_i64()
+                .map(Value::I64)
+                .or_else(|| v.as_f64().map(Value::F64)),
+            serde_json::Value::String(v) => Some(Value::String(v.into())),
+            serde_json::Value::Array(v) => v
+                .into_iter()
+                .map(Self::from_json)
+                .collect::<Option<_>>()
+                .map(Value::Array),
+            serde_json::Value::Null | serde_json::Value::Object(_) => None,
+        }
+    }
+
+    /// The properties storing `key = self`.
+    pub(crate) fn to_properties(
+        &self,
+        key: Cow<'static, str>,
+    ) -> Vec<(Cow<'static, str>, Cow<'static, str>)> {
+        let mut properties = Vec::with_capacity(2);
+        if let Some(type_name) = self.type_name() {
+            properties.push((format!("{TYPE_PREFIX}{key}").into(), type_name.into()));
+        }
+        let value = match self {
+            Value::String(v) => v.clone(),
+            other => other.to_string().into(),
+        };
+        properties.push((key, value));
+        properties
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::I64(v) => write!(f, "{v}"),
+            Value::F64(v) => write!(f, "{v}"),
+            Value::Bool(v) => write!(f, "{v}"),
+            Value::String(v) => f.write_str(v),
+            Value::Array(_) => write!(f, "{}", self.to_json()),
+        }
+    }
+}
+
+macro_rules! impl_from {
+    ($($ty:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl From<$ty> for Value {
+                fn from(v: $ty) -> Self {
+                    Value::$variant(v.into())
+                }
+            }
+        )*
+    };
+}
+
+impl_from! {
+    i64 => I64,
+    i32 => I64,
+    u32 => I64,
+    f64 => F64,
+    f32 => F64,
+    bool => Bool,
+    &'static str => String,
+    String => String,
+    Cow<'static, str> => String,
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(v: Vec<T>) -> Self {
+        Value::Array(v.into_iter().map(Into::into).collect())
+    }
+}
+
+/// The attributes of a record with their types restored, leaving out everything reserved
+/// by this crate.
+pub fn attributes(record: &SpanRecord) -> Vec<(Cow<'static, str>, Value)> {
+    typed(&record.properties)
+}
+
+/// Same as [`attributes`], for any list of properties (e.g. those of an event).
+pub fn typed(
+    properties: &[(Cow<'static, str>, Cow<'static, str>)],
+) -> Vec<(Cow<'static, str>, Value)> {
+    properties
+        .iter()
+        .filter(|(k, _)| !k.starts_with("__"))
+        .map(|(k, v)| {
+            let value = properties
+                .iter()
+                .rev()
+                .find(|(tk, _)| tk.strip_prefix(TYPE_PREFIX) == Some(k))
+                .and_then(|(_, type_name)| Value::parse(type_name, v))
+                .unwrap_or_else(|| Value::String(v.clone()));
+            (k.clone(), value)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn properties(
+        attributes: &[(&'static str, Value)],
+    ) -> Vec<(Cow<'static, str>, Cow<'static, str>)> {
+        attributes
+            .iter()
+            .flat_map(|(key, value)| value.to_properties((*key).into()))
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let attributes = [
+            ("count", Value::I64(-3)),
+            ("ratio", Value::F64(0.5)),
+            ("cached", Value::Bool(true)),
+            ("route", Value::String("/users/:id".into())),
+            ("ids", Value::from(vec![1, 2])),
+            ("tags", Value::from(vec!["a", "b"])),
+        ];
+        let properties = properties(&attributes);
+        assert_eq!(
+            properties,
+            [
+                ("__type.count".into(), "i64".into()),
+                ("count".into(), "-3".into()),
+                ("__type.ratio".into(), "f64".into()),
+                ("ratio".into(), "0.5".into()),
+                ("__type.cached".into(), "bool".into()),
+                ("cached".into(), "true".into()),
+                ("route".into(), "/users/:id".into()),
+                ("__type.ids".into(), "array".into()),
+                ("ids".into(), "[1,2]".into()),
+                ("__type.tags".into(), "array".into()),
+                ("tags".into(), r#"["a","b"]"#.into()),
+            ]
+        );
+        let typed: Vec<_> = typed(&properties)
+            .into_iter()
+            .map(|(key, value)| (key.into_owned(), value))
+            .collect();
+        let expected: Vec<_> = attributes
+            .into_iter()
+            .map(|(key, value)| (key.to_owned(), value))
+            .collect();
+        assert_eq!(typed, expected);
+    }
+
+    #[test]
+    fn untyped_fallback() {
+        // A value that doesn't parse as its type stays a string, reserved properties are left out.
+        let properties: Vec<(Cow<'static, str>, Cow<'static, str>)> = vec![
+            ("__type.count".into(), "i64".into()),
+            ("count".into(), "many".into()),
+            ("__type.flag".into(), "bool".into()),
+            ("flag".into(), "false".into()),
+            ("__internal".into(), "hidden".into()),
+        ];
+        assert_eq!(
+            typed(&properties),
+            [
+                ("count".into(), Value::String("many".into())),
+                ("flag".into(), Value::Bool(false)),
+            ]
+        );
+    }
+}
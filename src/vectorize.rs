@@ -0,0 +1,142 @@
+use serde::{Deserialize, Serialize};
+use worker::{
+    d1::serde_wasm_bindgen,
+    js_sys::Promise,
+    wasm_bindgen::{self, prelude::*},
+    wasm_bindgen_futures::JsFuture,
+    EnvBinding,
+};
+
+use crate::{binding, current};
+
+// This version of `worker` has no Vectorize binding either, it's bound here. Queries record
+// how many matches came back and the range of their scores, which is what tells a relevant
+// result from a poor one.
+
+const DB_SYSTEM: &str = "cloudflare_vectorize";
+
+#[wasm_bindgen]
+extern "C" {
+    /// A Vectorize index binding.
+    #[derive(Clone)]
+    pub type VectorizeIndex;
+
+    #[wasm_bindgen(method)]
+    fn query(this: &VectorizeIndex, vector: JsValue, options: JsValue) -> Promise;
+
+    #[wasm_bindgen(method)]
+    fn upsert(this: &VectorizeIndex, vectors: JsValue) -> Promise;
+}
+
+impl EnvBinding for VectorizeIndex {
+    const TYPE_NAME: &'static str = "VectorizeIndexImpl";
+
+    // The class behind the binding isn't part of the API.
+    fn get(val: JsValue) -> worker::Result<Self> {
+        Ok(val.unchecked_into())
+    }
+}
+
+/// A vector to upsert.
+#[derive(Clone, Debug, Serialize)]
+pub struct Vector {
+    pub id: String,
+    pub values: Vec<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+}
+
+/// A vector matching a query.
+#[derive(Clone, Debug, Deserialize)]
+pub struct VectorMatch {
+    pub id: String,
+    pub score: f64,
+    #[serde(default)]
+    pub metadata: Option<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct QueryOptions {
+    top_k: u32,
+    return_metadata: bool,
+}
+
+#[derive(Deserialize)]
+struct Matches {
+    matches: Vec<VectorMatch>,
+}
+
+/// A `VectorizeIndex` whose operations get a client span each.
+#[derive(Clone)]
+pub struct TracedVectorize {
+    index: VectorizeIndex,
+    name: String,
+}
+
+impl TracedVectorize {
+    /// Traces the operations on `index`, bound as `name`.
+    pub fn new(index: VectorizeIndex, name: impl Into<String>) -> Self {
+        Self {
+            index,
+            name: name.into(),
+        }
+    }
+
+    /// Traces the index bound as `binding` in `env`.
+    pub fn from_env(env: &worker::Env, binding: &str) -> worker::Result<Self> {
+        Ok(Self::new(env.get_binding(binding)?, binding))
+    }
+
+    /// Finds the `top_k` vectors closest to `vector`.
+    pub async fn query(
+        &self,
+        vector: &[f32],
+        top_k: u32,
+        return_metadata: bool,
+    ) -> worker::Result<Vec<VectorMatch>> {
+        binding::call("query", &self.name, self.properties("query"), async {
+            current::add_attribute("vectorize.top_k", top_k);
+            let serializer = serde_wasm_bindgen::Serializer::json_compatible();
+            let options = QueryOptions {
+                top_k,
+                return_metadata,
+            };
+            let promise = self.index.query(
+                vector.serialize(&serializer)?,
+                options.serialize(&serializer)?,
+            );
+            let Matches { matches } =
+                serde_wasm_bindgen::from_value(JsFuture::from(promise).await?)?;
+            current::add_attribute("vectorize.matches.count", matches.len() as i64);
+            let scores = matches.iter().map(|m| m.score);
+            if let (Some(min), Some(max)) =
+                (scores.clone().reduce(f64::min), scores.reduce(f64::max))
+            {
+                current::add_attribute("vectorize.score.min", min);
+                current::add_attribute("vectorize.score.max", max);
+            }
+            Ok(matches)
+        })
+        .await
+    }
+
+    /// Inserts `vectors`, or replaces the ones with the same ids.
+    pub async fn upsert(&self, vectors: &[Vector]) -> worker::Result<()> {
+        binding::call("upsert", &self.name, self.properties("upsert"), async {
+            current::add_attribute("vectorize.vectors.count", vectors.len() as i64);
+            let vectors = vectors.serialize(&serde_wasm_bindgen::Serializer::json_compatible())?;
+            JsFuture::from(self.index.upsert(vectors)).await?;
+            Ok(())
+        })
+        .await
+    }
+
+    fn properties(&self, operation: &str) -> Vec<(&'static str, String)> {
+        vec![
+            ("db.system", DB_SYSTEM.to_owned()),
+            ("db.operation.name", operation.to_owned()),
+            ("db.namespace", self.name.clone()),
+        ]
+    }
+}
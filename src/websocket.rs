@@ -0,0 +1,183 @@
+use std::{future::Future, rc::Rc};
+
+use minitrace::collector::SpanRecord;
+use worker::{MessageEvent, WebSocket};
+
+use crate::{
+    builder::LocalSpanBuilder,
+    collector::Collector,
+    error,
+    kind::{SpanKind, SPAN_KIND},
+    lazy,
+    limits::Limits,
+    local_future::LocalFutureExt,
+    processor,
+    propagation::TraceContext,
+    scoped_span::ScopedSpan,
+};
+
+// A WebSocket outlives the request that opened it, and so does the handling of its messages:
+// by the time they come in, the request's collector is long gone. `WebSocketTracing`
+// remembers the context of the span the connection was accepted in, and traces every inbound
+// message in a collector of its own continuing that trace, so all the messages of a
+// connection end up under its span, Durable Object or not. Their records are exported as soon
+// as the message is handled, there's no `ctx.wait_until` to defer it to.
+//
+// ```ignore
+// let pair = WebSocketPair::new()?;
+// pair.server.accept()?;
+// let tracing = WebSocketTracing::new(export);
+// wasm_bindgen_futures::spawn_local(async move {
+//     let mut events = pair.server.events().unwrap();
+//     while let Some(Ok(WebsocketEvent::Message(message))) = events.next().await {
+//         let _ = tracing.message(&message, || handle(&pair.server, &message)).await;
+//     }
+// });
+// ```
+//
+// Outbound messages get a producer span under the current span, see `WebSocketTracing::send`.
+
+/// Traces the messages of a WebSocket connection.
+pub struct WebSocketTracing<E> {
+    inner: Rc<Inner<E>>,
+}
+
+struct Inner<E> {
+    export: E,
+    limits: Limits,
+    connection: TraceContext,
+}
+
+/// Configures a [`WebSocketTracing`], see `WebSocketTracing::builder`.
+#[must_use]
+pub struct WebSocketTracingBuilder<E> {
+    export: E,
+    limits: Limits,
+    connection: TraceContext,
+}
+
+impl<E> WebSocketTracingBuilder<E> {
+    /// Caps what each span can carry, see [`Limits`].
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Freezes the configuration into a tracing that can be cloned.
+    pub fn build(self) -> WebSocketTracing<E> {
+        WebSocketTracing {
+            inner: Rc::new(Inner {
+                export: self.export,
+                limits: self.limits,
+                connection: self.connection,
+            }),
+        }
+    }
+}
+
+impl<E> Clone for WebSocketTracing<E> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<E, Fut> WebSocketTracing<E>
+where
+    E: Fn(Vec<SpanRecord>) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    /// Ties the messages to the current span, or to a new trace outside of one, and hands the
+    /// records of each of them to `export`.
+    pub fn new(export: E) -> Self {
+        Self::builder(export).build()
+    }
+
+    /// Like `new`, configured before it's built. The messages are tied to the span that's
+    /// current now, not when it's built.
+    pub fn builder(export: E) -> WebSocketTracingBuilder<E> {
+        WebSocketTracingBuilder {
+            export,
+            limits: Limits::default(),
+            connection: TraceContext::current().unwrap_or_else(TraceContext::new_root),
+        }
+    }
+
+    /// The context the message spans continue.
+    pub fn connection(&self) -> &TraceContext {
+        &self.inner.connection
+    }
+
+    /// Runs `handler` on an inbound `message` inside a consumer span under the connection's,
+    /// and exports its records before returning.
+    pub async fn message<H, HFut>(&self, message: &MessageEvent, handler: H) -> worker::Result<()>
+    where
+        H: FnOnce() -> HFut,
+        HFut: Future<Output = worker::Result<()>>,
+    {
+        let mut collector =
+            Collector::start_remote(&self.inner.connection, true).with_limits(self.inner.limits);
+        let (opcode, size) = match message.text() {
+            Some(text) => ("text", text.len()),
+            None => ("binary", message.bytes().map_or(0, |bytes| bytes.len())),
+        };
+        let properties = [
+            (SPAN_KIND, SpanKind::Consumer.as_str().to_owned()),
+            ("websocket.opcode", opcode.to_owned()),
+        ];
+
+        let receive = async {
+            let res = handler().await;
+            if let Err(err) = &res {
+                error::record(err.to_string());
+            }
+            res
+        }
+        .in_local_span("websocket receive")
+        .with_properties(properties)
+        .with_attribute("websocket.message.size", size as i64);
+        let res = collector.run(receive).await;
+
+        let mut records = collector.collect();
+        processor::run(&mut records);
+        if !records.is_empty() {
+            lazy::resolve(&mut records);
+            (self.inner.export)(records).await;
+        }
+        res
+    }
+
+    /// Sends a text message on `socket` inside a producer span under the current span.
+    pub fn send_with_str(&self, socket: &WebSocket, data: impl AsRef<str>) -> worker::Result<()> {
+        let data = data.as_ref();
+        let _span = send_span("text", data.len());
+        record(socket.send_with_str(data))
+    }
+
+    /// Sends a binary message on `socket` inside a producer span under the current span.
+    pub fn send_with_bytes(
+        &self,
+        socket: &WebSocket,
+        data: impl AsRef<[u8]>,
+    ) -> worker::Result<()> {
+        let data = data.as_ref();
+        let _span = send_span("binary", data.len());
+        record(socket.send_with_bytes(data))
+    }
+}
+
+fn send_span(opcode: &'static str, size: usize) -> ScopedSpan {
+    LocalSpanBuilder::new("websocket send")
+        .kind(SpanKind::Producer)
+        .property("websocket.opcode", opcode)
+        .attribute("websocket.message.size", size as i64)
+        .enter()
+}
+
+fn record(res: worker::Result<()>) -> worker::Result<()> {
+    if let Err(err) = &res {
+        error::record(err.to_string());
+    }
+    res
+}